@@ -2,7 +2,7 @@ use std::error::Error;
 use url::Url;
 use std::str::FromStr;
 
-use ue_rs::download_and_hash;
+use ue_rs::{DownloadOptions, ExpectedHashes, download_and_hash};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let client = reqwest::blocking::Client::new();
@@ -13,7 +13,14 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let tempdir = tempfile::tempdir()?;
     let path = tempdir.path().join("tmpfile");
-    let res = download_and_hash(&client, url, &path, None, None)?;
+    let res = download_and_hash(
+        &client,
+        url,
+        &path,
+        &ExpectedHashes::default(),
+        None,
+        &DownloadOptions::default(),
+    )?;
     tempdir.close()?;
 
     println!("hash: {}", res.hash_sha256);
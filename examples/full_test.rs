@@ -2,14 +2,18 @@ use std::error::Error;
 use std::borrow::Cow;
 
 use anyhow::{Context, Result};
-use hard_xml::XmlRead;
 use url::Url;
 
 fn get_pkgs_to_download(resp: &omaha::Response) -> Result<Vec<(Url, omaha::Hash<omaha::Sha256>)>> {
     let mut to_download: Vec<(Url, omaha::Hash<_>)> = Vec::new();
 
     for app in &resp.apps {
-        let manifest = &app.update_check.manifest;
+        let Some(update_check) = &app.update_check else {
+            continue;
+        };
+        let Some(manifest) = &update_check.manifest else {
+            continue;
+        };
 
         for pkg in &manifest.packages {
             #[rustfmt::skip]
@@ -25,15 +29,11 @@ fn get_pkgs_to_download(resp: &omaha::Response) -> Result<Vec<(Url, omaha::Hash<
             //       not sure if nebraska sends us more than one right now but i suppose this is
             //       for mirrors?
             #[rustfmt::skip]
-            let url = app.update_check.urls.get(0)
+            let url = update_check.urls.first()
                 .map(|u| u.join(&pkg.name));
 
-            match (url, hash_sha256) {
-                (Some(Ok(url)), Some(hash)) => {
-                    to_download.push((url, hash.clone()));
-                }
-
-                _ => (),
+            if let (Some(Ok(url)), Some(hash)) = (url, hash_sha256) {
+                to_download.push((url, hash.clone()));
             }
         }
     }
@@ -52,25 +52,30 @@ fn main() -> Result<(), Box<dyn Error>> {
     // request
     ////
     let parameters = ue_rs::request::Parameters {
-        app_version: Cow::Borrowed(APP_VERSION_DEFAULT),
+        server_url: None,
+
+        apps: vec![ue_rs::request::AppParameters::os(Cow::Borrowed(APP_VERSION_DEFAULT), Cow::Borrowed(TRACK_DEFAULT))],
         machine_id: Cow::Borrowed(MACHINE_ID_DEFAULT),
+        boot_id: ue_rs::request::read_boot_id().ok(),
+
+        board: None,
+        arch: None,
+        oem: None,
+        oem_version: None,
+        oem_platform: None,
 
-        track: Cow::Borrowed(TRACK_DEFAULT),
+        delta_okay: false,
+        retry_policy: Default::default(),
     };
 
-    let response_text = ue_rs::request::perform(&client, parameters).context(format!(
-        "perform({APP_VERSION_DEFAULT}, {MACHINE_ID_DEFAULT}, {TRACK_DEFAULT}) failed"
+    let parsed = ue_rs::request::perform_parsed(&client, parameters).context(format!(
+        "perform_parsed({APP_VERSION_DEFAULT}, {MACHINE_ID_DEFAULT}, {TRACK_DEFAULT}) failed"
     ))?;
 
-    println!("response:\n\t{:#?}", response_text);
+    println!("response:\n\t{:#?}", parsed.raw);
     println!();
 
-    ////
-    // parse response
-    ////
-    let resp = omaha::Response::from_str(&response_text).context("failed to parse response")?;
-
-    let pkgs_to_dl = get_pkgs_to_download(&resp).context("failed to get packages to download")?;
+    let pkgs_to_dl = get_pkgs_to_download(&parsed.response).context("failed to get packages to download")?;
 
     ////
     // download
@@ -80,7 +85,18 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         let tempdir = tempfile::tempdir()?;
         let path = tempdir.path().join("tmpfile");
-        let res = ue_rs::download_and_hash(&client, url.clone(), &path, Some(expected_sha256.clone()), None).context(format!("download_and_hash({url:?}) failed"))?;
+        let res = ue_rs::download_and_hash(
+            &client,
+            url.clone(),
+            &path,
+            &ue_rs::ExpectedHashes {
+                sha256: Some(expected_sha256.clone()),
+                ..Default::default()
+            },
+            None,
+            &ue_rs::DownloadOptions::default(),
+        )
+        .context(format!("download_and_hash({url:?}) failed"))?;
         tempdir.close()?;
 
         println!("\texpected sha256:   {}", expected_sha256);
@@ -13,10 +13,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     const TRACK_DEFAULT: &str = "stable";
 
     let parameters = request::Parameters {
-        app_version: Cow::Borrowed(APP_VERSION_DEFAULT),
+        server_url: None,
+
+        apps: vec![request::AppParameters::os(Cow::Borrowed(APP_VERSION_DEFAULT), Cow::Borrowed(TRACK_DEFAULT))],
         machine_id: Cow::Borrowed(MACHINE_ID_DEFAULT),
+        boot_id: request::read_boot_id().ok(),
+
+        board: None,
+        arch: None,
+        oem: None,
+        oem_version: None,
+        oem_platform: None,
 
-        track: Cow::Borrowed(TRACK_DEFAULT),
+        delta_okay: false,
+        retry_policy: Default::default(),
     };
 
     let response = request::perform(&client, parameters).context(format!(
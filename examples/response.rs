@@ -2,7 +2,6 @@ use std::error::Error;
 
 use anyhow::Context;
 use hard_xml::XmlRead;
-use omaha;
 
 #[rustfmt::skip]
 const RESPONSE_XML: &str =
@@ -38,7 +37,13 @@ fn main() -> Result<(), Box<dyn Error>> {
     for app in &resp.apps {
         println!("app id {}:", app.id);
 
-        let manifest = &app.update_check.manifest;
+        let Some(update_check) = &app.update_check else {
+            continue;
+        };
+        let Some(manifest) = &update_check.manifest else {
+            println!("  status {}", update_check.status);
+            continue;
+        };
         println!("  version {}:", manifest.version);
 
         for pkg in &manifest.packages {
@@ -58,16 +63,14 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .map(|a| &a.sha256)
                 });
 
-            #[rustfmt::skip]
-            hash_sha256
-                .map(|h| {
-                    println!("      sha256: {}", h);
-                });
+            if let Some(h) = hash_sha256 {
+                println!("      sha256: {}", h);
+            }
 
             println!();
             println!("      urls:");
 
-            for url in &app.update_check.urls {
+            for url in &update_check.urls {
                 println!(
                     "        {}",
                     url.join(&pkg.name).context(format!("failed to join URL with {:?}", pkg.name))?
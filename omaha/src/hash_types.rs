@@ -1,9 +1,11 @@
 use std::fmt;
+use std::io;
 use std::str;
 
 use sha2::Digest;
 
 use anyhow::{Error as CodecError, anyhow};
+use tracing::warn;
 
 #[rustfmt::skip]
 use ct_codecs::{
@@ -20,6 +22,35 @@ pub struct Sha1;
 #[derive(PartialEq, Eq, Clone)]
 pub struct Sha256;
 
+#[derive(PartialEq, Eq, Clone)]
+pub struct Blake3;
+
+#[derive(PartialEq, Eq, Clone)]
+pub struct Sha512;
+
+// Arrays implement Default only up to 32 elements, so the 64 bytes of a
+// SHA-512 digest need their own type.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Sha512Digest([u8; 64]);
+
+impl Default for Sha512Digest {
+    fn default() -> Self {
+        Sha512Digest([0u8; 64])
+    }
+}
+
+impl AsRef<[u8]> for Sha512Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for Sha512Digest {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
 pub trait HashAlgo {
     const HASH_NAME: &'static str;
 
@@ -43,7 +74,7 @@ impl HashAlgo for Sha1 {
         let boxed_array: Box<Self::Output> = match v.into_boxed_slice().try_into() {
             Ok(a) => a,
             Err(e) => {
-                println!("Unexpected length {}", e.len());
+                warn!("unexpected hash length {}", e.len());
                 #[allow(clippy::box_default)]
                 Box::new(Self::Output::default())
             }
@@ -66,7 +97,30 @@ impl HashAlgo for Sha256 {
         let boxed_array: Box<Self::Output> = match v.into_boxed_slice().try_into() {
             Ok(a) => a,
             Err(e) => {
-                println!("Unexpected length {}", e.len());
+                warn!("unexpected hash length {}", e.len());
+                #[allow(clippy::box_default)]
+                Box::new(Self::Output::default())
+            }
+        };
+        *boxed_array
+    }
+}
+
+impl HashAlgo for Blake3 {
+    const HASH_NAME: &'static str = "Blake3";
+    type Output = [u8; 32];
+
+    fn hasher() -> impl digest::DynDigest {
+        blake3::Hasher::new()
+    }
+
+    fn from_boxed(s: Box<[u8]>) -> Self::Output {
+        let mut v = s.into_vec();
+        v.resize(Self::Output::default().len(), 0);
+        let boxed_array: Box<Self::Output> = match v.into_boxed_slice().try_into() {
+            Ok(a) => a,
+            Err(e) => {
+                warn!("unexpected hash length {}", e.len());
                 #[allow(clippy::box_default)]
                 Box::new(Self::Output::default())
             }
@@ -75,6 +129,25 @@ impl HashAlgo for Sha256 {
     }
 }
 
+impl HashAlgo for Sha512 {
+    const HASH_NAME: &'static str = "Sha512";
+    type Output = Sha512Digest;
+
+    fn hasher() -> impl digest::DynDigest {
+        sha2::Sha512::new()
+    }
+
+    fn from_boxed(s: Box<[u8]>) -> Self::Output {
+        let mut digest = Self::Output::default();
+        if s.len() != digest.0.len() {
+            warn!("unexpected hash length {}", s.len());
+            return digest;
+        }
+        digest.0.copy_from_slice(&s);
+        digest
+    }
+}
+
 #[derive(PartialEq, Eq, Clone)]
 pub struct Hash<T: HashAlgo>(T::Output);
 
@@ -136,4 +209,96 @@ impl<T: HashAlgo> Hash<T> {
     pub fn from_hex(hash_hex: &str) -> Result<Self, CodecError> {
         Self::decode::<Hex>(hash_hex)
     }
+
+    pub fn to_base64(&self) -> String {
+        // Encoding only fails if the length of the output would overflow.
+        Base64::encode_to_string(self.0.as_ref()).unwrap_or_default()
+    }
+}
+
+// Writer that passes all data through to the inner writer, and hashes
+// what the inner writer accepted, e.g. to hash a download while it is
+// written to disk through Response::copy_to.
+pub struct HashingWriter<T: HashAlgo, W: io::Write> {
+    inner: W,
+    hasher: Box<dyn digest::DynDigest>,
+    _algo: std::marker::PhantomData<T>,
+}
+
+impl<T: HashAlgo + 'static, W: io::Write> HashingWriter<T, W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Box::new(T::hasher()),
+            _algo: std::marker::PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    // Return the inner writer and the hash of all data written so far.
+    pub fn finalize(self) -> (W, Hash<T>) {
+        (self.inner, Hash::from_bytes(self.hasher.finalize()))
+    }
+}
+
+impl<T: HashAlgo, W: io::Write> io::Write for HashingWriter<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only hash what was actually written, as writes can be short.
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blake3() {
+        // Test vector of the empty input, from the BLAKE3 reference.
+        const EMPTY_HEX: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+        let hash = Hash::<Blake3>::from_bytes(digest::DynDigest::finalize(Box::new(Blake3::hasher())));
+        assert_eq!(hash, Hash::<Blake3>::from_hex(EMPTY_HEX).unwrap());
+        assert_eq!(hash.to_string(), EMPTY_HEX);
+
+        let base64 = Base64::encode_to_string(Vec::from(hash.clone())).unwrap();
+        assert_eq!(Hash::<Blake3>::from_base64(&base64).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_sha512() {
+        // Test vector of "abc", from FIPS 180-2.
+        const ABC_HEX: &str = "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f";
+
+        let mut hasher = Sha512::hasher();
+        digest::DynDigest::update(&mut hasher, b"abc");
+        let hash = Hash::<Sha512>::from_bytes(digest::DynDigest::finalize(Box::new(hasher)));
+        assert_eq!(hash, Hash::<Sha512>::from_hex(ABC_HEX).unwrap());
+        assert_eq!(hash.to_string(), ABC_HEX);
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        use std::io::Write;
+
+        let mut writer = HashingWriter::<Sha256, _>::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        io::copy(&mut &b"!"[..], &mut writer).unwrap();
+        let (data, hash) = writer.finalize();
+
+        let mut hasher = Sha256::hasher();
+        digest::DynDigest::update(&mut hasher, b"hello world!");
+        assert_eq!(data, b"hello world!");
+        assert_eq!(hash, Hash::<Sha256>::from_bytes(digest::DynDigest::finalize(Box::new(hasher))));
+    }
 }
@@ -12,3 +12,9 @@ pub use request::Request;
 
 pub mod response;
 pub use response::Response;
+
+#[cfg(feature = "serde")]
+mod serde_support;
+
+#[cfg(feature = "quick-xml")]
+pub mod quick;
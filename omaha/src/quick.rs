@@ -0,0 +1,457 @@
+// Reading responses and writing requests with quick-xml, as an alternative
+// to the XmlRead and XmlWrite implementations of hard-xml. Responses are
+// read while they are parsed, e.g. from a reader of the HTTP body, and are
+// the same as those of XmlRead, as are the written requests.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer};
+
+use crate as omaha;
+use self::omaha::{request, response, Request, Response};
+
+// Attributes of an element, unescaped.
+struct Attributes {
+    element: String,
+    values: Vec<(String, String)>,
+}
+
+impl Attributes {
+    fn from_start(start: &BytesStart<'_>) -> Result<Self> {
+        let element = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+
+        let mut values = Vec::new();
+        for attr in start.attributes() {
+            let attr = attr.context(format!("invalid attribute of element {:?}", element))?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr.unescape_value().context(format!("invalid attribute {:?} of element {:?}", key, element))?;
+            values.push((key, value.into_owned()));
+        }
+
+        Ok(Attributes {
+            element,
+            values,
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn string(&self, key: &str) -> Option<Cow<'static, str>> {
+        self.get(key).map(|v| Cow::Owned(v.to_string()))
+    }
+
+    fn require_string(&self, key: &str) -> Result<Cow<'static, str>> {
+        self.string(key).ok_or_else(|| self.missing(key))
+    }
+
+    fn parse_with<T, E: fmt::Display>(&self, key: &str, from_str: impl FnOnce(&str) -> Result<T, E>) -> Result<Option<T>> {
+        self.get(key).map(|v| from_str(v).map_err(|e| anyhow!("invalid attribute {:?} of element {:?}: {}", key, self.element, e))).transpose()
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>>
+    where
+        T::Err: fmt::Display,
+    {
+        self.parse_with(key, T::from_str)
+    }
+
+    fn require<T: FromStr>(&self, key: &str) -> Result<T>
+    where
+        T::Err: fmt::Display,
+    {
+        self.parse(key)?.ok_or_else(|| self.missing(key))
+    }
+
+    // Booleans are as lenient as those of hard-xml.
+    fn bool(&self, key: &str) -> Result<Option<bool>> {
+        self.parse_with(key, |v| match v {
+            "t" | "true" | "y" | "yes" | "on" | "1" => Ok(true),
+            "f" | "false" | "n" | "no" | "off" | "0" => Ok(false),
+            _ => bool::from_str(v),
+        })
+    }
+
+    fn missing(&self, key: &str) -> anyhow::Error {
+        anyhow!("missing attribute {:?} of element {:?}", key, self.element)
+    }
+}
+
+// Read a response from reader, while it is parsed. Like XmlRead, unknown
+// attributes are skipped, and unknown elements are skipped or kept in the
+// extensions.
+pub fn read_response<R: BufRead>(reader: R) -> Result<Response<'static>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+
+    // Names of the elements that are open, outermost first.
+    let mut open: Vec<String> = Vec::new();
+    let mut resp = None;
+
+    loop {
+        buf.clear();
+        let (start, is_empty) = match reader.read_event_into(&mut buf).context("failed to read response")? {
+            Event::Start(start) => (start, false),
+            Event::Empty(start) => (start, true),
+            Event::End(_) => {
+                open.pop();
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        if let (Some(parent), Some(resp)) = (open.last(), resp.as_mut()) {
+            if let Some(extensions) = extensions_of(resp, parent, &name) {
+                let xml = read_raw(&mut reader, start, is_empty)?;
+                extensions.push(response::RawElement {
+                    name: Cow::Owned(name),
+                    xml: Cow::Owned(xml),
+                });
+                continue;
+            }
+        }
+
+        let attrs = Attributes::from_start(&start)?;
+        read_element(&mut resp, open.last().map(String::as_str), &name, &attrs)?;
+
+        if !is_empty {
+            open.push(name);
+        }
+    }
+
+    let resp = resp.ok_or_else(|| anyhow!("missing element \"response\""))?;
+    for app in &resp.apps {
+        if let Some(update_check) = &app.update_check {
+            if update_check.status == response::UpdateCheckStatus::Ok && update_check.manifest.is_none() {
+                bail!("missing element \"manifest\" of update check of app {}", app.id);
+            }
+        }
+    }
+
+    Ok(resp)
+}
+
+// Extensions of the element parent, if name is none of its known children.
+fn extensions_of<'r>(resp: &'r mut Response<'static>, parent: &str, name: &str) -> Option<&'r mut Vec<response::RawElement<'static>>> {
+    match (parent, name) {
+        ("response", "daystart" | "app") | ("app", "ping" | "updatecheck" | "event") | ("updatecheck", "urls" | "manifest") => None,
+        ("response", _) => Some(&mut resp.extensions),
+        ("app", _) => resp.apps.last_mut().map(|app| &mut app.extensions),
+        ("updatecheck", _) => last_update_check(resp).map(|update_check| &mut update_check.extensions),
+        _ => None,
+    }
+}
+
+// Read the rest of the element that starts with start, and write all of it
+// as XML again.
+fn read_raw<R: BufRead>(reader: &mut Reader<R>, start: BytesStart<'_>, is_empty: bool) -> Result<String> {
+    let mut writer = Writer::new(Vec::new());
+
+    if is_empty {
+        writer.write_event(Event::Empty(start)).context("failed to write element")?;
+    } else {
+        writer.write_event(Event::Start(start)).context("failed to write element")?;
+
+        let mut buf = Vec::new();
+        let mut depth = 1;
+        while depth > 0 {
+            buf.clear();
+            let event = reader.read_event_into(&mut buf).context("failed to read response")?;
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                Event::Eof => bail!("unexpected end of response"),
+                _ => {}
+            }
+            writer.write_event(event).context("failed to write element")?;
+        }
+    }
+
+    String::from_utf8(writer.into_inner()).context("element is not valid UTF-8")
+}
+
+pub fn response_from_str(xml: &str) -> Result<Response<'static>> {
+    read_response(xml.as_bytes())
+}
+
+// Add the element name of parent to resp. Elements are added to the last of
+// the apps, packages etc. before them, which are those that are open.
+fn read_element(resp: &mut Option<Response<'static>>, parent: Option<&str>, name: &str, attrs: &Attributes) -> Result<()> {
+    let (parent, resp) = match (parent, resp.as_mut()) {
+        (None, None) if name == "response" => {
+            *resp = Some(Response {
+                protocol_version: attrs.require_string("protocol")?,
+                server: attrs.string("server"),
+                day_start: None,
+                apps: Vec::new(),
+                extensions: Vec::new(),
+            });
+            return Ok(());
+        }
+        (Some(parent), Some(resp)) => (parent, resp),
+        _ => return Ok(()),
+    };
+
+    match (parent, name) {
+        ("response", "daystart") => {
+            resp.day_start = Some(response::DayStart {
+                elapsed_seconds: attrs.require("elapsed_seconds")?,
+                elapsed_days: attrs.parse("elapsed_days")?,
+            });
+        }
+        ("response", "app") => {
+            resp.apps.push(response::App {
+                id: attrs.require("appid")?,
+                status: attrs.require_string("status")?,
+                ping: None,
+                update_check: None,
+                events: Vec::new(),
+                extensions: Vec::new(),
+            });
+        }
+        ("app", "ping") => {
+            if let Some(app) = resp.apps.last_mut() {
+                app.ping = Some(response::Ping {
+                    status: attrs.require_string("status")?,
+                });
+            }
+        }
+        ("app", "event") => {
+            if let Some(app) = resp.apps.last_mut() {
+                app.events.push(response::EventAck {
+                    status: attrs.require_string("status")?,
+                });
+            }
+        }
+        ("app", "updatecheck") => {
+            if let Some(app) = resp.apps.last_mut() {
+                app.update_check = Some(response::UpdateCheck {
+                    status: attrs.require("status")?,
+                    urls: Vec::new(),
+                    skipped_urls: Vec::new(),
+                    manifest: None,
+                    extensions: Vec::new(),
+                });
+            }
+        }
+        ("urls", "url") => {
+            if let Some(update_check) = last_update_check(resp) {
+                match response::parse_codebase(attrs.get("codebase")) {
+                    Ok(url) => update_check.urls.push(url),
+                    Err(skipped) => update_check.skipped_urls.push(skipped),
+                }
+            }
+        }
+        ("updatecheck", "manifest") => {
+            if let Some(update_check) = last_update_check(resp) {
+                update_check.manifest = Some(response::Manifest {
+                    version: attrs.require_string("version")?,
+                    packages: Vec::new(),
+                    actions: Vec::new(),
+                });
+            }
+        }
+        ("packages", "package") => {
+            if let Some(manifest) = last_update_check(resp).and_then(|u| u.manifest.as_mut()) {
+                manifest.packages.push(response::Package {
+                    name: attrs.require_string("name")?,
+                    hash: attrs.parse_with("hash", omaha::Hash::from_base64)?,
+                    size: attrs.parse("size")?.unwrap_or(omaha::FileSize::from_bytes(0)),
+                    required: attrs.bool("required")?.unwrap_or(false),
+                    hash_sha256: attrs.parse_with("hash_sha256", omaha::Hash::from_hex)?,
+                });
+            }
+        }
+        ("actions", "action") => {
+            if let Some(manifest) = last_update_check(resp).and_then(|u| u.manifest.as_mut()) {
+                manifest.actions.push(response::Action {
+                    event: attrs.require("event")?,
+                    sha256: attrs.parse_with("sha256", omaha::Hash::from_base64)?.ok_or_else(|| attrs.missing("sha256"))?,
+                    disable_payload_backoff: attrs.bool("DisablePayloadBackoff")?,
+                    success_action: attrs.parse("successaction")?,
+                    deadline: attrs.string("deadline"),
+                    metadata_size: attrs.parse("MetadataSize")?,
+                    metadata_signature_rsa: attrs.string("MetadataSignatureRsa"),
+                    is_delta_payload: attrs.bool("IsDeltaPayload")?,
+                });
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn last_update_check<'r>(resp: &'r mut Response<'static>) -> Option<&'r mut response::UpdateCheck<'static>> {
+    resp.apps.last_mut().and_then(|app| app.update_check.as_mut())
+}
+
+// Start of element name with the attributes that have a value, in order.
+fn element<'a>(name: &'a str, attrs: &[(&str, Option<String>)]) -> BytesStart<'a> {
+    let mut start = BytesStart::new(name);
+    for (key, value) in attrs {
+        if let Some(value) = value {
+            start.push_attribute((*key, value.as_str()));
+        }
+    }
+    start
+}
+
+// Write request into writer, just like XmlWrite does.
+pub fn write_request<W: Write>(req: &Request<'_>, writer: W) -> Result<()> {
+    let mut writer = Writer::new(writer);
+
+    let start = element(
+        "request",
+        &[
+            ("protocol", Some(req.protocol_version.to_string())),
+            ("version", Some(req.version.to_string())),
+            ("updaterversion", Some(req.updater_version.to_string())),
+            ("installsource", Some(req.install_source.to_string())),
+            ("ismachine", Some(req.is_machine.to_string())),
+        ],
+    );
+    writer.write_event(Event::Start(start)).context("failed to write request")?;
+
+    let os = element(
+        "os",
+        &[
+            ("platform", Some(req.os.platform.to_string())),
+            ("version", Some(req.os.version.to_string())),
+            ("sp", Some(req.os.service_pack.to_string())),
+            ("arch", req.os.arch.as_ref().map(|a| a.to_string())),
+        ],
+    );
+    writer.write_event(Event::Empty(os)).context("failed to write request")?;
+
+    for app in &req.apps {
+        write_app(&mut writer, app).context("failed to write request")?;
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("request"))).context("failed to write request")?;
+    Ok(())
+}
+
+fn write_app<W: Write>(writer: &mut Writer<W>, app: &request::App<'_>) -> std::io::Result<()> {
+    let start = element(
+        "app",
+        &[
+            ("appid", Some(app.id.to_string())),
+            ("version", Some(app.version.to_string())),
+            ("track", Some(app.track.to_string())),
+            ("previousversion", app.previous_version.as_ref().map(|v| v.to_string())),
+            ("fromtrack", app.from_track.as_ref().map(|v| v.to_string())),
+            ("bootid", app.boot_id.as_ref().map(|v| v.to_string())),
+            ("oem", app.oem.as_ref().map(|v| v.to_string())),
+            ("oemversion", app.oem_version.as_ref().map(|v| v.to_string())),
+            ("oemplatform", app.oem_platform.as_ref().map(|v| v.to_string())),
+            ("board", app.board.as_ref().map(|v| v.to_string())),
+            ("machineid", Some(app.machine_id.to_string())),
+        ],
+    );
+
+    if app.ping.is_none() && app.update_check.is_none() && app.events.is_empty() {
+        return writer.write_event(Event::Empty(start));
+    }
+    writer.write_event(Event::Start(start))?;
+
+    if let Some(ping) = &app.ping {
+        let ping = element(
+            "ping",
+            &[("r", ping.rollcall_days.map(|d| d.to_string())), ("a", ping.active_days.map(|d| d.to_string()))],
+        );
+        writer.write_event(Event::Empty(ping))?;
+    }
+
+    if let Some(update_check) = &app.update_check {
+        let update_check = element("updatecheck", &[("delta_okay", Some(update_check.delta_okay.to_string()))]);
+        writer.write_event(Event::Empty(update_check))?;
+    }
+
+    for event in &app.events {
+        let event = element(
+            "event",
+            &[
+                ("eventtype", Some(event.event_type.to_string())),
+                ("eventresult", Some(event.event_result.to_string())),
+                ("errorcode", event.error_code.map(|c| c.to_string())),
+                ("previousversion", event.previous_version.as_ref().map(|v| v.to_string())),
+                ("downloaded", event.downloaded.map(|d| d.to_string())),
+                ("total", event.total.map(|t| t.to_string())),
+                ("download_time_ms", event.download_time_ms.map(|t| t.to_string())),
+            ],
+        );
+        writer.write_event(Event::Empty(event))?;
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("app")))
+}
+
+pub fn request_to_string(req: &Request<'_>) -> Result<String> {
+    let mut xml = Vec::new();
+    write_request(req, &mut xml)?;
+    String::from_utf8(xml).context("request is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::{XmlRead, XmlWrite};
+
+    const RESPONSE_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<response protocol="3.0" server="nebraska">
+  <daystart elapsed_seconds="49007"></daystart>
+  <app appid="e96281a6-d1af-4bde-9a0a-97b76e56dc57" status="ok">
+    <ping status="ok"></ping>
+    <updatecheck status="ok">
+      <urls>
+        <url codebase="https://update.release.flatcar-linux.net/amd64-usr/3732.0.0/"></url>
+      </urls>
+      <manifest version="3732.0.0">
+        <packages>
+          <package name="oem-azure.gz" hash="wepxwEV9L2SS1l/ycEZSqWM3dDc=" hash_sha256="3aed3129de50b959a97e4913ba485bd60e72d2bb6aa377d5ed404103f0680043" size="40897503" required="false"></package>
+        </packages>
+        <actions>
+          <action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA=" DisablePayloadBackoff="true" unknown="1"><unknown/></action>
+        </actions>
+      </manifest>
+    </updatecheck>
+    <event status="ok"></event>
+    <cohort name="beta"><hint value="1"/></cohort>
+  </app>
+</response>"#;
+
+    #[test]
+    fn test_read_response() {
+        let quick = response_from_str(RESPONSE_XML).unwrap();
+        let hard = Response::from_str(RESPONSE_XML).unwrap();
+        assert_eq!(quick.to_string().unwrap(), hard.to_string().unwrap());
+
+        assert!(response_from_str(&RESPONSE_XML.replace(r#" protocol="3.0""#, "")).is_err());
+        assert!(response_from_str(&RESPONSE_XML.replace(r#"event="postinstall""#, r#"event="reboot""#)).is_err());
+        assert!(response_from_str(&RESPONSE_XML.replace("</app>", "")).is_err());
+    }
+
+    #[test]
+    fn test_write_request() {
+        let xml = r#"<request protocol="3.0" version="ue-rs-0.0.0" updaterversion="ue-rs-0.0.0" installsource="scheduler" ismachine="1">
+  <os platform="CoreOS" version="Chateau" sp="3510.2.0_x86_64" arch="x86_64"></os>
+  <app appid="{e96281a6-d1af-4bde-9a0a-97b76e56dc57}" version="3510.2.0" track="stable" oem="a&amp;b" machineid="abce671d61774703ac7be60715220bfe">
+    <ping r="-1"></ping>
+    <updatecheck delta_okay="false"></updatecheck>
+    <event eventtype="3" eventresult="2" previousversion="3510.1.0"></event>
+  </app>
+  <app appid="{e96281a6-d1af-4bde-9a0a-97b76e56dc57}" version="1" track="beta" machineid="abce671d61774703ac7be60715220bfe"></app>
+</request>"#;
+        let req = Request::from_str(xml).unwrap();
+        assert_eq!(request_to_string(&req).unwrap(), req.to_string().unwrap());
+    }
+}
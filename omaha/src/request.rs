@@ -1,27 +1,71 @@
 use std::borrow::Cow;
-use std::fmt;
 
-use hard_xml::XmlWrite;
+use hard_xml::{XmlRead, XmlValue, XmlWrite};
 
 use crate as omaha;
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(XmlValue, Debug)]
 pub enum InstallSource {
+    #[xml(value = "ondemand")]
     OnDemand,
+    #[xml(value = "scheduler")]
     Scheduler,
 }
 
-impl fmt::Display for InstallSource {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            InstallSource::OnDemand => f.write_str("ondemand"),
-            InstallSource::Scheduler => f.write_str("scheduler"),
-        }
-    }
+// Event types of the Omaha protocol that are used by Flatcar and Nebraska.
+#[derive(XmlValue, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventType {
+    #[xml(value = "3")]
+    UpdateComplete,
+    #[xml(value = "13")]
+    UpdateDownloadStarted,
+    #[xml(value = "14")]
+    UpdateDownloadFinished,
+    #[xml(value = "800")]
+    UpdateInstalled,
+}
+
+#[derive(XmlValue, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventResult {
+    #[xml(value = "0")]
+    Error,
+    #[xml(value = "1")]
+    Success,
+    #[xml(value = "2")]
+    SuccessReboot,
+}
+
+// Report of the outcome of e.g. a download or an installation.
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[xml(tag = "event")]
+pub struct Event<'a> {
+    #[xml(attr = "eventtype")]
+    pub event_type: EventType,
+
+    #[xml(attr = "eventresult")]
+    pub event_result: EventResult,
+
+    #[xml(attr = "errorcode")]
+    pub error_code: Option<i32>,
+
+    #[xml(attr = "previousversion")]
+    pub previous_version: Option<Cow<'a, str>>,
+
+    // Bytes downloaded so far, of total bytes, and the time it took.
+    #[xml(attr = "downloaded")]
+    pub downloaded: Option<u64>,
+
+    #[xml(attr = "total")]
+    pub total: Option<u64>,
+
+    #[xml(attr = "download_time_ms")]
+    pub download_time_ms: Option<u64>,
 }
 
-#[derive(XmlWrite)]
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "os")]
 pub struct Os<'a> {
     #[xml(attr = "platform")]
@@ -32,13 +76,36 @@ pub struct Os<'a> {
 
     #[xml(attr = "sp")]
     pub service_pack: Cow<'a, str>,
+
+    #[xml(attr = "arch")]
+    pub arch: Option<Cow<'a, str>>,
 }
 
-#[derive(XmlWrite)]
+// Days since the last rollcall and since the last active ping, so that the
+// server can count active instances. -1 means that there was none before.
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[xml(tag = "ping")]
+pub struct Ping {
+    #[xml(attr = "r")]
+    pub rollcall_days: Option<i32>,
+
+    #[xml(attr = "a")]
+    pub active_days: Option<i32>,
+}
+
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "updatecheck")]
-pub struct AppUpdateCheck;
+pub struct AppUpdateCheck {
+    // Whether the client is able to apply delta payloads. Older clients
+    // do not send it, which means that they are not.
+    #[xml(attr = "delta_okay", default)]
+    pub delta_okay: bool,
+}
 
-#[derive(XmlWrite)]
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "app")]
 pub struct App<'a> {
     #[xml(attr = "appid")]
@@ -50,6 +117,14 @@ pub struct App<'a> {
     #[xml(attr = "track")]
     pub track: Cow<'a, str>,
 
+    // Version that ran before the current one, e.g. after a rollback.
+    #[xml(attr = "previousversion")]
+    pub previous_version: Option<Cow<'a, str>>,
+
+    // Track that the machine is migrating from.
+    #[xml(attr = "fromtrack")]
+    pub from_track: Option<Cow<'a, str>>,
+
     #[xml(attr = "bootid")]
     pub boot_id: Option<omaha::Uuid>,
 
@@ -59,14 +134,27 @@ pub struct App<'a> {
     #[xml(attr = "oemversion")]
     pub oem_version: Option<Cow<'a, str>>,
 
+    #[xml(attr = "oemplatform")]
+    pub oem_platform: Option<Cow<'a, str>>,
+
+    #[xml(attr = "board")]
+    pub board: Option<Cow<'a, str>>,
+
     #[xml(attr = "machineid")]
     pub machine_id: Cow<'a, str>,
 
+    #[xml(child = "ping")]
+    pub ping: Option<Ping>,
+
     #[xml(child = "updatecheck")]
     pub update_check: Option<AppUpdateCheck>,
+
+    #[xml(child = "event")]
+    pub events: Vec<Event<'a>>,
 }
 
-#[derive(XmlWrite)]
+#[derive(XmlRead, XmlWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "request")]
 pub struct Request<'a> {
     #[xml(attr = "protocol")]
@@ -90,3 +178,110 @@ pub struct Request<'a> {
     #[xml(child = "app")]
     pub apps: Vec<App<'a>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_to_string() {
+        let event = Event {
+            event_type: EventType::UpdateDownloadFinished,
+            event_result: EventResult::Error,
+            error_code: Some(2),
+            previous_version: Some(Cow::Borrowed("3510.2.0")),
+            downloaded: None,
+            total: None,
+            download_time_ms: None,
+        };
+        assert_eq!(
+            event.to_string().unwrap(),
+            r#"<event eventtype="14" eventresult="0" errorcode="2" previousversion="3510.2.0"/>"#
+        );
+
+        let event = Event {
+            event_type: EventType::UpdateComplete,
+            event_result: EventResult::SuccessReboot,
+            error_code: None,
+            previous_version: None,
+            downloaded: Some(1024),
+            total: Some(1024),
+            download_time_ms: Some(20),
+        };
+        assert_eq!(
+            event.to_string().unwrap(),
+            r#"<event eventtype="3" eventresult="2" downloaded="1024" total="1024" download_time_ms="20"/>"#
+        );
+    }
+
+    #[test]
+    fn test_ping_to_string() {
+        let ping = Ping {
+            rollcall_days: Some(-1),
+            active_days: Some(1),
+        };
+        assert_eq!(ping.to_string().unwrap(), r#"<ping r="-1" a="1"/>"#);
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = Request {
+            protocol_version: Cow::Borrowed("3.0"),
+            version: Cow::Borrowed("ue-rs-0.0.0"),
+            updater_version: Cow::Borrowed("ue-rs-0.0.0"),
+            install_source: InstallSource::Scheduler,
+            is_machine: 1,
+            os: Os {
+                platform: Cow::Borrowed("CoreOS"),
+                version: Cow::Borrowed("Chateau"),
+                service_pack: Cow::Borrowed("3510.2.0_x86_64"),
+                arch: Some(Cow::Borrowed("x86_64")),
+            },
+            apps: vec![App {
+                id: omaha::uuid!("{e96281a6-d1af-4bde-9a0a-97b76e56dc57}"),
+                version: Cow::Borrowed("3510.2.0"),
+                track: Cow::Borrowed("stable"),
+                previous_version: None,
+                from_track: Some(Cow::Borrowed("beta")),
+                boot_id: None,
+                oem: Some(Cow::Borrowed("azure")),
+                oem_version: None,
+                oem_platform: None,
+                board: Some(Cow::Borrowed("amd64-usr")),
+                machine_id: Cow::Borrowed("abce671d61774703ac7be60715220bfe"),
+                ping: Some(Ping {
+                    rollcall_days: Some(-1),
+                    active_days: None,
+                }),
+                update_check: Some(AppUpdateCheck {
+                    delta_okay: false,
+                }),
+                events: vec![Event {
+                    event_type: EventType::UpdateComplete,
+                    event_result: EventResult::SuccessReboot,
+                    error_code: None,
+                    previous_version: Some(Cow::Borrowed("3510.1.0")),
+                    downloaded: None,
+                    total: None,
+                    download_time_ms: None,
+                }],
+            }],
+        };
+
+        let written = request.to_string().unwrap();
+        let parsed = Request::from_str(&written).unwrap();
+        assert_eq!(parsed.apps[0].events[0].event_result, EventResult::SuccessReboot);
+        assert_eq!(parsed.apps[0].from_track.as_deref(), Some("beta"));
+        assert_eq!(parsed.to_string().unwrap(), written);
+    }
+
+    #[test]
+    fn test_update_check_without_delta_okay() {
+        let update_check = AppUpdateCheck::from_str(r#"<updatecheck/>"#).unwrap();
+        assert!(!update_check.delta_okay);
+        assert_eq!(update_check.to_string().unwrap(), r#"<updatecheck delta_okay="false"/>"#);
+
+        let update_check = AppUpdateCheck::from_str(r#"<updatecheck delta_okay="true"/>"#).unwrap();
+        assert!(update_check.delta_okay);
+    }
+}
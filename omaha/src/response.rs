@@ -2,7 +2,7 @@ use std::borrow::Cow;
 use std::str::FromStr;
 use std::fmt;
 
-use hard_xml::XmlRead;
+use hard_xml::{XmlRead, XmlValue, XmlWrite};
 use url::Url;
 
 use crate as omaha;
@@ -17,99 +17,167 @@ mod sha256_hex {
     pub(crate) fn from_str(s: &str) -> Result<omaha::Hash<Sha256>, CodecError> {
         <omaha::Hash<Sha256>>::from_hex(s)
     }
+
+    #[inline]
+    pub(crate) fn to_str(hash: &omaha::Hash<Sha256>) -> String {
+        hash.to_string()
+    }
+}
+
+// Hashes that are given in base64 instead of hex.
+mod base64 {
+    use crate as omaha;
+    use self::omaha::HashAlgo;
+    use anyhow::Error as CodecError;
+
+    #[inline]
+    pub(crate) fn from_str<T: HashAlgo>(s: &str) -> Result<omaha::Hash<T>, CodecError> {
+        <omaha::Hash<T>>::from_base64(s)
+    }
+
+    #[inline]
+    pub(crate) fn to_str<T: HashAlgo>(hash: &omaha::Hash<T>) -> String {
+        hash.to_base64()
+    }
+}
+
+// Child element that isn't part of the protocol, e.g. custom metadata of a
+// server, kept as it is so that it is written again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawElement<'a> {
+    pub name: Cow<'a, str>,
+    pub xml: Cow<'a, str>,
 }
 
-#[derive(XmlRead, Debug)]
+impl RawElement<'_> {
+    pub fn into_owned(self) -> RawElement<'static> {
+        RawElement {
+            name: owned(self.name),
+            xml: owned(self.xml),
+        }
+    }
+}
+
+impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for RawElement<'a> {
+    fn from_reader(reader: &mut hard_xml::XmlReader<'__input>) -> hard_xml::XmlResult<Self> {
+        let (name, xml) = reader.read_raw_element()?;
+        Ok(RawElement {
+            name: Cow::Borrowed(name),
+            xml: Cow::Borrowed(xml),
+        })
+    }
+}
+
+impl hard_xml::XmlWrite for RawElement<'_> {
+    fn to_writer<W: std::io::Write>(&self, writer: &mut hard_xml::XmlWriter<W>) -> hard_xml::XmlResult<()> {
+        Ok(writer.write_raw(&self.xml)?)
+    }
+}
+
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "package")]
 pub struct Package<'a> {
     #[xml(attr = "name")]
     pub name: Cow<'a, str>,
 
-    #[xml(attr = "hash")]
+    #[xml(attr = "hash", with = "base64")]
     pub hash: Option<omaha::Hash<Sha1>>,
 
-    #[xml(attr = "size")]
+    #[xml(attr = "size", default = "omaha::FileSize::from_bytes(0)")]
     pub size: omaha::FileSize,
 
-    #[xml(attr = "required")]
+    #[xml(attr = "required", default = "false")]
     pub required: bool,
 
     #[xml(attr = "hash_sha256", with = "sha256_hex")]
     pub hash_sha256: Option<omaha::Hash<Sha256>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(XmlValue, Debug, PartialEq, Eq)]
 pub enum ActionEvent {
+    #[xml(value = "preinstall")]
     PreInstall,
+    #[xml(value = "install")]
     Install,
+    #[xml(value = "postinstall")]
     PostInstall,
+    #[xml(value = "update")]
     Update,
 }
 
-impl fmt::Display for ActionEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ActionEvent::PreInstall => f.write_str("preinstall"),
-            ActionEvent::Install => f.write_str("install"),
-            ActionEvent::PostInstall => f.write_str("postinstall"),
-            ActionEvent::Update => f.write_str("update"),
-        }
-    }
-}
-
-impl FromStr for ActionEvent {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "preinstall" => ActionEvent::PreInstall,
-            "install" => ActionEvent::Install,
-            "postinstall" => ActionEvent::PostInstall,
-            "update" => ActionEvent::Update,
-
-            _ => return Err(format!("unknown success action \"{}\"", s)),
-        })
-    }
-}
-
-#[derive(Debug, PartialEq, Eq)]
+#[derive(XmlValue, Debug, PartialEq, Eq)]
 pub enum SuccessAction {
+    #[xml(value = "default")]
     Default,
+    #[xml(value = "exitsilently")]
     ExitSilently,
+    #[xml(value = "exitsilentlyonlaunchcmd")]
     ExitSilentlyOnLaunchCommand,
 }
 
-impl fmt::Display for SuccessAction {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateCheckStatus {
+    Ok,
+    NoUpdate,
+    ErrorOsNotSupported,
+    ErrorUnsupportedProtocol,
+    ErrorPluginRestrictedHost,
+    ErrorHash,
+    ErrorInternal,
+    // Any status that is not documented in the protocol, as servers like
+    // Nebraska might send their own.
+    Unknown(String),
+}
+
+impl UpdateCheckStatus {
+    pub fn is_error(&self) -> bool {
+        !matches!(self, UpdateCheckStatus::Ok | UpdateCheckStatus::NoUpdate)
+    }
+}
+
+impl fmt::Display for UpdateCheckStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SuccessAction::Default => f.write_str("default"),
-            SuccessAction::ExitSilently => f.write_str("exitsilently"),
-            SuccessAction::ExitSilentlyOnLaunchCommand => f.write_str("exitsilentlyonlaunchcmd"),
+            UpdateCheckStatus::Ok => f.write_str("ok"),
+            UpdateCheckStatus::NoUpdate => f.write_str("noupdate"),
+            UpdateCheckStatus::ErrorOsNotSupported => f.write_str("error-osnotsupported"),
+            UpdateCheckStatus::ErrorUnsupportedProtocol => f.write_str("error-unsupportedProtocol"),
+            UpdateCheckStatus::ErrorPluginRestrictedHost => f.write_str("error-pluginRestrictedHost"),
+            UpdateCheckStatus::ErrorHash => f.write_str("error-hash"),
+            UpdateCheckStatus::ErrorInternal => f.write_str("error-internal"),
+            UpdateCheckStatus::Unknown(s) => f.write_str(s),
         }
     }
 }
 
-impl FromStr for SuccessAction {
+impl FromStr for UpdateCheckStatus {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
-            "default" => SuccessAction::Default,
-            "exitsilently" => SuccessAction::ExitSilently,
-            "exitsilentlyonlaunchcmd" => SuccessAction::ExitSilentlyOnLaunchCommand,
-
-            _ => return Err(format!("unknown success action \"{}\"", s)),
+            "ok" => UpdateCheckStatus::Ok,
+            "noupdate" => UpdateCheckStatus::NoUpdate,
+            "error-osnotsupported" => UpdateCheckStatus::ErrorOsNotSupported,
+            "error-unsupportedProtocol" => UpdateCheckStatus::ErrorUnsupportedProtocol,
+            "error-pluginRestrictedHost" => UpdateCheckStatus::ErrorPluginRestrictedHost,
+            "error-hash" => UpdateCheckStatus::ErrorHash,
+            "error-internal" => UpdateCheckStatus::ErrorInternal,
+
+            _ => UpdateCheckStatus::Unknown(s.to_string()),
         })
     }
 }
 
-#[derive(XmlRead, Debug)]
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "action")]
-pub struct Action {
+pub struct Action<'a> {
     #[xml(attr = "event")]
     pub event: ActionEvent,
 
-    #[xml(attr = "sha256")]
+    #[xml(attr = "sha256", with = "base64")]
     pub sha256: omaha::Hash<Sha256>,
 
     #[xml(attr = "DisablePayloadBackoff")]
@@ -117,6 +185,40 @@ pub struct Action {
 
     #[xml(attr = "successaction")]
     pub success_action: Option<SuccessAction>,
+
+    // Time until which the update must be applied, or "now".
+    #[xml(attr = "deadline")]
+    pub deadline: Option<Cow<'a, str>>,
+
+    // Size of the payload metadata, i.e. header and manifest, and its
+    // signature in base64, to verify the metadata before downloading all.
+    #[xml(attr = "MetadataSize")]
+    pub metadata_size: Option<u64>,
+
+    #[xml(attr = "MetadataSignatureRsa")]
+    pub metadata_signature_rsa: Option<Cow<'a, str>>,
+
+    #[xml(attr = "IsDeltaPayload")]
+    pub is_delta_payload: Option<bool>,
+}
+
+// Fail on an unknown attribute or element of name when parsing strictly.
+fn unknown_field(reader: &hard_xml::XmlReader<'_>, name: &str, key: &str) -> hard_xml::XmlResult<()> {
+    if reader.is_strict() {
+        return Err(hard_xml::XmlError::UnknownField {
+            name: name.to_owned(),
+            field: key.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+// Skip an unknown child element of name, which starts next, unless parsing
+// strictly.
+fn skip_element(reader: &mut hard_xml::XmlReader<'_>, name: &str, tag: &str) -> hard_xml::XmlResult<()> {
+    unknown_field(reader, name, tag)?;
+    reader.next();
+    reader.read_to_end(tag)
 }
 
 // for Manifest and UpdateCheck, we've customised the XmlRead implementation (using `cargo expand`
@@ -125,10 +227,11 @@ pub struct Action {
 // just nicer to use.
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Manifest<'a> {
     pub version: Cow<'a, str>,
     pub packages: Vec<Package<'a>>,
-    pub actions: Vec<Action>,
+    pub actions: Vec<Action<'a>>,
 }
 
 impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
@@ -146,6 +249,8 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
         while let Some((k, v)) = reader.find_attribute()? {
             if k == "version" {
                 __self_version = Some(v);
+            } else {
+                unknown_field(reader, "Manifest", k)?;
             }
         }
 
@@ -171,7 +276,9 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
                 "packages" => {
                     reader.read_till_element_start("packages")?;
 
-                    while (reader.find_attribute()?).is_some() {}
+                    while let Some((k, _)) = reader.find_attribute()? {
+                        unknown_field(reader, "Manifest", k)?;
+                    }
 
                     if let Ok(Token::ElementEnd { end: ElementEnd::Empty, .. })
                         = reader.next().ok_or(XmlError::MissingField {
@@ -191,8 +298,7 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
                             }
 
                             tag => {
-                                reader.next();
-                                reader.read_to_end(tag)?;
+                                skip_element(reader, "Manifest", tag)?;
                             }
                         }
                     }
@@ -201,7 +307,9 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
                 "actions" => {
                     reader.read_till_element_start("actions")?;
 
-                    while (reader.find_attribute()?).is_some() {}
+                    while let Some((k, _)) = reader.find_attribute()? {
+                        unknown_field(reader, "Manifest", k)?;
+                    }
 
                     if let Ok(Token::ElementEnd { end: ElementEnd::Empty, .. })
                         = reader.next().ok_or(XmlError::MissingField {
@@ -217,20 +325,18 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
                         match __tag {
                             "action" => {
                                 __self_actions
-                                    .push(<Action as hard_xml::XmlRead>::from_reader(reader)?);
+                                    .push(<Action<'a> as hard_xml::XmlRead>::from_reader(reader)?);
                             }
 
                             tag => {
-                                reader.next();
-                                reader.read_to_end(tag)?;
+                                skip_element(reader, "Manifest", tag)?;
                             }
                         }
                     }
                 },
 
                 tag => {
-                    reader.next();
-                    reader.read_to_end(tag)?;
+                    skip_element(reader, "Manifest", tag)?;
                 }
             }
         }
@@ -246,12 +352,46 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for Manifest<'a> {
         })
     }
 }
+// <url> of an update check that was skipped, because its codebase is
+// missing or not a valid URL. Strict parsing fails on it instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SkippedUrl {
+    pub codebase: Option<String>,
+    pub error: String,
+}
+
+pub(crate) fn parse_codebase(codebase: Option<&str>) -> Result<Url, SkippedUrl> {
+    let Some(codebase) = codebase else {
+        return Err(SkippedUrl {
+            codebase: None,
+            error: "missing codebase".to_string(),
+        });
+    };
+
+    Url::from_str(codebase).map_err(|e| SkippedUrl {
+        codebase: Some(codebase.to_string()),
+        error: format!("invalid codebase {:?}: {}", codebase, e),
+    })
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UpdateCheck<'a> {
-    pub status: Cow<'a, str>,
+    pub status: UpdateCheckStatus,
     pub urls: Vec<Url>,
 
-    pub manifest: Manifest<'a>,
+    // Not part of urls, e.g. to tell typos in the URLs of a channel apart
+    // from a channel without URLs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub skipped_urls: Vec<SkippedUrl>,
+
+    // Only responses with status ok need to have a manifest.
+    pub manifest: Option<Manifest<'a>>,
+
+    // Unknown child elements, like those of Response.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extensions: Vec<RawElement<'a>>,
 }
 
 impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for UpdateCheck<'a> {
@@ -264,34 +404,35 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for UpdateCheck<'a> {
         let mut __self_status = None;
         let mut __self_manifest = None;
         let mut __self_urls = Vec::new();
+        let mut __self_skipped_urls = Vec::new();
+        let mut __self_extensions = Vec::new();
 
         reader.read_till_element_start("updatecheck")?;
 
         while let Some((k, v)) = reader.find_attribute()? {
             if k == "status" {
-                __self_status = Some(v);
+                __self_status = Some(
+                    UpdateCheckStatus::from_str(&v)
+                        .map_err(|e| XmlError::FromStr(e.into()))?,
+                );
+            } else {
+                unknown_field(reader, "UpdateCheck", k)?;
             }
         }
 
+        let status = __self_status
+            .ok_or(XmlError::MissingField {
+                name: "UpdateCheck".to_owned(),
+                field: "status".to_owned(),
+            })?;
+
         if let Ok(Token::ElementEnd { end: ElementEnd::Empty, .. })
             = reader.next().ok_or(XmlError::MissingField {
                 name: "UpdateCheck".to_owned(),
                 field: "manifest".to_owned(),
             })?
         {
-            return Ok(UpdateCheck {
-                status: __self_status
-                    .ok_or(XmlError::MissingField {
-                        name: "UpdateCheck".to_owned(),
-                        field: "status".to_owned(),
-                    })?,
-                urls: __self_urls,
-                manifest: __self_manifest
-                    .ok_or(XmlError::MissingField {
-                        name: "UpdateCheck".to_owned(),
-                        field: "manifest".to_owned(),
-                    })?,
-            });
+            return UpdateCheck::new(status, __self_urls, __self_skipped_urls, __self_manifest, __self_extensions);
         }
 
         while let Some(__tag) = reader.find_element_start(Some("updatecheck"))? {
@@ -299,7 +440,9 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for UpdateCheck<'a> {
                 "urls" => {
                     reader.read_till_element_start("urls")?;
 
-                    while (reader.find_attribute()?).is_some() {}
+                    while let Some((k, _)) = reader.find_attribute()? {
+                        unknown_field(reader, "UpdateCheck", k)?;
+                    }
                     if let Ok(Token::ElementEnd { end: ElementEnd::Empty, .. })
                         = reader.next().ok_or(XmlError::MissingField {
                             name: "UpdateCheck".to_owned(),
@@ -313,21 +456,34 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for UpdateCheck<'a> {
                         match __tag {
                             "url" => {
                                 reader.read_till_element_start("url")?;
+                                let mut codebase = None;
                                 while let Some((k, v)) = reader.find_attribute()? {
                                     if k == "codebase" {
-                                        __self_urls.push(
-                                            Url::from_str(&v)
-                                                .map_err(|e| XmlError::FromStr(e.into()))?,
-                                        )
+                                        codebase = Some(v);
+                                    } else {
+                                        unknown_field(reader, "UpdateCheck", k)?;
                                     }
                                 }
 
+                                match parse_codebase(codebase.as_deref()) {
+                                    Ok(url) => __self_urls.push(url),
+                                    Err(skipped) if reader.is_strict() => {
+                                        return Err(match skipped.codebase {
+                                            None => XmlError::MissingField {
+                                                name: "UpdateCheck".to_owned(),
+                                                field: "codebase".to_owned(),
+                                            },
+                                            Some(_) => XmlError::FromStr(skipped.error.into()),
+                                        });
+                                    }
+                                    Err(skipped) => __self_skipped_urls.push(skipped),
+                                }
+
                                 reader.read_to_end("url")?;
                             },
 
                             tag => {
-                                reader.next();
-                                reader.read_to_end(tag)?;
+                                skip_element(reader, "UpdateCheck", tag)?;
                             }
                         }
                     }
@@ -340,29 +496,113 @@ impl<'__input: 'a, 'a> hard_xml::XmlRead<'__input> for UpdateCheck<'a> {
                 }
 
                 tag => {
-                    reader.next();
-                    reader.read_to_end(tag)?;
+                    unknown_field(reader, "UpdateCheck", tag)?;
+                    __self_extensions.push(<RawElement<'_> as hard_xml::XmlRead>::from_reader(reader)?);
                 }
             }
         }
 
+        UpdateCheck::new(status, __self_urls, __self_skipped_urls, __self_manifest, __self_extensions)
+    }
+}
+
+// Write the flattened packages, actions and urls into their container tags again.
+impl hard_xml::XmlWrite for Manifest<'_> {
+    fn to_writer<W: std::io::Write>(&self, writer: &mut hard_xml::XmlWriter<W>) -> hard_xml::XmlResult<()> {
+        writer.write_element_start("manifest")?;
+        writer.write_attribute("version", &self.version)?;
+        writer.write_element_end_open()?;
+
+        writer.write_element_start("packages")?;
+        writer.write_element_end_open()?;
+        for package in &self.packages {
+            package.to_writer(writer)?;
+        }
+        writer.write_element_end_close("packages")?;
+
+        writer.write_element_start("actions")?;
+        writer.write_element_end_open()?;
+        for action in &self.actions {
+            action.to_writer(writer)?;
+        }
+        writer.write_element_end_close("actions")?;
+
+        writer.write_element_end_close("manifest")?;
+        Ok(())
+    }
+}
+
+impl hard_xml::XmlWrite for UpdateCheck<'_> {
+    fn to_writer<W: std::io::Write>(&self, writer: &mut hard_xml::XmlWriter<W>) -> hard_xml::XmlResult<()> {
+        writer.write_element_start("updatecheck")?;
+        writer.write_attribute("status", &self.status.to_string())?;
+
+        if self.urls.is_empty() && self.manifest.is_none() && self.extensions.is_empty() {
+            writer.write_element_end_empty()?;
+            return Ok(());
+        }
+        writer.write_element_end_open()?;
+
+        writer.write_element_start("urls")?;
+        writer.write_element_end_open()?;
+        for url in &self.urls {
+            writer.write_element_start("url")?;
+            writer.write_attribute("codebase", url.as_str())?;
+            writer.write_element_end_empty()?;
+        }
+        writer.write_element_end_close("urls")?;
+
+        if let Some(manifest) = &self.manifest {
+            manifest.to_writer(writer)?;
+        }
+
+        for extension in &self.extensions {
+            extension.to_writer(writer)?;
+        }
+
+        writer.write_element_end_close("updatecheck")?;
+        Ok(())
+    }
+}
+
+impl<'a> UpdateCheck<'a> {
+    fn new(status: UpdateCheckStatus, urls: Vec<Url>, skipped_urls: Vec<SkippedUrl>, manifest: Option<Manifest<'a>>, extensions: Vec<RawElement<'a>>) -> hard_xml::XmlResult<Self> {
+        if status == UpdateCheckStatus::Ok && manifest.is_none() {
+            return Err(hard_xml::XmlError::MissingField {
+                name: "UpdateCheck".to_owned(),
+                field: "manifest".to_owned(),
+            });
+        }
+
         Ok(UpdateCheck {
-            status: __self_status
-                .ok_or(XmlError::MissingField {
-                    name: "UpdateCheck".to_owned(),
-                    field: "status".to_owned(),
-                })?,
-            urls: __self_urls,
-            manifest: __self_manifest
-                .ok_or(XmlError::MissingField {
-                    name: "UpdateCheck".to_owned(),
-                    field: "manifest".to_owned(),
-                })?,
+            status,
+            urls,
+            skipped_urls,
+            manifest,
+            extensions,
         })
     }
 }
 
-#[derive(XmlRead, Debug)]
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[xml(tag = "ping")]
+pub struct Ping<'a> {
+    #[xml(attr = "status")]
+    pub status: Cow<'a, str>,
+}
+
+// Acknowledgement of an event that was reported in the request.
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[xml(tag = "event")]
+pub struct EventAck<'a> {
+    #[xml(attr = "status")]
+    pub status: Cow<'a, str>,
+}
+
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "app")]
 pub struct App<'a> {
     #[xml(attr = "appid")]
@@ -371,16 +611,351 @@ pub struct App<'a> {
     #[xml(attr = "status")]
     pub status: Cow<'a, str>,
 
+    #[xml(child = "ping")]
+    pub ping: Option<Ping<'a>>,
+
+    // Responses to e.g. only pings or events have no updatecheck.
     #[xml(child = "updatecheck")]
-    pub update_check: UpdateCheck<'a>,
+    pub update_check: Option<UpdateCheck<'a>>,
+
+    #[xml(child = "event")]
+    pub events: Vec<EventAck<'a>>,
+
+    // Unknown child elements, like those of Response.
+    #[xml(unknown)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extensions: Vec<RawElement<'a>>,
 }
 
-#[derive(XmlRead, Debug)]
+// Time on the server, relative to the start of its day, e.g. to let
+// clients schedule update checks and backoff in days of the server.
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[xml(tag = "daystart")]
+pub struct DayStart {
+    #[xml(attr = "elapsed_seconds")]
+    pub elapsed_seconds: u64,
+
+    #[xml(attr = "elapsed_days")]
+    pub elapsed_days: Option<u64>,
+}
+
+#[derive(XmlRead, XmlWrite, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xml(tag = "response")]
 pub struct Response<'a> {
     #[xml(attr = "protocol")]
     pub protocol_version: Cow<'a, str>,
 
+    // Name of the server software, e.g. "nebraska".
+    #[xml(attr = "server")]
+    pub server: Option<Cow<'a, str>>,
+
+    #[xml(child = "daystart")]
+    pub day_start: Option<DayStart>,
+
     #[xml(child = "app")]
     pub apps: Vec<App<'a>>,
+
+    // Child elements that aren't part of the protocol, in their order.
+    // They are written after the known children, so writing a parsed
+    // response moves them there, e.g. one in front of daystart behind apps.
+    #[xml(unknown)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extensions: Vec<RawElement<'a>>,
+}
+
+// Responses borrow from the XML they were parsed from. The into_owned
+// methods copy the borrowed strings, so that a response can outlive it.
+
+fn owned(s: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+impl Package<'_> {
+    pub fn into_owned(self) -> Package<'static> {
+        Package {
+            name: owned(self.name),
+            hash: self.hash,
+            size: self.size,
+            required: self.required,
+            hash_sha256: self.hash_sha256,
+        }
+    }
+}
+
+impl Action<'_> {
+    pub fn into_owned(self) -> Action<'static> {
+        Action {
+            event: self.event,
+            sha256: self.sha256,
+            disable_payload_backoff: self.disable_payload_backoff,
+            success_action: self.success_action,
+            deadline: self.deadline.map(owned),
+            metadata_size: self.metadata_size,
+            metadata_signature_rsa: self.metadata_signature_rsa.map(owned),
+            is_delta_payload: self.is_delta_payload,
+        }
+    }
+}
+
+impl Manifest<'_> {
+    pub fn into_owned(self) -> Manifest<'static> {
+        Manifest {
+            version: owned(self.version),
+            packages: self.packages.into_iter().map(Package::into_owned).collect(),
+            actions: self.actions.into_iter().map(Action::into_owned).collect(),
+        }
+    }
+}
+
+impl UpdateCheck<'_> {
+    pub fn into_owned(self) -> UpdateCheck<'static> {
+        UpdateCheck {
+            status: self.status,
+            urls: self.urls,
+            skipped_urls: self.skipped_urls,
+            manifest: self.manifest.map(Manifest::into_owned),
+            extensions: self.extensions.into_iter().map(RawElement::into_owned).collect(),
+        }
+    }
+}
+
+impl App<'_> {
+    pub fn into_owned(self) -> App<'static> {
+        App {
+            id: self.id,
+            status: owned(self.status),
+            ping: self.ping.map(|p| Ping {
+                status: owned(p.status),
+            }),
+            update_check: self.update_check.map(UpdateCheck::into_owned),
+            events: self
+                .events
+                .into_iter()
+                .map(|e| EventAck {
+                    status: owned(e.status),
+                })
+                .collect(),
+            extensions: self.extensions.into_iter().map(RawElement::into_owned).collect(),
+        }
+    }
+}
+
+impl Response<'_> {
+    pub fn into_owned(self) -> Response<'static> {
+        Response {
+            protocol_version: owned(self.protocol_version),
+            server: self.server.map(owned),
+            day_start: self.day_start,
+            apps: self.apps.into_iter().map(App::into_owned).collect(),
+            extensions: self.extensions.into_iter().map(RawElement::into_owned).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<response protocol="3.0" server="nebraska">
+  <daystart elapsed_seconds="49007"></daystart>
+  <app appid="e96281a6-d1af-4bde-9a0a-97b76e56dc57" status="ok">
+    <ping status="ok"></ping>
+    <updatecheck status="noupdate"></updatecheck>
+  </app>
+</response>"#;
+
+    #[test]
+    fn test_response_write_roundtrip() {
+        const FULL_XML: &str = r#"<response protocol="3.0" server="nebraska">
+  <daystart elapsed_seconds="0"></daystart>
+  <app appid="e96281a6-d1af-4bde-9a0a-97b76e56dc57" status="ok">
+    <ping status="ok"></ping>
+    <updatecheck status="ok">
+      <urls>
+        <url codebase="https://update.release.flatcar-linux.net/amd64-usr/3732.0.0/"></url>
+      </urls>
+      <manifest version="3732.0.0">
+        <packages>
+          <package name="oem-azure.gz" hash="wepxwEV9L2SS1l/ycEZSqWM3dDc=" hash_sha256="3aed3129de50b959a97e4913ba485bd60e72d2bb6aa377d5ed404103f0680043" size="40897503" required="false"></package>
+        </packages>
+        <actions>
+          <action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA=" DisablePayloadBackoff="true"></action>
+        </actions>
+      </manifest>
+    </updatecheck>
+    <event status="ok"></event>
+  </app>
+</response>"#;
+
+        let written = Response::from_str(FULL_XML).unwrap().to_string().unwrap();
+        assert_eq!(
+            written,
+            concat!(
+                r#"<response protocol="3.0" server="nebraska"><daystart elapsed_seconds="0"/>"#,
+                r#"<app appid="{e96281a6-d1af-4bde-9a0a-97b76e56dc57}" status="ok"><ping status="ok"/>"#,
+                r#"<updatecheck status="ok"><urls><url codebase="https://update.release.flatcar-linux.net/amd64-usr/3732.0.0/"/></urls>"#,
+                r#"<manifest version="3732.0.0"><packages>"#,
+                r#"<package name="oem-azure.gz" hash="wepxwEV9L2SS1l/ycEZSqWM3dDc=" size="40897503" required="false" hash_sha256="3aed3129de50b959a97e4913ba485bd60e72d2bb6aa377d5ed404103f0680043"/>"#,
+                r#"</packages><actions><action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA=" DisablePayloadBackoff="true"/></actions>"#,
+                r#"</manifest></updatecheck><event status="ok"/></app></response>"#,
+            )
+        );
+
+        // Writing what was read back must not change anything.
+        assert_eq!(Response::from_str(&written).unwrap().to_string().unwrap(), written);
+    }
+
+    #[test]
+    fn test_response_into_owned() {
+        let resp = {
+            let xml = RESPONSE_XML.to_string();
+            Response::from_str(&xml).unwrap().into_owned()
+        };
+        assert_eq!(resp.protocol_version, "3.0");
+        assert_eq!(resp.apps[0].ping.as_ref().map(|p| &*p.status), Some("ok"));
+    }
+
+    #[test]
+    fn test_response_ping() {
+        let resp = Response::from_str(RESPONSE_XML).unwrap();
+        assert_eq!(resp.apps[0].ping.as_ref().map(|p| &*p.status), Some("ok"));
+    }
+
+    #[test]
+    fn test_response_day_start() {
+        let resp = Response::from_str(RESPONSE_XML).unwrap();
+        let day_start = resp.day_start.unwrap();
+        assert_eq!(day_start.elapsed_seconds, 49007);
+        assert_eq!(day_start.elapsed_days, None);
+    }
+
+    #[test]
+    fn test_action_attributes() {
+        const ACTION_XML: &str = r#"<action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA=" DisablePayloadBackoff="true" deadline="now" MetadataSize="1234" MetadataSignatureRsa="c2lnbmF0dXJl" IsDeltaPayload="false"></action>"#;
+
+        let action = Action::from_str(ACTION_XML).unwrap();
+        assert_eq!(action.event, ActionEvent::PostInstall);
+        assert_eq!(action.deadline.as_deref(), Some("now"));
+        assert_eq!(action.metadata_size, Some(1234));
+        assert_eq!(action.metadata_signature_rsa.as_deref(), Some("c2lnbmF0dXJl"));
+        assert_eq!(action.is_delta_payload, Some(false));
+
+        let action = Action::from_str(r#"<action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA="/>"#).unwrap();
+        assert_eq!(action.deadline, None);
+        assert_eq!(action.metadata_size, None);
+        assert!(action.to_string().unwrap().contains(r#"event="postinstall""#));
+
+        assert!(Action::from_str(r#"<action event="reboot" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA="/>"#).is_err());
+        assert_eq!(
+            "exitsilentlyonlaunchcmd".parse(),
+            Ok(SuccessAction::ExitSilentlyOnLaunchCommand)
+        );
+    }
+
+    #[test]
+    fn test_package_defaults() {
+        let package = Package::from_str(r#"<package name="oem-azure.gz"/>"#).unwrap();
+        assert_eq!(package.size.bytes(), 0);
+        assert!(!package.required);
+    }
+
+    #[test]
+    fn test_response_events_only() {
+        const PING_XML: &str = r#"<response protocol="3.0" server="nebraska">
+  <app appid="e96281a6-d1af-4bde-9a0a-97b76e56dc57" status="ok">
+    <ping status="ok"/>
+    <event status="ok"/>
+    <event status="ok"/>
+  </app>
+</response>"#;
+
+        let resp = Response::from_str(PING_XML).unwrap();
+        assert!(resp.apps[0].update_check.is_none());
+
+        let events: Vec<_> = resp.apps[0].events.iter().map(|e| &*e.status).collect();
+        assert_eq!(events, ["ok", "ok"]);
+    }
+
+    #[test]
+    fn test_response_noupdate() {
+        let resp = Response::from_str(RESPONSE_XML).unwrap();
+        assert_eq!(resp.apps[0].update_check.as_ref().unwrap().status, UpdateCheckStatus::NoUpdate);
+        assert!(resp.apps[0].update_check.as_ref().unwrap().manifest.is_none());
+
+        let ok = RESPONSE_XML.replace("noupdate", "ok");
+        assert!(Response::from_str(&ok).is_err());
+
+        let error = RESPONSE_XML.replace("noupdate", "error-internal");
+        assert_eq!(
+            Response::from_str(&error).unwrap().apps[0].update_check.as_ref().unwrap().status,
+            UpdateCheckStatus::ErrorInternal
+        );
+
+        let unknown = RESPONSE_XML.replace("noupdate", "error-unknownApplication");
+        let resp = Response::from_str(&unknown).unwrap();
+        let status = &resp.apps[0].update_check.as_ref().unwrap().status;
+        assert_eq!(status, &UpdateCheckStatus::Unknown("error-unknownApplication".to_string()));
+        assert!(status.is_error());
+        assert_eq!(status.to_string(), "error-unknownApplication");
+    }
+
+    #[test]
+    fn test_response_strict() {
+        let resp = Response::from_str_strict(RESPONSE_XML).unwrap();
+        assert_eq!(resp.server.as_deref(), Some("nebraska"));
+
+        let unknown_attr = RESPONSE_XML.replace(r#"status="noupdate""#, r#"status="noupdate" foo="bar""#);
+        assert!(Response::from_str(&unknown_attr).is_ok());
+        assert_eq!(
+            Response::from_str_strict(&unknown_attr).unwrap_err().to_string(),
+            r#"unknown field "foo" in element "UpdateCheck""#
+        );
+
+        let unknown_element = RESPONSE_XML.replace("<ping status=\"ok\"></ping>", "<ping status=\"ok\"></ping><foo/>");
+        assert!(Response::from_str(&unknown_element).is_ok());
+        assert_eq!(
+            Response::from_str_strict(&unknown_element).unwrap_err().to_string(),
+            r#"unknown field "foo" in element "App""#
+        );
+
+        let manifest = r#"<manifest version="3732.0.0"><packages><package name="a" size="1" required="false"/><foo/></packages></manifest>"#;
+        assert_eq!(Manifest::from_str(manifest).unwrap().packages.len(), 1);
+        assert!(Manifest::from_str_strict(manifest).is_err());
+    }
+
+    #[test]
+    fn test_response_extensions() {
+        let xml = RESPONSE_XML
+            .replace(
+                "<daystart",
+                r#"<cohort name="beta"><hint value="a &amp; b"/></cohort><daystart"#,
+            )
+            .replace("<ping", "<rollback/><ping")
+            .replace(
+                "<updatecheck status=\"noupdate\"></updatecheck>",
+                r#"<updatecheck status="noupdate"><delta size="1"></delta></updatecheck>"#,
+            );
+
+        let resp = Response::from_str(&xml).unwrap();
+        assert_eq!(resp.extensions.len(), 1);
+        assert_eq!(resp.extensions[0].name, "cohort");
+        assert_eq!(
+            resp.extensions[0].xml,
+            r#"<cohort name="beta"><hint value="a &amp; b"/></cohort>"#
+        );
+        assert_eq!(resp.apps[0].extensions[0].xml, "<rollback/>");
+        let update_check = resp.apps[0].update_check.as_ref().unwrap();
+        assert_eq!(update_check.extensions[0].xml, r#"<delta size="1"></delta>"#);
+
+        let written = resp.into_owned().to_string().unwrap();
+        // Extensions are moved behind the known children.
+        assert!(written.contains(r#"</app><cohort name="beta"><hint value="a &amp; b"/></cohort></response>"#));
+        assert!(written.contains(r#"<delta size="1"></delta></updatecheck><rollback/></app>"#));
+        assert_eq!(Response::from_str(&written).unwrap().to_string().unwrap(), written);
+
+        assert!(Response::from_str_strict(&xml).is_err());
+    }
 }
@@ -0,0 +1,134 @@
+// Serialize and Deserialize for the types that have their own string form
+// in the XML documents, so that JSON uses the same one. Digests are hex
+// encoded, also those that are base64 encoded in the XML.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate as omaha;
+
+// Serialize with Display, deserialize with FromStr.
+macro_rules! serde_via_str {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl Serialize for $ty {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+                    <$ty>::from_str(&s).map_err(de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+serde_via_str!(
+    omaha::Uuid,
+    omaha::response::ActionEvent,
+    omaha::response::SuccessAction,
+    omaha::response::UpdateCheckStatus,
+    omaha::request::InstallSource,
+    omaha::request::EventType,
+    omaha::request::EventResult,
+);
+
+impl<T: omaha::HashAlgo> Serialize for omaha::Hash<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, T: omaha::HashAlgo> Deserialize<'de> for omaha::Hash<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        if s.len() != 2 * T::Output::default().as_ref().len() {
+            return Err(de::Error::invalid_length(s.len(), &HexDigest::<T>(std::marker::PhantomData)));
+        }
+        omaha::Hash::from_hex(&s).map_err(de::Error::custom)
+    }
+}
+
+struct HexDigest<T>(std::marker::PhantomData<T>);
+
+impl<T: omaha::HashAlgo> de::Expected for HexDigest<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} hex digits of a {} digest",
+            2 * T::Output::default().as_ref().len(),
+            T::HASH_NAME
+        )
+    }
+}
+
+// Sizes are plain numbers of bytes.
+impl Serialize for omaha::FileSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.bytes() as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for omaha::FileSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = u64::deserialize(deserializer)?;
+        let bytes = usize::try_from(bytes).map_err(de::Error::custom)?;
+        Ok(omaha::FileSize::from_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE_XML: &str = r#"<response protocol="3.0">
+  <app appid="{e96281a6-d1af-4bde-9a0a-97b76e56dc57}" status="ok">
+    <updatecheck status="ok">
+      <urls>
+        <url codebase="https://update.release.flatcar-linux.net/amd64-usr/3732.0.0/"></url>
+      </urls>
+      <manifest version="3732.0.0">
+        <packages>
+          <package name="oem-azure.gz" hash="wepxwEV9L2SS1l/ycEZSqWM3dDc=" hash_sha256="3aed3129de50b959a97e4913ba485bd60e72d2bb6aa377d5ed404103f0680043" size="40897503" required="false"></package>
+        </packages>
+        <actions>
+          <action event="postinstall" sha256="stLG3U/o4Ar8TMHFwT/RWB0iNkaWOO6QtLrq6+AHBbA=" DisablePayloadBackoff="true"></action>
+        </actions>
+      </manifest>
+    </updatecheck>
+  </app>
+</response>"#;
+
+    #[test]
+    fn test_response_json_roundtrip() {
+        use hard_xml::{XmlRead, XmlWrite};
+
+        let response = omaha::Response::from_str(RESPONSE_XML).unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+
+        let app = &json["apps"][0];
+        assert_eq!(app["id"], "{e96281a6-d1af-4bde-9a0a-97b76e56dc57}");
+        assert_eq!(app["update_check"]["status"], "ok");
+        let package = &app["update_check"]["manifest"]["packages"][0];
+        assert_eq!(package["size"], 40897503);
+        assert_eq!(package["hash"], "c1ea71c0457d2f6492d65ff2704652a963377437");
+        assert_eq!(
+            package["hash_sha256"],
+            "3aed3129de50b959a97e4913ba485bd60e72d2bb6aa377d5ed404103f0680043"
+        );
+        assert_eq!(app["update_check"]["manifest"]["actions"][0]["event"], "postinstall");
+
+        let parsed: omaha::Response = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.to_string().unwrap(), response.to_string().unwrap());
+
+        assert!(serde_json::from_str::<omaha::Hash<omaha::Sha1>>(r#""c1ea71c0""#).is_err());
+        assert!(serde_json::from_str::<omaha::response::ActionEvent>(r#""reboot""#).is_err());
+    }
+}
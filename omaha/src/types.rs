@@ -1,6 +1,12 @@
+use std::fmt;
+use std::iter;
+use std::ops;
 use std::str;
 
-#[derive(Debug, Copy, Clone)]
+// Units of sizes, in multiples of 1024 bytes.
+const UNITS: [(char, &str); 4] = [('K', "KiB"), ('M', "MiB"), ('G', "GiB"), ('T', "TiB")];
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct FileSize(usize);
 
@@ -14,13 +20,82 @@ impl FileSize {
     pub fn bytes(&self) -> usize {
         self.0
     }
+
+    pub fn checked_add(self, other: FileSize) -> Option<FileSize> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: FileSize) -> Option<FileSize> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn saturating_sub(self, other: FileSize) -> FileSize {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+// Sizes saturate at usize::MAX instead of overflowing, see checked_add to
+// detect that.
+impl ops::Add for FileSize {
+    type Output = FileSize;
+
+    fn add(self, other: FileSize) -> FileSize {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
+impl ops::AddAssign for FileSize {
+    fn add_assign(&mut self, other: FileSize) {
+        *self = *self + other;
+    }
 }
 
+impl iter::Sum for FileSize {
+    fn sum<I: Iterator<Item = FileSize>>(iter: I) -> FileSize {
+        iter.fold(FileSize::default(), ops::Add::add)
+    }
+}
+
+// The plain number of bytes, as in the size attributes of responses, or with
+// "{:#}" the size in the largest unit that fits, e.g. "364.3 MiB".
+impl fmt::Display for FileSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() {
+            return write!(f, "{}", self.0);
+        }
+
+        let mut size = self.0 as f64;
+        let mut unit = None;
+        for (_, name) in UNITS {
+            if size < 1024.0 {
+                break;
+            }
+            size /= 1024.0;
+            unit = Some(name);
+        }
+
+        match unit {
+            Some(unit) => write!(f, "{:.1} {}", size, unit),
+            None => write!(f, "{} B", self.0),
+        }
+    }
+}
+
+// A number of bytes, optionally followed by one of the suffixes K, M, G or
+// T for KiB, MiB, GiB or TiB, e.g. "350M".
 impl str::FromStr for FileSize {
     type Err = <usize as str::FromStr>::Err;
 
     fn from_str(x: &str) -> Result<Self, Self::Err> {
-        usize::from_str(x).map(Self)
+        let (number, multiplier) = match UNITS.iter().position(|(suffix, _)| x.ends_with(*suffix)) {
+            Some(i) => (&x[..x.len() - 1], 1usize << (10 * (i + 1))),
+            None => (x, 1),
+        };
+
+        // A size that only overflows with its suffix is reported like a
+        // number that is too large itself.
+        let n = usize::from_str(number)?;
+        n.checked_mul(multiplier).map(Self).ok_or_else(|| usize::from_str(&format!("{}0", usize::MAX)).unwrap_err())
     }
 }
 
@@ -34,4 +109,30 @@ mod tests {
 
         assert_eq!(FileSize::from_bytes(TEST_SIZE).bytes(), TEST_SIZE);
     }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(FileSize::from_bytes(382_000_000).to_string(), "382000000");
+        assert_eq!(format!("{:#}", FileSize::from_bytes(382_000_000)), "364.3 MiB");
+        assert_eq!(format!("{:#}", FileSize::from_bytes(1023)), "1023 B");
+        assert_eq!(format!("{:#}", FileSize::from_bytes(1536)), "1.5 KiB");
+
+        assert_eq!("40897503".parse::<FileSize>().unwrap().bytes(), 40897503);
+        assert_eq!("350M".parse::<FileSize>().unwrap().bytes(), 350 << 20);
+        assert_eq!("2G".parse::<FileSize>().unwrap().bytes(), 2 << 30);
+        assert!("2X".parse::<FileSize>().is_err());
+        assert!("M".parse::<FileSize>().is_err());
+        assert!("-1K".parse::<FileSize>().is_err());
+        assert_eq!(
+            format!("{}T", usize::MAX).parse::<FileSize>().unwrap_err().kind(),
+            &std::num::IntErrorKind::PosOverflow
+        );
+
+        let sizes = [FileSize::from_bytes(1), FileSize::from_bytes(2)];
+        assert_eq!(sizes.into_iter().sum::<FileSize>(), FileSize::from_bytes(3));
+        assert_eq!(sizes[0].saturating_sub(sizes[1]), FileSize::default());
+        assert_eq!(sizes[0].checked_sub(sizes[1]), None);
+        assert_eq!(FileSize::from_bytes(usize::MAX) + sizes[0], FileSize::from_bytes(usize::MAX));
+        assert_eq!(FileSize::from_bytes(usize::MAX).checked_add(sizes[0]), None);
+    }
 }
@@ -5,7 +5,7 @@ use ::uuid::Uuid as WrappedUuid;
 
 // the only reason we're wrapping the upstream Uuid type here is so that Display formats it in
 // "braced" form in the XML document.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct Uuid(WrappedUuid);
 
@@ -14,6 +14,20 @@ impl Uuid {
     pub const fn from_uuid(uuid: WrappedUuid) -> Self {
         Uuid(uuid)
     }
+
+    #[inline]
+    pub const fn as_uuid(&self) -> &WrappedUuid {
+        &self.0
+    }
+
+    // Parse a UUID in braced form, as servers send it, or in bare form, e.g.
+    // as in /proc/sys/kernel/random/boot_id. Surrounding whitespace is
+    // ignored.
+    pub fn parse_flexible(uuid_str: &str) -> Result<Self, ::uuid::Error> {
+        let uuid_str = uuid_str.trim();
+        let bare = uuid_str.strip_prefix('{').and_then(|s| s.strip_suffix('}')).unwrap_or(uuid_str);
+        WrappedUuid::try_parse(bare).map(Uuid)
+    }
 }
 
 impl fmt::Display for Uuid {
@@ -33,7 +47,7 @@ impl str::FromStr for Uuid {
     type Err = ::uuid::Error;
 
     fn from_str(uuid_str: &str) -> Result<Self, Self::Err> {
-        WrappedUuid::from_str(uuid_str).map(Uuid)
+        Uuid::parse_flexible(uuid_str)
     }
 }
 
@@ -58,4 +72,17 @@ mod tests {
 
         assert_eq!(testid_from_uuid.to_string(), testid_from.to_string());
     }
+
+    #[test]
+    fn test_parse_flexible() {
+        const BRACED: &str = "{67e55044-10b1-426f-9247-bb680e5fe0c8}";
+
+        let braced = Uuid::parse_flexible(BRACED).unwrap();
+        assert_eq!(braced.to_string(), BRACED);
+        assert_eq!(Uuid::parse_flexible("67e55044-10b1-426f-9247-bb680e5fe0c8\n").unwrap(), braced);
+        assert_eq!(" 67e5504410b1426f9247bb680e5fe0c8".parse::<Uuid>().unwrap(), braced);
+        assert_eq!(braced.as_uuid().hyphenated().to_string(), &BRACED[1..BRACED.len() - 1]);
+
+        assert!(Uuid::parse_flexible("{67e55044-10b1-426f-9247-bb680e5fe0c8").is_err());
+    }
 }
@@ -1,448 +1,721 @@
+use std::convert::Infallible;
 use std::error::Error;
-use std::borrow::Cow;
-use std::ffi::OsStr;
+use std::fmt;
 use std::fs::File;
-use std::fs;
 use std::io;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::process::ExitCode;
 use std::str::FromStr;
 use std::time::Duration;
 
-#[macro_use]
-extern crate log;
-
-use anyhow::{Context, Result, bail, anyhow};
 use argh::FromArgs;
 use globset::{Glob, GlobSet, GlobSetBuilder};
-use hard_xml::XmlRead;
-use omaha::FileSize;
-use reqwest::blocking::Client;
-use reqwest::redirect::Policy;
+use regex::Regex;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use serde_json::json;
+use tracing_subscriber::EnvFilter;
 use url::Url;
 
-use update_format_crau::delta_update;
-use ue_rs::hash_on_disk;
-
-#[derive(Debug)]
-enum PackageStatus {
-    ToDownload,
-    DownloadIncomplete(omaha::FileSize),
-    DownloadFailed,
-    BadChecksum,
-    Unverified,
-    BadSignature,
-    Verified,
-}
+use ue_rs::error::ErrorKind;
+use ue_rs::{
+    CleanupPolicy, ContentEncoding, DigestAlgo, DownloadOrder, DownloadVerifyBuilder, HttpVersion, Netrc, PackageSelector, PhaseMetrics, PayloadInfo, PlannedPackage, ProgressConfig, PubkeySource,
+    RunReport, SignaturePolicy, Source,
+};
 
-#[derive(Debug)]
-struct Package<'a> {
-    url: Url,
-    name: Cow<'a, str>,
-    hash_sha256: Option<omaha::Hash<omaha::Sha256>>,
-    hash_sha1: Option<omaha::Hash<omaha::Sha1>>,
-    size: omaha::FileSize,
-    status: PackageStatus,
+#[derive(Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-impl<'a> Package<'a> {
-    #[rustfmt::skip]
-    // Return Sha256 hash of data in the given path.
-    // If maxlen is None, a simple read to the end of the file.
-    // If maxlen is Some, read only until the given length.
-    fn hash_on_disk<T: omaha::HashAlgo>(&mut self, path: &Path, maxlen: Option<usize>) -> Result<omaha::Hash<T>> {
-        hash_on_disk::<T>(path, maxlen)
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "text" => OutputFormat::Text,
+            "json" => OutputFormat::Json,
+
+            _ => return Err(format!("unknown output format \"{}\"", s)),
+        })
     }
+}
 
-    #[rustfmt::skip]
-    fn check_download(&mut self, in_dir: &Path) -> Result<()> {
-        let path = in_dir.join(&*self.name);
+// Value of --header, which likely carries a credential. Debug only shows
+// the name of the header, so that printing Args does not leak it.
+struct HeaderArg(String);
 
-        if !path.exists() {
-            // skip checking for existing downloads
-            info!("{} does not exist, skipping existing downloads.", path.display());
-            return Ok(());
+impl FromStr for HeaderArg {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(HeaderArg(s.to_string()))
+    }
+}
+
+impl fmt::Debug for HeaderArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.split_once(':') {
+            Some((name, _)) => write!(f, "\"{}: <redacted>\"", name.trim()),
+            None => f.write_str("<redacted>"),
         }
+    }
+}
 
-        let md = fs::metadata(&path).context({
-            format!("failed to get metadata, path ({:?})", path.display())
-        })?;
+#[derive(FromArgs, Debug)]
+/// Parse an update-engine Omaha XML response to extract sysext images, then download and verify
+/// their signatures.
+struct Args {
+    /// the directory to download the sysext images into
+    #[argh(option, short = 'o')]
+    output_dir: Option<String>,
 
-        let size_on_disk = md.len() as usize;
-        let expected_size = self.size.bytes();
+    /// target filename in directory, requires --payload-url, --verify-only or --take-first-match
+    #[argh(option, short = 'n')]
+    target_filename: Option<String>,
 
-        if size_on_disk < expected_size {
-            info!("{}: have downloaded {}/{} bytes, will resume", path.display(), size_on_disk, expected_size);
+    /// output filename of the packages matching a glob, as GLOB=NAME,
+    /// e.g. "oem-*.gz=oem.raw".
+    /// may be specified multiple times.
+    #[argh(option)]
+    name_map: Vec<String>,
 
-            self.status = PackageStatus::DownloadIncomplete(
-                omaha::FileSize::from_bytes(size_on_disk)
-            );
-            return Ok(());
-        }
+    /// path to the Omaha XML file, - to read from stdin, or an http(s)://
+    /// URL to fetch it from
+    #[argh(option, short = 'i')]
+    input_xml: Option<String>,
 
-        if size_on_disk == expected_size {
-            info!("{}: download complete, checking hash...", path.display());
-            let hash_sha256 = self.hash_on_disk::<omaha::Sha256>(&path, None).context({
-                format!("failed to hash_on_disk, path ({:?})", path.display())
-            })?;
-            let hash_sha1 = self.hash_on_disk::<omaha::Sha1>(&path, None).context({
-                format!("failed to hash_on_disk, path ({:?})", path.display())
-            })?;
-            if self.verify_checksum(hash_sha256, hash_sha1) {
-                info!("{}: good hash, will continue without re-download", path.display());
-            } else {
-                info!("{}: bad hash, will re-download", path.display());
-                self.status = PackageStatus::ToDownload;
-            }
+    /// URL to fetch remote update payload, or a local path or file:// URL
+    /// of an already downloaded payload
+    #[argh(option, short = 'u')]
+    payload_url: Option<String>,
+
+    /// path to an already downloaded payload, only verify and extract it
+    /// without any network access
+    #[argh(option, short = 'f')]
+    verify_only: Option<String>,
+
+    /// path to the public key file, or with the hardware-keys feature
+    /// a pkcs11: URI or tpm:<handle>.
+    /// may be specified multiple times to trust several keys, e.g. during a key rollover.
+    #[argh(option, short = 'p')]
+    pubkey_file: Vec<String>,
+
+    /// glob pattern to match update URLs.
+    /// may be specified multiple times.
+    #[argh(option, short = 'm')]
+    image_match: Vec<String>,
+
+    /// regex to match package names, for names that are awkward as globs.
+    /// may be specified multiple times.
+    #[argh(option)]
+    image_regex: Vec<String>,
+
+    /// only take the first matching entry
+    #[argh(switch, short = 't')]
+    take_first_match: bool,
+
+    /// order to download the matching packages in, response (default),
+    /// required-first or smallest-first
+    #[argh(option)]
+    order: Option<DownloadOrder>,
+
+    /// glob pattern of packages to download first, in the order given.
+    /// may be specified multiple times, instead of --order.
+    #[argh(option)]
+    priority: Vec<String>,
+
+    /// timeout in seconds for establishing a connection
+    #[argh(option)]
+    connect_timeout: Option<u64>,
+
+    /// timeout in seconds for each read on a connection
+    #[argh(option)]
+    read_timeout: Option<u64>,
+
+    /// overall timeout in seconds for a single download request
+    #[argh(option)]
+    timeout: Option<u64>,
+
+    /// path to a CA certificate in PEM or DER form, e.g. of the payload
+    /// host, to trust instead of the system certificates for downloads.
+    /// may be specified multiple times.
+    #[argh(option)]
+    ca_cert: Vec<String>,
+
+    /// base64 SHA-256 hash of the public key (SubjectPublicKeyInfo) of the
+    /// payload host, to only accept its certificate if it has this key.
+    /// may be specified multiple times, e.g. for a key rollover.
+    #[argh(option)]
+    pin_sha256: Vec<omaha::Hash<omaha::Sha256>>,
+
+    /// header to send with each download, e.g. "Authorization: Bearer TOKEN".
+    /// may be specified multiple times.
+    #[argh(option)]
+    header: Vec<HeaderArg>,
+
+    /// path to a file with a token to send as "Authorization: Bearer TOKEN"
+    /// with each download, e.g. for a private mirror
+    #[argh(option)]
+    token_file: Option<String>,
+
+    /// use credentials from $NETRC or ~/.netrc for downloads from the hosts
+    /// listed there
+    #[argh(switch)]
+    netrc: bool,
+
+    /// path to a netrc file with credentials for downloads, instead of
+    /// $NETRC or ~/.netrc
+    #[argh(option)]
+    netrc_file: Option<String>,
+
+    /// check each download URL with a HEAD request first, and fall back to
+    /// the next mirror if it is missing or has the wrong size
+    #[argh(switch)]
+    preflight: bool,
+
+    /// content encoding to accept for downloads, identity (default) or zstd
+    #[argh(option, default = "ContentEncoding::Identity")]
+    accept_encoding: ContentEncoding,
+
+    /// HTTP version of downloads, auto (default), http1, or http2 for
+    /// servers known to support it
+    #[argh(option, default = "HttpVersion::Auto")]
+    http_version: HttpVersion,
+
+    /// maximum number of idle connections to keep alive per host
+    #[argh(option)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// log the progress of downloads every given number of seconds, 10 by
+    /// default, 0 to disable
+    #[argh(option, default = "10")]
+    progress_interval: u64,
+
+    /// also log the progress of downloads every given number of bytes
+    #[argh(option)]
+    progress_bytes: Option<u64>,
+
+    /// overall time budget in seconds for downloading and verifying all
+    /// packages
+    #[argh(option)]
+    deadline: Option<u64>,
+
+    /// block device to write the verified image into directly, instead of
+    /// a file in the output directory
+    #[argh(option)]
+    target_device: Option<String>,
+
+    /// open the target device with O_DIRECT, bypassing the page cache
+    #[argh(switch)]
+    direct_io: bool,
+
+    /// sync the target device after writing each extent
+    #[argh(switch)]
+    sync_extents: bool,
+
+    /// signature version that must verify, e.g. 2.
+    /// may be specified multiple times.
+    #[argh(option)]
+    require_signature_version: Vec<u32>,
+
+    /// all signatures, or all of --require-signature-version, must verify
+    #[argh(switch)]
+    require_all_signatures: bool,
+
+    /// digest algorithm that payloads were signed over, sha256 or sha512.
+    /// defaults to sha256.
+    #[argh(option, default = "DigestAlgo::Sha256")]
+    signature_digest: DigestAlgo,
+
+    /// INSECURE, for development only: extract payloads without verifying
+    /// their signatures, e.g. unsigned local builds. no public key is needed.
+    #[argh(switch)]
+    extract_only: bool,
+
+    /// number of payload operations to decompress and write concurrently
+    #[argh(option, default = "1")]
+    extract_threads: usize,
+
+    /// also verify and extract the kernel of the payload, into a .kernel
+    /// file next to the extracted image
+    #[argh(switch)]
+    extract_kernel: bool,
+
+    /// number of packages to download at once
+    #[argh(option, default = "1")]
+    max_concurrent_downloads: usize,
+
+    /// number of packages to verify and extract at once
+    #[argh(option, default = "1")]
+    max_concurrent_verifications: usize,
+
+    /// what to do with the downloaded payloads afterwards, keep (default),
+    /// delete-on-success or always-delete
+    #[argh(option, default = "CleanupPolicy::Keep")]
+    cleanup: CleanupPolicy,
+
+    /// skip packages whose verified image from the same payload already
+    /// exists in the output directory, e.g. from an interrupted run
+    #[argh(switch)]
+    skip_existing: bool,
+
+    /// with --skip-existing, also check the hash of the existing image
+    #[argh(switch)]
+    rehash_existing: bool,
+
+    /// only print which packages would be downloaded, from which URLs and
+    /// into which files, without downloading or writing anything. an
+    /// http(s) URL given as --input-xml is still fetched
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// only list all packages of the Omaha response given by --input-xml,
+    /// with their hashes, sizes and URLs. a URL is fetched with the
+    /// timeouts of downloads
+    #[argh(switch)]
+    list_packages: bool,
+
+    /// output format of the result, text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+}
+
+impl Args {
+    fn image_match_glob_set(&self) -> Result<GlobSet, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+
+        for m in &*self.image_match {
+            builder.add(Glob::new(m)?);
         }
 
-        Ok(())
+        builder.build()
     }
 
-    fn download(&mut self, into_dir: &Path, client: &Client) -> Result<()> {
-        // FIXME: use _range_start for completing downloads
-        let _range_start = match self.status {
-            PackageStatus::ToDownload => 0,
-            PackageStatus::DownloadIncomplete(s) => s.bytes(),
-            _ => return Ok(()),
-        };
+    fn package_selectors(&self) -> Result<Vec<PackageSelector>, Box<dyn Error>> {
+        let mut selectors = vec![PackageSelector::Glob(self.image_match_glob_set()?)];
 
-        info!("downloading {}...", self.url);
-
-        let path = into_dir.join(&*self.name);
-        match ue_rs::download_and_hash(
-            client,
-            self.url.clone(),
-            &path,
-            self.hash_sha256.clone(),
-            self.hash_sha1.clone(),
-        ) {
-            Ok(ok) => ok,
-            Err(err) => {
-                error!("Downloading failed with error {}", err);
-                self.status = PackageStatus::DownloadFailed;
-                bail!("unable to download data(url {})", self.url);
-            }
-        };
+        for r in &*self.image_regex {
+            selectors.push(PackageSelector::Regex(Regex::new(r)?));
+        }
 
-        self.status = PackageStatus::Unverified;
-        Ok(())
+        Ok(selectors)
     }
 
-    fn verify_checksum(&mut self, calculated_sha256: omaha::Hash<omaha::Sha256>, calculated_sha1: omaha::Hash<omaha::Sha1>) -> bool {
-        debug!("    expected sha256:   {:?}", self.hash_sha256);
-        debug!("    calculated sha256: {}", calculated_sha256);
-        debug!("    sha256 match?      {}", self.hash_sha256 == Some(calculated_sha256.clone()));
-        debug!("    expected sha1:   {:?}", self.hash_sha1);
-        debug!("    calculated sha1: {}", calculated_sha1);
-        debug!("    sha1 match?      {}", self.hash_sha1 == Some(calculated_sha1.clone()));
-
-        if self.hash_sha256.is_some() && self.hash_sha256 != Some(calculated_sha256.clone()) || self.hash_sha1.is_some() && self.hash_sha1 != Some(calculated_sha1.clone()) {
-            self.status = PackageStatus::BadChecksum;
-            false
-        } else {
-            self.status = PackageStatus::Unverified;
-            true
+    fn download_order(&self) -> Result<DownloadOrder, Box<dyn Error>> {
+        if self.priority.is_empty() {
+            return Ok(self.order.clone().unwrap_or_default());
+        }
+        if self.order.is_some() {
+            return Err("Only one of the options can be given, --order or --priority.".into());
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        for p in &*self.priority {
+            builder.add(Glob::new(p)?);
         }
+
+        Ok(DownloadOrder::Priority(builder.build()?))
     }
 
-    fn verify_signature_on_disk(&mut self, from_path: &Path, pubkey_path: &str) -> Result<PathBuf> {
-        let upfile = File::open(from_path).context(format!("failed to open path ({:?})", from_path.display()))?;
+    fn ca_certificates(&self) -> Result<Vec<reqwest::Certificate>, Box<dyn Error>> {
+        let mut certs = Vec::new();
 
-        // Read update payload from file, read delta update header from the payload.
-        let header = delta_update::read_delta_update_header(&upfile).context(format!("failed to read_delta_update_header path ({:?})", from_path.display()))?;
+        for path in &*self.ca_cert {
+            let buf = std::fs::read(path).map_err(|err| format!("failed to read certificate {:?}: {}", path, err))?;
+            let cert = if buf.starts_with(b"-----BEGIN") {
+                reqwest::Certificate::from_pem(&buf)?
+            } else {
+                reqwest::Certificate::from_der(&buf)?
+            };
+            certs.push(cert);
+        }
 
-        let mut delta_archive_manifest = delta_update::get_manifest_bytes(&upfile, &header).context(format!("failed to get_manifest_bytes path ({:?})", from_path.display()))?;
+        Ok(certs)
+    }
 
-        // Extract signature from header.
-        let sigbytes = delta_update::get_signatures_bytes(&upfile, &header, &mut delta_archive_manifest).context(format!("failed to get_signatures_bytes path ({:?})", from_path.display()))?;
+    fn headers(&self) -> Result<HeaderMap, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
 
-        // tmp dir == "/var/tmp/outdir/.tmp"
-        let tmpdirpathbuf = from_path.parent().ok_or(anyhow!("unable to get parent dir"))?.parent().ok_or(anyhow!("unable to get parent dir"))?.join(".tmp");
-        let tmpdir = tmpdirpathbuf.as_path();
-        let datablobspath = tmpdir.join("ue_data_blobs");
+        for HeaderArg(h) in &*self.header {
+            let (name, value) = h.split_once(':').ok_or(format!("invalid --header {:?}, expected NAME: VALUE", h))?;
+            headers.append(HeaderName::from_str(name.trim())?, HeaderValue::from_str(value.trim())?);
+        }
 
-        // Get length of header and data, including header and manifest.
-        let header_data_length = delta_update::get_header_data_length(&header, &delta_archive_manifest).context("failed to get header data length")?;
-        let hdhash = self.hash_on_disk::<omaha::Sha256>(from_path, Some(header_data_length)).context(format!("failed to hash_on_disk path ({:?}) failed", from_path.display()))?;
-        let hdhashvec: Vec<u8> = hdhash.clone().into();
+        if let Some(path) = &self.token_file {
+            if headers.contains_key(AUTHORIZATION) {
+                return Err("--token-file conflicts with an Authorization --header".into());
+            }
+            let token = std::fs::read_to_string(path).map_err(|err| format!("failed to read token file {:?}: {}", path, err))?;
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token.trim()))?);
+        }
 
-        // Extract data blobs into a file, datablobspath.
-        delta_update::get_data_blobs(&upfile, &header, &delta_archive_manifest, datablobspath.as_path()).context(format!("failed to get_data_blobs path ({:?})", datablobspath.display()))?;
+        Ok(headers)
+    }
 
-        // Check for hash of data blobs with new_partition_info hash.
-        let pinfo_hash = match &delta_archive_manifest.new_partition_info.hash {
-            Some(hash) => hash,
-            None => bail!("unable to get new_partition_info hash"),
-        };
+    // A missing default netrc file is not an error, like with curl --netrc.
+    fn netrc(&self) -> Result<Option<Netrc>, Box<dyn Error>> {
+        if let Some(path) = &self.netrc_file {
+            return Ok(Some(Netrc::load(Path::new(path))?));
+        }
+        if !self.netrc {
+            return Ok(None);
+        }
 
-        let datahash = self.hash_on_disk::<omaha::Sha256>(datablobspath.as_path(), None).context(format!("failed to hash_on_disk path ({:?})", datablobspath.display()))?;
-        if datahash != omaha::Hash::from_bytes(pinfo_hash.as_slice()[..].into()) {
-            bail!(
-                "mismatch of data hash ({:?}) with new_partition_info hash ({:?})",
-                datahash,
-                pinfo_hash
-            );
+        match Netrc::default_path() {
+            Some(path) if path.exists() => Ok(Some(Netrc::load(&path)?)),
+            _ => Ok(None),
         }
+    }
 
-        // Parse signature data from sig blobs, data blobs, public key, and verify.
-        match delta_update::parse_signature_data(&sigbytes, hdhashvec.as_slice(), pubkey_path) {
-            Ok(_) => (),
-            _ => {
-                self.status = PackageStatus::BadSignature;
-                bail!(
-                    "unable to parse and verify signature, sigbytes ({:?}), hdhash ({:?}), pubkey_path ({:?})",
-                    sigbytes,
-                    hdhash,
-                    pubkey_path
-                );
-            }
-        };
+    fn name_map(&self) -> Result<Vec<(Glob, String)>, Box<dyn Error>> {
+        let mut map = Vec::new();
 
-        println!("Parsed and verified signature data from file {:?}", from_path);
+        for m in &*self.name_map {
+            let (glob, name) = m.split_once('=').ok_or(format!("invalid --name-map {:?}, expected GLOB=NAME", m))?;
+            map.push((Glob::new(glob)?, name.to_string()));
+        }
 
-        self.status = PackageStatus::Verified;
-        Ok(datablobspath)
+        Ok(map)
     }
 }
 
-#[rustfmt::skip]
-fn get_pkgs_to_download<'a>(resp: &'a omaha::Response, glob_set: &GlobSet)
-        -> Result<Vec<Package<'a>>> {
-    let mut to_download: Vec<_> = Vec::new();
+#[cfg(feature = "hardware-keys")]
+fn pubkey_source(key: String) -> PubkeySource {
+    match key.parse() {
+        Ok(hardware_key) => PubkeySource::Hardware(hardware_key),
+        Err(_) => PubkeySource::Path(key),
+    }
+}
 
-    for app in &resp.apps {
-        let manifest = &app.update_check.manifest;
+#[cfg(not(feature = "hardware-keys"))]
+fn pubkey_source(key: String) -> PubkeySource {
+    PubkeySource::Path(key)
+}
 
-        for pkg in &manifest.packages {
-            if !glob_set.is_match(&*pkg.name) {
-                info!("package `{}` doesn't match glob pattern, skipping", pkg.name);
-                continue;
-            }
+// Exit code for an error of run, so that scripts can tell e.g. a network
+// problem that is worth retrying from a payload with an invalid signature.
+fn exit_code(err: &(dyn Error + 'static)) -> u8 {
+    match ErrorKind::of(err) {
+        None => 1,
+        Some(ErrorKind::Config) => 2,
+        Some(ErrorKind::Network) => 3,
+        Some(ErrorKind::Http) => 4,
+        Some(ErrorKind::Protocol | ErrorKind::SizeMismatch) => 5,
+        Some(ErrorKind::ChecksumMismatch) => 6,
+        Some(ErrorKind::SignatureInvalid) => 7,
+        Some(ErrorKind::Io) => 8,
+    }
+}
 
-            let hash_sha256 = pkg.hash_sha256.as_ref();
-            let hash_sha1 = pkg.hash.as_ref();
+fn main() -> ExitCode {
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).with_writer(io::stderr).init();
 
-            // TODO: multiple URLs per package
-            //       not sure if nebraska sends us more than one right now but i suppose this is
-            //       for mirrors?
-            let Some(Ok(url)) = app.update_check.urls.first()
-                .map(|u| u.join(&pkg.name)) else {
-                warn!("can't get url for package `{}`, skipping", pkg.name);
-                continue;
-            };
+    let args: Args = argh::from_env();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(exit_code(&*err))
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    if args.format == OutputFormat::Text && !args.dry_run && !args.list_packages {
+        println!("{:?}", args);
+    }
 
-            if hash_sha256.is_none() && hash_sha1.is_none() {
-              warn!("package `{}` doesn't have a valid SHA256 or SHA1 hash, skipping", pkg.name);
-              continue;
+    // If input_xml exists, simply read it, unless it is a URL to fetch
+    // the response from. If not, try to read from payload_url.
+    let res_local = match args.input_xml {
+        Some(ref name) => {
+            if name == "-" {
+                Some(Source::Xml(io::read_to_string(io::stdin())?))
+            } else {
+                match Source::from_input_xml(name.clone()) {
+                    Source::XmlUrl(u) => Some(Source::XmlUrl(u)),
+                    _ => {
+                        let file = File::open(name)?;
+                        Some(Source::Xml(io::read_to_string(file)?))
+                    }
+                }
             }
-                    to_download.push(Package {
-                        url,
-                        name: Cow::Borrowed(&pkg.name),
-                        hash_sha256: hash_sha256.cloned(),
-                        hash_sha1: hash_sha1.cloned(),
-                        size: pkg.size,
-                        status: PackageStatus::ToDownload
-                    });
         }
+        None => None,
+    };
+
+    if args.list_packages && res_local.is_none() {
+        return Err("--list-packages requires --input-xml".into());
     }
 
-    Ok(to_download)
-}
+    let source = match (res_local, &args.payload_url, &args.verify_only) {
+        (Some(res), None, None) => res,
+        (None, Some(url), None) => Source::from_payload_url(url)?,
+        (None, None, Some(path)) => Source::LocalFile(path.into()),
+        (None, None, None) => return Err("Either --input-xml, --payload-url or --verify-only must be given.".into()),
+        _ => {
+            return Err("Only one of the options can be given, --input-xml, --payload-url or --verify-only.".into());
+        }
+    };
+
+    let selectors = args.package_selectors()?;
+    let name_map = args.name_map()?;
+    let order = args.download_order()?;
+    let ca_certificates = args.ca_certificates()?;
+    let headers = args.headers()?;
+    let netrc = args.netrc()?;
+
+    // Listing packages writes nothing, so it needs no output directory.
+    let output_dir_name = match args.output_dir {
+        Some(dir) => dir,
+        None if args.list_packages => String::new(),
+        None => return Err("--output-dir must be given".into()),
+    };
 
-// Read data from remote URL into File
-fn fetch_url_to_file<'a, U>(path: &'a Path, input_url: U, client: &'a Client) -> Result<Package<'a>>
-where
-    U: reqwest::IntoUrl + From<U> + std::clone::Clone + std::fmt::Debug,
-    Url: From<U>,
-{
-    let r = ue_rs::download_and_hash(client, input_url.clone(), path, None, None).context(format!("unable to download data(url {:?})", input_url))?;
-
-    Ok(Package {
-        name: Cow::Borrowed(path.file_name().unwrap_or(OsStr::new("fakepackage")).to_str().unwrap_or("fakepackage")),
-        hash_sha256: Some(r.hash_sha256),
-        hash_sha1: Some(r.hash_sha1),
-        size: FileSize::from_bytes(r.data.metadata().context(format!("failed to get metadata, path ({:?})", path.display()))?.len() as usize),
-        url: input_url.into(),
-        status: PackageStatus::Unverified,
+    let mut download_verify = DownloadVerifyBuilder::new(
+        output_dir_name.clone(),
+        args.pubkey_file.into_iter().map(pubkey_source).collect(),
+    )
+    .source(source)
+    .take_first_match(args.take_first_match)
+    .package_selectors(selectors)
+    .order(order)
+    .target_filename(args.target_filename)
+    .name_map(name_map)
+    .timeout(args.timeout.map(Duration::from_secs))
+    .deadline(args.deadline.map(Duration::from_secs))
+    .ca_certificates(ca_certificates)
+    .pinned_keys(args.pin_sha256)
+    .headers(headers)
+    .netrc(netrc)
+    .preflight(args.preflight)
+    .content_encoding(args.accept_encoding)
+    .http_version(args.http_version)
+    .pool_max_idle_per_host(args.pool_max_idle_per_host)
+    .progress(ProgressConfig {
+        interval: (args.progress_interval > 0).then(|| Duration::from_secs(args.progress_interval)),
+        bytes: args.progress_bytes,
     })
-}
+    .target_device(args.target_device)
+    .direct_io(args.direct_io)
+    .sync_extents(args.sync_extents)
+    .extract_threads(args.extract_threads)
+    .extract_kernel(args.extract_kernel)
+    .cleanup_policy(args.cleanup)
+    .skip_existing(args.skip_existing)
+    .rehash_existing(args.rehash_existing)
+    .max_concurrent_downloads(args.max_concurrent_downloads)
+    .max_concurrent_verifications(args.max_concurrent_verifications)
+    .signature_policy(SignaturePolicy {
+        required_versions: args.require_signature_version,
+        require_all: args.require_all_signatures,
+        digest: args.signature_digest,
+    })
+    .extract_only(args.extract_only);
 
-fn do_download_verify(pkg: &mut Package<'_>, output_filename: Option<String>, output_dir: &Path, unverified_dir: &Path, pubkey_file: &str, client: &Client) -> Result<()> {
-    pkg.check_download(unverified_dir)?;
+    if let Some(secs) = args.connect_timeout {
+        download_verify = download_verify.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.read_timeout {
+        download_verify = download_verify.read_timeout(Duration::from_secs(secs));
+    }
 
-    pkg.download(unverified_dir, client).context(format!("unable to download \"{:?}\"", pkg.name))?;
+    // Fetch the response with the same client configuration as downloads.
+    if args.list_packages {
+        let resp = download_verify.response()?;
+        match args.format {
+            OutputFormat::Text => print_packages_text(&resp),
+            OutputFormat::Json => print_packages_json(&resp)?,
+        }
 
-    // Unverified payload is stored in e.g. "output_dir/.unverified/oem.gz".
-    // Verified payload is stored in e.g. "output_dir/oem.raw".
-    let pkg_unverified = unverified_dir.join(&*pkg.name);
-    let pkg_verified = output_dir.join(output_filename.as_ref().map(OsStr::new).unwrap_or(pkg_unverified.with_extension("raw").file_name().unwrap_or_default()));
+        return Ok(());
+    }
 
-    let datablobspath = pkg.verify_signature_on_disk(&pkg_unverified, pubkey_file).context(format!("unable to verify signature \"{}\"", pkg.name))?;
+    if args.extract_only {
+        eprintln!("WARNING: --extract-only given, payload signatures are NOT verified");
+    }
 
-    // write extracted data into the final data.
-    debug!("data blobs written into file {:?}", pkg_verified);
-    fs::rename(datablobspath, pkg_verified)?;
+    let output_dir = Path::new(&*output_dir_name);
+    if !output_dir.try_exists()? {
+        return Err(format!("output directory `{}` does not exist", output_dir_name).into());
+    }
 
-    Ok(())
-}
+    let download_verify = download_verify.build()?;
 
-#[derive(FromArgs, Debug)]
-/// Parse an update-engine Omaha XML response to extract sysext images, then download and verify
-/// their signatures.
-struct Args {
-    /// the directory to download the sysext images into
-    #[argh(option, short = 'o')]
-    output_dir: String,
+    if args.dry_run {
+        let planned = download_verify.dry_run()?;
+        match args.format {
+            OutputFormat::Text => print_planned_text(&planned),
+            OutputFormat::Json => print_planned_json(&planned)?,
+        }
 
-    /// target filename in directory, requires --payload-url or --take-first-match
-    #[argh(option, short = 'n')]
-    target_filename: Option<String>,
+        return Ok(());
+    }
 
-    /// path to the Omaha XML file, or - to read from stdin
-    #[argh(option, short = 'i')]
-    input_xml: Option<String>,
+    let report = match download_verify.run() {
+        Err(err) if err.downcast_ref::<ue_rs::error::Error>() == Some(&ue_rs::error::Error::NoUpdate) => {
+            eprintln!("no update available");
+            RunReport::default()
+        }
+        res => res?,
+    };
 
-    /// URL to fetch remote update payload
-    #[argh(option, short = 'u')]
-    payload_url: Option<String>,
+    match args.format {
+        OutputFormat::Text => print_text(&report),
+        OutputFormat::Json => print_json(&report)?,
+    }
 
-    /// path to the public key file
-    #[argh(option, short = 'p')]
-    pubkey_file: String,
+    Ok(())
+}
 
-    /// glob pattern to match update URLs.
-    /// may be specified multiple times.
-    #[argh(option, short = 'm')]
-    image_match: Vec<String>,
+fn phase_json(m: &PhaseMetrics) -> serde_json::Value {
+    json!({
+        "duration_ms": m.duration.as_millis() as u64,
+        "bytes": m.bytes,
+        "bytes_per_sec": m.throughput(),
+    })
+}
 
-    /// only take the first matching entry
-    #[argh(switch, short = 't')]
-    take_first_match: bool,
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-impl Args {
-    fn image_match_glob_set(&self) -> Result<GlobSet, globset::Error> {
-        let mut builder = GlobSetBuilder::new();
+fn payload_json(info: &PayloadInfo) -> serde_json::Value {
+    #[rustfmt::skip]
+    let partitions: Vec<_> = info.partitions.iter().map(|p| json!({
+        "name": p.name,
+        "operations": p.operations,
+        "old_sha256": p.old_info.as_ref().map(|i| hex(&i.hash)),
+        "new_size": p.new_info.as_ref().map(|i| i.size),
+        "new_sha256": p.new_info.as_ref().map(|i| hex(&i.hash)),
+    })).collect();
+
+    json!({
+        "file_format_version": info.file_format_version,
+        "manifest_size": info.manifest_size,
+        "block_size": info.block_size,
+        "signatures_offset": info.signatures_offset,
+        "signatures_size": info.signatures_size,
+        "partitions": partitions,
+    })
+}
 
-        for m in &*self.image_match {
-            builder.add(Glob::new(m)?);
-        }
+fn print_text(report: &RunReport) {
+    if report.is_empty() {
+        eprintln!("no package was written");
+    }
 
-        builder.build()
+    for r in &report.packages {
+        println!("{}: {} -> {} ({})", r.name, r.url, r.output_path.display(), r.status);
     }
 }
 
-const HTTP_CONN_TIMEOUT: u64 = 20;
-const DOWNLOAD_TIMEOUT: u64 = 3600;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+fn print_json(report: &RunReport) -> Result<(), Box<dyn Error>> {
+    #[rustfmt::skip]
+    let packages: Vec<_> = report.packages.iter().map(|r| json!({
+        "name": r.name,
+        "url": r.url.as_str(),
+        "size": r.size.bytes(),
+        "sha1": r.hash_sha1.as_ref().map(|h| h.to_string()),
+        "sha256": r.hash_sha256.as_ref().map(|h| h.to_string()),
+        "status": r.status.to_string(),
+        "output_path": r.output_path.display().to_string(),
+        "output_size": r.output_size.bytes(),
+        "metrics": {
+            "download": phase_json(&r.metrics.download),
+            "hash": phase_json(&r.metrics.hash),
+            "extract": phase_json(&r.metrics.extract),
+            "verify": phase_json(&r.metrics.verify),
+        },
+        "payload": r.payload_info.as_ref().map(payload_json),
+        "kernel_output_path": r.kernel_output_path.as_ref().map(|p| p.display().to_string()),
+    })).collect();
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json!({ "packages": packages, "duration_ms": report.duration.as_millis() as u64 }))?
+    );
 
-    let args: Args = argh::from_env();
-    println!("{:?}", args);
+    Ok(())
+}
 
-    if args.payload_url.is_none() && !args.take_first_match && args.target_filename.is_some() {
-        return Err("--target-filename can only be specified with --take-first-match".into());
+fn print_planned_text(planned: &[PlannedPackage]) {
+    for p in planned {
+        println!("{}: {} -> {}", p.name, p.url, p.output_path.display());
     }
+}
 
-    let glob_set = args.image_match_glob_set()?;
+fn print_planned_json(planned: &[PlannedPackage]) -> Result<(), Box<dyn Error>> {
+    #[rustfmt::skip]
+    let packages: Vec<_> = planned.iter().map(|p| json!({
+        "name": p.name,
+        "url": p.url.as_str(),
+        "output_path": p.output_path.display().to_string(),
+    })).collect();
 
-    let output_dir = Path::new(&*args.output_dir);
-    if !output_dir.try_exists()? {
-        return Err(format!("output directory `{}` does not exist", args.output_dir).into());
-    }
+    println!("{}", serde_json::to_string_pretty(&json!({ "packages": packages }))?);
 
-    let unverified_dir = output_dir.join(".unverified");
-    let temp_dir = output_dir.join(".tmp");
-    fs::create_dir_all(&unverified_dir)?;
-    fs::create_dir_all(&temp_dir)?;
-
-    // The default policy of reqwest Client supports max 10 attempts on HTTP redirect.
-    let client = Client::builder()
-        .tcp_keepalive(Duration::from_secs(HTTP_CONN_TIMEOUT))
-        .connect_timeout(Duration::from_secs(HTTP_CONN_TIMEOUT))
-        .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT))
-        .redirect(Policy::default())
-        .build()?;
-
-    // If input_xml exists, simply read it.
-    // If not, try to read from payload_url.
-    let res_local = match args.input_xml {
-        Some(name) => {
-            if name == "-" {
-                Some(io::read_to_string(io::stdin())?)
-            } else {
-                let file = File::open(name)?;
-                Some(io::read_to_string(file)?)
-            }
-        }
-        None => None,
-    };
+    Ok(())
+}
 
-    match (&res_local, args.payload_url) {
-        (Some(_), Some(_)) => {
-            return Err("Only one of the options can be given, --input-xml or --payload-url.".into());
-        }
-        (Some(res), None) => res,
-        (None, Some(url)) => {
-            let u = Url::parse(&url)?;
-            let fname = u.path_segments().ok_or(anyhow!("failed to get path segments, url ({:?})", u))?.next_back().ok_or(anyhow!("failed to get path segments, url ({:?})", u))?;
-            let mut pkg_fake: Package;
-
-            let temp_payload_path = unverified_dir.join(fname);
-            pkg_fake = fetch_url_to_file(
-                &temp_payload_path,
-                Url::from_str(url.as_str()).context(anyhow!("failed to convert into url ({:?})", url))?,
-                &client,
-            )?;
-            do_download_verify(
-                &mut pkg_fake,
-                args.target_filename.clone(),
-                output_dir,
-                unverified_dir.as_path(),
-                args.pubkey_file.as_str(),
-                &client,
-            )?;
-
-            // verify only a fake package, early exit and skip the rest.
-            return Ok(());
+// Resolve the URLs of the given package against all URLs of its app.
+fn package_urls(update_check: &omaha::response::UpdateCheck<'_>, pkg: &omaha::response::Package<'_>) -> Vec<Url> {
+    update_check.urls.iter().filter_map(|u| u.join(&pkg.name).ok()).collect()
+}
+
+fn print_packages_text(resp: &omaha::Response<'_>) {
+    for app in &resp.apps {
+        let Some(update_check) = &app.update_check else {
+            continue;
+        };
+        let Some(manifest) = &update_check.manifest else {
+            continue;
+        };
+
+        for pkg in &manifest.packages {
+            let urls: Vec<_> = package_urls(update_check, pkg).iter().map(Url::to_string).collect();
+
+            println!(
+                "{} size={} sha1={} sha256={} urls={}",
+                pkg.name,
+                pkg.size.bytes(),
+                pkg.hash.as_ref().map(|h| h.to_string()).unwrap_or("-".to_string()),
+                pkg.hash_sha256.as_ref().map(|h| h.to_string()).unwrap_or("-".to_string()),
+                urls.join(","),
+            );
         }
-        (None, None) => return Err("Either --input-xml or --payload-url must be given.".into()),
-    };
+    }
+}
 
-    let response_text = res_local.ok_or(anyhow!("failed to get response text"))?;
-    debug!("response_text: {:?}", response_text);
-
-    ////
-    // parse response
-    ////
-    let resp = omaha::Response::from_str(&response_text)?;
-
-    let mut pkgs_to_dl = get_pkgs_to_download(&resp, &glob_set)?;
-
-    debug!("pkgs:\n\t{:#?}", pkgs_to_dl);
-    debug!("");
-
-    ////
-    // download
-    ////
-
-    for pkg in pkgs_to_dl.iter_mut() {
-        do_download_verify(
-            pkg,
-            args.target_filename.clone(),
-            output_dir,
-            unverified_dir.as_path(),
-            args.pubkey_file.as_str(),
-            &client,
-        )?;
-        if args.take_first_match {
-            break;
+fn print_packages_json(resp: &omaha::Response<'_>) -> Result<(), Box<dyn Error>> {
+    let mut packages = Vec::new();
+
+    for app in &resp.apps {
+        let Some(update_check) = &app.update_check else {
+            continue;
+        };
+        let Some(manifest) = &update_check.manifest else {
+            continue;
+        };
+
+        for pkg in &manifest.packages {
+            let urls: Vec<_> = package_urls(update_check, pkg).iter().map(Url::to_string).collect();
+
+            packages.push(json!({
+                "appid": app.id.to_string(),
+                "version": manifest.version,
+                "name": pkg.name,
+                "size": pkg.size.bytes(),
+                "required": pkg.required,
+                "sha1": pkg.hash.as_ref().map(|h| h.to_string()),
+                "sha256": pkg.hash_sha256.as_ref().map(|h| h.to_string()),
+                "urls": urls,
+            }));
         }
     }
 
-    // clean up data
-    fs::remove_dir_all(temp_dir)?;
+    println!("{}", serde_json::to_string_pretty(&json!({ "packages": packages }))?);
 
     Ok(())
 }
@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+
+use argh::FromArgs;
+use tracing_subscriber::EnvFilter;
+
+use omaha::response::UpdateCheckStatus;
+use ue_rs::request::{self, AppParameters, Parameters};
+
+const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+// Exit codes, so that scripts can tell the outcomes apart.
+const EXIT_UPDATE_AVAILABLE: u8 = 0;
+const EXIT_ERROR: u8 = 1;
+const EXIT_NO_UPDATE: u8 = 2;
+
+#[derive(FromArgs)]
+/// Check an Omaha server for updates and print a summary of its response. Exits with 0 if an
+/// update is available, 2 if there is none, and 1 on errors.
+struct Args {
+    /// URL of the Omaha server, the public Flatcar update server by default
+    #[argh(option, short = 's')]
+    server: Option<String>,
+
+    /// ID of the app to check for updates, the Flatcar OS by default
+    #[argh(option)]
+    app_id: Option<omaha::Uuid>,
+
+    /// version of the app that is currently installed
+    #[argh(option, short = 'v')]
+    version: String,
+
+    /// track, or channel, to check, e.g. stable, beta or alpha
+    #[argh(option, short = 't', default = "String::from(\"stable\")")]
+    track: String,
+
+    /// board of the machine, e.g. amd64-usr
+    #[argh(option)]
+    board: Option<String>,
+
+    /// machine ID to send, read from /etc/machine-id by default
+    #[argh(option)]
+    machine_id: Option<String>,
+
+    /// print the raw response document after the summary
+    #[argh(switch)]
+    raw: bool,
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).with_writer(io::stderr).init();
+
+    let args: Args = argh::from_env();
+    match run(args) {
+        Ok(true) => ExitCode::from(EXIT_UPDATE_AVAILABLE),
+        Ok(false) => ExitCode::from(EXIT_NO_UPDATE),
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+// Return whether the server offers an update for any app.
+fn run(args: Args) -> Result<bool, Box<dyn Error>> {
+    let machine_id = match args.machine_id {
+        Some(id) => id,
+        None => fs::read_to_string(MACHINE_ID_PATH).map_err(|err| format!("failed to read {}, use --machine-id: {}", MACHINE_ID_PATH, err))?.trim().to_string(),
+    };
+
+    let mut app = AppParameters::os(Cow::Owned(args.version), Cow::Owned(args.track));
+    if let Some(id) = args.app_id {
+        app.id = id;
+    }
+
+    let parameters = Parameters {
+        server_url: args.server.map(Cow::Owned),
+
+        apps: vec![app],
+        machine_id: Cow::Owned(machine_id),
+        boot_id: request::read_boot_id().ok(),
+
+        board: args.board.map(Cow::Owned),
+        arch: None,
+        oem: None,
+        oem_version: None,
+        oem_platform: None,
+
+        delta_okay: false,
+        retry_policy: Default::default(),
+    };
+
+    let client = reqwest::blocking::Client::new();
+    let parsed = request::perform_parsed(&client, parameters)?;
+
+    let update_available = print_summary(&parsed.response)?;
+    if args.raw {
+        println!("{}", parsed.raw);
+    }
+
+    Ok(update_available)
+}
+
+fn print_summary(resp: &omaha::Response<'_>) -> Result<bool, Box<dyn Error>> {
+    let mut update_available = false;
+
+    println!("protocol {}, {} apps", resp.protocol_version, resp.apps.len());
+    for app in &resp.apps {
+        println!("app {}: status {}", app.id, app.status);
+
+        let Some(update_check) = &app.update_check else {
+            println!("  no updatecheck");
+            continue;
+        };
+        println!("  updatecheck: {}", update_check.status);
+
+        match &update_check.status {
+            UpdateCheckStatus::Ok => {}
+            UpdateCheckStatus::NoUpdate => continue,
+            status => return Err(format!("update check of app {} failed with status {}", app.id, status).into()),
+        }
+
+        let Some(manifest) = &update_check.manifest else {
+            return Err(format!("update check of app {} has no manifest", app.id).into());
+        };
+        update_available = true;
+
+        println!("  version {}", manifest.version);
+        for skipped in &update_check.skipped_urls {
+            println!("  skipped url: {}", skipped.error);
+        }
+        for pkg in &manifest.packages {
+            println!(
+                "  package {} size={} required={} sha1={} sha256={}",
+                pkg.name,
+                pkg.size.bytes(),
+                pkg.required,
+                pkg.hash.as_ref().map(|h| h.to_string()).unwrap_or("-".to_string()),
+                pkg.hash_sha256.as_ref().map(|h| h.to_string()).unwrap_or("-".to_string()),
+            );
+            for url in update_check.urls.iter().filter_map(|u| u.join(&pkg.name).ok()) {
+                println!("    {}", url);
+            }
+        }
+    }
+
+    Ok(update_available)
+}
@@ -0,0 +1,591 @@
+use anyhow::{Context, Result, anyhow};
+use globset::Glob;
+use reqwest::blocking::Client;
+use reqwest::header::HeaderMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+use update_format_crau::delta_update::{SignaturePolicy, WriteOptions};
+use update_format_crau::verify_sig::PubkeySource;
+
+use super::hooks::Hooks;
+use super::{CleanupPolicy, ContentEncoding, DOWNLOAD_TIMEOUT, DownloadOrder, DownloadVerify, HTTP_CONN_TIMEOUT, HttpVersion, Netrc, Package, PackageSelector, PackageStatus, ProgressConfig, url_filename};
+use crate::error::ConfigError;
+use crate::util::Semaphore;
+
+// Where DownloadVerify gets the packages to verify from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    // Omaha response, whose packages matching the package selectors are downloaded.
+    Xml(String),
+    // Same as Xml, for a response to fetch from a URL first, e.g. one that a
+    // coordinator service publishes.
+    XmlUrl(Url),
+    // Same as Xml, for a response that was already parsed, e.g. by
+    // request::perform_parsed.
+    Response(Arc<omaha::Response<'static>>),
+    // Single remote payload.
+    PayloadUrl(Url),
+    // Already downloaded payload, only verified and extracted without any
+    // network access.
+    LocalFile(PathBuf),
+}
+
+impl Source {
+    // Either an http(s):// URL of an Omaha response, or the response itself.
+    pub fn from_input_xml(input_xml: String) -> Self {
+        match Url::parse(&input_xml) {
+            Ok(u) if matches!(u.scheme(), "http" | "https") => Source::XmlUrl(u),
+            _ => Source::Xml(input_xml),
+        }
+    }
+
+    // Either a URL of a remote payload, or a plain path or file:// URL of
+    // an already downloaded payload.
+    pub fn from_payload_url(payload_url: &str) -> Result<Self> {
+        match Url::parse(payload_url) {
+            Ok(u) if u.scheme() == "file" => Ok(Source::LocalFile(
+                u.to_file_path().map_err(|_| anyhow!("invalid file URL ({:?})", payload_url))?,
+            )),
+            Ok(u) => Ok(Source::PayloadUrl(u)),
+            Err(url::ParseError::RelativeUrlWithoutBase) => Ok(Source::LocalFile(PathBuf::from(payload_url))),
+            Err(err) => Err(err).context(format!("failed to parse url ({:?})", payload_url)),
+        }
+    }
+}
+
+pub struct DownloadVerifyBuilder {
+    output_dir: String,
+    target_filename: Option<String>,
+    name_map: Vec<(Glob, String)>,
+    source: Option<Source>,
+    pubkeys: Vec<PubkeySource>,
+    signature_policy: SignaturePolicy,
+    extract_only: bool,
+    take_first_match: bool,
+    selectors: Vec<PackageSelector>,
+    order: DownloadOrder,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    size_tolerance: u64,
+    ca_certificates: Vec<reqwest::Certificate>,
+    pinned_keys: Vec<omaha::Hash<omaha::Sha256>>,
+    preflight: bool,
+    content_encoding: ContentEncoding,
+    http_version: HttpVersion,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+    progress: ProgressConfig,
+    headers: HeaderMap,
+    netrc: Option<Netrc>,
+    client: Option<Client>,
+    target_device: Option<String>,
+    write_options: WriteOptions,
+    extract_kernel: bool,
+    cleanup_policy: CleanupPolicy,
+    skip_existing: bool,
+    rehash_existing: bool,
+    max_concurrent_downloads: usize,
+    max_concurrent_verifications: usize,
+    hooks: Hooks,
+}
+
+impl DownloadVerifyBuilder {
+    // Payloads are accepted if any of pubkeys verifies any of their
+    // signatures, e.g. the current and the previous key during a key rollover.
+    pub fn new(output_dir: String, pubkeys: Vec<PubkeySource>) -> Self {
+        Self {
+            output_dir,
+            target_filename: None,
+            name_map: Vec::new(),
+            source: None,
+            pubkeys,
+            signature_policy: SignaturePolicy::default(),
+            extract_only: false,
+            take_first_match: false,
+            selectors: Vec::new(),
+            order: DownloadOrder::default(),
+            connect_timeout: Duration::from_secs(HTTP_CONN_TIMEOUT),
+            read_timeout: Duration::from_secs(DOWNLOAD_TIMEOUT),
+            timeout: None,
+            deadline: None,
+            size_tolerance: 0,
+            ca_certificates: Vec::new(),
+            pinned_keys: Vec::new(),
+            preflight: false,
+            content_encoding: ContentEncoding::default(),
+            http_version: HttpVersion::default(),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            progress: ProgressConfig::default(),
+            headers: HeaderMap::new(),
+            netrc: None,
+            client: None,
+            target_device: None,
+            write_options: WriteOptions::default(),
+            extract_kernel: false,
+            cleanup_policy: CleanupPolicy::default(),
+            skip_existing: false,
+            rehash_existing: false,
+            max_concurrent_downloads: 1,
+            max_concurrent_verifications: 1,
+            hooks: Hooks::default(),
+        }
+    }
+
+    pub fn source(mut self, param_source: Source) -> Self {
+        self.source = Some(param_source);
+        self
+    }
+
+    // Packages of an Omaha response to download, those that match any of
+    // the selectors.
+    pub fn package_selectors(mut self, param_selectors: Vec<PackageSelector>) -> Self {
+        self.selectors = param_selectors;
+        self
+    }
+
+    // Only download the first package of an Omaha response that matches.
+    pub fn take_first_match(mut self, param_take_first_match: bool) -> Self {
+        self.take_first_match = param_take_first_match;
+        self
+    }
+
+    // Order to download the packages of an Omaha response in, by default
+    // the order of the response.
+    pub fn order(mut self, param_order: DownloadOrder) -> Self {
+        self.order = param_order;
+        self
+    }
+
+    // Signature slots that must verify, by default any of them.
+    pub fn signature_policy(mut self, param_signature_policy: SignaturePolicy) -> Self {
+        self.signature_policy = param_signature_policy;
+        self
+    }
+
+    // INSECURE, for development only: extract payloads without verifying
+    // their signatures, e.g. unsigned ones built locally. No public key is
+    // needed then, packages stay unverified in the report, and outputs are
+    // never skipped by a later run with skip_existing.
+    pub fn extract_only(mut self, param_extract_only: bool) -> Self {
+        self.extract_only = param_extract_only;
+        self
+    }
+
+    // Name of the verified file in output_dir, only for a single package.
+    pub fn target_filename(mut self, param_target_filename: Option<String>) -> Self {
+        self.target_filename = param_target_filename;
+        self
+    }
+
+    // Name of the verified file in output_dir for each package whose name
+    // matches a glob, e.g. to download several extensions at once. The first
+    // matching glob wins, other packages fall back to target_filename.
+    pub fn name_map(mut self, param_name_map: Vec<(Glob, String)>) -> Self {
+        self.name_map = param_name_map;
+        self
+    }
+
+    // Timeout for establishing a connection, also used as TCP keepalive interval.
+    pub fn connect_timeout(mut self, param_connect_timeout: Duration) -> Self {
+        self.connect_timeout = param_connect_timeout;
+        self
+    }
+
+    // Timeout for each individual read or write operation on a connection,
+    // i.e. how long a stalled transfer is tolerated.
+    pub fn read_timeout(mut self, param_read_timeout: Duration) -> Self {
+        self.read_timeout = param_read_timeout;
+        self
+    }
+
+    // Overall timeout of a single download request, from connecting
+    // until the whole response body has been received.
+    pub fn timeout(mut self, param_timeout: Option<Duration>) -> Self {
+        self.timeout = param_timeout;
+        self
+    }
+
+    // Overall time budget of DownloadVerify::run, after which it fails with
+    // Error::DeadlineExceeded even if a download still makes progress. The
+    // run is cancelled then, and waited for until its current step returns,
+    // e.g. a read of a download, a chunk of hashing or an operation of a
+    // payload being extracted.
+    // Nothing is written into the output directory after that.
+    pub fn deadline(mut self, param_deadline: Option<Duration>) -> Self {
+        self.deadline = param_deadline;
+        self
+    }
+
+    // Maximum difference in bytes between the Content-Length of a download
+    // and the size declared in the Omaha response.
+    pub fn size_tolerance(mut self, param_size_tolerance: u64) -> Self {
+        self.size_tolerance = param_size_tolerance;
+        self
+    }
+
+    // Trust anchors of payload servers: only accept those whose certificate
+    // chain leads to one of these CA certificates, instead of any in the
+    // system trust store. Any certificate that one of the CAs issued is
+    // accepted, see pinned_keys to only accept certain keys. Downloads then
+    // also require HTTPS. The response of Source::XmlUrl is fetched with the
+    // system trust store.
+    pub fn ca_certificates(mut self, param_ca_certificates: Vec<reqwest::Certificate>) -> Self {
+        self.ca_certificates = param_ca_certificates;
+        self
+    }
+
+    // Only accept payload servers whose certificate has one of these
+    // SHA-256 hashes of its public key, the pin-sha256 of HPKP, in
+    // addition to a valid certificate chain. Downloads then also require
+    // HTTPS. A client given with client has to be built with tls_info.
+    pub fn pinned_keys(mut self, param_pinned_keys: Vec<omaha::Hash<omaha::Sha256>>) -> Self {
+        self.pinned_keys = param_pinned_keys;
+        self
+    }
+
+    // Check each URL of a package with a HEAD request before downloading it,
+    // and move on to the next mirror if it is missing or has the wrong size.
+    pub fn preflight(mut self, param_preflight: bool) -> Self {
+        self.preflight = param_preflight;
+        self
+    }
+
+    // Content encodings to accept for payload downloads, by default only the
+    // payload as is.
+    pub fn content_encoding(mut self, param_content_encoding: ContentEncoding) -> Self {
+        self.content_encoding = param_content_encoding;
+        self
+    }
+
+    // HTTP version of payload downloads, by default HTTP/1.1.
+    pub fn http_version(mut self, param_http_version: HttpVersion) -> Self {
+        self.http_version = param_http_version;
+        self
+    }
+
+    // Maximum number of idle connections to keep alive per host, by
+    // default unlimited.
+    pub fn pool_max_idle_per_host(mut self, param_pool_max_idle_per_host: Option<usize>) -> Self {
+        self.pool_max_idle_per_host = param_pool_max_idle_per_host;
+        self
+    }
+
+    // How long to keep idle connections alive, by default 90 seconds.
+    pub fn pool_idle_timeout(mut self, param_pool_idle_timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = param_pool_idle_timeout;
+        self
+    }
+
+    // How often to log the progress of downloads, by default every 10
+    // seconds.
+    pub fn progress(mut self, param_progress: ProgressConfig) -> Self {
+        self.progress = param_progress;
+        self
+    }
+
+    // Static headers to send with every payload download, e.g.
+    // "Authorization: Bearer ..." for a mirror that requires a token. They
+    // are not sent when fetching the response of Source::XmlUrl. On a
+    // redirect to another host, only Authorization, Cookie and
+    // Proxy-Authorization are dropped, any other header, e.g. PRIVATE-TOKEN,
+    // is sent to the new host as well.
+    pub fn headers(mut self, param_headers: HeaderMap) -> Self {
+        self.headers = param_headers;
+        self
+    }
+
+    // Credentials for basic auth of payload downloads, by host, e.g. loaded
+    // from Netrc::default_path(). Unlike headers, they also apply to a
+    // client given to the builder.
+    pub fn netrc(mut self, param_netrc: Option<Netrc>) -> Self {
+        self.netrc = param_netrc;
+        self
+    }
+
+    // Client for payload downloads, e.g. to share connections with other
+    // requests of the caller. The timeouts, CA certificates, content
+    // encoding, HTTP version, pool options and headers of the builder do
+    // not apply to it, while pinned keys are still checked.
+    pub fn client(mut self, param_client: Client) -> Self {
+        self.client = Some(param_client);
+        self
+    }
+
+    // Write the verified data directly into the given block device, instead of
+    // into a file in output_dir. Only a single package can be processed then.
+    pub fn target_device(mut self, param_target_device: Option<String>) -> Self {
+        self.target_device = param_target_device;
+        self
+    }
+
+    // Open the target device with O_DIRECT, bypassing the page cache.
+    pub fn direct_io(mut self, param_direct_io: bool) -> Self {
+        self.write_options.direct_io = param_direct_io;
+        self
+    }
+
+    // Sync the target to disk after writing each extent.
+    pub fn sync_extents(mut self, param_sync_extents: bool) -> Self {
+        self.write_options.sync_extents = param_sync_extents;
+        self
+    }
+
+    // Apply up to this many payload operations concurrently when extracting,
+    // by default one at a time.
+    pub fn extract_threads(mut self, param_extract_threads: usize) -> Self {
+        self.write_options.threads = param_extract_threads;
+        self
+    }
+
+    // Also verify and extract the kernel of payloads that update it through
+    // the KERNEL install procedure. Payloads without it fail then.
+    pub fn extract_kernel(mut self, param_extract_kernel: bool) -> Self {
+        self.extract_kernel = param_extract_kernel;
+        self
+    }
+
+    // Whether to remove downloaded payloads once they are not needed anymore,
+    // by default they are kept.
+    pub fn cleanup_policy(mut self, param_cleanup_policy: CleanupPolicy) -> Self {
+        self.cleanup_policy = param_cleanup_policy;
+        self
+    }
+
+    // Skip packages of an Omaha response whose verified data an earlier run
+    // already wrote into output_dir, as long as the response still refers
+    // to the same payload. Does not apply to a target device.
+    pub fn skip_existing(mut self, param_skip_existing: bool) -> Self {
+        self.skip_existing = param_skip_existing;
+        self
+    }
+
+    // With skip_existing, also hash the existing data and only skip the
+    // package if it matches the hash in the manifest of its payload.
+    pub fn rehash_existing(mut self, param_rehash_existing: bool) -> Self {
+        self.rehash_existing = param_rehash_existing;
+        self
+    }
+
+    // Download up to this many packages of an Omaha response at once, by
+    // default one at a time.
+    pub fn max_concurrent_downloads(mut self, param_max_concurrent_downloads: usize) -> Self {
+        self.max_concurrent_downloads = param_max_concurrent_downloads;
+        self
+    }
+
+    // Verify and extract up to this many packages at once, by default one
+    // at a time. Each package is verified by the thread that downloaded it,
+    // so this is effectively capped by max_concurrent_downloads.
+    pub fn max_concurrent_verifications(mut self, param_max_concurrent_verifications: usize) -> Self {
+        self.max_concurrent_verifications = param_max_concurrent_verifications;
+        self
+    }
+
+    // Called for each package before it is downloaded and verified, or for
+    // a payload URL before it is verified. Returning an error aborts the
+    // run, e.g. due to a policy.
+    pub fn on_package_selected<F>(mut self, param_hook: F) -> Self
+    where
+        F: Fn(&Package<'_>) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        self.hooks.package_selected = Some(Box::new(param_hook));
+        self
+    }
+
+    // Called once a package is downloaded, before it is verified, with the
+    // download and hash metrics in its metrics.
+    pub fn on_download_complete<F>(mut self, param_hook: F) -> Self
+    where
+        F: Fn(&Package<'_>) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        self.hooks.download_complete = Some(Box::new(param_hook));
+        self
+    }
+
+    // Called with the path the verified data of a package was written into.
+    pub fn on_verified<F>(mut self, param_hook: F) -> Self
+    where
+        F: Fn(&Package<'_>, &Path) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        self.hooks.verified = Some(Box::new(param_hook));
+        self
+    }
+
+    // Called with the error that a package failed with, also if another
+    // hook aborted it.
+    pub fn on_failed<F>(mut self, param_hook: F) -> Self
+    where
+        F: Fn(&Package<'_>, &anyhow::Error) + Send + Sync + 'static,
+    {
+        self.hooks.failed = Some(Box::new(param_hook));
+        self
+    }
+
+    // Called with the name and the new status of a package whenever its
+    // status changes, starting with the status it is selected with, e.g.
+    // to send them through a channel to a daemon that shows them.
+    pub fn on_status_change<F>(mut self, param_hook: F) -> Self
+    where
+        F: Fn(&str, &PackageStatus) + Send + Sync + 'static,
+    {
+        self.hooks.status_changed = Some(Box::new(param_hook));
+        self
+    }
+
+    pub fn build(mut self) -> Result<DownloadVerify, ConfigError> {
+        let source = match self.source.take() {
+            None => return Err(ConfigError::MissingSource),
+            // Verify a payload behind a file:// URL in place.
+            Some(Source::PayloadUrl(u)) if u.scheme() == "file" => Source::LocalFile(u.to_file_path().map_err(|_| ConfigError::InvalidPayloadUrl(u))?),
+            Some(Source::PayloadUrl(u)) if url_filename(&u).is_none() => return Err(ConfigError::InvalidPayloadUrl(u)),
+            Some(source) => source,
+        };
+
+        if self.pubkeys.is_empty() && !self.extract_only {
+            return Err(ConfigError::MissingPubkey);
+        }
+
+        if self.max_concurrent_downloads == 0 || self.max_concurrent_verifications == 0 {
+            return Err(ConfigError::ZeroConcurrency);
+        }
+
+        // All packages of an Omaha response would be written into the same file.
+        if matches!(source, Source::Xml(_) | Source::XmlUrl(_) | Source::Response(_)) && !self.take_first_match && self.target_filename.is_some() {
+            return Err(ConfigError::TargetFilenameWithoutSingle);
+        }
+
+        Ok(self.finish(source))
+    }
+
+    // Fetch or parse the Omaha response of the source with the configured
+    // timeouts, like run() would, e.g. to list all of its packages. Unlike
+    // build(), no public key is needed, as nothing gets verified.
+    pub fn response(mut self) -> Result<Arc<omaha::Response<'static>>> {
+        let source = self.source.take().ok_or(ConfigError::MissingSource)?;
+        self.finish(source).response()
+    }
+
+    fn finish(self, source: Source) -> DownloadVerify {
+        DownloadVerify {
+            output_dir: self.output_dir,
+            target_filename: self.target_filename,
+            name_map: self.name_map.into_iter().map(|(glob, name)| (glob.compile_matcher(), name)).collect(),
+            source,
+            pubkeys: self.pubkeys,
+            signature_policy: self.signature_policy,
+            extract_only: self.extract_only,
+            take_first_match: self.take_first_match,
+            selectors: self.selectors,
+            order: self.order,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            timeout: self.timeout,
+            deadline: self.deadline,
+            size_tolerance: self.size_tolerance,
+            ca_certificates: self.ca_certificates,
+            pinned_keys: self.pinned_keys,
+            preflight: self.preflight,
+            content_encoding: self.content_encoding,
+            http_version: self.http_version,
+            pool_max_idle_per_host: self.pool_max_idle_per_host,
+            pool_idle_timeout: self.pool_idle_timeout,
+            progress: self.progress,
+            headers: self.headers,
+            netrc: self.netrc.map(Arc::new),
+            client: self.client,
+            target_device: self.target_device,
+            write_options: self.write_options,
+            extract_kernel: self.extract_kernel,
+            cleanup_policy: self.cleanup_policy,
+            skip_existing: self.skip_existing,
+            rehash_existing: self.rehash_existing,
+            max_concurrent_downloads: self.max_concurrent_downloads,
+            verifications: Arc::new(Semaphore::new(self.max_concurrent_verifications)),
+            hooks: Arc::new(self.hooks),
+            cancel: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> DownloadVerifyBuilder {
+        DownloadVerifyBuilder::new("/tmp".to_string(), vec![PubkeySource::Path("key.pem".to_string())])
+    }
+
+    fn local_file(source: Source) -> Option<PathBuf> {
+        match source {
+            Source::LocalFile(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_source_from_payload_url() {
+        assert_eq!(
+            local_file(Source::from_payload_url("/var/tmp/oem-azure.gz").unwrap()),
+            Some(PathBuf::from("/var/tmp/oem-azure.gz"))
+        );
+        assert_eq!(
+            local_file(Source::from_payload_url("oem-azure.gz").unwrap()),
+            Some(PathBuf::from("oem-azure.gz"))
+        );
+        assert_eq!(
+            local_file(Source::from_payload_url("file:///var/tmp/oem-azure.gz").unwrap()),
+            Some(PathBuf::from("/var/tmp/oem-azure.gz"))
+        );
+        assert!(matches!(
+            Source::from_payload_url("https://bincache.flatcar-linux.net/oem-azure.gz").unwrap(),
+            Source::PayloadUrl(u) if u.as_str() == "https://bincache.flatcar-linux.net/oem-azure.gz"
+        ));
+    }
+
+    #[test]
+    fn test_source_from_input_xml() {
+        assert!(matches!(
+            Source::from_input_xml("https://coordinator.example.com/response.xml".to_string()),
+            Source::XmlUrl(u) if u.host_str() == Some("coordinator.example.com")
+        ));
+        assert!(matches!(Source::from_input_xml("<response/>".to_string()), Source::Xml(_)));
+        assert!(matches!(
+            Source::from_input_xml("file:///tmp/response.xml".to_string()),
+            Source::Xml(_)
+        ));
+    }
+
+    #[test]
+    fn test_build() {
+        assert_eq!(builder().build().err(), Some(ConfigError::MissingSource));
+        assert_eq!(
+            DownloadVerifyBuilder::new("/tmp".to_string(), Vec::new()).source(Source::Xml(String::new())).build().err(),
+            Some(ConfigError::MissingPubkey)
+        );
+
+        assert_eq!(
+            builder().source(Source::Xml(String::new())).max_concurrent_downloads(0).build().err(),
+            Some(ConfigError::ZeroConcurrency)
+        );
+
+        let url = Url::parse("https://bincache.flatcar-linux.net/").unwrap();
+        assert_eq!(
+            builder().source(Source::PayloadUrl(url.clone())).build().err(),
+            Some(ConfigError::InvalidPayloadUrl(url))
+        );
+
+        let xml = builder().source(Source::Xml(String::new())).target_filename(Some("oem.raw".to_string()));
+        assert_eq!(xml.build().err(), Some(ConfigError::TargetFilenameWithoutSingle));
+        let xml = builder().source(Source::Xml(String::new())).target_filename(Some("oem.raw".to_string()));
+        assert!(xml.take_first_match(true).build().is_ok());
+
+        let dv = builder().source(Source::PayloadUrl(Url::parse("file:///var/tmp/oem-azure.gz").unwrap())).build().unwrap();
+        assert_eq!(local_file(dv.source), Some(PathBuf::from("/var/tmp/oem-azure.gz")));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::{ETAG, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use tracing::debug;
+
+const ETAG_KEY: &str = "etag";
+const LAST_MODIFIED_KEY: &str = "last-modified";
+
+// HTTP cache validators of a downloaded file. They are stored next to the
+// file, e.g. "oem.gz.validators" for "oem.gz", so that a later download of
+// the same URL can be skipped if the server reports it as not modified.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CacheValidators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn path_for(path: &Path) -> PathBuf {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".validators");
+        PathBuf::from(p)
+    }
+
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+        CacheValidators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    // Load validators stored for the file in path, if the file and its
+    // validators exist.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+
+        let content = fs::read_to_string(Self::path_for(path)).ok()?;
+        let mut validators = CacheValidators::default();
+
+        for line in content.lines() {
+            match line.split_once(": ") {
+                Some((ETAG_KEY, v)) => validators.etag = Some(v.to_string()),
+                Some((LAST_MODIFIED_KEY, v)) => validators.last_modified = Some(v.to_string()),
+                _ => debug!("ignoring unknown validator line {:?}", line),
+            }
+        }
+
+        (!validators.is_empty()).then_some(validators)
+    }
+
+    pub(crate) fn store(&self, path: &Path) -> Result<()> {
+        let vpath = Self::path_for(path);
+
+        if self.is_empty() {
+            Self::remove(path);
+            return Ok(());
+        }
+
+        let mut content = String::new();
+        if let Some(etag) = &self.etag {
+            content.push_str(&format!("{}: {}\n", ETAG_KEY, etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            content.push_str(&format!("{}: {}\n", LAST_MODIFIED_KEY, last_modified));
+        }
+
+        fs::write(&vpath, content).context(format!("failed to write validators ({:?})", vpath.display()))
+    }
+
+    // Remove validators of the file in path, so that the next download is
+    // unconditional. A missing validators file is not an error.
+    pub(crate) fn remove(path: &Path) {
+        _ = fs::remove_file(Self::path_for(path));
+    }
+
+    // Make the request conditional, so the server can reply with 304 Not Modified.
+    pub(crate) fn apply(&self, mut req: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_load() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("oem-azure.gz");
+
+        let validators = CacheValidators {
+            etag: Some("\"5f3e-60a1b2c3\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        };
+
+        // Validators without the file they belong to are ignored.
+        validators.store(&path).unwrap();
+        assert_eq!(CacheValidators::load(&path), None);
+
+        fs::write(&path, b"payload").unwrap();
+        assert_eq!(CacheValidators::load(&path), Some(validators));
+
+        CacheValidators::remove(&path);
+        assert_eq!(CacheValidators::load(&path), None);
+    }
+}
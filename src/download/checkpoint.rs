@@ -0,0 +1,254 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::digest::generic_array::GenericArray;
+use tracing::debug;
+
+const BLOCK_LEN: usize = 64;
+
+const SHA256_IV: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+const SHA1_IV: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+const BYTES_KEY: &str = "bytes";
+const SHA256_KEY: &str = "sha256";
+const SHA1_KEY: &str = "sha1";
+
+// State of ResumableHasher after the first bytes of a file. It is stored
+// next to the file, e.g. "oem.gz.hashstate" for "oem.gz", so that a later
+// download can resume the file without hashing it from the start again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HashCheckpoint {
+    pub(crate) bytes: u64,
+    sha256: [u32; 8],
+    sha1: [u32; 5],
+}
+
+// SHA-256 and SHA-1 of a stream. Both have 64-byte blocks, and in contrast
+// to the hashers of sha2 and sha1, the state after a whole number of blocks
+// can be saved in a HashCheckpoint and restored later.
+#[derive(Debug, Clone)]
+pub(crate) struct ResumableHasher {
+    sha256: [u32; 8],
+    sha1: [u32; 5],
+    // Bytes that went through the compression functions, always a multiple
+    // of BLOCK_LEN.
+    hashed: u64,
+    // Start of the next block, shorter than BLOCK_LEN.
+    pending: Vec<u8>,
+}
+
+fn words_to_hex(words: &[u32]) -> String {
+    words.iter().map(|w| format!("{:08x}", w)).collect()
+}
+
+fn hex_to_words<const N: usize>(hex: &str) -> Option<[u32; N]> {
+    if hex.len() != N * 8 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut words = [0u32; N];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = u32::from_str_radix(&hex[i * 8..(i + 1) * 8], 16).ok()?;
+    }
+    Some(words)
+}
+
+fn words_to_bytes(words: &[u32]) -> Box<[u8]> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+impl Default for ResumableHasher {
+    fn default() -> Self {
+        ResumableHasher {
+            sha256: SHA256_IV,
+            sha1: SHA1_IV,
+            hashed: 0,
+            pending: Vec::with_capacity(BLOCK_LEN),
+        }
+    }
+}
+
+impl ResumableHasher {
+    pub(crate) fn from_checkpoint(checkpoint: &HashCheckpoint) -> Self {
+        ResumableHasher {
+            sha256: checkpoint.sha256,
+            sha1: checkpoint.sha1,
+            hashed: checkpoint.bytes,
+            pending: Vec::with_capacity(BLOCK_LEN),
+        }
+    }
+
+    // State after the last whole block, bytes of a partial block are not
+    // part of it.
+    pub(crate) fn checkpoint(&self) -> HashCheckpoint {
+        HashCheckpoint {
+            bytes: self.hashed,
+            sha256: self.sha256,
+            sha1: self.sha1,
+        }
+    }
+
+    // data must be a whole number of blocks.
+    fn compress(&mut self, data: &[u8]) {
+        for block in data.chunks_exact(BLOCK_LEN) {
+            let block = GenericArray::from_slice(block);
+            sha2::compress256(&mut self.sha256, std::slice::from_ref(block));
+            sha1::compress(&mut self.sha1, std::slice::from_ref(block));
+        }
+        self.hashed += data.len() as u64;
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        if !self.pending.is_empty() {
+            let n = (BLOCK_LEN - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..n]);
+            data = &data[n..];
+
+            if self.pending.len() < BLOCK_LEN {
+                return;
+            }
+            let block = std::mem::replace(&mut self.pending, Vec::with_capacity(BLOCK_LEN));
+            self.compress(&block);
+        }
+
+        let whole = data.len() - data.len() % BLOCK_LEN;
+        self.compress(&data[..whole]);
+        self.pending.extend_from_slice(&data[whole..]);
+    }
+
+    // Hash the file in path from where the hasher stopped up to len bytes,
+    // e.g. the part of a partial download after its checkpoint.
+    pub(crate) fn update_from_file(&mut self, path: &Path, len: u64) -> Result<()> {
+        let start = self.hashed + self.pending.len() as u64;
+        let mut file = File::open(path).context(format!("failed to open path ({:?})", path.display()))?;
+        file.seek(SeekFrom::Start(start)).context(format!("failed to seek in {:?}", path.display()))?;
+
+        let mut reader = file.take(len.saturating_sub(start));
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let n = reader.read(&mut buf).context(format!("failed to read {:?}", path.display()))?;
+            if n == 0 {
+                break;
+            }
+            self.update(&buf[..n]);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finalize(mut self) -> (omaha::Hash<omaha::Sha256>, omaha::Hash<omaha::Sha1>) {
+        let bit_len = (self.hashed + self.pending.len() as u64) * 8;
+
+        // Both pad with a single 1 bit, zeros and the big-endian length in
+        // bits, up to a whole number of blocks.
+        let mut tail = std::mem::take(&mut self.pending);
+        tail.push(0x80);
+        while tail.len() % BLOCK_LEN != BLOCK_LEN - 8 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bit_len.to_be_bytes());
+        self.compress(&tail);
+
+        (
+            omaha::Hash::from_bytes(words_to_bytes(&self.sha256)),
+            omaha::Hash::from_bytes(words_to_bytes(&self.sha1)),
+        )
+    }
+}
+
+impl HashCheckpoint {
+    fn path_for(path: &Path) -> PathBuf {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".hashstate");
+        PathBuf::from(p)
+    }
+
+    // Load the checkpoint stored for the file in path, if the file still
+    // has at least the bytes that the checkpoint covers.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let len = fs::metadata(path).ok()?.len();
+        let content = fs::read_to_string(Self::path_for(path)).ok()?;
+
+        let (mut bytes, mut sha256, mut sha1) = (None, None, None);
+        for line in content.lines() {
+            match line.split_once(": ") {
+                Some((BYTES_KEY, v)) => bytes = v.parse().ok(),
+                Some((SHA256_KEY, v)) => sha256 = hex_to_words(v),
+                Some((SHA1_KEY, v)) => sha1 = hex_to_words(v),
+                _ => debug!("ignoring unknown checkpoint line {:?}", line),
+            }
+        }
+
+        let checkpoint = HashCheckpoint {
+            bytes: bytes?,
+            sha256: sha256?,
+            sha1: sha1?,
+        };
+        (checkpoint.bytes <= len && checkpoint.bytes.is_multiple_of(BLOCK_LEN as u64)).then_some(checkpoint)
+    }
+
+    pub(crate) fn store(&self, path: &Path) -> Result<()> {
+        let cpath = Self::path_for(path);
+        let content = format!(
+            "{}: {}\n{}: {}\n{}: {}\n",
+            BYTES_KEY,
+            self.bytes,
+            SHA256_KEY,
+            words_to_hex(&self.sha256),
+            SHA1_KEY,
+            words_to_hex(&self.sha1)
+        );
+
+        fs::write(&cpath, content).context(format!("failed to write checkpoint ({:?})", cpath.display()))
+    }
+
+    // Remove the checkpoint of the file in path. A missing checkpoint is not
+    // an error.
+    pub(crate) fn remove(path: &Path) {
+        _ = fs::remove_file(Self::path_for(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_from_reader;
+
+    #[test]
+    fn test_resumable_hasher() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let expected = (
+            hash_from_reader::<omaha::Sha256, _>(&data[..], None).unwrap(),
+            hash_from_reader::<omaha::Sha1, _>(&data[..], None).unwrap(),
+        );
+
+        for split in [0, 1, 63, 64, 65, 500, 1000] {
+            let mut hasher = ResumableHasher::default();
+            hasher.update(&data[..split]);
+
+            let checkpoint = hasher.checkpoint();
+            assert_eq!(checkpoint.bytes, (split - split % BLOCK_LEN) as u64);
+
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("oem.gz");
+            fs::write(&path, &data[..split]).unwrap();
+            checkpoint.store(&path).unwrap();
+            let checkpoint = HashCheckpoint::load(&path).unwrap();
+
+            let mut resumed = ResumableHasher::from_checkpoint(&checkpoint);
+            resumed.update(&data[checkpoint.bytes as usize..]);
+            assert_eq!(resumed.finalize(), expected, "split at {}", split);
+        }
+
+        // A checkpoint beyond the end of the file does not apply.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("oem.gz");
+        let mut hasher = ResumableHasher::default();
+        hasher.update(&data);
+        hasher.checkpoint().store(&path).unwrap();
+        fs::write(&path, &data[..100]).unwrap();
+        assert_eq!(HashCheckpoint::load(&path), None);
+    }
+}
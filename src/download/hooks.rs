@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use super::{Package, PackageStatus};
+
+pub(super) type PackageHook = Box<dyn Fn(&Package<'_>) -> Result<()> + Send + Sync>;
+pub(super) type VerifiedHook = Box<dyn Fn(&Package<'_>, &Path) -> Result<()> + Send + Sync>;
+pub(super) type FailedHook = Box<dyn Fn(&Package<'_>, &anyhow::Error) + Send + Sync>;
+pub(super) type StatusHook = Box<dyn Fn(&str, &PackageStatus) + Send + Sync>;
+
+// Callbacks of DownloadVerify for each package it processes, e.g. for
+// telemetry. All but failed can abort the run by returning an error.
+#[derive(Default)]
+pub(super) struct Hooks {
+    pub(super) package_selected: Option<PackageHook>,
+    pub(super) download_complete: Option<PackageHook>,
+    pub(super) verified: Option<VerifiedHook>,
+    pub(super) failed: Option<FailedHook>,
+    pub(super) status_changed: Option<StatusHook>,
+}
+
+impl Hooks {
+    pub(super) fn package_selected(&self, pkg: &Package<'_>) -> Result<()> {
+        match &self.package_selected {
+            Some(hook) => hook(pkg).context(format!("package {} aborted by on_package_selected", pkg.name)),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn download_complete(&self, pkg: &Package<'_>) -> Result<()> {
+        match &self.download_complete {
+            Some(hook) => hook(pkg).context(format!("package {} aborted by on_download_complete", pkg.name)),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn verified(&self, pkg: &Package<'_>, output_path: &Path) -> Result<()> {
+        match &self.verified {
+            Some(hook) => hook(pkg, output_path).context(format!("package {} aborted by on_verified", pkg.name)),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn failed(&self, pkg: &Package<'_>, err: &anyhow::Error) {
+        if let Some(hook) = &self.failed {
+            hook(pkg, err);
+        }
+    }
+
+    // Pass the current status of pkg on, if it differs from before.
+    pub(super) fn status_changed(&self, pkg: &Package<'_>, before: Option<&PackageStatus>) {
+        if let Some(hook) = &self.status_changed {
+            if before != Some(&pkg.status) {
+                hook(&pkg.name, &pkg.status);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+// Wall-clock duration and number of bytes processed by a single phase,
+// e.g. download or hashing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseMetrics {
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+impl PhaseMetrics {
+    #[inline]
+    pub fn new(duration: Duration, bytes: u64) -> Self {
+        PhaseMetrics {
+            duration,
+            bytes,
+        }
+    }
+
+    // Create metrics of a phase that started at the given instant and just finished.
+    #[inline]
+    pub fn since(start: Instant, bytes: u64) -> Self {
+        Self::new(start.elapsed(), bytes)
+    }
+
+    // Add up metrics of the same phase, e.g. hashing done in several steps.
+    pub fn add(&mut self, other: PhaseMetrics) {
+        self.duration += other.duration;
+        self.bytes += other.bytes;
+    }
+
+    // Return throughput in bytes per second, or None if the phase took no
+    // measurable time.
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            Some(self.bytes as f64 / secs)
+        } else {
+            None
+        }
+    }
+}
+
+// Metrics of all phases of processing a single package.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PackageMetrics {
+    pub download: PhaseMetrics,
+    pub hash: PhaseMetrics,
+    pub extract: PhaseMetrics,
+    pub verify: PhaseMetrics,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput() {
+        let mut m = PhaseMetrics::new(Duration::from_secs(2), 1000);
+        assert_eq!(m.throughput(), Some(500.0));
+
+        m.add(PhaseMetrics::new(Duration::from_secs(2), 3000));
+        assert_eq!(m.bytes, 4000);
+        assert_eq!(m.throughput(), Some(1000.0));
+
+        assert_eq!(PhaseMetrics::default().throughput(), None);
+    }
+}
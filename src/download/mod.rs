@@ -0,0 +1,2263 @@
+mod builder;
+pub use builder::{DownloadVerifyBuilder, Source};
+
+mod cache;
+use cache::CacheValidators;
+
+mod checkpoint;
+use checkpoint::{HashCheckpoint, ResumableHasher};
+
+mod hooks;
+use hooks::Hooks;
+
+mod metrics;
+pub use metrics::{PackageMetrics, PhaseMetrics};
+
+mod package;
+pub use package::{Package, PackageStatus};
+
+mod selector;
+pub use selector::PackageSelector;
+
+mod netrc;
+pub use netrc::Netrc;
+
+mod order;
+pub use order::DownloadOrder;
+
+mod progress;
+pub use progress::ProgressConfig;
+use progress::Progress;
+
+mod stamp;
+use stamp::VerifiedStamp;
+
+use anyhow::{Context, Result, anyhow, bail};
+use globset::GlobMatcher;
+use hard_xml::XmlRead;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, info, info_span, warn};
+use omaha::{FileSize, HashAlgo};
+use url::Url;
+
+use reqwest::StatusCode;
+use reqwest::header::{ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, HeaderMap, HeaderValue, RANGE};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::redirect::Policy;
+
+use sha2::digest::DynDigest;
+
+use update_format_crau::delta_update::{self, PayloadInfo, SignaturePolicy, WriteOptions};
+use update_format_crau::verify_sig::PubkeySource;
+
+use crate::util::{CancelFlag, Semaphore, try_map_concurrent, with_deadline_cancel};
+
+const MAX_DOWNLOAD_RETRY: u32 = 20;
+// Data with a bad checksum is downloaded once more from the same URL, as
+// it might have been corrupted in transit, but not 20 times.
+const MAX_CHECKSUM_RETRY: u32 = 1;
+const HTTP_CONN_TIMEOUT: u64 = 20;
+const DOWNLOAD_TIMEOUT: u64 = 3600;
+const COPY_CHUNKLEN: usize = 65536;
+const HASH_CHUNKLEN: usize = 10485760; // 10M
+                                       // Bytes downloaded between two hash checkpoints, see HashCheckpoint.
+const CHECKPOINT_INTERVAL: u64 = 64 * 1024 * 1024;
+
+pub struct DownloadResult<W = File> {
+    pub hash_sha256: omaha::Hash<omaha::Sha256>,
+    pub hash_sha1: omaha::Hash<omaha::Sha1>,
+    // Only calculated if an expected SHA-512 hash was given.
+    pub hash_sha512: Option<omaha::Hash<omaha::Sha512>>,
+    pub data: W,
+    pub download: PhaseMetrics,
+    pub hash: PhaseMetrics,
+}
+
+// Hashes the downloaded data must match. Hashes that are None are not
+// checked. Some mirrors only publish SHA-512 checksums.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedHashes {
+    pub sha256: Option<omaha::Hash<omaha::Sha256>>,
+    pub sha1: Option<omaha::Hash<omaha::Sha1>>,
+    pub sha512: Option<omaha::Hash<omaha::Sha512>>,
+}
+
+// Size of the data to download, as declared e.g. in the Omaha response.
+// Content-Length of the server response may differ by at most tolerance bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpectedSize {
+    pub size: FileSize,
+    pub tolerance: u64,
+}
+
+impl ExpectedSize {
+    pub fn new(size: FileSize, tolerance: u64) -> Self {
+        ExpectedSize {
+            size,
+            tolerance,
+        }
+    }
+
+    // Servers are not required to send Content-Length, e.g. with chunked
+    // transfer encoding, so only a known length can be checked.
+    pub fn check(&self, content_length: Option<u64>) -> Result<()> {
+        let Some(content_length) = content_length else {
+            debug!(
+                "no Content-Length in response, cannot check expected size {}",
+                self.size.bytes()
+            );
+            return Ok(());
+        };
+
+        let expected = self.size.bytes() as u64;
+        if content_length.abs_diff(expected) > self.tolerance {
+            return Err(crate::error::Error::SizeMismatch {
+                expected,
+                content_length,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+// Options of the requests of a single download. If timeout is Some, it
+// bounds each request, from connecting until the response body has been
+// fully received. With netrc, requests to the hosts it has credentials for
+// use basic auth. Once cancel is set, downloads fail between two chunks.
+// With pinned_keys, see check_pinned_keys, the client has to be built with
+// tls_info enabled.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    pub timeout: Option<Duration>,
+    pub progress: ProgressConfig,
+    pub netrc: Option<Arc<Netrc>>,
+    pub cancel: CancelFlag,
+    pub pinned_keys: Vec<omaha::Hash<omaha::Sha256>>,
+}
+
+impl DownloadOptions {
+    fn apply(&self, mut req: RequestBuilder, url: &Url) -> RequestBuilder {
+        if let Some(t) = self.timeout {
+            req = req.timeout(t);
+        }
+
+        // Credentials are looked up by the host of the original URL, so a
+        // redirect to another host does not get them.
+        let credentials = self.netrc.as_deref().zip(url.host_str()).and_then(|(netrc, host)| netrc.credentials(host));
+        if let Some((login, password)) = credentials {
+            req = req.basic_auth(login, password);
+        }
+
+        req
+    }
+
+    // Fail unless pinned_keys is empty, or the server that sent res, i.e.
+    // the last one after any redirects, presented a certificate with one of
+    // them as the SHA-256 hash of its SubjectPublicKeyInfo. This is the
+    // pin-sha256 of HPKP, see spki_sha256.
+    fn check_pinned_keys(&self, res: &Response) -> Result<()> {
+        if self.pinned_keys.is_empty() {
+            return Ok(());
+        }
+
+        let Some(cert) = res.extensions().get::<reqwest::tls::TlsInfo>().and_then(|info| info.peer_certificate()) else {
+            bail!(
+                "no certificate of {} to check the pinned keys against, it needs HTTPS and a client with tls_info",
+                res.url()
+            );
+        };
+        if !self.pinned_keys.contains(&spki_sha256(cert)?) {
+            return Err(crate::error::Error::PinnedKeyMismatch {
+                url: res.url().clone(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+// SHA-256 hash of the SubjectPublicKeyInfo of a DER encoded certificate,
+// e.g. like `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der
+// | openssl dgst -sha256 -binary`. Unlike a hash of the whole certificate,
+// it stays the same when the certificate is renewed with the same key.
+fn spki_sha256(cert_der: &[u8]) -> Result<omaha::Hash<omaha::Sha256>> {
+    use sha2::Digest;
+    use x509_cert::der::{Decode, Encode};
+
+    let cert = x509_cert::Certificate::from_der(cert_der).context("failed to parse certificate of server")?;
+    let spki = cert.tbs_certificate.subject_public_key_info.to_der().context("failed to encode public key of server")?;
+
+    Ok(omaha::Hash::from_bytes(sha2::Sha256::digest(&spki).as_slice().into()))
+}
+
+// What a server reported in response to a HEAD request, see preflight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preflight {
+    pub content_length: Option<u64>,
+    // Whether the server announced that it supports range requests, which
+    // are needed to resume a download.
+    pub accept_ranges: bool,
+}
+
+// Check with a HEAD request that url exists and has the expected size,
+// before committing to a full download, e.g. to move on to a mirror early
+// if the payload was already removed from a server.
+pub fn preflight<U>(client: &Client, url: U, expected_size: Option<ExpectedSize>, options: &DownloadOptions) -> Result<Preflight>
+where
+    U: reqwest::IntoUrl,
+{
+    let url = url.into_url()?;
+    let req = options.apply(client.head(url.clone()), &url);
+
+    let res = req.send().context("HEAD request failed")?;
+    options.check_pinned_keys(&res)?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(crate::error::Error::HttpStatus {
+            status: status.as_u16(),
+            url,
+        }
+        .into());
+    }
+
+    // The body of a HEAD response is always empty, so the length can only
+    // be taken from the header.
+    let content_length = res.headers().get(CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok());
+    if let Some(expected_size) = expected_size {
+        expected_size.check(content_length)?;
+    }
+
+    let accept_ranges = res.headers().get(ACCEPT_RANGES).is_some_and(|v| v.as_bytes() == b"bytes");
+
+    Ok(Preflight {
+        content_length,
+        accept_ranges,
+    })
+}
+
+// Hash the first len bytes of file through a memory mapping, which avoids
+// copying the data into a buffer first.
+#[cfg(feature = "mmap")]
+fn hash_mmap<T: omaha::HashAlgo>(file: &File, len: usize, cancel: &CancelFlag) -> Result<omaha::Hash<T>> {
+    if len == 0 {
+        bail!("unable to map 0 bytes");
+    }
+
+    // SAFETY: the mapping is only read, and only while hashing. If the file
+    // is truncated meanwhile, the process gets SIGBUS, like any reader of a
+    // mapping, so this is only used for files ue-rs itself wrote or owns.
+    let map = unsafe { memmap2::MmapOptions::new().len(len).map(file) }.context("failed to map file")?;
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let mut hasher = T::hasher();
+    for chunk in map.chunks(HASH_CHUNKLEN) {
+        cancel.check()?;
+        hasher.update(chunk);
+    }
+
+    Ok(omaha::Hash::from_bytes(Box::new(hasher).finalize()))
+}
+
+pub fn hash_on_disk<T: omaha::HashAlgo>(path: &Path, maxlen: Option<usize>) -> Result<omaha::Hash<T>> {
+    hash_on_disk_cancel(path, maxlen, &CancelFlag::default())
+}
+
+// Like hash_on_disk, but fail between two chunks once cancel is set.
+pub(crate) fn hash_on_disk_cancel<T: omaha::HashAlgo>(path: &Path, maxlen: Option<usize>, cancel: &CancelFlag) -> Result<omaha::Hash<T>> {
+    let mut file = File::open(path).context(format!("failed to open path({:?})", path.display()))?;
+
+    // Seek to the end instead of looking at the metadata, to also get the
+    // size of block devices.
+    let filelen = file.seek(SeekFrom::End(0)).context(format!("failed to get size of {:?}", path.display()))? as usize;
+    file.rewind().context(format!("failed to rewind {:?}", path.display()))?;
+
+    let maxlen_to_read: usize = match maxlen {
+        Some(len) => {
+            if filelen < len {
+                filelen
+            } else {
+                len
+            }
+        }
+        None => filelen,
+    };
+
+    let _span = debug_span!("hash", path = %path.display(), algo = T::HASH_NAME, bytes = maxlen_to_read).entered();
+
+    #[cfg(feature = "mmap")]
+    match hash_mmap::<T>(&file, maxlen_to_read, cancel) {
+        Ok(hash) => return Ok(hash),
+        Err(err) => debug!(
+            "failed to hash {:?} through mmap, falling back to reads: {:#}",
+            path.display(),
+            err
+        ),
+    }
+
+    hash_from_reader_cancel::<T, _>(file, Some(maxlen_to_read), cancel).context(format!("failed to hash {:?}", path.display()))
+}
+
+// Return the hash of the data from reader, until its end, or only of its
+// first maxlen bytes if given, e.g. of a network stream or a decompressor.
+pub fn hash_from_reader<T: omaha::HashAlgo, R: Read>(reader: R, maxlen: Option<usize>) -> Result<omaha::Hash<T>> {
+    hash_from_reader_cancel(reader, maxlen, &CancelFlag::default())
+}
+
+fn hash_from_reader_cancel<T: omaha::HashAlgo, R: Read>(reader: R, maxlen: Option<usize>, cancel: &CancelFlag) -> Result<omaha::Hash<T>> {
+    let mut hasher = T::hasher();
+    let mut reader = reader.take(maxlen.map_or(u64::MAX, |len| len as u64));
+    let mut databuf = vec![0u8; maxlen.map_or(HASH_CHUNKLEN, |len| len.min(HASH_CHUNKLEN))];
+
+    loop {
+        cancel.check()?;
+        let n = match reader.read(&mut databuf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err).context(format!("failed to read(chunklen {:?})", databuf.len())),
+        };
+
+        hasher.update(&databuf[..n]);
+    }
+
+    Ok(omaha::Hash::from_bytes(Box::new(hasher).finalize()))
+}
+
+// Content encodings that payload downloads accept. The expected hashes and
+// sizes always refer to the payload itself, i.e. the decoded data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentEncoding {
+    // Ask for the payload as is.
+    #[default]
+    Identity,
+    // Also accept zstd, which is decoded while downloading. Only the
+    // hashes can be checked then, as Content-Length is the encoded size.
+    Zstd,
+}
+
+impl ContentEncoding {
+    fn accept_encoding(self) -> HeaderValue {
+        match self {
+            ContentEncoding::Identity => HeaderValue::from_static("identity"),
+            ContentEncoding::Zstd => HeaderValue::from_static("zstd, identity;q=0.5"),
+        }
+    }
+}
+
+impl FromStr for ContentEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "identity" => ContentEncoding::Identity,
+            "zstd" => ContentEncoding::Zstd,
+            _ => bail!("unknown content encoding {:?}", s),
+        })
+    }
+}
+
+// Body of res, decoded according to its Content-Encoding, and whether it
+// was encoded. Servers should only encode it if the client asked for it,
+// see ContentEncoding, but any other encoding is refused as it would not
+// match the expected hashes.
+fn decoded_body(res: Response) -> Result<(Box<dyn Read>, bool)> {
+    let encoding = res.headers().get(CONTENT_ENCODING).map(|v| v.to_str().unwrap_or_default().trim().to_ascii_lowercase());
+
+    match encoding.as_deref() {
+        None | Some("identity") => Ok((Box::new(res), false)),
+        Some("zstd") => Ok((
+            Box::new(zstd::stream::read::Decoder::new(res).context("failed to set up zstd decoder")?),
+            true,
+        )),
+        Some(other) => bail!("unsupported Content-Encoding {:?}", other),
+    }
+}
+
+// If validators are given, the request is conditional, and the returned
+// response might have status 304 Not Modified. With a range_start above 0,
+// only the rest of the data is requested, unencoded so that it continues
+// the bytes on disk, and the response might have status 206 Partial Content
+// or 416 Range Not Satisfiable.
+fn get_response<U>(client: &Client, url: U, options: &DownloadOptions, validators: Option<&CacheValidators>, range_start: u64) -> Result<Response>
+where
+    U: reqwest::IntoUrl + Clone,
+    Url: From<U>,
+{
+    let client_url = url.clone();
+
+    let mut req = options.apply(client.get(url.clone()), &url.clone().into());
+    if let Some(v) = validators {
+        req = v.apply(req);
+    }
+    if range_start > 0 {
+        req = req.header(RANGE, format!("bytes={}-", range_start)).header(ACCEPT_ENCODING, "identity");
+    }
+
+    #[rustfmt::skip]
+    let res = req
+        .send()
+        .context(format!("client get & send{:?} failed ", client_url.as_str()))?;
+    options.check_pinned_keys(&res)?;
+
+    // Redirect was already handled at this point, so there is no need to touch
+    // response or url again. Simply print info and continue.
+    if <U as Into<Url>>::into(client_url) != *res.url() {
+        info!("redirected to URL {:?}", res.url());
+    }
+
+    // Return immediately on download failure on the client side.
+    let status = res.status();
+
+    if status == StatusCode::NOT_MODIFIED && validators.is_some() {
+        return Ok(res);
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && range_start > 0 {
+        return Ok(res);
+    }
+
+    if !status.is_success() {
+        return Err(crate::error::Error::HttpStatus {
+            status: status.as_u16(),
+            url: res.url().clone(),
+        }
+        .into());
+    }
+
+    Ok(res)
+}
+
+fn check_hashes(
+    calculated_sha256: &omaha::Hash<omaha::Sha256>,
+    calculated_sha1: &omaha::Hash<omaha::Sha1>,
+    calculated_sha512: Option<&omaha::Hash<omaha::Sha512>>,
+    expected: &ExpectedHashes,
+) -> Result<()> {
+    debug!("    expected sha256:   {:?}", expected.sha256);
+    debug!("    calculated sha256: {}", calculated_sha256);
+    debug!("    sha256 match?      {}", expected.sha256.as_ref() == Some(calculated_sha256));
+    debug!("    expected sha1:   {:?}", expected.sha1);
+    debug!("    calculated sha1: {}", calculated_sha1);
+    debug!("    sha1 match?      {}", expected.sha1.as_ref() == Some(calculated_sha1));
+
+    let mismatch = |algo| {
+        Err(crate::error::Error::ChecksumMismatch {
+            algo,
+        }
+        .into())
+    };
+
+    if expected.sha256.is_some() && expected.sha256.as_ref() != Some(calculated_sha256) {
+        return mismatch("sha256");
+    }
+    if expected.sha1.is_some() && expected.sha1.as_ref() != Some(calculated_sha1) {
+        return mismatch("sha1");
+    }
+    if let Some(expected_sha512) = &expected.sha512 {
+        debug!("    expected sha512:   {}", expected_sha512);
+        debug!("    calculated sha512: {:?}", calculated_sha512);
+
+        if calculated_sha512 != Some(expected_sha512) {
+            return mismatch("sha512");
+        }
+    }
+
+    Ok(())
+}
+
+// Start of the data in a 206 Partial Content response, from a Content-Range
+// header like "bytes 100-199/200".
+fn content_range_start(res: &Response) -> Option<u64> {
+    let value = res.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = value.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+// Make sure the bytes that checkpoint covers are on disk before storing it.
+fn store_checkpoint(file: &File, hasher: &ResumableHasher, path: &Path) -> Result<()> {
+    file.sync_data().context(format!("failed to sync {:?}", path.display()))?;
+    hasher.checkpoint().store(path)
+}
+
+// Copy body to the end of file while hashing it, storing a checkpoint every
+// CHECKPOINT_INTERVAL bytes, and also when reading fails, so that the next
+// try can resume from there. Returns the number of bytes copied and the
+// time spent hashing.
+fn copy_and_hash(body: &mut dyn Read, file: &mut File, hasher: &mut ResumableHasher, path: &Path, progress: &mut Progress, cancel: &CancelFlag) -> Result<(u64, Duration)> {
+    let mut bytes: u64 = 0;
+    let mut hash_duration = Duration::ZERO;
+    let mut last_checkpoint = hasher.checkpoint().bytes;
+    let mut databuf = vec![0u8; COPY_CHUNKLEN];
+
+    loop {
+        cancel.check()?;
+        let n = match body.read(&mut databuf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                if let Err(cp_err) = store_checkpoint(file, hasher, path) {
+                    warn!("failed to store checkpoint of {}: {:#}", path.display(), cp_err);
+                }
+                return Err(err).context("failed to read response body");
+            }
+        };
+
+        file.write_all(&databuf[..n]).context(format!("failed to write {} bytes", n))?;
+
+        let hash_start = Instant::now();
+        hasher.update(&databuf[..n]);
+        hash_duration += hash_start.elapsed();
+
+        bytes += n as u64;
+        progress.update(bytes);
+
+        let checkpoint = hasher.checkpoint().bytes;
+        if checkpoint - last_checkpoint >= CHECKPOINT_INTERVAL {
+            store_checkpoint(file, hasher, path)?;
+            last_checkpoint = checkpoint;
+        }
+    }
+    file.flush().context(format!("failed to flush {:?}", path.display()))?;
+
+    Ok((bytes, hash_duration))
+}
+
+fn do_download_and_hash<U>(client: &Client, url: U, path: &Path, expected_hashes: &ExpectedHashes, expected_size: Option<ExpectedSize>, options: &DownloadOptions) -> Result<DownloadResult>
+where
+    U: reqwest::IntoUrl + Clone,
+    Url: From<U>,
+{
+    let span = info_span!("download", url = url.as_str(), path = %path.display(), bytes = tracing::field::Empty).entered();
+
+    // If the file was downloaded before, only download it again if it
+    // was modified on the server. Otherwise resume a partial download, if
+    // the state of its hashes was saved, so that the bytes on disk do not
+    // have to be hashed again.
+    let validators = CacheValidators::load(path);
+    let checkpoint = match validators {
+        Some(_) => None,
+        None => HashCheckpoint::load(path),
+    };
+    let resume_from = match &checkpoint {
+        Some(_) => fs::metadata(path).map(|md| md.len()).unwrap_or_default(),
+        None => 0,
+    };
+
+    let download_start = Instant::now();
+    let mut res = get_response(client, url.clone(), options, validators.as_ref(), resume_from)?;
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        info!("cannot resume {}, downloading it again", path.display());
+        res = get_response(client, url, options, None, 0)?;
+    }
+    let not_modified = res.status() == StatusCode::NOT_MODIFIED;
+    let new_validators = CacheValidators::from_headers(res.headers());
+
+    let (file, bytes, hashed) = if not_modified {
+        info!("{} was not modified on the server, skipping download", path.display());
+
+        let file = File::open(path).context(format!("failed to open path ({:?})", path.display()))?;
+        let bytes = file.metadata().context(format!("failed to get metadata of {:?}", path.display()))?.len();
+        (file, bytes, None)
+    } else {
+        // Validators of the previous download do not apply anymore.
+        CacheValidators::remove(path);
+
+        // Servers that do not support ranges send all of the data.
+        let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed && content_range_start(&res) != Some(resume_from) {
+            HashCheckpoint::remove(path);
+            bail!("server sent partial content not starting at byte {}", resume_from);
+        }
+        let start = if resumed {
+            resume_from
+        } else {
+            0
+        };
+
+        let content_length = res.content_length();
+        let (mut body, encoded) = decoded_body(res)?;
+        if resumed && encoded {
+            HashCheckpoint::remove(path);
+            bail!("server sent encoded partial content");
+        }
+
+        // Catch truncated or substituted objects before writing anything.
+        if let (Some(expected), false) = (expected_size, encoded) {
+            expected.check(content_length.map(|len| start + len))?;
+        }
+
+        let mut hash_duration = Duration::ZERO;
+        let (mut file, mut hasher) = match (&checkpoint, resumed) {
+            (Some(checkpoint), true) => {
+                info!("resuming {} at {} bytes", path.display(), start);
+
+                // Only the bytes after the checkpoint need to be hashed.
+                let hash_start = Instant::now();
+                let mut hasher = ResumableHasher::from_checkpoint(checkpoint);
+                hasher.update_from_file(path, start)?;
+                hash_duration += hash_start.elapsed();
+
+                let file = fs::OpenOptions::new().append(true).open(path).context(format!("failed to open path ({:?})", path.display()))?;
+                (file, hasher)
+            }
+            _ => {
+                info!("writing to {}", path.display());
+
+                let file = File::create(path).context(format!("failed to create path ({:?})", path.display()))?;
+                (file, ResumableHasher::default())
+            }
+        };
+        HashCheckpoint::remove(path);
+
+        // Content-Length of encoded data is not the size on disk.
+        let total = match (content_length, encoded) {
+            (Some(len), false) => Some(start + len),
+            _ => expected_size.map(|e| e.size.bytes() as u64),
+        };
+        let mut progress = Progress::new(options.progress, total, start);
+
+        let (bytes, copy_hash_duration) = copy_and_hash(&mut body, &mut file, &mut hasher, path, &mut progress, &options.cancel)?;
+        hash_duration += copy_hash_duration;
+
+        (file, bytes, Some((hasher.finalize(), hash_duration)))
+    };
+    span.record("bytes", bytes);
+
+    // Downloaded data was hashed while it was written, data that was not
+    // modified is hashed from disk.
+    let hash_start = Instant::now();
+    let (calculated_sha256, calculated_sha1, hash_duration) = match hashed {
+        Some(((sha256, sha1), duration)) => (sha256, sha1, duration),
+        None => (
+            hash_on_disk_cancel::<omaha::Sha256>(path, None, &options.cancel)?,
+            hash_on_disk_cancel::<omaha::Sha1>(path, None, &options.cancel)?,
+            Duration::ZERO,
+        ),
+    };
+    let calculated_sha512 = match expected_hashes.sha512 {
+        Some(_) => Some(hash_on_disk_cancel::<omaha::Sha512>(path, None, &options.cancel)?),
+        None => None,
+    };
+    let hash_duration = hash_duration + hash_start.elapsed();
+
+    let download = PhaseMetrics::new(
+        download_start.elapsed().saturating_sub(hash_duration),
+        if not_modified {
+            0
+        } else {
+            bytes
+        },
+    );
+    let hash = PhaseMetrics::new(hash_duration, bytes);
+
+    if let Err(err) = check_hashes(
+        &calculated_sha256,
+        &calculated_sha1,
+        calculated_sha512.as_ref(),
+        expected_hashes,
+    ) {
+        // Never skip downloading or resume a file with bad checksum next time.
+        CacheValidators::remove(path);
+        HashCheckpoint::remove(path);
+        if let Err(err) = fs::remove_file(path) {
+            warn!("failed to remove {} with bad checksum: {}", path.display(), err);
+        }
+        return Err(err);
+    }
+
+    if !not_modified {
+        new_validators.store(path)?;
+    }
+
+    Ok(DownloadResult {
+        hash_sha256: calculated_sha256,
+        hash_sha1: calculated_sha1,
+        hash_sha512: calculated_sha512,
+        data: file,
+        download,
+        hash,
+    })
+}
+
+// Download from the given URL into an arbitrary writer, e.g. a pipe, a
+// Vec<u8> or io::sink(), hashing the data while it is being written.
+// In contrast to download_and_hash, no retry is done, as data that was
+// already passed to the writer cannot be taken back.
+pub fn download_and_hash_into<U, W>(
+    client: &Client,
+    url: U,
+    mut writer: W,
+    expected_hashes: &ExpectedHashes,
+    expected_size: Option<ExpectedSize>,
+    options: &DownloadOptions,
+) -> Result<DownloadResult<W>>
+where
+    U: reqwest::IntoUrl + Clone,
+    Url: From<U>,
+    W: Write,
+{
+    let span = info_span!("download", url = url.as_str(), bytes = tracing::field::Empty).entered();
+
+    let download_start = Instant::now();
+    let res = get_response(client, url, options, None, 0)?;
+    let content_length = res.content_length();
+    let (mut body, encoded) = decoded_body(res)?;
+    if let (Some(expected), false) = (expected_size, encoded) {
+        expected.check(content_length)?;
+    }
+    let total = match (content_length, encoded) {
+        (Some(len), false) => Some(len),
+        _ => expected_size.map(|e| e.size.bytes() as u64),
+    };
+    let mut progress = Progress::new(options.progress, total, 0);
+
+    let mut bytes: u64 = 0;
+    let mut hash_duration = Duration::ZERO;
+
+    let mut hasher_sha256 = omaha::Sha256::hasher();
+    let mut hasher_sha1 = omaha::Sha1::hasher();
+    let mut hasher_sha512 = expected_hashes.sha512.as_ref().map(|_| omaha::Sha512::hasher());
+    let mut databuf = vec![0u8; COPY_CHUNKLEN];
+
+    loop {
+        options.cancel.check()?;
+        let n = body.read(&mut databuf).context("failed to read response body")?;
+        if n == 0 {
+            break;
+        }
+
+        writer.write_all(&databuf[..n]).context(format!("failed to write {} bytes", n))?;
+
+        let hash_start = Instant::now();
+        hasher_sha256.update(&databuf[..n]);
+        hasher_sha1.update(&databuf[..n]);
+        if let Some(hasher) = hasher_sha512.as_mut() {
+            hasher.update(&databuf[..n]);
+        }
+        hash_duration += hash_start.elapsed();
+
+        bytes += n as u64;
+        progress.update(bytes);
+    }
+    writer.flush().context("failed to flush writer")?;
+    span.record("bytes", bytes);
+
+    // Hashing is done while downloading, so do not count it twice.
+    let download = PhaseMetrics::new(download_start.elapsed().saturating_sub(hash_duration), bytes);
+    let hash = PhaseMetrics::new(hash_duration, bytes);
+
+    let calculated_sha256 = omaha::Hash::from_bytes(Box::new(hasher_sha256).finalize());
+    let calculated_sha1 = omaha::Hash::from_bytes(Box::new(hasher_sha1).finalize());
+    let calculated_sha512 = hasher_sha512.map(|hasher| omaha::Hash::from_bytes(Box::new(hasher).finalize()));
+
+    check_hashes(
+        &calculated_sha256,
+        &calculated_sha1,
+        calculated_sha512.as_ref(),
+        expected_hashes,
+    )?;
+
+    Ok(DownloadResult {
+        hash_sha256: calculated_sha256,
+        hash_sha1: calculated_sha1,
+        hash_sha512: calculated_sha512,
+        data: writer,
+        download,
+        hash,
+    })
+}
+
+// Download into path, resuming a previous partial download if possible,
+// and retry on failures.
+pub fn download_and_hash<U>(client: &Client, url: U, path: &Path, expected_hashes: &ExpectedHashes, expected_size: Option<ExpectedSize>, options: &DownloadOptions) -> Result<DownloadResult>
+where
+    U: reqwest::IntoUrl + Clone,
+    Url: From<U>,
+{
+    let policy = crate::RetryPolicy {
+        max_tries: MAX_DOWNLOAD_RETRY,
+        initial_interval: Duration::from_secs(1),
+        max_interval: Duration::from_secs(1),
+    };
+    let checksum_mismatches = std::cell::Cell::new(0);
+
+    crate::retry_with_policy(
+        &policy,
+        || do_download_and_hash(client, url.clone(), path, expected_hashes, expected_size, options),
+        |err| {
+            if options.cancel.is_cancelled() {
+                return false;
+            }
+            match err.downcast_ref::<crate::error::Error>() {
+                Some(crate::error::Error::ChecksumMismatch {
+                    ..
+                }) => (),
+                // Another try would reach the same server.
+                Some(crate::error::Error::PinnedKeyMismatch {
+                    ..
+                }) => return false,
+                _ => return true,
+            }
+
+            checksum_mismatches.set(checksum_mismatches.get() + 1);
+            checksum_mismatches.get() <= MAX_CHECKSUM_RETRY
+        },
+    )
+    .map_err(crate::RetryError::into_anyhow)
+}
+
+#[rustfmt::skip]
+fn get_pkgs_to_download<'a>(resp: &'a omaha::Response, selectors: &[PackageSelector])
+        -> Result<Vec<Package<'a>>> {
+    let mut to_download: Vec<_> = Vec::new();
+    let mut no_update = false;
+
+    for app in &resp.apps {
+        let Some(update_check) = &app.update_check else {
+            debug!("no updatecheck for app {}, skipping", app.id);
+            continue;
+        };
+
+        match &update_check.status {
+            omaha::response::UpdateCheckStatus::Ok => {}
+            omaha::response::UpdateCheckStatus::NoUpdate => {
+                info!("no update for app {}", app.id);
+                no_update = true;
+                continue;
+            }
+            status => bail!("update check of app {} failed with status {}", app.id, status),
+        }
+
+        let Some(manifest) = &update_check.manifest else {
+            continue;
+        };
+
+        for skipped in &update_check.skipped_urls {
+            warn!("skipping url of app {}: {}", app.id, skipped.error);
+        }
+        // Rather than skipping every package for lack of URLs.
+        if update_check.urls.is_empty() && !update_check.skipped_urls.is_empty() {
+            bail!("no valid url for app {}, all {} were skipped", app.id, update_check.skipped_urls.len());
+        }
+
+        for pkg in &manifest.packages {
+            if !selectors.iter().any(|s| s.is_match(&pkg.name)) {
+                info!("package `{}` doesn't match any selector, skipping", pkg.name);
+                continue;
+            }
+
+            let hash_sha256 = pkg.hash_sha256.as_ref();
+            let hash_sha1 = pkg.hash.as_ref();
+
+            // Further URLs are mirrors, tried if downloading from the first fails.
+            let mut urls = update_check.urls.iter()
+                .filter_map(|u| u.join(&pkg.name).ok());
+            let Some(url) = urls.next() else {
+                warn!("can't get url for package `{}`, skipping", pkg.name);
+                continue;
+            };
+
+            if hash_sha256.is_none() && hash_sha1.is_none() {
+                warn!("package `{}` doesn't have a valid SHA256 or SHA1 hash, skipping", pkg.name);
+                continue;
+            }
+
+            to_download.push(Package {
+                url,
+                mirrors: urls.collect(),
+                name: Cow::Borrowed(&pkg.name),
+                hash_sha256: hash_sha256.cloned(),
+                hash_sha1: hash_sha1.cloned(),
+                hash_sha512: None,
+                size: pkg.size,
+                required: pkg.required,
+                status: PackageStatus::ToDownload,
+                metrics: PackageMetrics::default(),
+                payload_info: None,
+            });
+        }
+    }
+
+    // Only report that there is no update if there is nothing else to do.
+    if to_download.is_empty() && no_update {
+        return Err(crate::error::Error::NoUpdate.into());
+    }
+
+    Ok(to_download)
+}
+
+// Fetch the Omaha response document from url, see Source::XmlUrl.
+fn fetch_response_xml(client: &Client, url: &Url, options: &DownloadOptions) -> Result<String> {
+    let res = get_response(client, url.clone(), options, None, 0)?;
+    let (mut body, _) = decoded_body(res)?;
+
+    let mut xml = String::new();
+    body.read_to_string(&mut xml).context(format!("failed to read response from {}", url))?;
+    Ok(xml)
+}
+
+// Read data from remote URL into File
+fn fetch_url_to_file<'a, U>(path: &'a Path, input_url: U, client: &'a Client, options: &DownloadOptions) -> Result<Package<'a>>
+where
+    U: reqwest::IntoUrl + From<U> + std::clone::Clone + std::fmt::Debug,
+    Url: From<U>,
+{
+    let r = download_and_hash(client, input_url.clone(), path, &ExpectedHashes::default(), None, options).context(format!("unable to download data(url {:?})", input_url))?;
+
+    Ok(Package {
+        metrics: PackageMetrics {
+            download: r.download,
+            hash: r.hash,
+            ..Default::default()
+        },
+        name: Cow::Borrowed(path.file_name().unwrap_or(OsStr::new("fakepackage")).to_str().unwrap_or("fakepackage")),
+        hash_sha256: Some(r.hash_sha256),
+        hash_sha1: Some(r.hash_sha1),
+        hash_sha512: r.hash_sha512,
+        size: FileSize::from_bytes(r.data.metadata().context(format!("failed to get metadata, path ({:?})", path.display()))?.len() as usize),
+        url: input_url.into(),
+        mirrors: Vec::new(),
+        required: true,
+        status: PackageStatus::Unverified,
+        payload_info: None,
+    })
+}
+
+// Last path segment of a payload URL, which names the downloaded file.
+fn url_filename(u: &Url) -> Option<&str> {
+    u.path_segments()?.next_back().filter(|fname| !fname.is_empty())
+}
+
+// Payload on disk whose signature was verified, see verify_payload.
+#[derive(Debug)]
+pub struct VerifiedPayload {
+    pub path: PathBuf,
+    pub size: FileSize,
+    pub payload_info: PayloadInfo,
+    pub metrics: PackageMetrics,
+}
+
+// Verify the signature of a payload that already exists on disk, without
+// extracting anything or any network access.
+pub fn verify_payload(path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy) -> Result<VerifiedPayload> {
+    let mut pkg = Package::from_file(path)?;
+    pkg.verify_signature(path, pubkeys, policy).context(format!("unable to verify signature \"{}\"", pkg.name))?;
+
+    Ok(VerifiedPayload {
+        path: path.to_path_buf(),
+        size: pkg.size,
+        payload_info: pkg.payload_info.ok_or(anyhow!("no payload info of {:?}", path.display()))?,
+        metrics: pkg.metrics,
+    })
+}
+
+// Verified payload is stored in e.g. "output_dir/oem.raw",
+// unless an explicit output filename is given.
+fn verified_output_path(output_dir: &Path, pkg_name: &str, output_filename: Option<&str>) -> PathBuf {
+    match output_filename {
+        Some(filename) => output_dir.join(filename),
+        None => output_dir.join(Path::new(pkg_name).with_extension("raw").file_name().unwrap_or_default()),
+    }
+}
+
+// Kernel of a payload is stored next to its verified data, e.g.
+// "output_dir/oem.kernel".
+fn kernel_output_path(output_dir: &Path, pkg_name: &str, output_filename: Option<&str>) -> PathBuf {
+    verified_output_path(output_dir, pkg_name, output_filename).with_extension("kernel")
+}
+
+// Outcome of a single package processed by DownloadVerify::run.
+#[derive(Debug)]
+pub struct PackageReport {
+    pub name: String,
+    pub url: Url,
+    pub size: FileSize,
+    pub hash_sha256: Option<omaha::Hash<omaha::Sha256>>,
+    pub hash_sha1: Option<omaha::Hash<omaha::Sha1>>,
+    pub status: PackageStatus,
+    pub output_path: PathBuf,
+    pub output_size: FileSize,
+    pub metrics: PackageMetrics,
+    pub payload_info: Option<PayloadInfo>,
+    // Extracted kernel, with DownloadVerify::extract_kernel.
+    pub kernel_output_path: Option<PathBuf>,
+}
+
+impl PackageReport {
+    fn new(pkg: &Package<'_>, output_path: PathBuf) -> Result<Self> {
+        let md = fs::metadata(&output_path).context(format!("failed to get metadata, path ({:?})", output_path.display()))?;
+
+        Ok(PackageReport {
+            name: pkg.name.to_string(),
+            url: pkg.url.clone(),
+            size: pkg.size,
+            hash_sha256: pkg.hash_sha256.clone(),
+            hash_sha1: pkg.hash_sha1.clone(),
+            status: pkg.status.clone(),
+            output_path,
+            output_size: FileSize::from_bytes(md.len() as usize),
+            metrics: pkg.metrics,
+            payload_info: pkg.payload_info.clone(),
+            kernel_output_path: None,
+        })
+    }
+}
+
+// Outcome of DownloadVerify::run, e.g. to tell which packages were written where.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    // Packages that were verified and written, empty if none matched.
+    pub packages: Vec<PackageReport>,
+    pub duration: Duration,
+}
+
+impl RunReport {
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    // Bytes downloaded over the network for all packages.
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.packages.iter().map(|p| p.metrics.download.bytes).sum()
+    }
+}
+
+// Package that would be processed by DownloadVerify::run, see DownloadVerify::dry_run.
+#[derive(Debug)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub url: Url,
+    pub output_path: PathBuf,
+}
+
+impl PlannedPackage {
+    fn new(pkg: &Package<'_>, output_dir: &Path, output_filename: Option<&str>) -> Self {
+        PlannedPackage {
+            name: pkg.name.to_string(),
+            url: pkg.url.clone(),
+            output_path: verified_output_path(output_dir, &pkg.name, output_filename),
+        }
+    }
+}
+
+// What DownloadVerify::run does with the payloads downloaded into
+// output_dir/.unverified. Temporary files in output_dir/.tmp are always removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CleanupPolicy {
+    // Keep them, e.g. to resume or skip downloads in the next run.
+    #[default]
+    Keep,
+    // Remove them once all packages were verified, but keep them to resume
+    // downloads after a failure.
+    DeleteOnSuccess,
+    AlwaysDelete,
+}
+
+impl FromStr for CleanupPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "keep" => CleanupPolicy::Keep,
+            "delete-on-success" => CleanupPolicy::DeleteOnSuccess,
+            "always-delete" => CleanupPolicy::AlwaysDelete,
+            _ => bail!("unknown cleanup policy {:?}", s),
+        })
+    }
+}
+
+// HTTP version of payload downloads. The TLS backend does not negotiate
+// HTTP/2 through ALPN, so it is only used with prior knowledge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    // HTTP/1.1, as long as HTTP/2 can not be negotiated.
+    #[default]
+    Auto,
+    Http1Only,
+    // Speak HTTP/2 right away, which multiplexes the downloads of several
+    // packages over a single connection. Fails if the server does not
+    // support it.
+    Http2PriorKnowledge,
+}
+
+impl FromStr for HttpVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => HttpVersion::Auto,
+            "http1" => HttpVersion::Http1Only,
+            "http2" => HttpVersion::Http2PriorKnowledge,
+            _ => bail!("unknown HTTP version {:?}", s),
+        })
+    }
+}
+
+// Constructed and validated by DownloadVerifyBuilder. Clones share their
+// hooks and limits, e.g. to run on another thread.
+#[derive(Clone)]
+pub struct DownloadVerify {
+    output_dir: String,
+    target_filename: Option<String>,
+    name_map: Vec<(GlobMatcher, String)>,
+    source: Source,
+    pubkeys: Vec<PubkeySource>,
+    signature_policy: SignaturePolicy,
+    extract_only: bool,
+    take_first_match: bool,
+    selectors: Vec<PackageSelector>,
+    order: DownloadOrder,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    size_tolerance: u64,
+    ca_certificates: Vec<reqwest::Certificate>,
+    pinned_keys: Vec<omaha::Hash<omaha::Sha256>>,
+    preflight: bool,
+    content_encoding: ContentEncoding,
+    http_version: HttpVersion,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+    progress: ProgressConfig,
+    headers: HeaderMap,
+    netrc: Option<Arc<Netrc>>,
+    client: Option<Client>,
+    target_device: Option<String>,
+    write_options: WriteOptions,
+    extract_kernel: bool,
+    cleanup_policy: CleanupPolicy,
+    skip_existing: bool,
+    rehash_existing: bool,
+    max_concurrent_downloads: usize,
+    verifications: Arc<Semaphore>,
+    hooks: Arc<Hooks>,
+    // Set when the deadline of a run is exceeded, see run.
+    cancel: CancelFlag,
+}
+
+impl DownloadVerify {
+    fn download_options(&self) -> DownloadOptions {
+        DownloadOptions {
+            timeout: self.timeout,
+            progress: self.progress,
+            netrc: self.netrc.clone(),
+            cancel: self.cancel.clone(),
+            pinned_keys: self.pinned_keys.clone(),
+        }
+    }
+
+    fn download_verify(&self, pkg: &mut Package<'_>, output_dir: &Path, unverified_dir: &Path, client: &Client) -> Result<PathBuf> {
+        self.cancel.check()?;
+        self.track_status(pkg, |pkg| pkg.check_download(unverified_dir, &self.cancel))?;
+
+        self.track_status(pkg, |pkg| {
+            pkg.download(
+                unverified_dir,
+                client,
+                self.size_tolerance,
+                self.preflight,
+                &self.download_options(),
+            )
+        })
+        .context(format!("unable to download \"{:?}\"", pkg.name))?;
+        self.hooks.download_complete(pkg)?;
+
+        // Unverified payload is stored in e.g. "output_dir/.unverified/oem.gz".
+        let pkg_unverified = unverified_dir.join(&*pkg.name);
+
+        self.verify_and_extract(pkg, &pkg_unverified, output_dir)
+    }
+
+    // Run a step of processing pkg, and pass its status on if the step
+    // changed it.
+    fn track_status<'a, T>(&self, pkg: &mut Package<'a>, step: impl FnOnce(&mut Package<'a>) -> T) -> T {
+        let before = pkg.status.clone();
+        let res = step(pkg);
+        self.hooks.status_changed(pkg, Some(&before));
+        res
+    }
+
+    // Process a single package, and call the hooks around it.
+    fn with_hooks<'a>(&self, pkg: &mut Package<'a>, process: impl FnOnce(&mut Package<'a>) -> Result<PathBuf>) -> Result<PathBuf> {
+        let res = self.hooks.package_selected(pkg).and_then(|_| {
+            self.hooks.status_changed(pkg, None);
+            process(pkg)
+        });
+        let res = res.and_then(|output_path| {
+            self.hooks.verified(pkg, &output_path)?;
+            Ok(output_path)
+        });
+
+        if let Err(err) = &res {
+            self.hooks.failed(pkg, err);
+        }
+
+        res
+    }
+
+    // Write the partitions of the payload in pkg_unverified into targets,
+    // after verifying its signature unless extract_only is set.
+    fn extract(&self, pkg: &mut Package<'_>, pkg_unverified: &Path, targets: &[(String, PathBuf)], options: WriteOptions) -> Result<()> {
+        if self.extract_only {
+            return self.track_status(pkg, |pkg| pkg.extract_partitions_unverified(pkg_unverified, targets, options)).context(format!("unable to extract \"{}\"", pkg.name));
+        }
+
+        self.track_status(pkg, |pkg| {
+            pkg.verify_and_extract_partitions(pkg_unverified, &self.pubkeys, &self.signature_policy, targets, options)
+        })
+        .context(format!("unable to verify signature \"{}\"", pkg.name))
+    }
+
+    // Return path of the extracted data in output_dir, or the target device.
+    // With extract_kernel, the kernel is written next to the data in output_dir,
+    // see kernel_output_path.
+    fn verify_and_extract(&self, pkg: &mut Package<'_>, pkg_unverified: &Path, output_dir: &Path) -> Result<PathBuf> {
+        let _permit = self.verifications.acquire();
+        self.cancel.check()?;
+        let kernel_path = kernel_output_path(output_dir, &pkg.name, self.output_filename(&pkg.name));
+
+        if let Some(device) = &self.target_device {
+            let mut targets = vec![(delta_update::PARTITION_USR.to_string(), PathBuf::from(device))];
+            if self.extract_kernel {
+                targets.push((delta_update::PARTITION_KERNEL.to_string(), kernel_path));
+            }
+
+            let options = WriteOptions {
+                cancel: self.cancel.clone(),
+                ..self.write_options.clone()
+            };
+            self.extract(pkg, pkg_unverified, &targets, options)?;
+            return Ok(PathBuf::from(device));
+        }
+
+        let pkg_verified = verified_output_path(output_dir, &pkg.name, self.output_filename(&pkg.name));
+
+        let mut partitions = vec![delta_update::PARTITION_USR];
+        if self.extract_kernel {
+            partitions.push(delta_update::PARTITION_KERNEL);
+        }
+        // Packages can be verified concurrently, so each one needs its own
+        // temporary directory.
+        let temp_dir = output_dir.join(".tmp").join(&*pkg.name);
+        fs::create_dir_all(&temp_dir)?;
+        VerifiedStamp::remove(&pkg_verified);
+
+        let extracted = package::partition_targets(&temp_dir, &partitions);
+        let options = WriteOptions {
+            cancel: self.cancel.clone(),
+            ..Default::default()
+        };
+        self.extract(pkg, pkg_unverified, &extracted, options)?;
+
+        // write extracted data into the final data.
+        for (name, datablobspath) in extracted {
+            self.cancel.check()?;
+            let dest = match name.as_str() {
+                delta_update::PARTITION_USR => &pkg_verified,
+                _ => &kernel_path,
+            };
+            debug!("data blobs of {} written into file {:?}", name, dest);
+            fs::rename(datablobspath, dest)?;
+        }
+
+        // Only packages with known hashes can be skipped in a later run, and
+        // never ones whose signature was not verified.
+        let stamp = VerifiedStamp::from_package(pkg);
+        if !self.extract_only && (stamp.payload_sha256.is_some() || stamp.payload_sha1.is_some()) {
+            self.cancel.check()?;
+            stamp.store(&pkg_verified)?;
+        }
+
+        Ok(pkg_verified)
+    }
+
+    // Return the output path of a package if an earlier run already wrote
+    // the verified data of the same payload there, see skip_existing.
+    fn existing_output(&self, pkg: &Package<'_>, output_dir: &Path) -> Option<PathBuf> {
+        if !self.skip_existing || self.target_device.is_some() {
+            return None;
+        }
+
+        let output_path = verified_output_path(output_dir, &pkg.name, self.output_filename(&pkg.name));
+        let stamp = VerifiedStamp::load(&output_path).filter(|stamp| stamp.matches(pkg))?;
+
+        if self.extract_kernel && !kernel_output_path(output_dir, &pkg.name, self.output_filename(&pkg.name)).exists() {
+            return None;
+        }
+
+        if self.rehash_existing {
+            let data_sha256 = stamp.data_sha256.as_deref()?;
+            match hash_on_disk_cancel::<omaha::Sha256>(&output_path, None, &self.cancel) {
+                Ok(hash) if hash.to_string() == data_sha256 => {}
+                Ok(_) => {
+                    warn!(
+                        "{} does not match the hash of its payload, processing it again",
+                        output_path.display()
+                    );
+                    return None;
+                }
+                Err(err) => {
+                    warn!("failed to hash {}, processing it again: {:#}", output_path.display(), err);
+                    return None;
+                }
+            }
+        }
+
+        Some(output_path)
+    }
+
+    // Name of the verified file of a package in output_dir, unless it is
+    // derived from the package name.
+    fn output_filename(&self, pkg_name: &str) -> Option<&str> {
+        match self.name_map.iter().find(|(glob, _)| glob.is_match(pkg_name)) {
+            Some((_, name)) => Some(name),
+            None => self.target_filename.as_deref(),
+        }
+    }
+
+    // Fail before downloading anything if name_map would write several
+    // packages into the same file.
+    fn check_output_paths(&self, pkgs: &[Package<'_>]) -> Result<()> {
+        if self.target_device.is_some() {
+            return Ok(());
+        }
+
+        let output_dir = Path::new(&*self.output_dir);
+        let mut paths = HashSet::new();
+        for pkg in pkgs {
+            let path = verified_output_path(output_dir, &pkg.name, self.output_filename(&pkg.name));
+            if !paths.insert(path.clone()) {
+                bail!("several packages would be written into {:?}", path.display());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Packages of resp to process, in the order to process them in.
+    fn selected_packages<'a>(&self, resp: &'a omaha::Response) -> Result<Vec<Package<'a>>> {
+        let mut pkgs = get_pkgs_to_download(resp, &self.selectors)?;
+        if self.take_first_match {
+            pkgs.truncate(1);
+        }
+        self.order.sort(&mut pkgs);
+        self.check_output_paths(&pkgs)?;
+
+        Ok(pkgs)
+    }
+
+    fn report(&self, pkg: &Package<'_>, output_path: PathBuf) -> Result<PackageReport> {
+        let mut report = PackageReport::new(pkg, output_path)?;
+        if self.extract_kernel {
+            report.kernel_output_path = Some(kernel_output_path(
+                Path::new(&*self.output_dir),
+                &pkg.name,
+                self.output_filename(&pkg.name),
+            ));
+        }
+
+        Ok(report)
+    }
+
+    // Verify the existing payload in the given path, and extract it into output_dir.
+    fn verify_local_payload(&self, path: &Path, output_dir: &Path) -> Result<Vec<PackageReport>> {
+        let mut pkg_local = Package::from_file(path)?;
+
+        let output_path = self.with_hooks(&mut pkg_local, |pkg| self.verify_and_extract(pkg, path, output_dir))?;
+
+        Ok(vec![self.report(&pkg_local, output_path)?])
+    }
+
+    // Return the packages that run() would download and verify, and where
+    // they would be written, without downloading anything or changes to the
+    // filesystem. The only network access is fetching the response of
+    // Source::XmlUrl, which is needed to know its packages.
+    pub fn dry_run(&self) -> Result<Vec<PlannedPackage>> {
+        let mut planned = self.planned_packages()?;
+
+        if let Some(device) = &self.target_device {
+            for p in planned.iter_mut() {
+                p.output_path = PathBuf::from(device);
+            }
+        }
+
+        Ok(planned)
+    }
+
+    fn planned_packages(&self) -> Result<Vec<PlannedPackage>> {
+        let output_dir = Path::new(&*self.output_dir);
+
+        match &self.source {
+            Source::LocalFile(path) => {
+                let pkg_local = Package::from_file(path)?;
+                return Ok(vec![PlannedPackage::new(
+                    &pkg_local,
+                    output_dir,
+                    self.output_filename(&pkg_local.name),
+                )]);
+            }
+            Source::PayloadUrl(u) => {
+                let fname = url_filename(u).ok_or(anyhow!("failed to get file name, url ({:?})", u))?;
+
+                return Ok(vec![PlannedPackage {
+                    name: fname.to_string(),
+                    output_path: verified_output_path(output_dir, fname, self.output_filename(fname)),
+                    url: u.clone(),
+                }]);
+            }
+            Source::Xml(_) | Source::XmlUrl(_) | Source::Response(_) => (),
+        }
+
+        let resp = self.response()?;
+        let pkgs = self.selected_packages(&resp)?;
+
+        let planned = pkgs.iter().map(|pkg| PlannedPackage::new(pkg, output_dir, self.output_filename(&pkg.name))).collect();
+
+        Ok(planned)
+    }
+
+    // Return the Omaha response of the source, fetching it first for
+    // Source::XmlUrl, e.g. to list all of its packages.
+    pub fn response(&self) -> Result<Arc<omaha::Response<'static>>> {
+        match &self.source {
+            Source::Xml(input_xml) => {
+                debug!("response_text: {:?}", input_xml);
+
+                Ok(Arc::new(omaha::Response::from_str(input_xml)?.into_owned()))
+            }
+            Source::XmlUrl(u) => {
+                info!("fetching Omaha response from {}", u);
+                let fetched = fetch_response_xml(&self.response_client()?, u, &self.download_options())?;
+                debug!("response_text: {:?}", fetched);
+
+                Ok(Arc::new(omaha::Response::from_str(&fetched)?.into_owned()))
+            }
+            Source::Response(resp) => Ok(resp.clone()),
+            Source::PayloadUrl(_) | Source::LocalFile(_) => bail!("a single payload has no Omaha response"),
+        }
+    }
+
+    pub fn run(&self) -> Result<RunReport> {
+        let start = Instant::now();
+        let res = match self.deadline {
+            // The run is cancelled and waited for once the deadline is
+            // exceeded, before its files are cleaned up.
+            Some(deadline) => {
+                let mut download_verify = self.clone();
+                download_verify.cancel = CancelFlag::default();
+                let cancel = download_verify.cancel.clone();
+                with_deadline_cancel(move || download_verify.run_packages(), deadline, cancel)
+            }
+            None => self.run_packages(),
+        };
+
+        // An error of the run is more relevant than one of the cleanup.
+        match (self.cleanup(res.is_ok()), &res) {
+            (Err(err), Ok(_)) => return Err(err),
+            (Err(err), Err(_)) => warn!("{:#}", err),
+            (Ok(()), _) => {}
+        }
+
+        Ok(RunReport {
+            packages: res?,
+            duration: start.elapsed(),
+        })
+    }
+
+    // Remove temporary files, and the unverified payloads as given by
+    // cleanup_policy.
+    fn cleanup(&self, success: bool) -> Result<()> {
+        let output_dir = Path::new(&*self.output_dir);
+
+        let mut dirs = vec![output_dir.join(".tmp")];
+        match self.cleanup_policy {
+            CleanupPolicy::Keep => {}
+            CleanupPolicy::DeleteOnSuccess if !success => {}
+            CleanupPolicy::DeleteOnSuccess | CleanupPolicy::AlwaysDelete => dirs.push(output_dir.join(".unverified")),
+        }
+
+        for dir in dirs {
+            match fs::remove_dir_all(&dir) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                res => res.context(format!("failed to clean up {:?}", dir.display()))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Client for all payload downloads and their retries in a run, which
+    // keeps connections alive in between.
+    fn client(&self) -> Result<Client> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+
+        // The default policy of reqwest Client supports max 10 attempts on HTTP redirect.
+        let mut builder = Client::builder().tcp_keepalive(self.connect_timeout).connect_timeout(self.connect_timeout).timeout(self.read_timeout).redirect(Policy::default());
+
+        // CA certificates replace the system trust store, and would be
+        // pointless for plain HTTP, also after a redirect.
+        if !self.ca_certificates.is_empty() {
+            builder = builder.tls_built_in_root_certs(false).https_only(true);
+            for cert in &self.ca_certificates {
+                builder = builder.add_root_certificate(cert.clone());
+            }
+        }
+        // Pinned keys are checked against the certificate of each response.
+        if !self.pinned_keys.is_empty() {
+            builder = builder.tls_info(true).https_only(true);
+        }
+
+        // Static headers likely carry credentials, keep them out of logs.
+        let mut headers = self.headers.clone();
+        headers.values_mut().for_each(|value| value.set_sensitive(true));
+        headers.entry(ACCEPT_ENCODING).or_insert_with(|| self.content_encoding.accept_encoding());
+        builder = builder.default_headers(headers);
+        builder = match self.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1Only => builder.http1_only(),
+            HttpVersion::Http2PriorKnowledge => builder.http2_prior_knowledge(),
+        };
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    // Client for fetching the response of Source::XmlUrl from the Omaha
+    // server, with the timeouts of the downloads, but none of their headers
+    // or CA certificates.
+    fn response_client(&self) -> Result<Client> {
+        let builder = Client::builder().tcp_keepalive(self.connect_timeout).connect_timeout(self.connect_timeout).timeout(self.read_timeout).redirect(Policy::default());
+
+        Ok(builder.build()?)
+    }
+
+    // Return a report for each package that was verified and written into output_dir.
+    fn run_packages(&self) -> Result<Vec<PackageReport>> {
+        let output_dir = Path::new(&*self.output_dir);
+        let unverified_dir = output_dir.join(".unverified");
+        let temp_dir = output_dir.join(".tmp");
+        fs::create_dir_all(&unverified_dir)?;
+        fs::create_dir_all(&temp_dir)?;
+
+        // A local payload does not need to be downloaded, verify it in place
+        // and skip all network access.
+        if let Source::LocalFile(path) = &self.source {
+            return self.verify_local_payload(path, output_dir);
+        }
+
+        let client = self.client()?;
+
+        let resp = match &self.source {
+            Source::Xml(_) | Source::XmlUrl(_) | Source::Response(_) => self.response()?,
+            Source::PayloadUrl(u) => {
+                let fname = url_filename(u).ok_or(anyhow!("failed to get file name, url ({:?})", u))?;
+
+                let temp_payload_path = unverified_dir.join(fname);
+                let mut pkg_fake = fetch_url_to_file(&temp_payload_path, u.clone(), &client, &self.download_options())?;
+                let output_path = self.with_hooks(&mut pkg_fake, |pkg| {
+                    self.download_verify(pkg, output_dir, unverified_dir.as_path(), &client)
+                })?;
+
+                // verify only a fake package, early exit and skip the rest.
+                return Ok(vec![self.report(&pkg_fake, output_path)?]);
+            }
+            Source::LocalFile(_) => unreachable!(),
+        };
+
+        let mut pkgs_to_dl = self.selected_packages(&resp)?;
+
+        if self.target_device.is_some() && pkgs_to_dl.len() > 1 {
+            bail!(
+                "{} packages match, but only one can be written to the target device",
+                pkgs_to_dl.len()
+            );
+        }
+
+        debug!("pkgs:\n\t{:#?}", pkgs_to_dl);
+        debug!("");
+
+        ////
+        // download
+        ////
+
+        try_map_concurrent(pkgs_to_dl.iter_mut().collect(), self.max_concurrent_downloads, |pkg| {
+            let _span = info_span!("package", name = %pkg.name, size = pkg.size.bytes()).entered();
+
+            let output_path = self.with_hooks(pkg, |pkg| match self.existing_output(pkg, output_dir) {
+                Some(output_path) => {
+                    info!("{} is already verified in {}, skipping", pkg.name, output_path.display());
+                    self.track_status(pkg, |pkg| pkg.status = PackageStatus::Verified);
+                    Ok(output_path)
+                }
+                None => self.download_verify(pkg, output_dir, unverified_dir.as_path(), &client),
+            })?;
+            self.report(pkg, output_path)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_expected_size() {
+        let expected = ExpectedSize::new(FileSize::from_bytes(1000), 0);
+        assert!(expected.check(Some(1000)).is_ok());
+        assert!(expected.check(None).is_ok());
+
+        let err = expected.check(Some(999)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(&crate::error::Error::SizeMismatch {
+                expected: 1000,
+                content_length: 999,
+            })
+        );
+
+        let expected = ExpectedSize::new(FileSize::from_bytes(1000), 10);
+        assert!(expected.check(Some(1010)).is_ok());
+        assert!(expected.check(Some(1011)).is_err());
+    }
+
+    #[test]
+    fn test_hash_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        fs::write(&path, b"hello world").unwrap();
+
+        let full = hash_on_disk::<omaha::Sha256>(&path, None).unwrap();
+        assert_eq!(
+            full,
+            omaha::Hash::from_bytes(Box::new(Sha256::new().chain_update(b"hello world")).finalize())
+        );
+
+        let prefix = hash_on_disk::<omaha::Sha256>(&path, Some(5)).unwrap();
+        assert_eq!(
+            prefix,
+            omaha::Hash::from_bytes(Box::new(Sha256::new().chain_update(b"hello")).finalize())
+        );
+        assert_eq!(hash_on_disk::<omaha::Sha256>(&path, Some(100)).unwrap(), full);
+
+        let from_reader = hash_from_reader::<omaha::Sha256, _>(&b"hello world"[..], Some(5)).unwrap();
+        assert_eq!(from_reader, prefix);
+        assert_eq!(hash_from_reader::<omaha::Sha256, _>(&b"hello world"[..], None).unwrap(), full);
+
+        let empty = dir.path().join("empty");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(
+            hash_on_disk::<omaha::Sha256>(&empty, None).unwrap(),
+            omaha::Hash::from_bytes(Box::new(Sha256::new()).finalize())
+        );
+    }
+
+    #[test]
+    fn test_check_hashes_sha512() {
+        let sha256 = hash_from_reader::<omaha::Sha256, _>(&b"data"[..], None).unwrap();
+        let sha1 = hash_from_reader::<omaha::Sha1, _>(&b"data"[..], None).unwrap();
+        let sha512 = hash_from_reader::<omaha::Sha512, _>(&b"data"[..], None).unwrap();
+        let other = hash_from_reader::<omaha::Sha512, _>(&b"other"[..], None).unwrap();
+
+        let expected = ExpectedHashes {
+            sha512: Some(sha512.clone()),
+            ..Default::default()
+        };
+        assert!(check_hashes(&sha256, &sha1, Some(&sha512), &expected).is_ok());
+        let err = check_hashes(&sha256, &sha1, Some(&other), &expected).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(&crate::error::Error::ChecksumMismatch {
+                algo: "sha512"
+            })
+        );
+        assert!(check_hashes(&sha256, &sha1, None, &expected).is_err());
+        assert!(check_hashes(&sha256, &sha1, None, &ExpectedHashes::default()).is_ok());
+    }
+
+    #[test]
+    fn test_get_pkgs_to_download_noupdate() {
+        const NOUPDATE_XML: &str = r#"<response protocol="3.0" server="nebraska">
+  <app appid="e96281a6-d1af-4bde-9a0a-97b76e56dc57" status="ok">
+    <updatecheck status="noupdate"></updatecheck>
+  </app>
+</response>"#;
+
+        let resp = omaha::Response::from_str(NOUPDATE_XML).unwrap();
+        let err = get_pkgs_to_download(&resp, &[]).unwrap_err();
+        assert_eq!(err.downcast_ref::<crate::error::Error>(), Some(&crate::error::Error::NoUpdate));
+    }
+
+    #[test]
+    fn test_get_pkgs_to_download_skipped_urls() {
+        let xml = fs::read_to_string("src/testdata/omaha-response-example.xml").unwrap();
+        let selectors = [PackageSelector::Name("oem-azure.gz".to_string())];
+
+        let typo = xml.replacen(r#"codebase="https://"#, r#"codebase="htps//"#, 1);
+        let resp = omaha::Response::from_str(&typo).unwrap();
+        let update_check = resp.apps[0].update_check.as_ref().unwrap();
+        assert_eq!(update_check.skipped_urls.len(), 1);
+        assert!(update_check.skipped_urls[0].error.starts_with(r#"invalid codebase "htps//"#));
+        assert!(omaha::Response::from_str_strict(&xml).is_ok());
+        assert!(omaha::Response::from_str_strict(&typo).is_err());
+
+        assert!(update_check.urls.is_empty());
+        let err = get_pkgs_to_download(&resp, &selectors).unwrap_err();
+        assert!(err.to_string().contains("all 1 were skipped"), "{:#}", err);
+
+        let resp = omaha::Response::from_str(&xml).unwrap();
+        assert_eq!(get_pkgs_to_download(&resp, &selectors).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dry_run_response() {
+        let xml = fs::read_to_string("src/testdata/omaha-response-example.xml").unwrap();
+        let resp = omaha::Response::from_str(&xml).unwrap().into_owned();
+        let selector = PackageSelector::Glob(globset::GlobSetBuilder::new().add(globset::Glob::new("oem-*.gz").unwrap()).build().unwrap());
+
+        let download_verify = DownloadVerifyBuilder::new("/tmp".to_string(), vec![PubkeySource::Path("key.pem".to_string())])
+            .source(Source::Response(std::sync::Arc::new(resp)))
+            .package_selectors(vec![selector])
+            .build()
+            .unwrap();
+
+        let planned = download_verify.dry_run().unwrap();
+        let names: Vec<_> = planned.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["oem-azure.gz", "oem-qemu.gz"]);
+        assert_eq!(planned[0].output_path, PathBuf::from("/tmp/oem-azure.raw"));
+    }
+
+    #[test]
+    fn test_hooks() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let dir = tempfile::tempdir().unwrap();
+        let payload = dir.path().join("oem-azure.gz");
+        fs::write(&payload, b"not a payload").unwrap();
+
+        let selected = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let (s, f) = (selected.clone(), failed.clone());
+
+        let download_verify = DownloadVerifyBuilder::new(
+            dir.path().to_str().unwrap().to_string(),
+            vec![PubkeySource::Path("key.pem".to_string())],
+        )
+        .source(Source::LocalFile(payload))
+        .on_package_selected(move |pkg| {
+            assert_eq!(pkg.name, "oem-azure.gz");
+            s.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .on_verified(|_, _| panic!("invalid payload verified"))
+        .on_failed(move |_, _| {
+            f.fetch_add(1, Ordering::SeqCst);
+        })
+        .build()
+        .unwrap();
+
+        assert!(download_verify.run().is_err());
+        assert_eq!(selected.load(Ordering::SeqCst), 1);
+        assert_eq!(failed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cleanup_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = dir.path().join("oem-azure.gz");
+        fs::write(&payload, b"not a payload").unwrap();
+
+        let run = |policy| {
+            DownloadVerifyBuilder::new(
+                dir.path().to_str().unwrap().to_string(),
+                vec![PubkeySource::Path("key.pem".to_string())],
+            )
+            .source(Source::LocalFile(payload.clone()))
+            .cleanup_policy(policy)
+            .build()
+            .unwrap()
+            .run()
+        };
+
+        assert!(run(CleanupPolicy::DeleteOnSuccess).is_err());
+        assert!(dir.path().join(".unverified").exists());
+        assert!(!dir.path().join(".tmp").exists());
+
+        assert!(run(CleanupPolicy::AlwaysDelete).is_err());
+        assert!(!dir.path().join(".unverified").exists());
+    }
+
+    #[test]
+    fn test_name_map() {
+        let xml = fs::read_to_string("src/testdata/omaha-response-example.xml").unwrap();
+        let selector = PackageSelector::Glob(globset::GlobSetBuilder::new().add(globset::Glob::new("oem-*.gz").unwrap()).build().unwrap());
+        let builder = || DownloadVerifyBuilder::new("/tmp".to_string(), vec![PubkeySource::Path("key.pem".to_string())]).source(Source::Xml(xml.clone())).package_selectors(vec![selector.clone()]);
+
+        let name_map = vec![(globset::Glob::new("oem-azure.*").unwrap(), "azure.raw".to_string()), (globset::Glob::new("oem-*").unwrap(), "other.raw".to_string())];
+        let planned = builder().name_map(name_map).build().unwrap().dry_run().unwrap();
+        let paths: Vec<_> = planned.iter().map(|p| p.output_path.clone()).collect();
+        assert_eq!(paths, [PathBuf::from("/tmp/azure.raw"), PathBuf::from("/tmp/other.raw")]);
+
+        let name_map = vec![(globset::Glob::new("oem-*").unwrap(), "oem.raw".to_string())];
+        assert!(builder().name_map(name_map).build().unwrap().dry_run().is_err());
+    }
+
+    #[test]
+    fn test_skip_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("oem-azure.raw");
+        fs::write(&output_path, b"verified data").unwrap();
+
+        let xml = fs::read_to_string("src/testdata/omaha-response-example.xml").unwrap();
+        let download_verify = DownloadVerifyBuilder::new(
+            dir.path().to_str().unwrap().to_string(),
+            vec![PubkeySource::Path("key.pem".to_string())],
+        )
+        .source(Source::Xml(xml.clone()))
+        .package_selectors(vec![PackageSelector::Name("oem-azure.gz".to_string())])
+        .skip_existing(true)
+        .rehash_existing(true)
+        .build()
+        .unwrap();
+
+        let resp = omaha::Response::from_str(&xml).unwrap();
+        let pkgs = get_pkgs_to_download(&resp, &[PackageSelector::Name("oem-azure.gz".to_string())]).unwrap();
+        assert_eq!(download_verify.existing_output(&pkgs[0], dir.path()), None);
+
+        let mut stamp = VerifiedStamp::from_package(&pkgs[0]);
+        stamp.data_sha256 = Some(hash_from_reader::<omaha::Sha256, _>(&b"verified data"[..], None).unwrap().to_string());
+        stamp.store(&output_path).unwrap();
+
+        let report = download_verify.run().unwrap();
+        assert_eq!(report.packages.len(), 1);
+        assert_eq!(report.packages[0].output_path, output_path);
+        assert!(matches!(report.packages[0].status, PackageStatus::Verified));
+        assert_eq!(report.bytes_downloaded(), 0);
+
+        fs::write(&output_path, b"corrupted data").unwrap();
+        assert_eq!(download_verify.existing_output(&pkgs[0], dir.path()), None);
+    }
+
+    #[test]
+    fn test_verify_payload() {
+        use update_format_crau::delta_update::writer::PayloadWriter;
+        use update_format_crau::verify_sig;
+
+        let image = vec![1u8; 8192];
+        let private_key = verify_sig::get_private_key_file("src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let payload = PayloadWriter::new().build(&image, private_key).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("oem-azure.gz");
+        fs::write(&path, &payload).unwrap();
+
+        let policy = SignaturePolicy::default();
+        let verified = verify_payload(
+            &path,
+            &[PubkeySource::Path("src/testdata/public_key_test_pkcs8.pem".to_string())],
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(verified.size.bytes(), payload.len());
+        assert_eq!(
+            verified.payload_info.partitions[0].new_info.as_ref().map(|i| i.size),
+            Some(image.len() as u64)
+        );
+
+        assert!(verify_payload(
+            &path,
+            &[PubkeySource::Path("src/testdata/public_key_test_ecdsa_p256.pem".to_string())],
+            &policy
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_signature_digest_sha512() {
+        use rsa::RsaPrivateKey;
+        use update_format_crau::delta_update::writer::{PayloadSigner, PayloadWriter};
+        use update_format_crau::verify_sig;
+        use update_format_crau::verify_sig::DigestAlgo;
+
+        struct Sha512Signer(RsaPrivateKey);
+
+        impl PayloadSigner for Sha512Signer {
+            fn signature_size(&self) -> Result<usize> {
+                self.0.signature_size()
+            }
+
+            fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+                verify_sig::sign_rsa_pkcs_with(DigestAlgo::Sha512, data, self.0.clone())
+            }
+        }
+
+        let image = vec![3u8; 8192];
+        let private_key = verify_sig::get_private_key_file("src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let payload = PayloadWriter::new().build_with_signer(&image, &Sha512Signer(private_key)).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("oem-azure.gz");
+        fs::write(&path, &payload).unwrap();
+
+        let builder = || {
+            DownloadVerifyBuilder::new(
+                dir.path().to_str().unwrap().to_string(),
+                vec![PubkeySource::Path("src/testdata/public_key_test_pkcs8.pem".to_string())],
+            )
+            .source(Source::LocalFile(path.clone()))
+        };
+
+        // The default policy hashes with SHA-256, which does not verify.
+        assert!(builder().build().unwrap().run().is_err());
+
+        let policy = SignaturePolicy {
+            digest: DigestAlgo::Sha512,
+            ..Default::default()
+        };
+        let report = builder().signature_policy(policy).build().unwrap().run().unwrap();
+        assert!(matches!(report.packages[0].status, PackageStatus::Verified));
+        assert_eq!(fs::read(dir.path().join("oem-azure.raw")).unwrap(), image);
+    }
+
+    #[test]
+    fn test_extract_only() {
+        use update_format_crau::delta_update::writer::PayloadWriter;
+        use update_format_crau::verify_sig;
+
+        let image = vec![2u8; 8192];
+        let private_key = verify_sig::get_private_key_file("src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let payload = PayloadWriter::new().build(&image, private_key).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("oem-azure.gz");
+        fs::write(&path, &payload).unwrap();
+
+        let builder = || DownloadVerifyBuilder::new(dir.path().to_str().unwrap().to_string(), Vec::new()).source(Source::LocalFile(path.clone()));
+        assert!(builder().build().is_err());
+
+        let report = builder().extract_only(true).build().unwrap().run().unwrap();
+        assert_eq!(report.packages.len(), 1);
+        assert!(matches!(report.packages[0].status, PackageStatus::Unverified));
+        assert_eq!(fs::read(dir.path().join("oem-azure.raw")).unwrap(), image);
+        assert_eq!(VerifiedStamp::load(&dir.path().join("oem-azure.raw")), None);
+    }
+
+    fn fixture_data() -> (Vec<u8>, ExpectedHashes) {
+        let data = update_format_crau::testutil::pseudo_random_image(300_000);
+        let expected = ExpectedHashes {
+            sha256: Some(hash_from_reader::<omaha::Sha256, _>(&data[..], None).unwrap()),
+            sha1: Some(hash_from_reader::<omaha::Sha1, _>(&data[..], None).unwrap()),
+            sha512: None,
+        };
+        (data, expected)
+    }
+
+    #[test]
+    fn test_download_resume_after_truncation() {
+        use crate::test_support::{Fault, FixtureServer};
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        server.add_file("oem-azure.gz", data.clone());
+        server.push_fault("oem-azure.gz", Fault::Truncate(100_000));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("oem-azure.gz");
+        let client = Client::new();
+        download_and_hash(
+            &client,
+            server.url("oem-azure.gz"),
+            &path,
+            &expected,
+            None,
+            &DownloadOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // The second try only asks for the bytes that were missing.
+        let requests = server.requests_of("oem-azure.gz");
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("range"), None);
+        assert_eq!(requests[1].header("range"), Some("bytes=100000-"));
+
+        // Same for a server that is too slow to finish within the timeout.
+        server.add_file("slow.gz", data.clone());
+        server.push_fault(
+            "slow.gz",
+            Fault::Trickle {
+                chunk: 16384,
+                delay: Duration::from_millis(100),
+            },
+        );
+        let options = DownloadOptions {
+            timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let path = dir.path().join("slow.gz");
+        download_and_hash(&client, server.url("slow.gz"), &path, &expected, None, &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let requests = server.requests_of("slow.gz");
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].header("range").is_some_and(|r| r != "bytes=0-"));
+    }
+
+    #[test]
+    fn test_download_retry_classification() {
+        use crate::test_support::{Fault, FixtureServer};
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        server.add_file("oem-azure.gz", data.clone());
+        let dir = tempfile::tempdir().unwrap();
+        let client = Client::new();
+
+        // Overload and connection errors are retried.
+        server.push_fault("oem-azure.gz", Fault::Status(503));
+        server.push_fault("oem-azure.gz", Fault::Reset);
+        let path = dir.path().join("oem-azure.gz");
+        download_and_hash(
+            &client,
+            server.url("oem-azure.gz"),
+            &path,
+            &expected,
+            None,
+            &DownloadOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(server.requests_of("oem-azure.gz").len(), 3);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // Bad checksums are only retried MAX_CHECKSUM_RETRY times.
+        server.add_file("corrupted.gz", data[1..].to_vec());
+        let path = dir.path().join("corrupted.gz");
+        let Err(err) = download_and_hash(
+            &client,
+            server.url("corrupted.gz"),
+            &path,
+            &expected,
+            None,
+            &DownloadOptions::default(),
+        ) else {
+            panic!("corrupted download succeeded");
+        };
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::ChecksumMismatch { .. })
+        ));
+        assert_eq!(server.requests_of("corrupted.gz").len(), 1 + MAX_CHECKSUM_RETRY as usize);
+        assert!(!path.exists());
+    }
+
+    // Serve data as package oem-azure.gz of a response, and return the URL
+    // of the response.
+    fn serve_fixture_response(server: &crate::test_support::FixtureServer, data: Vec<u8>, expected: &ExpectedHashes) -> Url {
+        let xml = format!(
+            concat!(
+                r#"<response protocol="3.0"><app appid="{{e96281a6-d1af-4bde-9a0a-97b76e56dc57}}" status="ok">"#,
+                r#"<updatecheck status="ok"><urls><url codebase="{}"/></urls><manifest version="1"><packages>"#,
+                r#"<package name="oem-azure.gz" hash_sha256="{}" size="{}" required="false"/></packages></manifest></updatecheck></app></response>"#,
+            ),
+            server.url("files/"),
+            expected.sha256.as_ref().unwrap(),
+            data.len()
+        );
+        server.add_file("files/oem-azure.gz", data);
+        server.add_file("response.xml", xml.into_bytes());
+
+        server.url("response.xml")
+    }
+
+    #[test]
+    fn test_headers_only_for_payloads() {
+        use crate::test_support::FixtureServer;
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        let response_url = serve_fixture_response(&server, data, &expected);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("private-token", "secret".parse().unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        let download_verify = DownloadVerifyBuilder::new(
+            dir.path().to_str().unwrap().to_string(),
+            vec![PubkeySource::Path("key.pem".to_string())],
+        )
+        .source(Source::XmlUrl(response_url))
+        .package_selectors(vec![PackageSelector::Name("oem-azure.gz".to_string())])
+        .headers(headers)
+        .build()
+        .unwrap();
+
+        // The data is no payload, so only its download succeeds.
+        assert!(download_verify.run().is_err());
+        assert_eq!(server.requests_of("response.xml")[0].header("private-token"), None);
+        assert_eq!(
+            server.requests_of("files/oem-azure.gz")[0].header("private-token"),
+            Some("secret")
+        );
+    }
+
+    #[test]
+    fn test_pinned_keys() {
+        use crate::test_support::FixtureServer;
+        use x509_cert::der::{DecodePem, Encode};
+
+        let pem = fs::read("src/testdata/certificate_test.pem").unwrap();
+        let cert = x509_cert::Certificate::from_pem(pem).unwrap().to_der().unwrap();
+        assert_eq!(
+            spki_sha256(&cert).unwrap().to_base64(),
+            "sSO7X91HwxKj7IHPW0lHMq8PqQhDgd8gSZ7HiQEJSx4="
+        );
+        assert!(spki_sha256(b"no certificate").is_err());
+
+        // Without TLS, there is no certificate to check the pins against.
+        let server = FixtureServer::start().unwrap();
+        server.add_file("payload", b"data".to_vec());
+        let mut options = DownloadOptions::default();
+        preflight(&Client::new(), server.url("payload"), None, &options).unwrap();
+        options.pinned_keys = vec![spki_sha256(&cert).unwrap()];
+        let err = preflight(&Client::new(), server.url("payload"), None, &options).unwrap_err();
+        assert!(err.to_string().starts_with("no certificate of"));
+    }
+
+    #[test]
+    fn test_builder_response() {
+        use crate::test_support::FixtureServer;
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        let response_url = serve_fixture_response(&server, data, &expected);
+
+        // Listing packages needs neither public keys nor an output directory.
+        let resp = DownloadVerifyBuilder::new(String::new(), Vec::new()).source(Source::XmlUrl(response_url)).response().unwrap();
+        let manifest = resp.apps[0].update_check.as_ref().unwrap().manifest.as_ref().unwrap();
+        assert_eq!(manifest.packages[0].name, "oem-azure.gz");
+        assert_eq!(server.requests_of("response.xml").len(), 1);
+        assert!(server.requests_of("files/oem-azure.gz").is_empty());
+
+        let payload_url = Url::parse("https://example.com/oem-azure.gz").unwrap();
+        assert!(DownloadVerifyBuilder::new(String::new(), Vec::new()).source(Source::PayloadUrl(payload_url)).response().is_err());
+    }
+
+    #[test]
+    fn test_deadline_cancels_run() {
+        use crate::test_support::{Fault, FixtureServer};
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        let response_url = serve_fixture_response(&server, data, &expected);
+        server.push_fault(
+            "files/oem-azure.gz",
+            Fault::Trickle {
+                chunk: 4096,
+                delay: Duration::from_millis(20),
+            },
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let download_verify = DownloadVerifyBuilder::new(
+            dir.path().to_str().unwrap().to_string(),
+            vec![PubkeySource::Path("key.pem".to_string())],
+        )
+        .source(Source::XmlUrl(response_url))
+        .package_selectors(vec![PackageSelector::Name("oem-azure.gz".to_string())])
+        .deadline(Some(Duration::from_millis(200)))
+        .cleanup_policy(CleanupPolicy::AlwaysDelete)
+        .build()
+        .unwrap();
+
+        // Trickling all of the data would take more than a second.
+        let start = Instant::now();
+        let err = download_verify.run().unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::DeadlineExceeded { .. })
+        ));
+
+        // The download stopped before the cleanup, and is not retried.
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!dir.path().join(".unverified").exists());
+        assert_eq!(server.requests_of("files/oem-azure.gz").len(), 1);
+    }
+
+    #[test]
+    fn test_get_response_http_status() {
+        use crate::error::ErrorKind;
+        use crate::test_support::FixtureServer;
+
+        let server = FixtureServer::start().unwrap();
+        let err = get_response(&Client::new(), server.url("missing.gz"), &DownloadOptions::default(), None, 0).unwrap_err();
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::Http));
+        assert_eq!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(&crate::error::Error::HttpStatus {
+                status: 404,
+                url: server.url("missing.gz"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_package_mirror_failover() {
+        use crate::test_support::FixtureServer;
+
+        let (data, expected) = fixture_data();
+        let server = FixtureServer::start().unwrap();
+        let mut corrupted = data.clone();
+        corrupted[1000] ^= 0xff;
+        server.add_file("corrupted/oem-azure.gz", corrupted);
+        server.add_file("mirror/oem-azure.gz", data.clone());
+
+        let dir = tempfile::tempdir().unwrap();
+        let client = Client::new();
+        let package = |url| Package {
+            url,
+            mirrors: vec![server.url("missing/oem-azure.gz"), server.url("mirror/oem-azure.gz")],
+            name: "oem-azure.gz".into(),
+            hash_sha256: expected.sha256.clone(),
+            hash_sha1: expected.sha1.clone(),
+            hash_sha512: None,
+            size: FileSize::from_bytes(data.len()),
+            required: true,
+            status: PackageStatus::ToDownload,
+            metrics: PackageMetrics::default(),
+            payload_info: None,
+        };
+
+        // The corrupted payload is given up after retrying it, and with
+        // preflight, the missing one is skipped without downloading it.
+        let mut pkg = package(server.url("corrupted/oem-azure.gz"));
+        pkg.download(dir.path(), &client, 0, true, &DownloadOptions::default()).unwrap();
+        assert_eq!(pkg.url, server.url("mirror/oem-azure.gz"));
+        assert_eq!(fs::read(dir.path().join("oem-azure.gz")).unwrap(), data);
+        assert_eq!(
+            server.requests_of("corrupted/oem-azure.gz").iter().filter(|r| r.method == "GET").count(),
+            1 + MAX_CHECKSUM_RETRY as usize
+        );
+        assert!(server.requests_of("missing/oem-azure.gz").iter().all(|r| r.method == "HEAD"));
+    }
+}
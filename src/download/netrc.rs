@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+// Login and password for a host, from a machine entry of a .netrc file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    // None for the default entry, which applies to all other hosts.
+    machine: Option<String>,
+    login: String,
+    password: Option<String>,
+}
+
+// Credentials of a .netrc file, applied as basic auth to downloads from the
+// hosts it lists, like curl --netrc does. Only the machine, default, login,
+// password, account and macdef tokens are known, accounts and macros are
+// ignored.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Netrc {
+    entries: Vec<Entry>,
+}
+
+// Never print passwords, e.g. in a Debug of DownloadVerify.
+impl std::fmt::Debug for Netrc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.entries.iter().map(|e| e.machine.as_deref().unwrap_or("default"))).finish()
+    }
+}
+
+impl std::str::FromStr for Netrc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut lines = s.lines();
+
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+
+            while let Some(token) = tokens.next() {
+                let mut value = |what| tokens.next().with_context(|| format!("missing {} after {:?}", what, token));
+
+                match token {
+                    "machine" => entries.push(Entry {
+                        machine: Some(value("host name")?.to_string()),
+                        login: String::new(),
+                        password: None,
+                    }),
+                    "default" => entries.push(Entry {
+                        machine: None,
+                        login: String::new(),
+                        password: None,
+                    }),
+                    "login" | "password" | "account" => {
+                        let value = value("value")?.to_string();
+                        let Some(entry) = entries.last_mut() else {
+                            bail!("{:?} outside of a machine entry", token);
+                        };
+                        match token {
+                            "login" => entry.login = value,
+                            "password" => entry.password = Some(value),
+                            _ => {}
+                        }
+                    }
+                    // A macro runs until the next empty line.
+                    "macdef" => {
+                        value("macro name")?;
+                        lines.by_ref().take_while(|l| !l.trim().is_empty()).for_each(drop);
+                        break;
+                    }
+                    _ => bail!("unknown token {:?}", token),
+                }
+            }
+        }
+
+        Ok(Netrc {
+            entries,
+        })
+    }
+}
+
+impl Netrc {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).context(format!("failed to read netrc ({:?})", path.display()))?;
+        content.parse().context(format!("failed to parse netrc ({:?})", path.display()))
+    }
+
+    // Path of the .netrc file to use by default, $NETRC if it is set, or
+    // .netrc in the home directory.
+    pub fn default_path() -> Option<PathBuf> {
+        match env::var_os("NETRC") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => env::var_os("HOME").map(|home| Path::new(&home).join(".netrc")),
+        }
+    }
+
+    // Login and password for host. The first machine entry of host wins,
+    // and the default entry, wherever it is, only applies if there is none.
+    pub fn credentials(&self, host: &str) -> Option<(&str, Option<&str>)> {
+        let entry = self.entries.iter().find(|e| e.machine.as_deref().is_some_and(|m| m.eq_ignore_ascii_case(host))).or_else(|| self.entries.iter().find(|e| e.machine.is_none()))?;
+
+        Some((&entry.login, entry.password.as_deref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netrc_credentials() {
+        let netrc: Netrc = "machine mirror.example.com login alice password secret\n\
+                            macdef init\n\
+                            cd /pub\n\
+                            \n\
+                            machine other.example.com\n  login bob\n  account x\n\
+                            default login anonymous password guest\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(netrc.credentials("mirror.example.com"), Some(("alice", Some("secret"))));
+        assert_eq!(netrc.credentials("MIRROR.example.com"), Some(("alice", Some("secret"))));
+        assert_eq!(netrc.credentials("other.example.com"), Some(("bob", None)));
+        assert_eq!(
+            netrc.credentials("update.release.flatcar-linux.net"),
+            Some(("anonymous", Some("guest")))
+        );
+        assert!(!format!("{:?}", netrc).contains("secret"));
+
+        assert_eq!("machine a login b".parse::<Netrc>().unwrap().credentials("c"), None);
+        assert!("login b".parse::<Netrc>().is_err());
+        assert!("machine".parse::<Netrc>().is_err());
+    }
+}
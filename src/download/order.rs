@@ -0,0 +1,98 @@
+use anyhow::{Result, bail};
+use globset::GlobSet;
+use std::str::FromStr;
+
+use super::Package;
+
+// Order in which DownloadVerify processes the packages of an Omaha response,
+// e.g. to secure the most important ones first when disk space or time is
+// tight. Packages that compare equal keep the order of the response.
+#[derive(Debug, Clone, Default)]
+pub enum DownloadOrder {
+    #[default]
+    Response,
+    // Packages that the response marks as required before optional ones.
+    RequiredFirst,
+    SmallestFirst,
+    // Packages matching an earlier glob of the set before those matching a
+    // later one, and packages matching none of them last.
+    Priority(GlobSet),
+}
+
+impl DownloadOrder {
+    pub(super) fn sort(&self, pkgs: &mut [Package<'_>]) {
+        match self {
+            DownloadOrder::Response => {}
+            DownloadOrder::RequiredFirst => pkgs.sort_by_key(|pkg| !pkg.required),
+            DownloadOrder::SmallestFirst => pkgs.sort_by_key(|pkg| pkg.size.bytes()),
+            DownloadOrder::Priority(glob_set) => pkgs.sort_by_key(|pkg| glob_set.matches(&*pkg.name).into_iter().min().unwrap_or(usize::MAX)),
+        }
+    }
+}
+
+// Priority needs its globs, so it can not be parsed from a string.
+impl FromStr for DownloadOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "response" => DownloadOrder::Response,
+            "required-first" => DownloadOrder::RequiredFirst,
+            "smallest-first" => DownloadOrder::SmallestFirst,
+            _ => bail!("unknown download order {:?}", s),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globset::{Glob, GlobSetBuilder};
+    use std::borrow::Cow;
+    use url::Url;
+
+    use crate::download::{PackageMetrics, PackageStatus};
+
+    fn package(name: &'static str, size: usize, required: bool) -> Package<'static> {
+        Package {
+            url: Url::parse("https://example.com/").unwrap().join(name).unwrap(),
+            mirrors: Vec::new(),
+            name: Cow::Borrowed(name),
+            hash_sha256: None,
+            hash_sha1: None,
+            hash_sha512: None,
+            size: omaha::FileSize::from_bytes(size),
+            required,
+            status: PackageStatus::ToDownload,
+            metrics: PackageMetrics::default(),
+            payload_info: None,
+        }
+    }
+
+    fn sorted(order: DownloadOrder) -> Vec<String> {
+        let mut pkgs = vec![package("docker.raw", 300, false), package("oem-azure.raw", 200, true), package("podman.raw", 100, false)];
+        order.sort(&mut pkgs);
+        pkgs.iter().map(|pkg| pkg.name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_download_order() {
+        assert_eq!(sorted(DownloadOrder::Response), ["docker.raw", "oem-azure.raw", "podman.raw"]);
+        assert_eq!(
+            sorted(DownloadOrder::RequiredFirst),
+            ["oem-azure.raw", "docker.raw", "podman.raw"]
+        );
+        assert_eq!(
+            sorted(DownloadOrder::SmallestFirst),
+            ["podman.raw", "oem-azure.raw", "docker.raw"]
+        );
+
+        let glob_set = GlobSetBuilder::new().add(Glob::new("oem-*").unwrap()).add(Glob::new("podman*").unwrap()).build().unwrap();
+        assert_eq!(
+            sorted(DownloadOrder::Priority(glob_set)),
+            ["oem-azure.raw", "podman.raw", "docker.raw"]
+        );
+
+        assert!(DownloadOrder::from_str("priority").is_err());
+    }
+}
@@ -0,0 +1,475 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tracing::{debug, error, info, info_span, warn};
+use reqwest::blocking::Client;
+use url::Url;
+
+use update_format_crau::delta_update;
+use update_format_crau::delta_update::{CancelFlag, DeltaUpdateFileHeader, SignaturePolicy, WriteOptions};
+use update_format_crau::proto;
+use update_format_crau::verify_sig::{DigestAlgo, PubkeySource};
+
+use crate::download::hash_on_disk_cancel;
+use crate::download::{PackageMetrics, PhaseMetrics};
+use crate::download::cache::CacheValidators;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageStatus {
+    ToDownload,
+    DownloadIncomplete(omaha::FileSize),
+    DownloadFailed,
+    BadChecksum,
+    Unverified,
+    BadSignature,
+    Verified,
+}
+
+impl fmt::Display for PackageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackageStatus::ToDownload => f.write_str("to-download"),
+            PackageStatus::DownloadIncomplete(_) => f.write_str("download-incomplete"),
+            PackageStatus::DownloadFailed => f.write_str("download-failed"),
+            PackageStatus::BadChecksum => f.write_str("bad-checksum"),
+            PackageStatus::Unverified => f.write_str("unverified"),
+            PackageStatus::BadSignature => f.write_str("bad-signature"),
+            PackageStatus::Verified => f.write_str("verified"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Package<'a> {
+    pub url: Url,
+    // Further URLs of the same data, tried in order if downloading from url
+    // fails. url is set to the one that succeeded.
+    pub mirrors: Vec<Url>,
+    pub name: Cow<'a, str>,
+    pub hash_sha256: Option<omaha::Hash<omaha::Sha256>>,
+    pub hash_sha1: Option<omaha::Hash<omaha::Sha1>>,
+    pub hash_sha512: Option<omaha::Hash<omaha::Sha512>>,
+    pub size: omaha::FileSize,
+    // Whether the Omaha response marks the package as required, payloads
+    // that do not come from a response are.
+    pub required: bool,
+    pub status: PackageStatus,
+    pub metrics: PackageMetrics,
+    // Metadata of the payload, once its signature has been verified.
+    pub payload_info: Option<delta_update::PayloadInfo>,
+}
+
+impl Package<'_> {
+    // Construct a Package from a payload that already exists on the local
+    // disk, e.g. to verify it with verify_signature without any download.
+    pub fn from_file(path: &Path) -> Result<Package<'_>> {
+        let abspath = fs::canonicalize(path).context(format!("failed to canonicalize path ({:?})", path.display()))?;
+        let md = fs::metadata(&abspath).context(format!("failed to get metadata, path ({:?})", abspath.display()))?;
+
+        Ok(Package {
+            name: Cow::Borrowed(path.file_name().unwrap_or(OsStr::new("fakepackage")).to_str().unwrap_or("fakepackage")),
+            hash_sha256: None,
+            hash_sha1: None,
+            hash_sha512: None,
+            size: omaha::FileSize::from_bytes(md.len() as usize),
+            url: Url::from_file_path(&abspath).map_err(|_| anyhow!("failed to convert path ({:?}) into url", abspath.display()))?,
+            mirrors: Vec::new(),
+            required: true,
+            status: PackageStatus::Unverified,
+            metrics: PackageMetrics::default(),
+            payload_info: None,
+        })
+    }
+
+    #[rustfmt::skip]
+    // Return Sha256 hash of data in the given path.
+    // If maxlen is None, a simple read to the end of the file.
+    // If maxlen is Some, read only until the given length.
+    // Fails between two chunks once cancel is set.
+    fn hash_on_disk<T: omaha::HashAlgo>(&mut self, path: &Path, maxlen: Option<usize>, cancel: &CancelFlag) -> Result<omaha::Hash<T>> {
+        let start = Instant::now();
+        let hash = hash_on_disk_cancel::<T>(path, maxlen, cancel)?;
+
+        let filelen = fs::metadata(path).map(|md| md.len()).unwrap_or_default();
+        let bytes = maxlen.map_or(filelen, |len| filelen.min(len as u64));
+        self.metrics.hash.add(PhaseMetrics::since(start, bytes));
+
+        Ok(hash)
+    }
+
+    #[rustfmt::skip]
+    pub fn check_download(&mut self, in_dir: &Path, cancel: &CancelFlag) -> Result<()> {
+        let path = in_dir.join(&*self.name);
+
+        if !path.exists() {
+            // skip checking for existing downloads
+            info!("{} does not exist, skipping existing downloads.", path.display());
+            return Ok(());
+        }
+
+        let md = fs::metadata(&path).context({
+            format!("failed to get metadata, path ({:?})", path.display())
+        })?;
+
+        let size_on_disk = md.len() as usize;
+        let expected_size = self.size.bytes();
+
+        if size_on_disk < expected_size {
+            info!("{}: have downloaded {}/{} bytes, will resume", path.display(), size_on_disk, expected_size);
+
+            self.status = PackageStatus::DownloadIncomplete(
+                omaha::FileSize::from_bytes(size_on_disk)
+            );
+            return Ok(());
+        }
+
+        if size_on_disk == expected_size {
+            info!("{}: download complete, checking hash...", path.display());
+            let hash_sha256 = self.hash_on_disk::<omaha::Sha256>(&path, None, cancel).context({
+                format!("failed to hash_on_disk, path ({:?})", path.display())
+            })?;
+            let hash_sha1 = self.hash_on_disk::<omaha::Sha1>(&path, None, cancel).context({
+                format!("failed to hash_on_disk, path ({:?})", path.display())
+            })?;
+            // SHA-512 is only calculated if it is expected, as Omaha does not send it.
+            let hash_sha512 = match self.hash_sha512 {
+                Some(_) => Some(self.hash_on_disk::<omaha::Sha512>(&path, None, cancel).context({
+                    format!("failed to hash_on_disk, path ({:?})", path.display())
+                })?),
+                None => None,
+            };
+            if self.verify_checksum(hash_sha256, hash_sha1, hash_sha512) {
+                info!("{}: good hash, will continue without re-download", path.display());
+            } else {
+                info!("{}: bad hash, will re-download", path.display());
+                CacheValidators::remove(&path);
+                fs::remove_file(&path).context(format!("failed to remove {:?}", path.display()))?;
+                self.status = PackageStatus::ToDownload;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The Content-Length of the response must not differ from the declared
+    // size of the package by more than size_tolerance bytes. With preflight,
+    // each URL is checked with a HEAD request first, see crate::preflight.
+    pub fn download(&mut self, into_dir: &Path, client: &Client, size_tolerance: u64, preflight: bool, options: &crate::DownloadOptions) -> Result<()> {
+        // Incomplete downloads are resumed by download_and_hash, if the
+        // state of their hashes was saved, see HashCheckpoint.
+        if !matches!(self.status, PackageStatus::ToDownload | PackageStatus::DownloadIncomplete(_)) {
+            return Ok(());
+        }
+
+        // A size of 0 means that no size was declared at all.
+        let expected_size = match self.size.bytes() {
+            0 => None,
+            _ => Some(crate::ExpectedSize::new(self.size, size_tolerance)),
+        };
+        let expected_hashes = crate::ExpectedHashes {
+            sha256: self.hash_sha256.clone(),
+            sha1: self.hash_sha1.clone(),
+            sha512: self.hash_sha512.clone(),
+        };
+
+        let path = into_dir.join(&*self.name);
+        let mut res = Err(anyhow!("no URL to download from"));
+        for url in std::iter::once(&self.url).chain(&self.mirrors) {
+            options.cancel.check()?;
+            if preflight {
+                match crate::preflight(client, url.clone(), expected_size, options) {
+                    Ok(p) => debug!("preflight of {}: {:?}", url, p),
+                    Err(err) => {
+                        error!("Preflight of {} failed with error {:#}", url, err);
+                        res = Err(err);
+                        continue;
+                    }
+                }
+            }
+
+            info!("downloading {}...", url);
+
+            res = crate::download_and_hash(client, url.clone(), &path, &expected_hashes, expected_size, options).map(|r| (r, url.clone()));
+            match &res {
+                Ok(_) => break,
+                Err(err) => error!("Downloading {} failed with error {}", url, err),
+            }
+        }
+
+        // Keep the error of the last try, e.g. to tell a checksum mismatch.
+        let (res, url) = match res {
+            Ok(ok) => ok,
+            Err(err) => {
+                self.status = PackageStatus::DownloadFailed;
+                return Err(err).context(format!("unable to download data(url {})", self.url));
+            }
+        };
+        self.url = url;
+
+        self.metrics.download.add(res.download);
+        self.metrics.hash.add(res.hash);
+
+        // Hashes were already checked against the expected ones, if any.
+        // Keep the calculated ones, to also know hashes that were not given.
+        self.hash_sha256 = Some(res.hash_sha256);
+        self.hash_sha1 = Some(res.hash_sha1);
+        if res.hash_sha512.is_some() {
+            self.hash_sha512 = res.hash_sha512;
+        }
+
+        self.status = PackageStatus::Unverified;
+        Ok(())
+    }
+
+    pub fn verify_checksum(&mut self, calculated_sha256: omaha::Hash<omaha::Sha256>, calculated_sha1: omaha::Hash<omaha::Sha1>, calculated_sha512: Option<omaha::Hash<omaha::Sha512>>) -> bool {
+        debug!("    expected sha256:   {:?}", self.hash_sha256);
+        debug!("    calculated sha256: {}", calculated_sha256);
+        debug!("    sha256 match?      {}", self.hash_sha256 == Some(calculated_sha256.clone()));
+        debug!("    expected sha1:   {:?}", self.hash_sha1);
+        debug!("    calculated sha1: {}", calculated_sha1);
+        debug!("    sha1 match?      {}", self.hash_sha1 == Some(calculated_sha1.clone()));
+
+        debug!("    expected sha512:   {:?}", self.hash_sha512);
+        debug!("    calculated sha512: {:?}", calculated_sha512);
+
+        if self.hash_sha256.is_some() && self.hash_sha256 != Some(calculated_sha256.clone())
+            || self.hash_sha1.is_some() && self.hash_sha1 != Some(calculated_sha1.clone())
+            || self.hash_sha512.is_some() && self.hash_sha512 != calculated_sha512
+        {
+            self.status = PackageStatus::BadChecksum;
+            false
+        } else {
+            self.status = PackageStatus::Unverified;
+            true
+        }
+    }
+
+    // Verify the payload in from_path, and extract its data blobs into tmpdir.
+    // Return the path to the extracted data.
+    pub fn verify_signature_on_disk(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy, tmpdir: &Path) -> Result<PathBuf> {
+        let mut extracted = self.verify_partitions_on_disk(from_path, pubkeys, policy, tmpdir, &[delta_update::PARTITION_USR])?;
+
+        extracted.pop().map(|(_, path)| path).ok_or(anyhow!("no data extracted from {:?}", from_path.display()))
+    }
+
+    // Verify the payload in from_path, and extract the data blobs of the given
+    // partitions into tmpdir, e.g. USR and KERNEL, see delta_update::get_partitions.
+    // Return the name of each partition with the path to its extracted data.
+    pub fn verify_partitions_on_disk(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy, tmpdir: &Path, partitions: &[&str]) -> Result<Vec<(String, PathBuf)>> {
+        let targets = partition_targets(tmpdir, partitions);
+
+        self.verify_and_extract_partitions(from_path, pubkeys, policy, &targets, WriteOptions::default())?;
+
+        Ok(targets)
+    }
+
+    // Verify the payload in from_path, and write the data of its main partition
+    // directly into target, e.g. a block device, instead of into a temporary file.
+    pub fn verify_signature_to_target(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy, target: &Path, options: WriteOptions) -> Result<PathBuf> {
+        let targets = [(delta_update::PARTITION_USR.to_string(), target.to_path_buf())];
+
+        self.verify_and_extract_partitions(from_path, pubkeys, policy, &targets, options)?;
+
+        Ok(target.to_path_buf())
+    }
+
+    // Open the payload in from_path, and read its header and manifest.
+    fn open_payload(&mut self, from_path: &Path) -> Result<(File, DeltaUpdateFileHeader, proto::DeltaArchiveManifest)> {
+        let upfile = File::open(from_path).context(format!("failed to open path ({:?})", from_path.display()))?;
+
+        // Read update payload from file, read delta update header from the payload.
+        let header = delta_update::read_delta_update_header(&upfile).context(format!("failed to read_delta_update_header path ({:?})", from_path.display()))?;
+
+        let delta_archive_manifest = delta_update::get_manifest_bytes(&upfile, &header).context(format!("failed to get_manifest_bytes path ({:?})", from_path.display()))?;
+
+        let payload_info = delta_update::PayloadInfo::new(&header, &delta_archive_manifest);
+        info!(
+            "payload format version {}, block size {}, {} partitions with {} operations",
+            payload_info.file_format_version,
+            payload_info.block_size,
+            payload_info.partitions.len(),
+            payload_info.operations()
+        );
+        self.payload_info = Some(payload_info);
+
+        Ok((upfile, header, delta_archive_manifest))
+    }
+
+    // Open the payload in from_path, and verify its signature.
+    fn open_verified(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy, cancel: &CancelFlag) -> Result<(File, DeltaUpdateFileHeader, proto::DeltaArchiveManifest)> {
+        let (upfile, header, mut delta_archive_manifest) = self.open_payload(from_path)?;
+
+        // Extract signature from header.
+        let sigbytes = delta_update::get_signatures_bytes(&upfile, &header, &mut delta_archive_manifest).context(format!("failed to get_signatures_bytes path ({:?})", from_path.display()))?;
+
+        // Get length of header and data, including header and manifest.
+        let header_data_length = delta_update::get_header_data_length(&header, &delta_archive_manifest).context("failed to get header data length")?;
+        // Hash it with the algorithm the signatures were created over.
+        let (hdhash, hdhashvec): (String, Vec<u8>) = match policy.digest {
+            DigestAlgo::Sha256 => self.hash_on_disk::<omaha::Sha256>(from_path, Some(header_data_length), cancel).map(|h| (format!("{:?}", h), h.into())),
+            DigestAlgo::Sha512 => self.hash_on_disk::<omaha::Sha512>(from_path, Some(header_data_length), cancel).map(|h| (format!("{:?}", h), h.into())),
+        }
+        .context(format!("failed to hash_on_disk path ({:?}) failed", from_path.display()))?;
+
+        // Parse signature data from sig blobs, data blobs, public key, and verify.
+        // This is done before extracting anything, as the data might be
+        // written directly to a block device.
+        let verify_span = info_span!("verify", path = %from_path.display(), bytes = header_data_length).entered();
+        let verify_start = Instant::now();
+        let res_verify = delta_update::parse_signature_data_with_policy(&sigbytes, hdhashvec.as_slice(), pubkeys, policy);
+        self.metrics.verify.add(PhaseMetrics::since(verify_start, header_data_length as u64));
+
+        match res_verify {
+            Ok(_) => (),
+            Err(err) => {
+                self.status = PackageStatus::BadSignature;
+                return Err(anyhow::Error::from(crate::error::Error::SignatureInvalid).context(format!(
+                    "unable to parse and verify signature ({:#}), sigbytes ({:?}), hdhash ({}), pubkeys ({:?})",
+                    err, sigbytes, hdhash, pubkeys
+                )));
+            }
+        };
+        drop(verify_span);
+
+        info!("Parsed and verified signature data from file {:?}", from_path);
+
+        Ok((upfile, header, delta_archive_manifest))
+    }
+
+    // Only verify the signature of the payload in from_path, without
+    // extracting anything. Metadata of the payload is in payload_info then.
+    pub fn verify_signature(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy) -> Result<()> {
+        self.open_verified(from_path, pubkeys, policy, &CancelFlag::default())?;
+
+        self.status = PackageStatus::Verified;
+        Ok(())
+    }
+
+    // Verify the payload in from_path, then write the data of each partition
+    // into the path given with its name, e.g. USR into a block device and
+    // KERNEL into a file.
+    pub fn verify_and_extract_partitions(&mut self, from_path: &Path, pubkeys: &[PubkeySource], policy: &SignaturePolicy, targets: &[(String, PathBuf)], options: WriteOptions) -> Result<()> {
+        let (upfile, header, delta_archive_manifest) = self.open_verified(from_path, pubkeys, policy, &options.cancel)?;
+        self.extract_partitions(from_path, &upfile, &header, &delta_archive_manifest, targets, options)?;
+
+        self.status = PackageStatus::Verified;
+        Ok(())
+    }
+
+    // Like verify_and_extract_partitions, but without verifying the
+    // signature, e.g. of an unsigned payload built locally for development.
+    // The data still has to match the hashes of the manifest. The package
+    // stays unverified.
+    pub fn extract_partitions_unverified(&mut self, from_path: &Path, targets: &[(String, PathBuf)], options: WriteOptions) -> Result<()> {
+        let (upfile, header, delta_archive_manifest) = self.open_payload(from_path)?;
+        warn!("extracting {:?} WITHOUT verifying its signature", from_path);
+
+        self.extract_partitions(from_path, &upfile, &header, &delta_archive_manifest, targets, options)
+    }
+
+    fn extract_partitions(
+        &mut self,
+        from_path: &Path,
+        upfile: &File,
+        header: &DeltaUpdateFileHeader,
+        delta_archive_manifest: &proto::DeltaArchiveManifest,
+        targets: &[(String, PathBuf)],
+        options: WriteOptions,
+    ) -> Result<()> {
+        let payload_partitions = delta_update::get_partitions(delta_archive_manifest);
+
+        for (name, datablobspath) in targets {
+            let Some(partition) = payload_partitions.iter().find(|p| p.name == *name) else {
+                bail!("partition {} not found in payload ({:?})", name, from_path.display());
+            };
+
+            // Extract data blobs into a file, datablobspath.
+            let extract_start = Instant::now();
+            info_span!("extract", partition = %partition.name, path = %datablobspath.display(), operations = partition.operations.len()).in_scope(|| {
+                delta_update::get_partition_data_blobs(upfile, header, partition, None, datablobspath.as_path(), options.clone())
+                    .context(format!("failed to get_data_blobs path ({:?})", datablobspath.display()))
+            })?;
+            let extracted_len = fs::metadata(datablobspath).map(|md| md.len()).unwrap_or_default();
+            self.metrics.extract.add(PhaseMetrics::since(extract_start, extracted_len));
+
+            // Check for hash of data blobs with the new partition info hash.
+            let Some(new_info) = partition.new_info else {
+                bail!("unable to get new partition info of {}", partition.name);
+            };
+            let pinfo_hash = match &new_info.hash {
+                Some(hash) => hash,
+                None => bail!("unable to get new partition info hash of {}", partition.name),
+            };
+
+            // A block device is usually larger than the data written to it.
+            let datahash = self
+                .hash_on_disk::<omaha::Sha256>(
+                    datablobspath.as_path(),
+                    new_info.size.map(|size| size as usize),
+                    &options.cancel,
+                )
+                .context(format!("failed to hash_on_disk path ({:?})", datablobspath.display()))?;
+            if datahash != omaha::Hash::from_bytes(pinfo_hash.as_slice()[..].into()) {
+                bail!(
+                    "mismatch of data hash ({:?}) with new partition info hash ({:?}) of {}",
+                    datahash,
+                    pinfo_hash,
+                    partition.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Temporary files in tmpdir to extract the given partitions into. The main
+// partition is extracted into e.g. "ue_data_blobs", others into e.g.
+// "ue_data_blobs.kernel".
+pub(crate) fn partition_targets(tmpdir: &Path, partitions: &[&str]) -> Vec<(String, PathBuf)> {
+    partitions
+        .iter()
+        .map(|name| match *name {
+            delta_update::PARTITION_USR => (name.to_string(), tmpdir.join("ue_data_blobs")),
+            other => (name.to_string(), tmpdir.join(format!("ue_data_blobs.{}", other.to_lowercase()))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use update_format_crau::testutil::PayloadBuilder;
+    use update_format_crau::verify_sig;
+
+    #[test]
+    fn test_verify_signature_on_disk() {
+        let image: Vec<u8> = (0..12000u32).map(|i| (i % 253) as u8).collect();
+        let pubkeys = [PubkeySource::Path("src/testdata/public_key_test_pkcs8.pem".to_string())];
+        let private_key = verify_sig::get_private_key_file("src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let signed = PayloadBuilder::new(image.clone()).chunk_size(4096).signer(Box::new(private_key)).build_file().unwrap();
+        let mut pkg = Package::from_file(signed.path()).unwrap();
+        let extracted = pkg.verify_signature_on_disk(signed.path(), &pubkeys, &SignaturePolicy::default(), tmpdir.path()).unwrap();
+        assert!(matches!(pkg.status, PackageStatus::Verified));
+        assert_eq!(fs::read(extracted).unwrap(), image);
+
+        // Unsigned payloads and payloads of another key are rejected.
+        let unsigned = PayloadBuilder::new(image.clone()).build_file().unwrap();
+        let mut pkg = Package::from_file(unsigned.path()).unwrap();
+        assert!(pkg.verify_signature_on_disk(unsigned.path(), &pubkeys, &SignaturePolicy::default(), tmpdir.path()).is_err());
+
+        let ecdsa_pubkeys = [PubkeySource::Path("src/testdata/public_key_test_ecdsa_p256.pem".to_string())];
+        let mut pkg = Package::from_file(signed.path()).unwrap();
+        assert!(pkg.verify_signature_on_disk(signed.path(), &ecdsa_pubkeys, &SignaturePolicy::default(), tmpdir.path()).is_err());
+        assert!(matches!(pkg.status, PackageStatus::BadSignature));
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+// When to log the progress of a download: every interval and every bytes
+// downloaded, whichever comes first. A trigger that is None is disabled, so
+// ProgressConfig::disabled() logs nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressConfig {
+    pub interval: Option<Duration>,
+    pub bytes: Option<u64>,
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        ProgressConfig {
+            interval: Some(Duration::from_secs(10)),
+            bytes: None,
+        }
+    }
+}
+
+impl ProgressConfig {
+    pub fn disabled() -> Self {
+        ProgressConfig {
+            interval: None,
+            bytes: None,
+        }
+    }
+}
+
+// Progress of a single download, logged according to a ProgressConfig.
+// Bytes that were already on disk when resuming count towards the total,
+// but not towards the rate.
+pub(crate) struct Progress {
+    config: ProgressConfig,
+    total: Option<u64>,
+    resumed: u64,
+    start: Instant,
+    last_at: Instant,
+    last_bytes: u64,
+}
+
+impl Progress {
+    pub(crate) fn new(config: ProgressConfig, total: Option<u64>, resumed: u64) -> Self {
+        let now = Instant::now();
+        Progress {
+            config,
+            total,
+            resumed,
+            start: now,
+            last_at: now,
+            last_bytes: 0,
+        }
+    }
+
+    fn due(&self, now: Instant, bytes: u64) -> bool {
+        self.config.interval.is_some_and(|interval| now.duration_since(self.last_at) >= interval) || self.config.bytes.is_some_and(|b| bytes - self.last_bytes >= b)
+    }
+
+    // Log the progress if it is due, with bytes downloaded so far.
+    pub(crate) fn update(&mut self, bytes: u64) {
+        let now = Instant::now();
+        if !self.due(now, bytes) {
+            return;
+        }
+        self.last_at = now;
+        self.last_bytes = bytes;
+
+        let done = self.resumed + bytes;
+        let rate = rate(bytes, now.duration_since(self.start));
+        match self.total {
+            Some(total) if total > 0 => info!(
+                "downloaded {} of {} bytes ({}%), {} bytes/s, ETA {}",
+                done,
+                total,
+                done * 100 / total,
+                rate,
+                eta(total.saturating_sub(done), rate).map_or("unknown".to_string(), |eta| format!("{}s", eta.as_secs())),
+            ),
+            _ => info!("downloaded {} bytes, {} bytes/s", done, rate),
+        }
+    }
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => (bytes as f64 / secs) as u64,
+        _ => 0,
+    }
+}
+
+fn eta(remaining: u64, rate: u64) -> Option<Duration> {
+    (rate > 0).then(|| Duration::from_secs(remaining.div_ceil(rate)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_due() {
+        let progress = Progress::new(
+            ProgressConfig {
+                interval: None,
+                bytes: Some(1000),
+            },
+            Some(5000),
+            0,
+        );
+        let now = Instant::now();
+        assert!(!progress.due(now, 999));
+        assert!(progress.due(now, 1000));
+
+        let progress = Progress::new(ProgressConfig::disabled(), None, 0);
+        assert!(!progress.due(now + Duration::from_secs(3600), u64::MAX));
+
+        assert_eq!(rate(3000, Duration::from_secs(2)), 1500);
+        assert_eq!(eta(3001, 1500), Some(Duration::from_secs(3)));
+        assert_eq!(eta(3000, 0), None);
+    }
+}
@@ -0,0 +1,44 @@
+use globset::GlobSet;
+use regex::Regex;
+
+// Which packages of an Omaha response to download, by their name. Package
+// names that contain glob metacharacters, e.g. brackets, are easier to
+// select by their exact name or a regex.
+#[derive(Debug, Clone)]
+pub enum PackageSelector {
+    Name(String),
+    // Matches anywhere in the name, unless anchored with ^ and $.
+    Regex(Regex),
+    Glob(GlobSet),
+}
+
+impl PackageSelector {
+    pub fn is_match(&self, pkg_name: &str) -> bool {
+        match self {
+            PackageSelector::Name(name) => name == pkg_name,
+            PackageSelector::Regex(regex) => regex.is_match(pkg_name),
+            PackageSelector::Glob(glob_set) => glob_set.is_match(pkg_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globset::{Glob, GlobSetBuilder};
+
+    #[test]
+    fn test_package_selector() {
+        let name = PackageSelector::Name("oem-[azure].gz".to_string());
+        assert!(name.is_match("oem-[azure].gz"));
+        assert!(!name.is_match("oem-a.gz"));
+
+        let regex = PackageSelector::Regex(Regex::new(r"^oem-\[.*\]\.gz$").unwrap());
+        assert!(regex.is_match("oem-[azure].gz"));
+        assert!(!regex.is_match("oem-azure.gz"));
+
+        let glob = PackageSelector::Glob(GlobSetBuilder::new().add(Glob::new("oem-*.gz").unwrap()).build().unwrap());
+        assert!(glob.is_match("oem-azure.gz"));
+        assert!(!glob.is_match("flatcar_production_update.gz"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use update_format_crau::delta_update;
+
+use super::Package;
+
+const PAYLOAD_SHA256_KEY: &str = "payload-sha256";
+const PAYLOAD_SHA1_KEY: &str = "payload-sha1";
+const DATA_SHA256_KEY: &str = "data-sha256";
+
+// Record of the payload that the verified data of a package was extracted
+// from. It is stored next to the data, e.g. "oem.raw.verified" for "oem.raw",
+// so that a later run can skip the package if the Omaha response still has
+// the same payload. data_sha256 is the hash of the data from the manifest of
+// the payload, in hex, to check the data on disk against.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct VerifiedStamp {
+    pub(crate) payload_sha256: Option<String>,
+    pub(crate) payload_sha1: Option<String>,
+    pub(crate) data_sha256: Option<String>,
+}
+
+impl VerifiedStamp {
+    fn path_for(path: &Path) -> PathBuf {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".verified");
+        PathBuf::from(p)
+    }
+
+    pub(crate) fn from_package(pkg: &Package<'_>) -> Self {
+        let data_info = pkg.payload_info.as_ref().and_then(|info| info.partitions.iter().find(|p| p.name == delta_update::PARTITION_USR).and_then(|p| p.new_info.as_ref()));
+
+        VerifiedStamp {
+            payload_sha256: pkg.hash_sha256.as_ref().map(ToString::to_string),
+            payload_sha1: pkg.hash_sha1.as_ref().map(ToString::to_string),
+            data_sha256: data_info.map(|info| info.hash.iter().map(|b| format!("{:02x}", b)).collect()),
+        }
+    }
+
+    // Whether the stamp was written for the payload that pkg refers to. At
+    // least one hash must be known, and all known hashes must match.
+    pub(crate) fn matches(&self, pkg: &Package<'_>) -> bool {
+        let other = Self::from_package(pkg);
+        let pairs = [(&self.payload_sha256, &other.payload_sha256), (&self.payload_sha1, &other.payload_sha1)];
+
+        pairs.iter().any(|(a, b)| a.is_some() && b.is_some()) && pairs.iter().all(|(a, b)| a.is_none() || b.is_none() || a == b)
+    }
+
+    // Load the stamp stored for the file in path, if the file and its stamp
+    // exist.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        if !path.exists() {
+            return None;
+        }
+
+        let content = fs::read_to_string(Self::path_for(path)).ok()?;
+        let mut stamp = VerifiedStamp::default();
+
+        for line in content.lines() {
+            match line.split_once(": ") {
+                Some((PAYLOAD_SHA256_KEY, v)) => stamp.payload_sha256 = Some(v.to_string()),
+                Some((PAYLOAD_SHA1_KEY, v)) => stamp.payload_sha1 = Some(v.to_string()),
+                Some((DATA_SHA256_KEY, v)) => stamp.data_sha256 = Some(v.to_string()),
+                _ => debug!("ignoring unknown stamp line {:?}", line),
+            }
+        }
+
+        Some(stamp)
+    }
+
+    pub(crate) fn store(&self, path: &Path) -> Result<()> {
+        let spath = Self::path_for(path);
+
+        let mut content = String::new();
+        for (key, value) in [(PAYLOAD_SHA256_KEY, &self.payload_sha256), (PAYLOAD_SHA1_KEY, &self.payload_sha1), (DATA_SHA256_KEY, &self.data_sha256)] {
+            if let Some(value) = value {
+                content.push_str(&format!("{}: {}\n", key, value));
+            }
+        }
+
+        fs::write(&spath, content).context(format!("failed to write stamp ({:?})", spath.display()))
+    }
+
+    // Remove the stamp of the file in path, e.g. before the file is
+    // overwritten. A missing stamp is not an error.
+    pub(crate) fn remove(path: &Path) {
+        _ = fs::remove_file(Self::path_for(path));
+    }
+}
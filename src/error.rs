@@ -0,0 +1,231 @@
+use std::fmt;
+
+use update_format_crau::delta_update;
+use url::Url;
+
+// Errors that callers might want to handle specifically.
+// They are returned wrapped in anyhow::Error, use downcast_ref::<Error>()
+// to get them back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // Content-Length of the server response does not match the size that
+    // was declared for the download, e.g. in the Omaha response.
+    SizeMismatch {
+        expected: u64,
+        content_length: u64,
+    },
+    // Hash of downloaded data does not match the expected one, e.g. "sha256".
+    ChecksumMismatch {
+        algo: &'static str,
+    },
+    // The Omaha server has no update for any of the apps.
+    NoUpdate,
+    // A server responded to a request of url with an HTTP status that is not
+    // a success.
+    HttpStatus {
+        status: u16,
+        url: Url,
+    },
+    // An operation did not finish within its overall time budget.
+    DeadlineExceeded {
+        deadline: std::time::Duration,
+    },
+    // None of the public keys verifies the signature of a payload, or the
+    // signature policy is not fulfilled.
+    SignatureInvalid,
+    // The server of url presented a certificate whose public key is not
+    // one of the pinned ones.
+    PinnedKeyMismatch {
+        url: Url,
+    },
+}
+
+// Category of an error, e.g. to decide whether to retry or how to report it,
+// without matching the error types of every crate involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Connecting to or reading from a server failed, or took too long.
+    Network,
+    // A server responded with an HTTP status that is not a success.
+    Http,
+    // A download is not of the size that was declared for it.
+    SizeMismatch,
+    ChecksumMismatch,
+    SignatureInvalid,
+    Io,
+    Config,
+    // A response or payload is malformed or inconsistent, e.g. invalid XML.
+    Protocol,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::SizeMismatch {
+                ..
+            } => ErrorKind::SizeMismatch,
+            Error::ChecksumMismatch {
+                ..
+            } => ErrorKind::ChecksumMismatch,
+            Error::NoUpdate => ErrorKind::Protocol,
+            Error::HttpStatus {
+                ..
+            } => ErrorKind::Http,
+            Error::DeadlineExceeded {
+                ..
+            } => ErrorKind::Network,
+            Error::SignatureInvalid => ErrorKind::SignatureInvalid,
+            Error::PinnedKeyMismatch {
+                ..
+            } => ErrorKind::SignatureInvalid,
+        }
+    }
+}
+
+impl ErrorKind {
+    // Category of the first error in the chain of err that has a known one.
+    // Works for anyhow::Error through its Deref, e.g. ErrorKind::of(&*err).
+    pub fn of(err: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        let mut cur = Some(err);
+
+        while let Some(e) = cur {
+            if let Some(kind) = Self::of_single(e) {
+                return Some(kind);
+            }
+            cur = e.source();
+        }
+
+        None
+    }
+
+    fn of_single(err: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(e) = err.downcast_ref::<Error>() {
+            return Some(e.kind());
+        }
+        if err.is::<ConfigError>() {
+            return Some(ErrorKind::Config);
+        }
+        if let Some(e) = err.downcast_ref::<delta_update::Error>() {
+            return Some(Self::of_payload(e));
+        }
+        if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+            return Some(if e.is_status() {
+                ErrorKind::Http
+            } else {
+                ErrorKind::Network
+            });
+        }
+        if err.is::<std::io::Error>() {
+            return Some(ErrorKind::Io);
+        }
+        if err.is::<hard_xml::XmlError>() || err.is::<url::ParseError>() {
+            return Some(ErrorKind::Protocol);
+        }
+
+        None
+    }
+
+    fn of_payload(err: &delta_update::Error) -> Self {
+        match err {
+            delta_update::Error::SourceHashMismatch {
+                ..
+            } => ErrorKind::ChecksumMismatch,
+            _ => ErrorKind::Protocol,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SizeMismatch {
+                expected,
+                content_length,
+            } => write!(f, "Content-Length {} does not match expected size {}", content_length, expected),
+            Error::ChecksumMismatch {
+                algo,
+            } => write!(f, "checksum mismatch for {}", algo),
+            Error::NoUpdate => f.write_str("no update available"),
+            Error::HttpStatus {
+                status,
+                url,
+            } => write!(f, "server responded with HTTP status {} to {}", status, url),
+            Error::DeadlineExceeded {
+                deadline,
+            } => write!(f, "deadline of {:?} exceeded", deadline),
+            Error::SignatureInvalid => f.write_str("invalid signature"),
+            Error::PinnedKeyMismatch {
+                url,
+            } => write!(f, "public key of the server of {} is not pinned", url),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Invalid combination of options given to DownloadVerifyBuilder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    // None of an Omaha response, a payload URL or a local payload was given.
+    MissingSource,
+    // No public key to verify the signatures of payloads with.
+    MissingPubkey,
+    // A target filename was given for all packages of an Omaha response,
+    // which would overwrite each other.
+    TargetFilenameWithoutSingle,
+    // A limit of concurrent downloads or verifications is 0.
+    ZeroConcurrency,
+    // Payload URL without a file name to download into, e.g. "https://example.com/".
+    InvalidPayloadUrl(url::Url),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::MissingSource => f.write_str("either an Omaha response, a payload URL or a local payload must be given"),
+            ConfigError::MissingPubkey => f.write_str("at least one public key must be given"),
+            ConfigError::TargetFilenameWithoutSingle => f.write_str("target filename can only be given for a single package, e.g. with take_first_match"),
+            ConfigError::ZeroConcurrency => f.write_str("concurrency limits must be at least 1"),
+            ConfigError::InvalidPayloadUrl(u) => write!(f, "invalid payload URL {}", u),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind_of() {
+        let err = anyhow::Error::from(Error::ChecksumMismatch {
+            algo: "sha256",
+        })
+        .context("download failed");
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::ChecksumMismatch));
+
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound)).context("failed to open payload");
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::Io));
+
+        let err: Box<dyn std::error::Error> = ConfigError::MissingPubkey.into();
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::Config));
+
+        assert_eq!(ErrorKind::of(&*anyhow::anyhow!("unknown")), None);
+
+        // Errors of payloads come wrapped in anyhow from update-format-crau.
+        let err = anyhow::Error::from(delta_update::Error::OversizedChunk {
+            data_offset: 0,
+            limit: 4096,
+        })
+        .context("failed to extract payload");
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::Protocol));
+
+        let err = anyhow::Error::from(delta_update::Error::SourceHashMismatch {
+            partition: "root".into(),
+            expected: vec![0],
+            actual: vec![1],
+        });
+        assert_eq!(ErrorKind::of(&*err), Some(ErrorKind::ChecksumMismatch));
+    }
+}
@@ -1,9 +1,28 @@
 mod download;
-pub use download::DownloadResult;
+pub use download::{DownloadOptions, DownloadResult, ExpectedHashes, ExpectedSize, Netrc};
 pub use download::download_and_hash;
+pub use download::download_and_hash_into;
+pub use download::{Preflight, preflight};
 pub use download::hash_on_disk;
+pub use download::hash_from_reader;
+pub use download::{CleanupPolicy, ContentEncoding, DownloadOrder, DownloadVerify, DownloadVerifyBuilder, HttpVersion, PackageReport, PlannedPackage, RunReport, Source};
+pub use download::{Package, PackageSelector, PackageStatus};
+pub use download::{VerifiedPayload, verify_payload};
+pub use download::{PackageMetrics, PhaseMetrics};
+pub use download::ProgressConfig;
+
+pub use update_format_crau::delta_update::{PayloadInfo, SignaturePolicy};
+pub use update_format_crau::verify_sig::{DigestAlgo, PubkeySource};
+
+pub mod error;
 
 mod util;
-pub use util::retry_loop;
+pub use util::{CancelFlag, FailedAttempt, RetryError, RetryPolicy, retry_loop, retry_with_policy, retry_with_policy_async, with_deadline_cancel};
 
 pub mod request;
+
+mod updater;
+pub use updater::{UpdateReport, Updater};
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
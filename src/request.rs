@@ -1,7 +1,14 @@
 use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use anyhow::{Context, Result};
-use hard_xml::XmlWrite;
+use anyhow::{Context, Result, bail};
+#[cfg(not(feature = "quick-xml"))]
+use hard_xml::{XmlRead, XmlWrite};
+use tracing::{Instrument, Span, debug, info_span};
+use url::Url;
 
 //
 // SERVER=https://public.update.flatcar-linux.net/v1/update/
@@ -18,19 +25,148 @@ const UPDATER_VERSION_STR: &str = "ue-rs-0.0.0";
 
 const OS_PLATFORM: &str = "CoreOS";
 const OS_VERSION: &str = "Chateau";
+const OS_ARCH_DEFAULT: &str = "x86_64";
+
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+const OEM_RELEASE_PATH: &str = "/etc/oem-release";
 
 const APP_ID: omaha::Uuid = omaha::uuid!("{e96281a6-d1af-4bde-9a0a-97b76e56dc57}");
 
-pub struct Parameters<'a> {
-    pub app_version: Cow<'a, str>,
+// Parameters of one app to check for updates, e.g. the OS itself or an
+// OEM sysext.
+#[derive(Clone)]
+pub struct AppParameters<'a> {
+    pub id: omaha::Uuid,
+
+    pub version: Cow<'a, str>,
     pub track: Cow<'a, str>,
 
+    // Version that ran before, e.g. after a rollback.
+    pub previous_version: Option<Cow<'a, str>>,
+    // Track that the machine migrates from, when switching channels.
+    pub from_track: Option<Cow<'a, str>>,
+}
+
+impl<'a> AppParameters<'a> {
+    // Parameters of the Flatcar OS app.
+    pub fn os(version: Cow<'a, str>, track: Cow<'a, str>) -> Self {
+        AppParameters {
+            id: APP_ID,
+            version,
+            track,
+            previous_version: None,
+            from_track: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Parameters<'a> {
+    // Omaha endpoint, e.g. of a self-hosted Nebraska.
+    // The public Flatcar update server if not given.
+    pub server_url: Option<Cow<'a, str>>,
+
+    // The first app is the OS, its version is also sent as the version of the OS.
+    pub apps: Vec<AppParameters<'a>>,
+
     pub machine_id: Cow<'a, str>,
+    // Random ID of the current boot, see read_boot_id.
+    pub boot_id: Option<omaha::Uuid>,
+
+    // Nebraska groups payloads per board, e.g. "amd64-usr".
+    pub board: Option<Cow<'a, str>>,
+    // Architecture of the machine, x86_64 if not given.
+    pub arch: Option<Cow<'a, str>>,
+    // OEM of the machine and its version, see OemRelease.
+    pub oem: Option<Cow<'a, str>>,
+    pub oem_version: Option<Cow<'a, str>>,
+    // Platform of the OEM, e.g. "azure" or "qemu".
+    pub oem_platform: Option<Cow<'a, str>>,
+
+    // Whether delta payloads can be applied.
+    pub delta_okay: bool,
+
+    // Retries of perform and perform_async on transient errors.
+    pub retry_policy: crate::RetryPolicy,
 }
 
-pub fn perform(client: &reqwest::blocking::Client, parameters: Parameters<'_>) -> Result<String> {
+fn read_boot_id_from(path: &Path) -> Result<omaha::Uuid> {
+    let boot_id = fs::read_to_string(path).context(format!("failed to read {}", path.display()))?;
+    omaha::Uuid::parse_flexible(&boot_id).context(format!("invalid boot ID {:?}", boot_id.trim()))
+}
+
+// Read the ID of the current boot from the kernel, so that the server can
+// tell reboots apart from new instances.
+pub fn read_boot_id() -> Result<omaha::Uuid> {
+    read_boot_id_from(Path::new(BOOT_ID_PATH))
+}
+
+// OEM of the machine, as given in /etc/oem-release, e.g.
+//   ID=azure
+//   VERSION_ID=2.6.0.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OemRelease {
+    pub id: String,
+    pub version: Option<String>,
+}
+
+impl FromStr for OemRelease {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut id = None;
+        let mut version = None;
+
+        for line in s.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').to_string();
+
+            match key.trim() {
+                "ID" => id = Some(value),
+                "VERSION_ID" => version = Some(value),
+                _ => {}
+            }
+        }
+
+        Ok(OemRelease {
+            id: id.context("no ID in OEM release")?,
+            version,
+        })
+    }
+}
+
+impl OemRelease {
+    pub fn read() -> Result<Self> {
+        let release = fs::read_to_string(OEM_RELEASE_PATH).context(format!("failed to read {}", OEM_RELEASE_PATH))?;
+        Self::from_str(&release)
+    }
+}
+
+// Request that is ready to be sent with either a blocking or an async client.
+struct PreparedRequest {
+    url: String,
+    body: String,
+    span: Span,
+    retry_policy: crate::RetryPolicy,
+}
+
+// Without events, check all apps for updates. With events, only report
+// them for the first app, without checking for updates.
+fn prepare<'a>(parameters: Parameters<'a>, events: Vec<omaha::request::Event<'a>>) -> Result<PreparedRequest> {
+    let server_url = parameters.server_url.unwrap_or(Cow::Borrowed(UPDATE_URL));
+    let Some(os_app) = parameters.apps.first() else {
+        bail!("no apps to check for updates");
+    };
+    let span = info_span!("request", url = %server_url, app_version = %os_app.version, track = %os_app.track);
+    let entered = span.enter();
+
     let req_body = {
-        let r = omaha::Request {
+        let arch = parameters.arch.unwrap_or(Cow::Borrowed(OS_ARCH_DEFAULT));
+        let service_pack = format!("{}_{}", os_app.version, arch);
+
+        let mut r = omaha::Request {
             protocol_version: Cow::Borrowed(PROTOCOL_VERSION),
 
             version: Cow::Borrowed(UPDATER_VERSION_STR),
@@ -42,43 +178,290 @@ pub fn perform(client: &reqwest::blocking::Client, parameters: Parameters<'_>) -
             os: omaha::request::Os {
                 platform: Cow::Borrowed(OS_PLATFORM),
                 version: Cow::Borrowed(OS_VERSION),
-                #[rustfmt::skip]
-                service_pack: Cow::Owned(
-                    format!("{}_{}", parameters.app_version, "x86_64")
-                ),
+                service_pack: Cow::Owned(service_pack),
+                arch: Some(arch),
             },
 
+            apps: parameters
+                .apps
+                .into_iter()
+                .map(|app| omaha::request::App {
+                    id: app.id,
+                    version: app.version,
+                    track: app.track,
+
+                    previous_version: app.previous_version,
+                    from_track: app.from_track,
+
+                    boot_id: parameters.boot_id,
+
+                    oem: parameters.oem.clone(),
+                    oem_version: parameters.oem_version.clone(),
+                    oem_platform: parameters.oem_platform.clone(),
+
+                    board: parameters.board.clone(),
+
+                    machine_id: parameters.machine_id.clone(),
+
+                    ping: None,
+
+                    update_check: Some(omaha::request::AppUpdateCheck {
+                        delta_okay: parameters.delta_okay,
+                    }),
+                    events: Vec::new(),
+                })
+                .collect(),
+        };
+
+        if !events.is_empty() {
+            r.apps.truncate(1);
+            r.apps[0].update_check = None;
+            r.apps[0].events = events;
+        }
+
+        #[cfg(not(feature = "quick-xml"))]
+        let req_body = r.to_string().context("failed to convert to string")?;
+        #[cfg(feature = "quick-xml")]
+        let req_body = omaha::quick::request_to_string(&r)?;
+
+        req_body
+    };
+
+    debug!("request body:\n\t{}", req_body);
+
+    drop(entered);
+    Ok(PreparedRequest {
+        url: server_url.into_owned(),
+        body: req_body,
+        span,
+        retry_policy: parameters.retry_policy,
+    })
+}
+
+fn check_status(status: reqwest::StatusCode, url: &Url) -> Result<()> {
+    if status.is_success() {
+        return Ok(());
+    }
+
+    Err(crate::error::Error::HttpStatus {
+        status: status.as_u16(),
+        url: url.clone(),
+    }
+    .into())
+}
+
+// Errors of the server itself, overload and failures to connect or to
+// transfer are worth retrying. Other client errors, and errors building the
+// request or parsing the response, would just fail again.
+fn is_retryable(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<crate::error::Error>() {
+        Some(crate::error::Error::HttpStatus {
+            status,
+            ..
+        }) => *status >= 500 || *status == 429,
+        Some(_) => false,
+        None => err.chain().filter_map(|e| e.downcast_ref::<reqwest::Error>()).any(|e| e.is_connect() || e.is_timeout() || e.is_request() || e.is_body()),
+    }
+}
+
+fn send(client: &reqwest::blocking::Client, req: PreparedRequest) -> Result<String> {
+    let _span = req.span.enter();
+
+    crate::retry_with_policy(
+        &req.retry_policy,
+        || {
             #[rustfmt::skip]
-            apps: vec![
-                omaha::request::App {
-                    id: APP_ID,
-                    version: parameters.app_version,
-                    track: parameters.track,
+            let resp = client.post(&req.url)
+                .body(req.body.clone())
+                .send()
+                .context(format!("client post send({}) failed", req.url))?;
+            check_status(resp.status(), resp.url())?;
 
-                    boot_id: None,
+            resp.text().context("failed to get response")
+        },
+        is_retryable,
+    )
+    .map_err(crate::RetryError::into_anyhow)
+}
 
-                    oem: None,
-                    oem_version: None,
+pub fn perform(client: &reqwest::blocking::Client, parameters: Parameters<'_>) -> Result<String> {
+    send(client, prepare(parameters, Vec::new())?)
+}
 
-                    machine_id: parameters.machine_id,
+// Report events to the server, e.g. the outcome of an update, for the
+// first app of parameters.
+pub fn report_events<'a>(client: &reqwest::blocking::Client, parameters: Parameters<'a>, events: Vec<omaha::request::Event<'a>>) -> Result<ParsedResponse> {
+    if events.is_empty() {
+        bail!("no events to report");
+    }
 
-                    update_check: Some(omaha::request::AppUpdateCheck)
-                }
+    ParsedResponse::parse(send(client, prepare(parameters, events)?)?)
+}
+
+// Response of the server, together with the XML it was parsed from,
+// e.g. for debugging.
+#[derive(Debug)]
+pub struct ParsedResponse {
+    // Shared, e.g. with Source::Response of DownloadVerify.
+    pub response: Arc<omaha::Response<'static>>,
+    pub raw: String,
+}
+
+impl ParsedResponse {
+    pub fn parse(raw: String) -> Result<Self> {
+        #[cfg(not(feature = "quick-xml"))]
+        let response = omaha::Response::from_str(&raw).context("failed to parse response")?.into_owned();
+        #[cfg(feature = "quick-xml")]
+        let response = omaha::quick::response_from_str(&raw).context("failed to parse response")?;
+
+        Ok(ParsedResponse {
+            response: Arc::new(response),
+            raw,
+        })
+    }
+}
+
+// Like perform, but parse the response.
+pub fn perform_parsed(client: &reqwest::blocking::Client, parameters: Parameters<'_>) -> Result<ParsedResponse> {
+    ParsedResponse::parse(perform(client, parameters)?)
+}
+
+// Like perform, retrying with the same policy. Waiting in between needs a
+// tokio runtime, as the async client of reqwest does.
+pub async fn perform_async(client: &reqwest::Client, parameters: Parameters<'_>) -> Result<String> {
+    let req = prepare(parameters, Vec::new())?;
+
+    crate::retry_with_policy_async(
+        &req.retry_policy,
+        || async {
+            #[rustfmt::skip]
+            let resp = client.post(&req.url)
+                .body(req.body.clone())
+                .send()
+                .await
+                .context(format!("client post send({}) failed", req.url))?;
+            check_status(resp.status(), resp.url())?;
+
+            resp.text().await.context("failed to get response")
+        },
+        is_retryable,
+    )
+    .instrument(req.span.clone())
+    .await
+    .map_err(crate::RetryError::into_anyhow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+
+    #[test]
+    fn test_prepare() {
+        const OEM_APP_ID: omaha::Uuid = omaha::uuid!("{9e4a6c6f-0b1a-4d3e-8d3f-4f7a1b2c3d4e}");
+
+        let params = Parameters {
+            server_url: None,
+            apps: vec![
+                AppParameters::os(Cow::Borrowed("3510.2.0"), Cow::Borrowed("stable")),
+                AppParameters {
+                    id: OEM_APP_ID,
+                    version: Cow::Borrowed("1.0"),
+                    track: Cow::Borrowed("beta"),
+                    previous_version: None,
+                    from_track: None,
+                },
             ],
+            machine_id: Cow::Borrowed("abce671d61774703ac7be60715220bfe"),
+            boot_id: None,
+            board: None,
+            arch: Some(Cow::Borrowed("aarch64")),
+            oem: None,
+            oem_version: None,
+            oem_platform: None,
+            delta_okay: false,
+            retry_policy: crate::RetryPolicy::default(),
         };
+        let req = prepare(params.clone(), Vec::new()).unwrap();
 
-        r.to_string().context("failed to convert to string")?
-    };
+        assert_eq!(req.url, UPDATE_URL);
+
+        let parsed = omaha::Request::from_str(&req.body).unwrap();
+        assert_eq!(parsed.os.service_pack, "3510.2.0_aarch64");
+        let apps: Vec<_> = parsed.apps.iter().map(|a| (a.id.to_string(), &*a.track)).collect();
+        assert_eq!(apps, [(APP_ID.to_string(), "stable"), (OEM_APP_ID.to_string(), "beta")]);
+        assert!(parsed.apps.iter().all(|a| a.update_check.is_some()));
+
+        let event = omaha::request::Event {
+            event_type: omaha::request::EventType::UpdateComplete,
+            event_result: omaha::request::EventResult::Success,
+            error_code: None,
+            previous_version: None,
+            downloaded: None,
+            total: None,
+            download_time_ms: None,
+        };
+        let req = prepare(params, vec![event]).unwrap();
+        let parsed = omaha::Request::from_str(&req.body).unwrap();
+        assert_eq!(parsed.apps.len(), 1);
+        assert!(parsed.apps[0].update_check.is_none());
+        assert_eq!(parsed.apps[0].events.len(), 1);
+    }
+
+    #[test]
+    fn test_oem_release() {
+        let release = OemRelease::from_str("ID=azure\nVERSION_ID=2.6.0.2\nNAME=\"Microsoft Azure\"\n").unwrap();
+        assert_eq!(
+            release,
+            OemRelease {
+                id: "azure".to_string(),
+                version: Some("2.6.0.2".to_string()),
+            }
+        );
+
+        assert_eq!(OemRelease::from_str("ID=\"qemu\"").unwrap().version, None);
+        assert!(OemRelease::from_str("VERSION_ID=1").is_err());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |status| {
+            anyhow::Error::from(crate::error::Error::HttpStatus {
+                status,
+                url: Url::parse("https://public.update.flatcar-linux.net/v1/update/").unwrap(),
+            })
+        };
+
+        assert!(is_retryable(&status(503)));
+        assert!(is_retryable(&status(429)));
+        assert!(!is_retryable(&status(404)));
+        assert!(!is_retryable(&status(408)));
+        assert!(!is_retryable(&anyhow::anyhow!("failed to parse response")));
+
+        // Nothing listens on port 1, so connecting fails.
+        let connect = reqwest::blocking::Client::new().post("http://127.0.0.1:1/").send().unwrap_err();
+        assert!(is_retryable(&anyhow::Error::from(connect).context("client post send failed")));
+        let builder = reqwest::blocking::Client::new().post("http://[::1/").send().unwrap_err();
+        assert!(!is_retryable(&anyhow::Error::from(builder)));
+        assert!(check_status(
+            reqwest::StatusCode::OK,
+            &Url::parse("https://public.update.flatcar-linux.net/v1/update/").unwrap()
+        )
+        .is_ok());
+    }
 
-    // TODO: remove
-    println!("request body:\n\t{}", req_body);
-    println!();
+    #[test]
+    fn test_read_boot_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boot_id");
 
-    #[rustfmt::skip]
-    let resp = client.post(UPDATE_URL)
-        .body(req_body)
-        .send()
-        .context("client post send({UPDATE_URL}) failed")?;
+        fs::write(&path, "5a9f3c1e-7d2b-4c8a-9e6f-0b1d2c3e4f5a\n").unwrap();
+        assert_eq!(
+            read_boot_id_from(&path).unwrap().to_string(),
+            "{5a9f3c1e-7d2b-4c8a-9e6f-0b1d2c3e4f5a}"
+        );
 
-    resp.text().context("failed to get response")
+        fs::write(&path, "not a uuid").unwrap();
+        assert!(read_boot_id_from(&path).is_err());
+    }
 }
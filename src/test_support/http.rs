@@ -0,0 +1,303 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use reqwest::StatusCode;
+use tracing::debug;
+use url::Url;
+
+// Connections that stall, e.g. of a client that crashed, must not block
+// the server forever.
+const CONN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Request as a test server received it.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    // Value of the first header called name, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<ReceivedRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).context("failed to read request line")?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line {:?}", request_line);
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).context("failed to read header")?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = ReceivedRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+
+    let content_length = match request.header("content-length") {
+        Some(len) => len.parse().context(format!("invalid content length {:?}", len))?,
+        None => 0,
+    };
+    request.body = vec![0u8; content_length];
+    reader.read_exact(&mut request.body).context("failed to read request body")?;
+
+    Ok(request)
+}
+
+// Write the status line and headers of a response. Each connection only
+// carries a single request.
+pub(super) fn write_head(stream: &mut TcpStream, status: StatusCode, headers: &[(&str, String)]) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status.as_u16(), status.canonical_reason().unwrap_or(""));
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    stream.write_all(head.as_bytes()).context("failed to write response header")
+}
+
+// Thread that accepts connections on localhost, and passes the request of
+// each to a handler, one after another. It stops when dropped.
+pub(super) struct ServerThread {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ServerThread {
+    pub(super) fn spawn<F>(handler: F) -> Result<Self>
+    where
+        F: Fn(ReceivedRequest, TcpStream) -> Result<()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").context("failed to bind test server")?;
+        let addr = listener.local_addr().context("failed to get address of test server")?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let res = stream.context("failed to accept connection").and_then(|stream| {
+                        stream.set_read_timeout(Some(CONN_TIMEOUT)).context("failed to set read timeout")?;
+                        handler(read_request(&stream)?, stream)
+                    });
+                    if let Err(err) = res {
+                        debug!("test server: {:#}", err);
+                    }
+                }
+            })
+        };
+
+        Ok(ServerThread {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub(super) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ServerThread {
+    fn drop(&mut self) {
+        // Wake up the thread, which is blocked in accepting connections.
+        self.shutdown.store(true, Ordering::SeqCst);
+        _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+// Failure of a single GET request to a FixtureServer.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    // Close the connection without responding at all.
+    Reset,
+    // Respond with the given status and an empty body, e.g. 503.
+    Status(u16),
+    // Send the body in pieces of chunk bytes, waiting delay after each.
+    Trickle {
+        chunk: usize,
+        delay: Duration,
+    },
+    // Announce the whole body, but close the connection after the given
+    // number of bytes of it.
+    Truncate(usize),
+}
+
+#[derive(Default)]
+struct FixtureState {
+    files: HashMap<String, Vec<u8>>,
+    // Faults of the next GET requests of each file, in order.
+    faults: HashMap<String, VecDeque<Fault>>,
+    requests: Vec<ReceivedRequest>,
+}
+
+// HTTP server for tests of downloads. It serves files, also in ranges of
+// "Range: bytes=N-" or "bytes=N-M", and fails requests of a file as told,
+// so that resuming, retrying and failing over to mirrors can be tested
+// deterministically.
+pub struct FixtureServer {
+    server: ServerThread,
+    state: Arc<Mutex<FixtureState>>,
+}
+
+impl FixtureServer {
+    pub fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(FixtureState::default()));
+
+        let server = {
+            let state = state.clone();
+            ServerThread::spawn(move |request, stream| serve_fixture(&state, request, stream))?
+        };
+
+        Ok(FixtureServer {
+            server,
+            state,
+        })
+    }
+
+    pub fn url(&self, name: &str) -> Url {
+        Url::parse(&format!("http://{}/{}", self.server.addr(), name)).expect("valid URL of fixture server")
+    }
+
+    pub fn add_file(&self, name: &str, data: Vec<u8>) {
+        self.state.lock().unwrap().files.insert(name.to_string(), data);
+    }
+
+    // Fail the next GET request of name that has no fault yet. HEAD requests
+    // are never failed.
+    pub fn push_fault(&self, name: &str, fault: Fault) {
+        self.state.lock().unwrap().faults.entry(name.to_string()).or_default().push_back(fault);
+    }
+
+    // Requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    // Like requests, only those of name.
+    pub fn requests_of(&self, name: &str) -> Vec<ReceivedRequest> {
+        let path = format!("/{}", name);
+        self.requests().into_iter().filter(|r| r.path == path).collect()
+    }
+}
+
+// Parse a Range header of a single range, into its first byte and its last
+// byte if given.
+fn parse_range(value: &str) -> Option<(usize, Option<usize>)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+    Some((start.trim().parse().ok()?, end))
+}
+
+fn serve_fixture(state: &Mutex<FixtureState>, request: ReceivedRequest, mut stream: TcpStream) -> Result<()> {
+    let name = request.path.trim_start_matches('/').to_string();
+    let head_only = request.method == "HEAD";
+    let range = request.header("range").map(|r| parse_range(r).context(format!("unsupported range {:?}", r))).transpose();
+
+    let (data, fault) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        let fault = match head_only {
+            true => None,
+            false => state.faults.get_mut(&name).and_then(|f| f.pop_front()),
+        };
+        (state.files.get(&name).cloned(), fault)
+    };
+
+    match fault {
+        Some(Fault::Reset) => {
+            _ = stream.shutdown(Shutdown::Both);
+            return Ok(());
+        }
+        Some(Fault::Status(status)) => {
+            let status = StatusCode::from_u16(status).context(format!("invalid status {}", status))?;
+            return write_head(&mut stream, status, &[("Content-Length", "0".to_string())]);
+        }
+        _ => {}
+    }
+
+    let Some(data) = data else {
+        return write_head(&mut stream, StatusCode::NOT_FOUND, &[("Content-Length", "0".to_string())]);
+    };
+
+    let (status, body, mut headers) = match range? {
+        Some((start, _)) if start >= data.len() => {
+            let headers = vec![("Content-Range", format!("bytes */{}", data.len())), ("Content-Length", "0".to_string())];
+            return write_head(&mut stream, StatusCode::RANGE_NOT_SATISFIABLE, &headers);
+        }
+        Some((start, end)) => {
+            let end = end.map_or(data.len() - 1, |end| end.min(data.len() - 1));
+            let content_range = format!("bytes {}-{}/{}", start, end, data.len());
+            (
+                StatusCode::PARTIAL_CONTENT,
+                &data[start..=end],
+                vec![("Content-Range", content_range)],
+            )
+        }
+        None => (StatusCode::OK, &data[..], Vec::new()),
+    };
+    headers.push(("Content-Length", body.len().to_string()));
+    headers.push(("Accept-Ranges", "bytes".to_string()));
+    write_head(&mut stream, status, &headers)?;
+    if head_only {
+        return Ok(());
+    }
+
+    match fault {
+        Some(Fault::Truncate(len)) => {
+            stream.write_all(&body[..len.min(body.len())]).context("failed to write response body")?;
+            _ = stream.shutdown(Shutdown::Both);
+        }
+        Some(Fault::Trickle {
+            chunk,
+            delay,
+        }) => {
+            for piece in body.chunks(chunk.max(1)) {
+                stream.write_all(piece).context("failed to write response body")?;
+                stream.flush().context("failed to write response body")?;
+                thread::sleep(delay);
+            }
+        }
+        _ => stream.write_all(body).context("failed to write response body")?,
+    }
+
+    Ok(())
+}
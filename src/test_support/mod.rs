@@ -0,0 +1,220 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use hard_xml::XmlWrite;
+use reqwest::StatusCode;
+use sha2::Digest;
+use url::Url;
+
+mod http;
+pub use http::{Fault, FixtureServer, ReceivedRequest};
+
+use http::{ServerThread, write_head};
+
+const UPDATE_PATH: &str = "/v1/update/";
+const FILES_PATH: &str = "/files/";
+
+#[derive(Default)]
+struct State {
+    // Body of the responses to update checks, none until it is set.
+    response: Option<String>,
+    files: HashMap<String, Vec<u8>>,
+    // Bodies of the update checks received so far.
+    requests: Vec<String>,
+}
+
+// Omaha server for tests, listening on localhost in a thread of its own.
+// It answers update checks with a configurable response and serves the
+// payloads of its packages, so that request::perform and DownloadVerify can
+// be tested end to end. Connections are handled one after another.
+pub struct MockOmahaServer {
+    server: ServerThread,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockOmahaServer {
+    pub fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server = {
+            let state = state.clone();
+            ServerThread::spawn(move |request, stream| handle(&state, request, stream))?
+        };
+
+        Ok(MockOmahaServer {
+            server,
+            state,
+        })
+    }
+
+    // URL to send update checks to, e.g. as server_url of request::Parameters.
+    pub fn update_url(&self) -> String {
+        format!("http://{}{}", self.server.addr(), UPDATE_PATH)
+    }
+
+    // Base URL of the files, which responses use as codebase of their packages.
+    pub fn files_url(&self) -> Url {
+        Url::parse(&format!("http://{}{}", self.server.addr(), FILES_PATH)).expect("valid URL of mock Omaha server")
+    }
+
+    pub fn set_response(&self, response: &omaha::Response<'_>) -> Result<()> {
+        let xml = response.to_string().context("failed to write response")?;
+        self.set_response_xml(xml);
+        Ok(())
+    }
+
+    // Like set_response, e.g. for responses that omaha::Response cannot
+    // represent.
+    pub fn set_response_xml(&self, xml: String) {
+        self.state.lock().unwrap().response = Some(xml);
+    }
+
+    // Serve data as name below files_url, and return the package to list it
+    // in a response, with its size and hashes.
+    pub fn add_file(&self, name: &str, data: Vec<u8>) -> omaha::response::Package<'static> {
+        let package = omaha::response::Package {
+            name: Cow::Owned(name.to_string()),
+            hash: Some(omaha::Hash::from_bytes(sha1::Sha1::digest(&data).to_vec().into_boxed_slice())),
+            size: omaha::FileSize::from_bytes(data.len()),
+            required: false,
+            hash_sha256: Some(omaha::Hash::from_bytes(sha2::Sha256::digest(&data).to_vec().into_boxed_slice())),
+        };
+
+        self.state.lock().unwrap().files.insert(name.to_string(), data);
+        package
+    }
+
+    // Response offering an update of app_id to version, made of packages
+    // that are served by add_file.
+    pub fn update_response(&self, app_id: omaha::Uuid, version: &str, packages: Vec<omaha::response::Package<'static>>) -> omaha::Response<'static> {
+        omaha::Response {
+            protocol_version: Cow::Borrowed("3.0"),
+            server: None,
+            day_start: None,
+            apps: vec![omaha::response::App {
+                id: app_id,
+                status: Cow::Borrowed("ok"),
+                ping: None,
+                update_check: Some(omaha::response::UpdateCheck {
+                    status: omaha::response::UpdateCheckStatus::Ok,
+                    urls: vec![self.files_url()],
+                    skipped_urls: Vec::new(),
+                    manifest: Some(omaha::response::Manifest {
+                        version: Cow::Owned(version.to_string()),
+                        packages,
+                        actions: Vec::new(),
+                    }),
+                    extensions: Vec::new(),
+                }),
+                events: Vec::new(),
+                extensions: Vec::new(),
+            }],
+            extensions: Vec::new(),
+        }
+    }
+
+    // Bodies of the update checks received so far, oldest first.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+fn handle(state: &Mutex<State>, request: ReceivedRequest, mut stream: TcpStream) -> Result<()> {
+    let mut state = state.lock().unwrap();
+    let (status, content_type, data) = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", UPDATE_PATH) => {
+            state.requests.push(String::from_utf8_lossy(&request.body).into_owned());
+            match &state.response {
+                Some(xml) => (StatusCode::OK, "application/xml", xml.clone().into_bytes()),
+                None => (StatusCode::INTERNAL_SERVER_ERROR, "text/plain", b"no response set".to_vec()),
+            }
+        }
+        ("GET", path) => match path.strip_prefix(FILES_PATH).and_then(|name| state.files.get(name)) {
+            Some(data) => (StatusCode::OK, "application/octet-stream", data.clone()),
+            None => (StatusCode::NOT_FOUND, "text/plain", b"not found".to_vec()),
+        },
+        _ => (StatusCode::METHOD_NOT_ALLOWED, "text/plain", b"method not allowed".to_vec()),
+    };
+    drop(state);
+
+    let headers = [("Content-Type", content_type.to_string()), ("Content-Length", data.len().to_string())];
+    write_head(&mut stream, status, &headers)?;
+    stream.write_all(&data).context("failed to write response body")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use update_format_crau::delta_update::writer::PayloadWriter;
+    use update_format_crau::verify_sig;
+
+    use crate::request::{self, AppParameters, Parameters};
+    use crate::{DownloadVerifyBuilder, PackageSelector, PackageStatus, PubkeySource, Source};
+
+    fn parameters(server: &MockOmahaServer) -> Parameters<'static> {
+        Parameters {
+            server_url: Some(Cow::Owned(server.update_url())),
+
+            apps: vec![AppParameters::os(Cow::Borrowed("3732.0.0"), Cow::Borrowed("stable"))],
+            machine_id: Cow::Borrowed("f9e1b1ee7b5e4b8f8ac5b8b8b5bd3b8c"),
+            boot_id: None,
+
+            board: None,
+            arch: None,
+            oem: None,
+            oem_version: None,
+            oem_platform: None,
+
+            delta_okay: false,
+            retry_policy: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_mock_omaha_server() {
+        let server = MockOmahaServer::start().unwrap();
+        let client = reqwest::blocking::Client::new();
+
+        let image = vec![3u8; 8192];
+        let private_key = verify_sig::get_private_key_file("src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let payload = PayloadWriter::new().build(&image, private_key).unwrap();
+
+        let os_app = AppParameters::os(Cow::Borrowed(""), Cow::Borrowed(""));
+        let package = server.add_file("oem-azure.gz", payload);
+        server.set_response(&server.update_response(os_app.id, "3733.0.0", vec![package])).unwrap();
+
+        let parsed = request::perform_parsed(&client, parameters(&server)).unwrap();
+        assert_eq!(server.requests().len(), 1);
+        assert!(server.requests()[0].contains(r#"version="3732.0.0""#));
+
+        let (status_tx, status_rx) = std::sync::mpsc::channel();
+        let dir = tempfile::tempdir().unwrap();
+        let report = DownloadVerifyBuilder::new(
+            dir.path().to_str().unwrap().to_string(),
+            vec![PubkeySource::Path("src/testdata/public_key_test_pkcs8.pem".to_string())],
+        )
+        .source(Source::Response(parsed.response))
+        .package_selectors(vec![PackageSelector::Name("oem-azure.gz".to_string())])
+        .on_status_change(move |name, status| status_tx.send((name.to_string(), status.clone())).unwrap())
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+        assert_eq!(report.packages.len(), 1);
+        assert!(matches!(report.packages[0].status, PackageStatus::Verified));
+        assert_eq!(fs::read(&report.packages[0].output_path).unwrap(), image);
+
+        let statuses: Vec<_> = status_rx.try_iter().collect();
+        assert_eq!(
+            statuses,
+            [("oem-azure.gz".to_string(), PackageStatus::ToDownload), ("oem-azure.gz".to_string(), PackageStatus::Unverified), ("oem-azure.gz".to_string(), PackageStatus::Verified),]
+        );
+    }
+}
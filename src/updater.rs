@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use omaha::request::{Event, EventResult, EventType};
+use reqwest::blocking::Client;
+use tracing::{info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::request::{self, ParsedResponse, Parameters};
+use crate::{DownloadVerifyBuilder, RunReport, Source};
+
+// Error codes of update_engine, which Nebraska knows about.
+const ERROR_CODE_GENERIC: i32 = 1;
+const ERROR_CODE_DOWNLOAD_TRANSFER: i32 = 9;
+const ERROR_CODE_HASH_MISMATCH: i32 = 10;
+const ERROR_CODE_SIZE_MISMATCH: i32 = 11;
+const ERROR_CODE_PAYLOAD_VERIFICATION: i32 = 12;
+
+// Whole update flow: check for updates at the Omaha server, then download,
+// verify and extract the packages of the response that download_verify
+// selects through its package selectors. The source of download_verify is
+// set to the response.
+pub struct Updater<'a> {
+    parameters: Parameters<'a>,
+    download_verify: DownloadVerifyBuilder,
+    client: Option<Client>,
+    report_events: bool,
+}
+
+#[derive(Debug)]
+pub struct UpdateReport {
+    pub response: ParsedResponse,
+    // Empty if the server has no update.
+    pub run: RunReport,
+}
+
+fn error_code(err: &anyhow::Error) -> i32 {
+    match ErrorKind::of(&**err) {
+        Some(ErrorKind::Network | ErrorKind::Http) => ERROR_CODE_DOWNLOAD_TRANSFER,
+        Some(ErrorKind::ChecksumMismatch) => ERROR_CODE_HASH_MISMATCH,
+        Some(ErrorKind::SizeMismatch) => ERROR_CODE_SIZE_MISMATCH,
+        Some(ErrorKind::SignatureInvalid) => ERROR_CODE_PAYLOAD_VERIFICATION,
+        Some(ErrorKind::Io | ErrorKind::Config | ErrorKind::Protocol) | None => ERROR_CODE_GENERIC,
+    }
+}
+
+// Events that tell the server about the outcome of DownloadVerify::run.
+fn outcome_events(result: &Result<RunReport>) -> Vec<Event<'static>> {
+    match result {
+        Ok(report) if report.is_empty() => Vec::new(),
+        Ok(report) => vec![Event {
+            event_type: EventType::UpdateDownloadFinished,
+            event_result: EventResult::Success,
+            error_code: None,
+            previous_version: None,
+            downloaded: Some(report.bytes_downloaded()),
+            total: Some(report.packages.iter().map(|p| p.size.bytes() as u64).sum()),
+            download_time_ms: Some(report.packages.iter().map(|p| p.metrics.download.duration.as_millis() as u64).sum()),
+        }],
+        Err(err) if err.downcast_ref::<Error>() == Some(&Error::NoUpdate) => Vec::new(),
+        Err(err) => vec![Event {
+            event_type: EventType::UpdateComplete,
+            event_result: EventResult::Error,
+            error_code: Some(error_code(err)),
+            previous_version: None,
+            downloaded: None,
+            total: None,
+            download_time_ms: None,
+        }],
+    }
+}
+
+impl<'a> Updater<'a> {
+    pub fn new(parameters: Parameters<'a>, download_verify: DownloadVerifyBuilder) -> Self {
+        Self {
+            parameters,
+            download_verify,
+            client: None,
+            report_events: false,
+        }
+    }
+
+    // Client for the update check, e.g. with a proxy or timeouts configured.
+    // Downloads use the client that DownloadVerify builds itself.
+    pub fn client(mut self, param_client: Client) -> Self {
+        self.client = Some(param_client);
+        self
+    }
+
+    // Report the outcome of downloading and verifying the update back to
+    // the server, so that it shows up e.g. in Nebraska. Failing to report
+    // does not fail the update.
+    pub fn report_events(mut self, param_report_events: bool) -> Self {
+        self.report_events = param_report_events;
+        self
+    }
+
+    pub fn run(self) -> Result<UpdateReport> {
+        let client = self.client.unwrap_or_default();
+        let response = request::perform_parsed(&client, self.parameters.clone()).context("update check failed")?;
+
+        let download_verify = self.download_verify.source(Source::Response(response.response.clone())).build()?;
+        let result = download_verify.run();
+
+        let events = outcome_events(&result);
+        if self.report_events && !events.is_empty() {
+            if let Err(err) = request::report_events(&client, self.parameters, events) {
+                warn!("failed to report events: {:#}", err);
+            }
+        }
+
+        let run = match result {
+            Err(err) if err.downcast_ref::<Error>() == Some(&Error::NoUpdate) => {
+                info!("no update available");
+                RunReport::default()
+            }
+            res => res?,
+        };
+
+        Ok(UpdateReport {
+            response,
+            run,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_events() {
+        assert!(outcome_events(&Ok(RunReport::default())).is_empty());
+        assert!(outcome_events(&Err(Error::NoUpdate.into())).is_empty());
+
+        let events = outcome_events(&Err(Error::SizeMismatch {
+            expected: 1,
+            content_length: 2,
+        }
+        .into()));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_result, EventResult::Error);
+        assert_eq!(events[0].error_code, Some(ERROR_CODE_SIZE_MISMATCH));
+
+        let events = outcome_events(&Err(anyhow::anyhow!("bad signature")));
+        assert_eq!(events[0].error_code, Some(ERROR_CODE_GENERIC));
+
+        let err = anyhow::Error::from(Error::SignatureInvalid).context("failed to verify payload");
+        assert_eq!(error_code(&err), ERROR_CODE_PAYLOAD_VERIFICATION);
+        let err = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound)).context("failed to open payload");
+        assert_eq!(error_code(&err), ERROR_CODE_GENERIC);
+    }
+}
@@ -1,25 +1,435 @@
 use core::time::Duration;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Condvar, Mutex};
+use std::thread;
 use std::thread::sleep;
+use std::time::SystemTime;
+use tracing::warn;
+
+pub use update_format_crau::delta_update::CancelFlag;
 
 const RETRY_INTERVAL_MSEC: u64 = 1000;
 
-pub fn retry_loop<F, T, E>(mut func: F, max_tries: u32) -> Result<T, E>
+// Error of an attempt of retry_loop or retry_with_policy, and when it failed.
+#[derive(Debug)]
+pub struct FailedAttempt<E> {
+    pub at: SystemTime,
+    pub error: E,
+}
+
+// All errors of an operation that retry_loop or retry_with_policy gave up
+// on, in the order of the attempts. There is at least one of them.
+#[derive(Debug)]
+pub struct RetryError<E> {
+    pub attempts: Vec<FailedAttempt<E>>,
+}
+
+impl<E> RetryError<E> {
+    pub fn last(&self) -> &E {
+        &self.attempts.last().expect("no failed attempt").error
+    }
+
+    pub fn into_last(mut self) -> E {
+        self.attempts.pop().expect("no failed attempt").error
+    }
+}
+
+impl RetryError<anyhow::Error> {
+    // Last error with the number of attempts as context, so that typed
+    // errors in it can still be downcast.
+    pub fn into_anyhow(self) -> anyhow::Error {
+        let tries = self.attempts.len();
+        let err = self.into_last();
+
+        if tries > 1 {
+            err.context(format!("failed after {} attempts", tries))
+        } else {
+            err
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed after {} attempts", self.attempts.len())?;
+
+        let first = self.attempts.first().map(|a| a.at);
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            let offset = first.and_then(|first| attempt.at.duration_since(first).ok()).unwrap_or_default();
+            write!(f, "\n  attempt {} (+{:.1}s): {}", i + 1, offset.as_secs_f64(), attempt.error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.last())
+    }
+}
+
+pub fn retry_loop<F, T, E>(func: F, max_tries: u32) -> Result<T, RetryError<E>>
 where
     F: FnMut() -> Result<T, E>,
+    E: fmt::Display,
 {
-    let mut tries = 0;
+    let policy = RetryPolicy {
+        max_tries,
+        initial_interval: Duration::from_millis(RETRY_INTERVAL_MSEC),
+        max_interval: Duration::from_millis(RETRY_INTERVAL_MSEC),
+    };
+
+    retry_with_policy(&policy, func, |_| true)
+}
+
+// How often to try an operation, and how long to wait in between. The
+// interval doubles after each failed try, up to max_interval.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_tries: u32,
+    pub initial_interval: Duration,
+    pub max_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_tries: 5,
+            initial_interval: Duration::from_millis(RETRY_INTERVAL_MSEC),
+            max_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Interval to wait after the given number of failed tries.
+    pub fn interval(&self, tries: u32) -> Duration {
+        let factor = 1u32.checked_shl(tries.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_interval.saturating_mul(factor).min(self.max_interval)
+    }
+}
+
+// Log and record a failed attempt, and return how long to wait before the
+// next one, or None to give up.
+fn record_failure<E, R>(policy: &RetryPolicy, attempts: &mut Vec<FailedAttempt<E>>, err: E, retryable: &R) -> Option<Duration>
+where
+    E: fmt::Display,
+    R: Fn(&E) -> bool,
+{
+    let tries = attempts.len() as u32 + 1;
+    warn!("attempt {}/{} failed: {:#}", tries, policy.max_tries, err);
+
+    let give_up = tries >= policy.max_tries || !retryable(&err);
+    attempts.push(FailedAttempt {
+        at: SystemTime::now(),
+        error: err,
+    });
+
+    (!give_up).then(|| policy.interval(tries))
+}
+
+// Like retry_loop, but back off according to policy, and give up at once
+// on errors that retryable does not consider transient.
+// Each failed attempt is logged, and all of their errors are returned.
+pub fn retry_with_policy<F, T, E, R>(policy: &RetryPolicy, mut func: F, retryable: R) -> Result<T, RetryError<E>>
+where
+    F: FnMut() -> Result<T, E>,
+    E: fmt::Display,
+    R: Fn(&E) -> bool,
+{
+    let mut attempts = Vec::new();
 
     loop {
         match func() {
-            ok @ Ok(_) => return ok,
-            err @ Err(_) => {
-                tries += 1;
+            Ok(val) => return Ok(val),
+            Err(err) => match record_failure(policy, &mut attempts, err, &retryable) {
+                Some(interval) => sleep(interval),
+                None => {
+                    return Err(RetryError {
+                        attempts,
+                    })
+                }
+            },
+        }
+    }
+}
+
+// Like retry_with_policy, for async operations. It waits with the timer of
+// tokio, whose runtime the async client of reqwest needs anyway.
+pub async fn retry_with_policy_async<F, Fut, T, E, R>(policy: &RetryPolicy, mut func: F, retryable: R) -> Result<T, RetryError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: fmt::Display,
+    R: Fn(&E) -> bool,
+{
+    let mut attempts = Vec::new();
 
-                if tries >= max_tries {
-                    return err;
+    loop {
+        match func().await {
+            Ok(val) => return Ok(val),
+            Err(err) => match record_failure(policy, &mut attempts, err, &retryable) {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => {
+                    return Err(RetryError {
+                        attempts,
+                    })
                 }
-                sleep(Duration::from_millis(RETRY_INTERVAL_MSEC));
+            },
+        }
+    }
+}
+
+// Run func on a separate thread, and return its result or
+// Error::DeadlineExceeded if it does not finish within deadline. This bounds
+// the total time also if func blocks, e.g. on a server that trickles data.
+// Once the deadline is exceeded, cancel is set and func is waited for, so
+// that it does not keep changing anything in the background. func has to
+// check cancel regularly, e.g. between chunks it reads or writes, for this
+// to be quick.
+pub fn with_deadline_cancel<F, T>(func: F, deadline: Duration, cancel: CancelFlag) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new().name("with-deadline".to_string()).spawn(move || {
+        _ = tx.send(func());
+    })?;
+
+    match rx.recv_timeout(deadline) {
+        Ok(res) => res,
+        Err(RecvTimeoutError::Timeout) => {
+            cancel.cancel();
+            // The result does not matter anymore, only that func is done.
+            _ = rx.recv();
+            Err(crate::error::Error::DeadlineExceeded {
+                deadline,
             }
+            .into())
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(anyhow::anyhow!("operation with deadline panicked")),
+    }
+}
+
+// Counting semaphore, to limit how many threads do something at once.
+pub(crate) struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+pub(crate) struct SemaphoreGuard<'a>(&'a Semaphore);
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
         }
     }
+
+    pub(crate) fn acquire(&self) -> SemaphoreGuard<'_> {
+        let mut permits = self.released.wait_while(self.permits.lock().unwrap(), |permits| *permits == 0).unwrap();
+        *permits -= 1;
+
+        SemaphoreGuard(self)
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+// Call func for each of items on up to threads threads at once, and return
+// the results in the order of items, or the first error. Once func failed,
+// no further items are started.
+pub(crate) fn try_map_concurrent<T, R, E, F>(items: Vec<T>, threads: usize, func: F) -> Result<Vec<R>, E>
+where
+    T: Send,
+    R: Send,
+    E: Send,
+    F: Fn(T) -> Result<R, E> + Sync,
+{
+    let threads = threads.min(items.len());
+    if threads <= 1 {
+        return items.into_iter().map(func).collect();
+    }
+
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some((i, item)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+
+                    let res = func(item);
+                    if res.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    results.lock().unwrap().push((i, res));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, res)| res).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_retry_policy_interval() {
+        let policy = RetryPolicy {
+            max_tries: 10,
+            initial_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(5),
+        };
+
+        assert_eq!(policy.interval(1), Duration::from_secs(1));
+        assert_eq!(policy.interval(2), Duration::from_secs(2));
+        assert_eq!(policy.interval(3), Duration::from_secs(4));
+        assert_eq!(policy.interval(4), Duration::from_secs(5));
+        assert_eq!(policy.interval(100), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_retry_with_policy() {
+        let policy = RetryPolicy {
+            max_tries: 3,
+            initial_interval: Duration::ZERO,
+            max_interval: Duration::ZERO,
+        };
+
+        let mut tries = 0;
+        let res: Result<(), _> = retry_with_policy(
+            &policy,
+            || {
+                tries += 1;
+                Err(format!("transient {}", tries))
+            },
+            |_| true,
+        );
+        let err = res.unwrap_err();
+        assert_eq!(tries, 3);
+        assert_eq!(
+            err.attempts.iter().map(|a| a.error.as_str()).collect::<Vec<_>>(),
+            ["transient 1", "transient 2", "transient 3"]
+        );
+        assert!(err.attempts.windows(2).all(|w| w[0].at <= w[1].at));
+        assert!(err.to_string().starts_with("failed after 3 attempts\n  attempt 1 (+0.0s): transient 1"));
+
+        let mut tries = 0;
+        let res: Result<(), RetryError<&str>> = retry_with_policy(
+            &policy,
+            || {
+                tries += 1;
+                Err("fatal")
+            },
+            |err| *err != "fatal",
+        );
+        assert_eq!((res.unwrap_err().into_last(), tries), ("fatal", 1));
+    }
+
+    #[test]
+    fn test_retry_with_policy_async() {
+        let policy = RetryPolicy {
+            max_tries: 3,
+            initial_interval: Duration::from_millis(1),
+            max_interval: Duration::from_millis(1),
+        };
+        let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        let tries = AtomicUsize::new(0);
+        let res = rt.block_on(retry_with_policy_async(
+            &policy,
+            || async {
+                match tries.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err("transient"),
+                    _ => Ok("done"),
+                }
+            },
+            |err| *err != "fatal",
+        ));
+        assert_eq!((res.unwrap(), tries.load(Ordering::SeqCst)), ("done", 2));
+
+        let res: Result<(), _> = rt.block_on(retry_with_policy_async(
+            &policy,
+            || async { Err("fatal") },
+            |err| *err != "fatal",
+        ));
+        assert_eq!(res.unwrap_err().attempts.len(), 1);
+    }
+
+    #[test]
+    fn test_with_deadline_cancel() {
+        let cancel = CancelFlag::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let err = with_deadline_cancel(
+            {
+                let (cancel, stopped) = (cancel.clone(), stopped.clone());
+                move || {
+                    while cancel.check().is_ok() {
+                        sleep(Duration::from_millis(1));
+                    }
+                    stopped.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            },
+            Duration::from_millis(10),
+            cancel.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::DeadlineExceeded { .. })
+        ));
+        assert!(cancel.is_cancelled());
+        assert!(stopped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_try_map_concurrent() {
+        let res: Result<Vec<_>, ()> = try_map_concurrent((0..10).collect(), 4, |i| Ok(i * 2));
+        assert_eq!(res.unwrap(), (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+        let res = try_map_concurrent((0..10).collect(), 4, |i| {
+            if i == 3 {
+                Err(i)
+            } else {
+                Ok(i)
+            }
+        });
+        assert_eq!(res, Err(3));
+
+        let semaphore = Semaphore::new(2);
+        let running = Mutex::new(0);
+        let res: Result<Vec<_>, ()> = try_map_concurrent((0..8).collect(), 8, |_| {
+            let _permit = semaphore.acquire();
+            *running.lock().unwrap() += 1;
+            let now = *running.lock().unwrap();
+            sleep(Duration::from_millis(10));
+            *running.lock().unwrap() -= 1;
+            Ok(now)
+        });
+        assert!(res.unwrap().iter().all(|&n| n <= 2));
+    }
 }
@@ -54,7 +54,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     delta_update::get_data_blobs(&upfile, &header, &delta_archive_manifest, datablobspath.as_path())?;
 
     // Parse signature data from the signature containing data, version, special fields.
-    let sigdata = match delta_update::parse_signature_data(&sigbytes, hdhashvec.as_slice(), PUBKEY_FILE) {
+    let sigdata = match delta_update::parse_signature_data(&sigbytes, hdhashvec.as_slice(), &[PUBKEY_FILE.into()]) {
         Ok(data) => data,
         _ => {
             return Err(format!(
@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fs;
+
+use argh::FromArgs;
+
+use update_format_crau::delta_update::writer::{Compression, PayloadSigner, PayloadWriter};
+use update_format_crau::verify_sig;
+
+#[derive(FromArgs, Debug)]
+/// Build a signed full CrAU update payload from an image, e.g. to generate
+/// test fixtures.
+struct Args {
+    /// path to the input image
+    #[argh(positional)]
+    image: String,
+
+    /// path to write the payload to
+    #[argh(option, short = 'o')]
+    output: String,
+
+    /// path to the private key in PEM or DER format, PKCS#8 or PKCS#1.
+    /// with the hardware-keys feature, also a pkcs11: URI or tpm:<handle>
+    #[argh(option, short = 'k')]
+    private_key: String,
+
+    /// where to read the PIN of a pkcs11: key, or the authorization value
+    /// of a tpm: key, from: env:<name> or file:<path> (only file: for tpm:).
+    /// needs the hardware-keys feature
+    #[argh(option)]
+    pin: Option<String>,
+
+    /// size in bytes of the data of each operation
+    #[argh(option)]
+    chunk_size: Option<u64>,
+
+    /// do not compress the data of operations
+    #[argh(switch)]
+    no_compress: bool,
+
+    /// compress the data of operations with xz instead of bzip2, which
+    /// the update_engine of Flatcar does not support yet
+    #[argh(switch)]
+    xz: bool,
+}
+
+#[cfg(feature = "hardware-keys")]
+fn signer(key: &str, pin: Option<&str>) -> Result<Box<dyn PayloadSigner>, Box<dyn Error>> {
+    use update_format_crau::verify_sig::hardware::{HardwareKey, HardwareSigner, PinSource};
+
+    if let Ok(hardware_key) = key.parse::<HardwareKey>() {
+        let mut signer = HardwareSigner::new(hardware_key);
+        if let Some(pin) = pin {
+            signer = signer.pin(pin.parse::<PinSource>()?);
+        }
+        return Ok(Box::new(signer));
+    }
+    if pin.is_some() {
+        return Err("--pin is only used with pkcs11: and tpm: keys".into());
+    }
+    Ok(Box::new(verify_sig::get_private_key_file(key)?))
+}
+
+#[cfg(not(feature = "hardware-keys"))]
+fn signer(key: &str, pin: Option<&str>) -> Result<Box<dyn PayloadSigner>, Box<dyn Error>> {
+    if pin.is_some() {
+        return Err("--pin needs the hardware-keys feature".into());
+    }
+    Ok(Box::new(verify_sig::get_private_key_file(key)?))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = argh::from_env();
+
+    let signer = signer(&args.private_key, args.pin.as_deref())?;
+
+    let mut writer = PayloadWriter::new();
+    if let Some(chunk_size) = args.chunk_size {
+        writer = writer.chunk_size(chunk_size);
+    }
+    if args.no_compress {
+        writer = writer.compression(Compression::None);
+    } else if args.xz {
+        writer = writer.compression(Compression::Xz);
+    }
+
+    let image = fs::read(&args.image)?;
+    let payload = writer.build_with_signer(&image, signer.as_ref())?;
+    fs::write(&args.output, payload)?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fs;
+
+use argh::FromArgs;
+
+use update_format_crau::delta_update::writer::{self, PayloadSigner};
+use update_format_crau::verify_sig;
+
+#[derive(FromArgs, Debug)]
+/// Sign a CrAU update payload, replacing any signatures it already has,
+/// e.g. to sign a payload built without a key or to re-sign it with a new one.
+struct Args {
+    /// path to the input payload
+    #[argh(positional)]
+    payload: String,
+
+    /// path to write the signed payload to
+    #[argh(option, short = 'o')]
+    output: String,
+
+    /// path to the private key in PEM or DER format, PKCS#8 or PKCS#1.
+    /// with the hardware-keys feature, also a pkcs11: URI or tpm:<handle>
+    #[argh(option, short = 'k')]
+    private_key: String,
+
+    /// where to read the PIN of a pkcs11: key, or the authorization value
+    /// of a tpm: key, from: env:<name> or file:<path> (only file: for tpm:).
+    /// needs the hardware-keys feature
+    #[argh(option)]
+    pin: Option<String>,
+}
+
+#[cfg(feature = "hardware-keys")]
+fn signer(key: &str, pin: Option<&str>) -> Result<Box<dyn PayloadSigner>, Box<dyn Error>> {
+    use update_format_crau::verify_sig::hardware::{HardwareKey, HardwareSigner, PinSource};
+
+    if let Ok(hardware_key) = key.parse::<HardwareKey>() {
+        let mut signer = HardwareSigner::new(hardware_key);
+        if let Some(pin) = pin {
+            signer = signer.pin(pin.parse::<PinSource>()?);
+        }
+        return Ok(Box::new(signer));
+    }
+    if pin.is_some() {
+        return Err("--pin is only used with pkcs11: and tpm: keys".into());
+    }
+    Ok(Box::new(verify_sig::get_private_key_file(key)?))
+}
+
+#[cfg(not(feature = "hardware-keys"))]
+fn signer(key: &str, pin: Option<&str>) -> Result<Box<dyn PayloadSigner>, Box<dyn Error>> {
+    if pin.is_some() {
+        return Err("--pin needs the hardware-keys feature".into());
+    }
+    Ok(Box::new(verify_sig::get_private_key_file(key)?))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = argh::from_env();
+
+    let signer = signer(&args.private_key, args.pin.as_deref())?;
+
+    let payload = fs::read(&args.payload)?;
+    let signed = writer::sign_payload(&payload, signer.as_ref())?;
+    fs::write(&args.output, signed)?;
+
+    Ok(())
+}
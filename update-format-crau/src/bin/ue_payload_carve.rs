@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use argh::FromArgs;
+
+use update_format_crau::delta_update;
+
+#[derive(FromArgs, Debug)]
+/// Split a CrAU update payload into its sections, the header, the raw
+/// manifest, the metadata signature, the data blob of each operation and the
+/// signatures, each written to a separate file, e.g. to diff two payloads.
+struct Args {
+    /// path to the update payload
+    #[argh(positional)]
+    payload: String,
+
+    /// directory to write the sections to, created if it does not exist
+    #[argh(option, short = 'o')]
+    output_dir: String,
+}
+
+// Copy length bytes at offset of the payload to name in dir, and print where
+// they came from.
+fn carve(upfile: &File, offset: u64, length: u64, dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    let mut buf = vec![0u8; length as usize];
+    upfile.read_exact_at(&mut buf, offset).map_err(|err| format!("failed to read {} ({} bytes at offset {}): {}", name, length, offset, err))?;
+
+    let path: PathBuf = dir.join(name);
+    fs::write(&path, buf).map_err(|err| format!("failed to write {:?}: {}", path.display(), err))?;
+    println!("{:>12} {:>12} {}", offset, length, name);
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = argh::from_env();
+
+    let upfile = File::open(&args.payload)?;
+    let header = delta_update::read_delta_update_header(&upfile)?;
+    let manifest = delta_update::get_manifest_bytes(&upfile, &header)?;
+
+    let dir = Path::new(&args.output_dir);
+    fs::create_dir_all(dir)?;
+
+    println!("{:>12} {:>12} file", "offset", "length");
+
+    let manifest_offset = header.header_size();
+    carve(&upfile, 0, manifest_offset, dir, "header.bin")?;
+    carve(&upfile, manifest_offset, header.manifest_size(), dir, "manifest.pb")?;
+
+    // Offsets of data blobs and signatures are relative to the end of the
+    // metadata signature, which only major version 2 has.
+    let metadata_signature_offset = manifest_offset + header.manifest_size();
+    let metadata_signature_size = header.metadata_signature_size() as u64;
+    if metadata_signature_size > 0 {
+        carve(
+            &upfile,
+            metadata_signature_offset,
+            metadata_signature_size,
+            dir,
+            "metadata_signature.pb",
+        )?;
+    }
+    let data_offset = metadata_signature_offset + metadata_signature_size;
+
+    for partition in delta_update::get_partitions(&manifest) {
+        for (i, op) in partition.operations.iter().enumerate() {
+            // Operations such as MOVE have no data.
+            let (Some(offset), Some(length)) = (op.data_offset, op.data_length) else {
+                continue;
+            };
+            let name = format!("{}-{:04}-{}.bin", partition.name, i, delta_update::operation_type_name(op));
+            carve(&upfile, data_offset + offset as u64, length as u64, dir, &name)?;
+        }
+    }
+
+    // Unsigned payloads have no signatures.
+    if let (Some(offset), Some(size)) = (manifest.signatures_offset, manifest.signatures_size) {
+        carve(&upfile, data_offset + offset, size, dir, "signatures.pb")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use std::error::Error;
+use std::fs::File;
+use std::str::FromStr;
+
+use argh::FromArgs;
+use protobuf::Message;
+use serde_json::json;
+
+use update_format_crau::delta_update::{self, DeltaUpdateFileHeader, PartitionUpdate};
+use update_format_crau::proto;
+
+#[derive(Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "text" => OutputFormat::Text,
+            "json" => OutputFormat::Json,
+
+            _ => return Err(format!("unknown output format \"{}\"", s)),
+        })
+    }
+}
+
+#[derive(FromArgs, Debug)]
+/// Print the header, manifest and signature slots of a CrAU update payload.
+struct Args {
+    /// path to the update payload
+    #[argh(positional)]
+    payload: String,
+
+    /// output format, text (default) or json
+    #[argh(option, default = "OutputFormat::Text")]
+    format: OutputFormat,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn extents_text(extents: &[proto::Extent]) -> String {
+    let extents: Vec<_> = extents.iter().map(|e| format!("{}+{}", e.start_block(), e.num_blocks())).collect();
+    extents.join(",")
+}
+
+fn info_text(info: Option<&proto::InstallInfo>) -> String {
+    match info {
+        Some(info) => format!("size={} sha256={}", info.size(), hex(info.hash())),
+        None => "-".to_string(),
+    }
+}
+
+fn print_text(header: &DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest, partitions: &[PartitionUpdate<'_>], signatures: &proto::Signatures) {
+    println!("header:");
+    println!("  file format version: {}", header.file_format_version());
+    println!("  manifest size: {}", header.manifest_size());
+    println!("  metadata signature size: {}", header.metadata_signature_size());
+
+    println!("manifest:");
+    println!("  block size: {}", manifest.block_size());
+    println!("  signatures offset: {}", manifest.signatures_offset());
+    println!("  signatures size: {}", manifest.signatures_size());
+
+    for p in partitions {
+        println!("  partition {}:", p.name);
+        println!("    old info: {}", info_text(p.old_info));
+        println!("    new info: {}", info_text(p.new_info));
+        println!("    operations: {}", p.operations.len());
+
+        for (i, op) in p.operations.iter().enumerate() {
+            println!(
+                "      {}: {} data_offset={} data_length={} src_extents=[{}] dst_extents=[{}]",
+                i,
+                delta_update::operation_type_name(op),
+                op.data_offset(),
+                op.data_length(),
+                extents_text(&op.src_extents),
+                extents_text(&op.dst_extents),
+            );
+        }
+    }
+
+    println!("signatures:");
+    for (i, sig) in signatures.signatures.iter().enumerate() {
+        println!("  slot {}: version {}, {} bytes", i, sig.version(), sig.data().len());
+    }
+}
+
+fn extents_json(extents: &[proto::Extent]) -> serde_json::Value {
+    extents.iter().map(|e| json!({ "start_block": e.start_block(), "num_blocks": e.num_blocks() })).collect()
+}
+
+fn info_json(info: Option<&proto::InstallInfo>) -> serde_json::Value {
+    match info {
+        Some(info) => json!({ "size": info.size(), "sha256": hex(info.hash()) }),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn print_json(header: &DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest, partitions: &[PartitionUpdate<'_>], signatures: &proto::Signatures) -> Result<(), Box<dyn Error>> {
+    #[rustfmt::skip]
+    let partitions: Vec<_> = partitions.iter().map(|p| json!({
+        "name": p.name,
+        "old_info": info_json(p.old_info),
+        "new_info": info_json(p.new_info),
+        "operations": p.operations.iter().map(|op| json!({
+            "type": delta_update::operation_type_name(op),
+            "data_offset": op.data_offset(),
+            "data_length": op.data_length(),
+            "src_extents": extents_json(&op.src_extents),
+            "dst_extents": extents_json(&op.dst_extents),
+        })).collect::<Vec<_>>(),
+    })).collect();
+
+    #[rustfmt::skip]
+    let signatures: Vec<_> = signatures.signatures.iter().map(|sig| json!({
+        "version": sig.version(),
+        "size": sig.data().len(),
+    })).collect();
+
+    let out = json!({
+        "header": {
+            "file_format_version": header.file_format_version(),
+            "manifest_size": header.manifest_size(),
+            "metadata_signature_size": header.metadata_signature_size(),
+        },
+        "manifest": {
+            "block_size": manifest.block_size(),
+            "signatures_offset": manifest.signatures_offset(),
+            "signatures_size": manifest.signatures_size(),
+            "partitions": partitions,
+        },
+        "signatures": signatures,
+    });
+
+    println!("{}", serde_json::to_string_pretty(&out)?);
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = argh::from_env();
+
+    let upfile = File::open(&args.payload)?;
+    let header = delta_update::read_delta_update_header(&upfile)?;
+    let mut manifest = delta_update::get_manifest_bytes(&upfile, &header)?;
+
+    // Unsigned payloads have no signature slots at all.
+    let signatures = match manifest.signatures_offset {
+        Some(_) => proto::Signatures::parse_from_bytes(&delta_update::get_signatures_bytes(&upfile, &header, &mut manifest)?)?,
+        None => proto::Signatures::new(),
+    };
+
+    let partitions = delta_update::get_partitions(&manifest);
+
+    match args.format {
+        OutputFormat::Text => print_text(&header, &manifest, &partitions, &signatures),
+        OutputFormat::Json => print_json(&header, &manifest, &partitions, &signatures)?,
+    }
+
+    Ok(())
+}
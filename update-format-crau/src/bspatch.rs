@@ -0,0 +1,178 @@
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use bzip2::read::BzDecoder;
+
+use crate::delta_update::Error;
+
+const BSDIFF_MAGIC: &[u8] = b"BSDIFF40";
+const BSDIFF_HEADER_SIZE: usize = 32;
+
+// Size of the chunks the new data is produced in.
+const OUTPUT_CHUNKLEN: usize = 1 << 20;
+
+// Decode a signed integer of the bsdiff format, which is stored as
+// little endian magnitude with the sign in the topmost bit.
+fn offtin(buf: &[u8]) -> i64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[..8]);
+
+    let magnitude = (u64::from_le_bytes(bytes) & !(1 << 63)) as i64;
+    if bytes[7] & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+fn read_offtin(r: &mut impl Read) -> Result<i64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf).context("failed to read control data")?;
+    Ok(offtin(&buf))
+}
+
+fn header_len(header: &[u8], offset: usize, name: &str) -> Result<usize> {
+    usize::try_from(offtin(&header[offset..])).context(format!("invalid {} in bsdiff header", name))
+}
+
+// Apply a patch in BSDIFF40 format to old, and pass the new data in order
+// to output, in chunks of at most OUTPUT_CHUNKLEN bytes, so that it is never
+// held in memory as a whole.
+// Fail with Error::OversizedChunk if the new data would be larger than
+// limit bytes, as its size is taken from the patch itself.
+pub(crate) fn bspatch(old: &[u8], patch: &[u8], limit: u64, data_offset: u64, mut output: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    if patch.len() < BSDIFF_HEADER_SIZE || &patch[..BSDIFF_MAGIC.len()] != BSDIFF_MAGIC {
+        bail!("bad bsdiff magic");
+    }
+
+    let ctrl_len = header_len(patch, 8, "control length")?;
+    let diff_len = header_len(patch, 16, "diff length")?;
+    let new_size = header_len(patch, 24, "new size")?;
+
+    if new_size as u64 > limit {
+        return Err(Error::OversizedChunk {
+            data_offset,
+            limit,
+        }
+        .into());
+    }
+
+    let body = &patch[BSDIFF_HEADER_SIZE..];
+    if ctrl_len.checked_add(diff_len).is_none_or(|len| len > body.len()) {
+        bail!("bsdiff header refers to more data than available");
+    }
+
+    let mut ctrl = BzDecoder::new(&body[..ctrl_len]);
+    let mut diff = BzDecoder::new(&body[ctrl_len..ctrl_len + diff_len]);
+    let mut extra = BzDecoder::new(&body[ctrl_len + diff_len..]);
+
+    let mut buf = vec![0u8; new_size.min(OUTPUT_CHUNKLEN)];
+    let mut newpos: usize = 0;
+    let mut oldpos: i64 = 0;
+
+    while newpos < new_size {
+        let add_len = usize::try_from(read_offtin(&mut ctrl)?).context("invalid add length")?;
+        let copy_len = usize::try_from(read_offtin(&mut ctrl)?).context("invalid copy length")?;
+        let seek = read_offtin(&mut ctrl)?;
+
+        // Add bytes of the diff block to the old data.
+        if add_len > new_size - newpos {
+            bail!("bsdiff add block exceeds the new size");
+        }
+        let mut remaining = add_len;
+        while remaining > 0 {
+            let chunk = &mut buf[..remaining.min(OUTPUT_CHUNKLEN)];
+            diff.read_exact(chunk).context("failed to read diff data")?;
+            for (i, b) in chunk.iter_mut().enumerate() {
+                let pos = oldpos + i as i64;
+                if pos >= 0 && (pos as usize) < old.len() {
+                    *b = b.wrapping_add(old[pos as usize]);
+                }
+            }
+            output(chunk)?;
+
+            remaining -= chunk.len();
+            oldpos += chunk.len() as i64;
+        }
+        newpos += add_len;
+
+        // Copy bytes of the extra block as they are.
+        if copy_len > new_size - newpos {
+            bail!("bsdiff copy block exceeds the new size");
+        }
+        let mut remaining = copy_len;
+        while remaining > 0 {
+            let chunk = &mut buf[..remaining.min(OUTPUT_CHUNKLEN)];
+            extra.read_exact(chunk).context("failed to read extra data")?;
+            output(chunk)?;
+
+            remaining -= chunk.len();
+        }
+        newpos += copy_len;
+        oldpos += seek;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use bzip2::Compression;
+    use bzip2::write::BzEncoder;
+
+    fn compress_bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn offtout(x: i64) -> [u8; 8] {
+        let mut bytes = x.unsigned_abs().to_le_bytes();
+        if x < 0 {
+            bytes[7] |= 0x80;
+        }
+        bytes
+    }
+
+    fn make_patch(ctrl: &[(i64, i64, i64)], diff: &[u8], extra: &[u8], new_size: i64) -> Vec<u8> {
+        let ctrl: Vec<u8> = ctrl.iter().flat_map(|(a, c, s)| [offtout(*a), offtout(*c), offtout(*s)].concat()).collect();
+        let (ctrl, diff, extra) = (compress_bzip2(&ctrl), compress_bzip2(diff), compress_bzip2(extra));
+
+        let mut patch = BSDIFF_MAGIC.to_vec();
+        patch.extend(offtout(ctrl.len() as i64));
+        patch.extend(offtout(diff.len() as i64));
+        patch.extend(offtout(new_size));
+        patch.extend(ctrl);
+        patch.extend(diff);
+        patch.extend(extra);
+        patch
+    }
+
+    #[test]
+    fn test_offtin() {
+        assert_eq!(offtin(&offtout(0)), 0);
+        assert_eq!(offtin(&offtout(4096)), 4096);
+        assert_eq!(offtin(&offtout(-42)), -42);
+    }
+
+    #[test]
+    fn test_bspatch() {
+        let old = b"hello world";
+        // Keep "hello", change ' ' into '_', append "rust", then take "ld" of old.
+        let patch = make_patch(&[(6, 4, 3), (2, 0, 0)], &[0, 0, 0, 0, 0, b'_' - b' ', 0, 0], b"rust", 12);
+
+        let mut new = Vec::new();
+        bspatch(old, &patch, 4096, 0, |chunk| {
+            new.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(new, b"hello_rustld");
+
+        let err = bspatch(old, &patch, 8, 0, |_| Ok(())).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::OversizedChunk { .. })));
+        assert!(bspatch(old, &patch[1..], 4096, 0, |_| Ok(())).is_err());
+    }
+}
@@ -1,35 +1,137 @@
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom};
+use std::fmt;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::path::Path;
 use std::mem;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
 use std::os::unix::prelude::FileExt;
-use log::{debug, info};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use tracing::{debug, info};
 use bzip2::read::BzDecoder;
+use xz2::read::XzDecoder;
 use anyhow::{Context, Result, anyhow, bail};
 
 use protobuf::Message;
+use rsa::sha2::{Digest, Sha256};
 
+use crate::bspatch::bspatch;
 use crate::proto::signatures::Signature;
 use crate::proto;
 use crate::verify_sig;
-use crate::verify_sig::get_public_key_pkcs_pem;
-use crate::verify_sig::KeyType::KeyTypePkcs8;
+use crate::verify_sig::{PubkeySource, PublicKey};
+
+pub mod writer;
 
 const DELTA_UPDATE_HEADER_SIZE: u64 = 4 + 8 + 8;
+// Major version 2 adds the size of the metadata signature to the header.
+const DELTA_UPDATE_HEADER_SIZE_V2: u64 = DELTA_UPDATE_HEADER_SIZE + 4;
 const DELTA_UPDATE_FILE_MAGIC: &[u8] = b"CrAU";
 
+// Upper bound of the manifest size, far above that of real payloads, so
+// that a crafted header cannot make us allocate arbitrary amounts of memory.
+const MAX_MANIFEST_SIZE: u64 = 64 << 20;
+
+// Name of the main partition, i.e. USR-A or USR-B, updated by partition_operations.
+pub const PARTITION_USR: &str = "USR";
+// Name of the kernel partition, updated by the KERNEL install procedure.
+pub const PARTITION_KERNEL: &str = "KERNEL";
+
+// Start block of an extent that does not refer to any blocks on disk.
+const SPARSE_HOLE: u64 = u64::MAX;
+
+// Errors of payload processing that callers might want to handle specifically.
+// They are returned wrapped in anyhow::Error, use downcast_ref::<Error>()
+// to get them back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    // Decompressed data of an operation is larger than its dst_extents.
+    OversizedChunk {
+        data_offset: u64,
+        limit: u64,
+    },
+    // Source partition given for a delta payload does not match the hash of
+    // its old partition info, so the payload was generated against another one.
+    SourceHashMismatch {
+        partition: String,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OversizedChunk {
+                data_offset,
+                limit,
+            } => write!(
+                f,
+                "decompressed data at offset {} exceeds the maximum size {}",
+                data_offset, limit
+            ),
+            Error::SourceHashMismatch {
+                partition,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "hash {} of source partition {} does not match old partition info hash {}",
+                hex(actual),
+                partition,
+                hex(expected)
+            ),
+        }
+    }
+}
+
+// None of the variants is caused by another error, the causes of failed
+// reads or writes are attached by the callers with anyhow context.
+impl std::error::Error for Error {}
+
 #[derive(Debug)]
 pub struct DeltaUpdateFileHeader {
     magic: [u8; 4],
     file_format_version: u64,
     manifest_size: u64,
+    metadata_signature_size: u32,
 }
 
 impl DeltaUpdateFileHeader {
+    pub fn file_format_version(&self) -> u64 {
+        self.file_format_version
+    }
+
+    pub fn manifest_size(&self) -> u64 {
+        self.manifest_size
+    }
+
+    // Always 0 for major version 1, which has no metadata signature.
+    pub fn metadata_signature_size(&self) -> u32 {
+        self.metadata_signature_size
+    }
+
+    // Size of the header itself, the manifest starts right after it.
+    #[inline]
+    pub fn header_size(&self) -> u64 {
+        match self.file_format_version {
+            1 => DELTA_UPDATE_HEADER_SIZE,
+            _ => DELTA_UPDATE_HEADER_SIZE_V2,
+        }
+    }
+
+    // Data blobs start after the manifest, and for major version 2 also
+    // after the metadata signature. Offsets out of range saturate, so that
+    // they fail the bounds checks of check_in_payload.
     #[inline]
     fn translate_offset(&self, offset: u64) -> u64 {
-        DELTA_UPDATE_HEADER_SIZE + self.manifest_size + offset
+        self.header_size().saturating_add(self.manifest_size).saturating_add(self.metadata_signature_size as u64).saturating_add(offset)
     }
 }
 
@@ -39,6 +141,7 @@ pub fn read_delta_update_header(f: &File) -> Result<DeltaUpdateFileHeader> {
         magic: [0; 4],
         file_format_version: 0,
         manifest_size: 0,
+        metadata_signature_size: 0,
     };
 
     f.read_exact_at(&mut header.magic, 0).context("failed to read header magic")?;
@@ -49,12 +152,25 @@ pub fn read_delta_update_header(f: &File) -> Result<DeltaUpdateFileHeader> {
     let mut buf = [0u8; 8];
     f.read_exact_at(&mut buf, header.magic.len() as u64).context("failed to read file format version")?;
     header.file_format_version = u64::from_be_bytes(buf);
-    if header.file_format_version != 1 {
+    if header.file_format_version != 1 && header.file_format_version != 2 {
         bail!("unsupported file format version");
     }
 
     f.read_exact_at(&mut buf, (header.magic.len() + mem::size_of::<u64>()) as u64).context("failed to read manifest size")?;
     header.manifest_size = u64::from_be_bytes(buf);
+    if header.manifest_size > MAX_MANIFEST_SIZE {
+        bail!(
+            "manifest size {} exceeds the maximum {}",
+            header.manifest_size,
+            MAX_MANIFEST_SIZE
+        );
+    }
+
+    if header.file_format_version == 2 {
+        let mut buf = [0u8; 4];
+        f.read_exact_at(&mut buf, DELTA_UPDATE_HEADER_SIZE).context("failed to read metadata signature size")?;
+        header.metadata_signature_size = u32::from_be_bytes(buf);
+    }
 
     Ok(header)
 }
@@ -63,12 +179,9 @@ pub fn read_delta_update_header(f: &File) -> Result<DeltaUpdateFileHeader> {
 // return DeltaArchiveManifest that contains manifest.
 pub fn get_manifest_bytes(f: &File, header: &DeltaUpdateFileHeader) -> Result<proto::DeltaArchiveManifest> {
     let manifest_bytes = {
+        check_in_payload(f, header.header_size(), header.manifest_size, "manifest")?;
         let mut buf = vec![0u8; header.manifest_size as usize];
-        f.read_exact_at(
-            &mut buf,
-            (header.magic.len() + mem::size_of::<u64>() + mem::size_of::<u64>()) as u64,
-        )
-        .context("failed to read manifest bytes")?;
+        f.read_exact_at(&mut buf, header.header_size()).context("failed to read manifest bytes")?;
         buf.into_boxed_slice()
     };
 
@@ -88,6 +201,7 @@ pub fn get_signatures_bytes<'a>(f: &'a File, header: &'a DeltaUpdateFileHeader,
 
     let signatures_bytes = match (manifest.signatures_offset, manifest.signatures_size) {
         (Some(sig_offset), Some(sig_size)) => {
+            check_in_payload(f, header.translate_offset(sig_offset), sig_size, "signature")?;
             let mut buf = vec![0u8; sig_size as usize];
             f.read_exact_at(&mut buf, header.translate_offset(sig_offset)).context("failed to read signature")?;
             Some(buf.into_boxed_slice())
@@ -98,6 +212,23 @@ pub fn get_signatures_bytes<'a>(f: &'a File, header: &'a DeltaUpdateFileHeader,
     signatures_bytes.ok_or(anyhow!("failed to get signature bytes slice"))
 }
 
+// Fail unless len bytes at offset are within the payload, before allocating
+// a buffer for them, as both come from the untrusted header or manifest.
+fn check_in_payload(f: &File, offset: u64, len: u64, what: &str) -> Result<()> {
+    let payload_len = f.metadata().context("failed to get metadata of payload")?.len();
+
+    match offset.checked_add(len) {
+        Some(end) if end <= payload_len => Ok(()),
+        _ => bail!(
+            "{} of {} bytes at offset {} exceeds the payload size {}",
+            what,
+            len,
+            offset,
+            payload_len
+        ),
+    }
+}
+
 // Return data length, including header and manifest.
 pub fn get_header_data_length(header: &DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest) -> Result<usize> {
     // Read from the beginning of the stream, which means the whole buffer including
@@ -110,58 +241,695 @@ pub fn get_header_data_length(header: &DeltaUpdateFileHeader, manifest: &proto::
     Ok(header.translate_offset(manifest.signatures_offset.ok_or(anyhow!("no signature offset"))?) as usize)
 }
 
+// A partition that is updated by the payload. Next to the main partition,
+// which is updated by partition_operations and checked with
+// new_partition_info, a payload can update additional partitions through
+// its install procedures, e.g. the kernel.
+#[derive(Debug)]
+pub struct PartitionUpdate<'a> {
+    pub name: String,
+    pub block_size: u64,
+    pub operations: &'a [proto::InstallOperation],
+    pub old_info: Option<&'a proto::InstallInfo>,
+    pub new_info: Option<&'a proto::InstallInfo>,
+}
+
+// Return all partitions updated by the payload, the main partition first,
+// named PARTITION_USR, followed by the install procedures named after their
+// type, e.g. KERNEL.
+pub fn get_partitions(manifest: &proto::DeltaArchiveManifest) -> Vec<PartitionUpdate<'_>> {
+    let block_size = manifest.block_size() as u64;
+
+    let mut partitions = vec![PartitionUpdate {
+        name: PARTITION_USR.to_string(),
+        block_size,
+        operations: &manifest.partition_operations,
+        old_info: manifest.old_partition_info.as_ref(),
+        new_info: manifest.new_partition_info.as_ref(),
+    }];
+
+    for procedure in &manifest.procedures {
+        let name = match procedure.type_.map(|t| t.enum_value()) {
+            Some(Ok(t)) => format!("{:?}", t),
+            Some(Err(t)) => format!("PROCEDURE_{}", t),
+            None => format!("{:?}", proto::install_procedure::Type::default()),
+        };
+
+        partitions.push(PartitionUpdate {
+            name,
+            block_size,
+            operations: &procedure.operations,
+            old_info: procedure.old_info.as_ref(),
+            new_info: procedure.new_info.as_ref(),
+        });
+    }
+
+    partitions
+}
+
+// Size and hash of a partition image, from its InstallInfo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub size: u64,
+    pub hash: Vec<u8>,
+}
+
+impl From<&proto::InstallInfo> for ImageInfo {
+    fn from(info: &proto::InstallInfo) -> Self {
+        ImageInfo {
+            size: info.size(),
+            hash: info.hash().to_vec(),
+        }
+    }
+}
+
+// Summary of a PartitionUpdate that does not borrow the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionSummary {
+    pub name: String,
+    pub operations: usize,
+    pub old_info: Option<ImageInfo>,
+    pub new_info: Option<ImageInfo>,
+}
+
+// Metadata of a payload from its header and manifest, e.g. to log or report
+// it without parsing the payload again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadInfo {
+    pub file_format_version: u64,
+    pub manifest_size: u64,
+    pub metadata_signature_size: u32,
+    pub block_size: u32,
+    pub signatures_offset: Option<u64>,
+    pub signatures_size: Option<u64>,
+    pub partitions: Vec<PartitionSummary>,
+}
+
+impl PayloadInfo {
+    pub fn new(header: &DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest) -> Self {
+        let partitions = get_partitions(manifest)
+            .into_iter()
+            .map(|p| PartitionSummary {
+                name: p.name,
+                operations: p.operations.len(),
+                old_info: p.old_info.map(ImageInfo::from),
+                new_info: p.new_info.map(ImageInfo::from),
+            })
+            .collect();
+
+        PayloadInfo {
+            file_format_version: header.file_format_version(),
+            manifest_size: header.manifest_size(),
+            metadata_signature_size: header.metadata_signature_size(),
+            block_size: manifest.block_size(),
+            signatures_offset: manifest.signatures_offset,
+            signatures_size: manifest.signatures_size,
+            partitions,
+        }
+    }
+
+    // Total number of operations of all partitions.
+    pub fn operations(&self) -> usize {
+        self.partitions.iter().map(|p| p.operations).sum()
+    }
+}
+
 // Take a buffer reader, delta file header, manifest as input.
 // Return path to data blobs, without header, manifest, or signatures.
 pub fn get_data_blobs<'a>(f: &'a File, header: &'a DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest, tmpfile: &Path) -> Result<()> {
-    let tmpdir = tmpfile.parent().ok_or(anyhow!("unable to get parent directory"))?;
-    fs::create_dir_all(tmpdir).context(format!("failed to create directory {:?}", tmpdir))?;
-    let mut outfile = File::create(tmpfile).context(format!("failed to create file {:?}", tmpfile))?;
+    let partitions = get_partitions(manifest);
+
+    get_partition_data_blobs(f, header, &partitions[0], None, tmpfile, WriteOptions::default())
+}
+
+// Like get_data_blobs, but for delta payloads. MOVE and BSDIFF operations
+// read their src_extents from source, which is the currently installed
+// partition or image that the payload was generated against.
+pub fn get_data_blobs_from_source<'a>(f: &'a File, header: &'a DeltaUpdateFileHeader, manifest: &proto::DeltaArchiveManifest, source: &Path, tmpfile: &Path) -> Result<()> {
+    let partitions = get_partitions(manifest);
+
+    get_partition_data_blobs(f, header, &partitions[0], Some(source), tmpfile, WriteOptions::default())
+}
+
+// Like get_data_blobs, but for any partition returned by get_partitions.
+// A source is only needed for delta payloads, see get_data_blobs_from_source.
+// It is checked against the old partition info, before applying anything.
+// The target can also be a block device, which is then written directly.
+pub fn get_partition_data_blobs(f: &File, header: &DeltaUpdateFileHeader, partition: &PartitionUpdate<'_>, source: Option<&Path>, target: &Path, options: WriteOptions) -> Result<()> {
+    let srcfile = match source {
+        Some(path) => {
+            let srcfile = File::open(path).context(format!("failed to open source {:?}", path))?;
+            verify_source(&srcfile, partition)?;
+            Some(srcfile)
+        }
+        None => None,
+    };
+
+    let target = Target::open(target, options)?;
+
+    apply_operations(f, header, partition.operations, partition.block_size, srcfile.as_ref(), &target)
+}
+
+// Check that source is the partition a delta payload was generated against,
+// by comparing the hash of its first old_info.size bytes with old_info.hash.
+// Fail with Error::SourceHashMismatch if it is not.
+pub fn verify_source(source: &File, partition: &PartitionUpdate<'_>) -> Result<()> {
+    let Some(expected) = partition.old_info.and_then(|info| info.hash.as_ref()) else {
+        debug!("no old partition info hash of {}, not checking source", partition.name);
+        return Ok(());
+    };
+
+    let mut len = match partition.old_info.and_then(|info| info.size) {
+        Some(size) => size,
+        None => source.metadata().context("failed to get metadata of source")?.len(),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    let mut pos: u64 = 0;
+
+    while len > 0 {
+        let chunklen = len.min(buf.len() as u64) as usize;
+        source.read_exact_at(&mut buf[..chunklen], pos).context(format!("failed to read source at {}", pos))?;
+        hasher.update(&buf[..chunklen]);
+
+        pos += chunklen as u64;
+        len -= chunklen as u64;
+    }
 
+    let actual = hasher.finalize().to_vec();
+    if actual != *expected {
+        return Err(Error::SourceHashMismatch {
+            partition: partition.name.clone(),
+            expected: expected.clone(),
+            actual,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+// Flag that tells an operation on another thread to stop, e.g. once its
+// deadline is exceeded. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    // Fail once the flag is set, e.g. before each chunk of a download.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("operation was cancelled");
+        }
+        Ok(())
+    }
+}
+
+// How the reconstructed data is written, see get_partition_data_blobs.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    // Open the target with O_DIRECT, bypassing the page cache.
+    pub direct_io: bool,
+    // Sync the target to disk after writing each extent.
+    pub sync_extents: bool,
+    // Number of operations applied concurrently, 0 or 1 for one at a time.
+    pub threads: usize,
+    // Stop before the next operation once set.
+    pub cancel: CancelFlag,
+}
+
+// Buffers and lengths of O_DIRECT writes must be aligned to the logical
+// block size of the device, which is at most the page size.
+const DIRECT_IO_ALIGN: usize = 4096;
+
+struct Target {
+    file: File,
+    options: WriteOptions,
+}
+
+impl Target {
+    // A block device is written in place, anything else is created as a
+    // regular file, truncating any existing one.
+    fn open(path: &Path, options: WriteOptions) -> Result<Self> {
+        let is_block_device = fs::metadata(path).map(|md| md.file_type().is_block_device()).unwrap_or(false);
+
+        let mut open_options = OpenOptions::new();
+        open_options.write(true);
+        if !is_block_device {
+            let dir = path.parent().ok_or(anyhow!("unable to get parent directory"))?;
+            fs::create_dir_all(dir).context(format!("failed to create directory {:?}", dir))?;
+            open_options.create(true).truncate(true);
+        }
+        if options.direct_io {
+            open_options.custom_flags(libc::O_DIRECT);
+        }
+
+        let file = open_options.open(path).context(format!("failed to open target {:?}", path))?;
+
+        Ok(Target {
+            file,
+            options,
+        })
+    }
+
+    fn write_at(&self, data: &[u8], offset: u64, block_size: u64) -> Result<()> {
+        if self.options.direct_io {
+            // Copy into an aligned buffer, zero padded to whole blocks.
+            let padded_len = (data.len() as u64).div_ceil(block_size) * block_size;
+            let mut buf = vec![0u8; padded_len as usize + DIRECT_IO_ALIGN];
+            let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGN);
+            let aligned = &mut buf[start..start + padded_len as usize];
+            aligned[..data.len()].copy_from_slice(data);
+
+            self.file.write_all_at(aligned, offset)?;
+        } else {
+            self.file.write_all_at(data, offset)?;
+        }
+
+        Ok(())
+    }
+
+    // Called whenever an extent was written completely.
+    fn sync_extent(&self) -> Result<()> {
+        if self.options.sync_extents {
+            self.file.sync_data().context("failed to sync target")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_operations(f: &File, header: &DeltaUpdateFileHeader, operations: &[proto::InstallOperation], block_size: u64, source: Option<&File>, target: &Target) -> Result<()> {
+    let threads = target.options.threads.min(operations.len());
+    if threads <= 1 {
+        for pop in operations {
+            target.options.cancel.check()?;
+            apply_operation(f, header, pop, block_size, source, target)?;
+        }
+
+        return Ok(());
+    }
+
+    // Operations write to disjoint extents and only read from the payload
+    // and the source, so they can be applied in any order. Each worker takes
+    // the next operation until all are done or one of them failed.
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    let worker = || -> Result<()> {
+        while !failed.load(Ordering::Relaxed) {
+            let Some(pop) = operations.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                break;
+            };
+
+            if let Err(err) = target.options.cancel.check().and_then(|_| apply_operation(f, header, pop, block_size, source, target)) {
+                failed.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    };
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|_| scope.spawn(worker)).collect();
+
+        for handle in handles {
+            handle.join().map_err(|_| anyhow!("extraction worker panicked"))??;
+        }
+
+        Ok(())
+    })
+}
+
+// Read the data blob of the given operation from the payload.
+// Return the data and its offset in the payload.
+fn read_operation_data(f: &File, header: &DeltaUpdateFileHeader, pop: &proto::InstallOperation) -> Result<(Vec<u8>, u64)> {
     // Read from the beginning of header, which means buffer including only data blobs.
     // It means it is necessary to call header.translate_offset(), in contrast to
     // get_header_data_length.
-    // Iterate each partition_operations to get data offset and data length.
-    for pop in &manifest.partition_operations {
-        let data_offset = pop.data_offset.ok_or(anyhow!("unable to get data offset"))?;
-        let data_length = pop.data_length.ok_or(anyhow!("unable to get data length"))?;
-        let block_size = manifest.block_size() as u64;
-        if pop.dst_extents.len() != 1 {
-            bail!(
-                "unexpected number of extents, only one can be handled: {}",
-                pop.dst_extents.len()
-            );
-        }
-        let start_block = block_size * pop.dst_extents[0].start_block.ok_or(anyhow!("unable to get start_block"))?;
-
-        let mut partdata = vec![0u8; data_length as usize];
-
-        let translated_offset = header.translate_offset(data_offset.into());
-        f.read_exact_at(&mut partdata, translated_offset).context(format!(
-            "failed to read data with length {:?} at {:?}",
-            data_length, translated_offset
-        ))?;
-
-        // In case of bzip2-compressed chunks, extract.
-        if pop.type_.ok_or(anyhow!("unable to get type_ from partition operations"))? == proto::install_operation::Type::REPLACE_BZ.into() {
-            let mut bzdecoder = BzDecoder::new(&partdata[..]);
-            let mut partdata_unpacked = Vec::new();
-            bzdecoder.read_to_end(&mut partdata_unpacked).context(format!("failed to unpack bzip2ed data at offset {:?}", translated_offset))?;
-
-            outfile.write_all_at(&partdata_unpacked, start_block).context(format!("failed to copy unpacked data at offset {:?}", translated_offset))?;
-        } else {
-            outfile.write_all_at(&partdata, start_block).context(format!("failed to copy plain data at offset {:?}", translated_offset))?;
+    let data_offset = pop.data_offset.ok_or(anyhow!("unable to get data offset"))?;
+    let data_length = pop.data_length.ok_or(anyhow!("unable to get data length"))?;
+
+    let translated_offset = header.translate_offset(data_offset.into());
+    check_in_payload(f, translated_offset, data_length.into(), "operation data")?;
+
+    let mut partdata = vec![0u8; data_length as usize];
+    f.read_exact_at(&mut partdata, translated_offset).context(format!(
+        "failed to read data with length {:?} at {:?}",
+        data_length, translated_offset
+    ))?;
+
+    Ok((partdata, translated_offset))
+}
+
+// Decompressors of the operations whose data blob holds the complete new data,
+// by operation type number, with a name for error messages.
+type Decompressor = for<'a> fn(OperationReader<'a>) -> Result<Box<dyn Read + 'a>>;
+
+fn decompress_none(reader: OperationReader<'_>) -> Result<Box<dyn Read + '_>> {
+    Ok(Box::new(reader))
+}
+
+fn decompress_bzip2(reader: OperationReader<'_>) -> Result<Box<dyn Read + '_>> {
+    Ok(Box::new(BzDecoder::new(reader)))
+}
+
+fn decompress_xz(reader: OperationReader<'_>) -> Result<Box<dyn Read + '_>> {
+    Ok(Box::new(XzDecoder::new(reader)))
+}
+
+const DECOMPRESSORS: &[(i32, &str, Decompressor)] = &[
+    (proto::install_operation::Type::REPLACE as i32, "plain", decompress_none),
+    (proto::install_operation::Type::REPLACE_BZ as i32, "bzip2", decompress_bzip2),
+    (proto::install_operation::Type::REPLACE_XZ as i32, "xz", decompress_xz),
+];
+
+// Return a name of the type of the operation, also for unknown types.
+pub fn operation_type_name(pop: &proto::InstallOperation) -> String {
+    match pop.type_.map(|t| t.enum_value()) {
+        Some(Ok(t)) => format!("{:?}", t),
+        Some(Err(t)) => format!("UNKNOWN_{}", t),
+        None => "NONE".to_string(),
+    }
+}
+
+fn apply_operation(f: &File, header: &DeltaUpdateFileHeader, pop: &proto::InstallOperation, block_size: u64, source: Option<&File>, target: &Target) -> Result<()> {
+    use proto::install_operation::Type;
+
+    let op_type = pop.type_.ok_or(anyhow!("unable to get type_ from partition operations"))?;
+
+    if pop.dst_extents.is_empty() {
+        bail!("no destination extents for {} operation", operation_type_name(pop));
+    }
+
+    // Never produce more than what fits into the destination extents,
+    // so a crafted payload cannot exhaust memory or disk.
+    let data_offset = header.translate_offset(pop.data_offset.unwrap_or_default().into());
+    let mut writer = ExtentWriter::new(target, &pop.dst_extents, block_size, data_offset)?;
+
+    if let Some((_, name, decompress)) = DECOMPRESSORS.iter().find(|(value, _, _)| *value == op_type.value()) {
+        let reader = decompress(operation_reader(f, header, pop)?)?;
+        copy_to_extents(reader, &mut writer).context(format!("failed to copy {} data at offset {:?}", name, data_offset))?;
+        return writer.finish();
+    }
+
+    match op_type.enum_value() {
+        Ok(Type::MOVE) => {
+            let source = source.ok_or(anyhow!("MOVE operation requires a source partition"))?;
+            copy_extents(source, &pop.src_extents, block_size, &mut writer).context("failed to copy source data")?;
+        }
+        Ok(Type::BSDIFF) => {
+            let source = source.ok_or(anyhow!("BSDIFF operation requires a source partition"))?;
+            let srcdata = read_extents(source, &pop.src_extents, block_size, pop.src_length)?;
+            let (patch, offset) = read_operation_data(f, header, pop)?;
+            let limit = writer.limit;
+            bspatch(&srcdata, &patch, limit, offset, |chunk| writer.write(chunk)).context(format!("failed to apply bsdiff at offset {:?}", offset))?;
+        }
+        _ => bail!("unsupported operation type {}", operation_type_name(pop)),
+    }
+
+    writer.finish()
+}
+
+// Reads the data blob of an operation directly from the payload, without
+// holding it in memory.
+struct OperationReader<'a> {
+    f: &'a File,
+    pos: u64,
+    end: u64,
+}
+
+impl Read for OperationReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min((self.end - self.pos) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let n = self.f.read_at(&mut buf[..len], self.pos)?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+fn operation_reader<'a>(f: &'a File, header: &DeltaUpdateFileHeader, pop: &proto::InstallOperation) -> Result<OperationReader<'a>> {
+    let data_offset = pop.data_offset.ok_or(anyhow!("unable to get data offset"))?;
+    let data_length = pop.data_length.ok_or(anyhow!("unable to get data length"))?;
+
+    let pos = header.translate_offset(data_offset.into());
+    check_in_payload(f, pos, data_length.into(), "operation data")?;
+
+    Ok(OperationReader {
+        f,
+        pos,
+        end: pos.checked_add(data_length.into()).ok_or(anyhow!("operation data offset {} out of range", pos))?,
+    })
+}
+
+// Size of the chunks data is copied in. It is a multiple of any block size,
+// so that only the last write of an operation can end in the middle of a block.
+const EXTRACT_CHUNKLEN: usize = 1 << 20;
+
+// Copy data from reader into the destination extents, through a buffer of
+// fixed size.
+fn copy_to_extents(mut reader: impl Read, writer: &mut ExtentWriter<'_>) -> Result<()> {
+    let mut buf = vec![0u8; EXTRACT_CHUNKLEN];
+
+    loop {
+        // Fill the whole buffer, as readers like BzDecoder return less.
+        let mut len = 0;
+        while len < buf.len() {
+            match reader.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        writer.write(&buf[..len])?;
+    }
+}
+
+// Splits data of an operation across its destination extents, in the order
+// of the extents, each one written at start_block * block_size.
+struct ExtentWriter<'a> {
+    target: &'a Target,
+    extents: &'a [proto::Extent],
+    block_size: u64,
+    data_offset: u64,
+    limit: u64,
+    written: u64,
+    // Current extent, and how many bytes were already written into it.
+    index: usize,
+    pos: u64,
+}
+
+impl<'a> ExtentWriter<'a> {
+    fn new(target: &'a Target, extents: &'a [proto::Extent], block_size: u64, data_offset: u64) -> Result<Self> {
+        let mut num_blocks: u64 = 0;
+        for extent in extents {
+            num_blocks = num_blocks.checked_add(extent.num_blocks.ok_or(anyhow!("unable to get num_blocks"))?).ok_or(anyhow!("too many destination blocks"))?;
+        }
+
+        Ok(ExtentWriter {
+            target,
+            extents,
+            block_size,
+            data_offset,
+            limit: block_size.checked_mul(num_blocks).ok_or(anyhow!("too many destination blocks"))?,
+            written: 0,
+            index: 0,
+            pos: 0,
+        })
+    }
+
+    // Fail with Error::OversizedChunk if the data does not fit into the
+    // remaining extents.
+    fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        if self.written + data.len() as u64 > self.limit {
+            return Err(Error::OversizedChunk {
+                data_offset: self.data_offset,
+                limit: self.limit,
+            }
+            .into());
+        }
+
+        while !data.is_empty() {
+            let extent = &self.extents[self.index];
+            let start_block = extent.start_block.ok_or(anyhow!("unable to get start_block"))?;
+            let extent_len = extent.num_blocks() * self.block_size;
+
+            let len = data.len().min((extent_len - self.pos) as usize);
+            let (chunk, rest) = data.split_at(len);
+
+            // Data for a sparse hole is skipped.
+            if start_block != SPARSE_HOLE {
+                let offset = start_block * self.block_size + self.pos;
+                self.target.write_at(chunk, offset, self.block_size).context(format!("failed to write {} bytes at {}", len, offset))?;
+            }
+
+            self.written += len as u64;
+            self.pos += len as u64;
+            data = rest;
+
+            if self.pos == extent_len {
+                self.target.sync_extent()?;
+                self.index += 1;
+                self.pos = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        // The last extent might not have been written completely.
+        if self.pos > 0 {
+            self.target.sync_extent()?;
+        }
+
+        Ok(())
+    }
+}
+
+// Check the given extents of source against its size, as they come from
+// the manifest, before anything is allocated or read for them.
+// Return their total length.
+fn check_source_extents(source: &File, extents: &[proto::Extent], block_size: u64) -> Result<u64> {
+    // Also works for block devices, whose metadata has no size.
+    let source_size = (&*source).seek(SeekFrom::End(0)).context("failed to get size of source")?;
+
+    let mut total: u64 = 0;
+    for extent in extents {
+        let start_block = extent.start_block.ok_or(anyhow!("unable to get start_block"))?;
+        let num_blocks = extent.num_blocks.ok_or(anyhow!("unable to get num_blocks"))?;
+
+        let len = num_blocks.checked_mul(block_size);
+        // A sparse hole reads nothing from source.
+        let end = match start_block {
+            SPARSE_HOLE => Some(0),
+            _ => start_block.checked_mul(block_size).zip(len).and_then(|(start, len)| start.checked_add(len)),
+        };
+        total = match (len.and_then(|len| total.checked_add(len)), end) {
+            (Some(total), Some(end)) if total <= source_size && end <= source_size => total,
+            _ => bail!(
+                "source extent of {} blocks at block {} exceeds the source size {}",
+                num_blocks,
+                start_block,
+                source_size
+            ),
+        };
+    }
+
+    Ok(total)
+}
+
+// Read the given extents of source, concatenated in the order of the extents.
+// If length is given, only that many bytes are returned.
+fn read_extents(source: &File, extents: &[proto::Extent], block_size: u64, length: Option<u64>) -> Result<Vec<u8>> {
+    let total = check_source_extents(source, extents, block_size)?;
+
+    let mut data = Vec::with_capacity(total as usize);
+    for extent in extents {
+        let start_block = extent.start_block();
+        let num_blocks = extent.num_blocks();
+
+        let pos = data.len();
+        data.resize(pos + (num_blocks * block_size) as usize, 0u8);
+
+        // A sparse hole reads as zeros.
+        if start_block != SPARSE_HOLE {
+            source.read_exact_at(&mut data[pos..], start_block * block_size).context(format!("failed to read {} blocks at block {}", num_blocks, start_block))?;
+        }
+    }
+
+    if let Some(len) = length {
+        data.truncate(len as usize);
+    }
+
+    Ok(data)
+}
+
+// Like read_extents, but pass the data on to writer, through a buffer of
+// fixed size.
+fn copy_extents(source: &File, extents: &[proto::Extent], block_size: u64, writer: &mut ExtentWriter<'_>) -> Result<()> {
+    check_source_extents(source, extents, block_size)?;
+
+    let mut buf = vec![0u8; EXTRACT_CHUNKLEN];
+    for extent in extents {
+        let start_block = extent.start_block();
+        let mut len = extent.num_blocks() * block_size;
+        let mut pos = start_block.wrapping_mul(block_size);
+
+        while len > 0 {
+            let chunk = &mut buf[..len.min(EXTRACT_CHUNKLEN as u64) as usize];
+            // A sparse hole reads as zeros.
+            if start_block == SPARSE_HOLE {
+                chunk.fill(0);
+            } else {
+                source.read_exact_at(chunk, pos).context(format!("failed to read {} bytes at {}", chunk.len(), pos))?;
+            }
+            writer.write(chunk)?;
+
+            pos = pos.wrapping_add(chunk.len() as u64);
+            len -= chunk.len() as u64;
         }
-        outfile.flush().context(format!("failed to flush at offset {:?}", translated_offset))?;
     }
 
     Ok(())
 }
 
+// Which signature slots must verify for a payload to be accepted.
+// The default accepts any slot that verifies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignaturePolicy {
+    // Versions of the slots to consider, e.g. [2] to demand that the
+    // "version 2" slot verifies. Empty means all slots.
+    pub required_versions: Vec<u32>,
+    // All of the considered slots must verify, not only one of them.
+    pub require_all: bool,
+    // Digest algorithm that callers hash the signed data with, before
+    // passing the digest in. parse_signature_data itself detects the
+    // algorithm from the length of the digest.
+    pub digest: verify_sig::DigestAlgo,
+}
+
+impl SignaturePolicy {
+    fn considers(&self, version: u32) -> bool {
+        self.required_versions.is_empty() || self.required_versions.contains(&version)
+    }
+}
+
 #[rustfmt::skip]
 // parse_signature_data takes bytes slices for signature and digest of data blobs,
-// and path to public key, to parse and verify the signature.
+// and trusted public keys, to parse and verify the signature.
+// Every key is tried against every signature slot, so that payloads signed
+// with either the current or the previous key verify during a key rollover.
 // Return only actual signature data, without version and special fields.
-pub fn parse_signature_data(sigbytes: &[u8], digest: &[u8], pubkeyfile: &str) -> Result<Vec<u8>> {
+pub fn parse_signature_data(sigbytes: &[u8], digest: &[u8], pubkeysources: &[PubkeySource]) -> Result<Vec<u8>> {
+    parse_signature_data_with_policy(sigbytes, digest, pubkeysources, &SignaturePolicy::default())
+}
+
+#[rustfmt::skip]
+// Like parse_signature_data, but only accept the signature if the slots
+// required by policy verify.
+// Return the signature data of the first verified slot.
+pub fn parse_signature_data_with_policy(sigbytes: &[u8], digest: &[u8], pubkeysources: &[PubkeySource], policy: &SignaturePolicy) -> Result<Vec<u8>> {
     // Signatures has a container of the fields, i.e. version, data, and
     // special fields.
     let sigmessage = match proto::Signatures::parse_from_bytes(sigbytes) {
@@ -169,28 +937,79 @@ pub fn parse_signature_data(sigbytes: &[u8], digest: &[u8], pubkeyfile: &str) ->
         _ => bail!("failed to parse signature messages"),
     };
 
+    if pubkeysources.is_empty() {
+        bail!("no public key given to verify the signature");
+    }
+
+    // Either RSA or ECDSA P-256 keys.
+    let mut pubkeys = Vec::with_capacity(pubkeysources.len());
+    for pubkeysource in pubkeysources {
+        pubkeys.push(pubkeysource.load().context(format!("failed to get public key ({:?})", pubkeysource))?);
+    }
+
     // sigmessages.signatures[] has a single element in case of dev update payloads,
     // while it could have multiple elements in case of production update payloads.
-    // For now we assume only dev update payloads are supported.
-    // Return the first valid signature, iterate into the next slot if invalid.
+    // Unless the policy requires all slots, return the first valid signature,
+    // iterate into the next slot if invalid.
+    let mut verified_data = None;
+    let mut verified_versions = Vec::new();
     for sig in sigmessage.signatures {
-        match verify_sig_pubkey(digest, &sig, pubkeyfile) {
+        if !policy.considers(sig.version()) {
+            debug!("skipping signature version {:?}, not required by policy", sig.version());
+            continue;
+        }
+
+        match verify_sig_any_pubkey(digest, &sig, pubkeysources, &pubkeys) {
+            Some(data) if !policy.require_all => return Ok(data),
+            Some(data) => {
+                verified_versions.push(sig.version());
+                verified_data.get_or_insert(data);
+            }
+            None if policy.require_all => bail!("signature version {:?} is invalid, but policy requires all signatures to verify", sig.version()),
+            None => info!("failed to verify signature version {:?}, jumping to the next slot", sig.version()),
+        }
+    }
+
+    if policy.require_all {
+        if let Some(missing) = policy.required_versions.iter().find(|v| !verified_versions.contains(v)) {
+            bail!("no valid signature of version {} found, but it is required by policy", missing);
+        }
+    }
+
+    match verified_data {
+        Some(data) => Ok(data),
+        None => bail!("failed to find a valid signature in any slot"),
+    }
+}
+
+// Try every public key against a signature slot.
+// Return the signature data if any of the keys verifies it.
+fn verify_sig_any_pubkey(digest: &[u8], sig: &Signature, pubkeysources: &[PubkeySource], pubkeys: &[PublicKey]) -> Option<Vec<u8>> {
+    for (pubkeysource, pubkey) in pubkeysources.iter().zip(pubkeys) {
+        match verify_sig_pubkey(digest, sig, pubkey) {
             Ok(sbox) => {
-                return Ok(sbox.to_vec());
+                debug!(
+                    "verified signature version {:?} with public key {:?}",
+                    sig.version(),
+                    pubkeysource
+                );
+                return Some(sbox.to_vec());
             }
             _ => {
-                info!("failed to verify signature, jumping to the next slot");
-                continue
+                info!(
+                    "failed to verify signature with public key {:?}, jumping to the next key",
+                    pubkeysource
+                );
             }
         };
     }
 
-    bail!("failed to find a valid signature in any slot");
+    None
 }
 
 // verify_sig_pubkey verifies signature with the given digest and the public key.
 // Return the verified signature data.
-pub fn verify_sig_pubkey(digest: &[u8], sig: &Signature, pubkeyfile: &str) -> Result<Box<[u8]>> {
+pub fn verify_sig_pubkey(digest: &[u8], sig: &Signature, pubkey: &PublicKey) -> Result<Box<[u8]>> {
     // The signature version is actually a numeration of the present signatures,
     // with the index starting at 2 if only one signature is present.
     // The Flatcar dev payload has only one signature but
@@ -208,21 +1027,212 @@ pub fn verify_sig_pubkey(digest: &[u8], sig: &Signature, pubkeyfile: &str) -> Re
     debug!("data: {:?}", sig.data());
     debug!("special_fields: {:?}", sig.special_fields());
 
-    // verify signature with pubkey
-    let pkcspem_pubkey = match get_public_key_pkcs_pem(pubkeyfile, KeyTypePkcs8) {
-        Ok(key) => key,
-        Err(err) => {
-            bail!("failed to get PKCS8 PEM public key ({:?}) with error {:?}", pubkeyfile, err);
+    verify_sig::verify_prehash(digest, sig.data(), pubkey).context(format!("verify signature ({:?}) failed", sig))?;
+
+    Ok(sigvec.clone().into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use bzip2::Compression;
+    use bzip2::write::BzEncoder;
+
+    fn compress_bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn extent(start_block: u64, num_blocks: u64) -> proto::Extent {
+        let mut e = proto::Extent::new();
+        e.start_block = Some(start_block);
+        e.num_blocks = Some(num_blocks);
+        e
+    }
+
+    fn tempfile_target() -> Target {
+        Target {
+            file: tempfile::tempfile().unwrap(),
+            options: WriteOptions::default(),
         }
-    };
+    }
+
+    #[test]
+    fn test_extent_writer() {
+        let target = tempfile_target();
+        let data: Vec<u8> = (1..=12).collect();
+        let extents = [extent(2, 2), extent(0, 1)];
+
+        let mut writer = ExtentWriter::new(&target, &extents, 4, 0).unwrap();
+        writer.write(&data[..3]).unwrap();
+        writer.write(&data[3..]).unwrap();
+        writer.finish().unwrap();
+
+        let mut written = Vec::new();
+        (&target.file).read_to_end(&mut written).unwrap();
+        assert_eq!(written, [9, 10, 11, 12, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut writer = ExtentWriter::new(&target, &extents[1..], 4, 0).unwrap();
+        assert!(writer.write(&data).is_err());
+    }
 
-    let res_verify = verify_sig::verify_rsa_pkcs_prehash(digest, sig.data(), pkcspem_pubkey);
-    match res_verify {
-        Ok(res_verify) => res_verify,
-        Err(err) => {
-            bail!("verify_rsa_pkcs signature ({:?}) failed with error {:?}", sig, err);
+    #[test]
+    fn test_oversized_manifest() {
+        let mut payload = DELTA_UPDATE_FILE_MAGIC.to_vec();
+        payload.extend(1u64.to_be_bytes());
+        payload.extend((MAX_MANIFEST_SIZE + 1).to_be_bytes());
+
+        let mut tmpfile = tempfile::tempfile().unwrap();
+        tmpfile.write_all(&payload).unwrap();
+        assert!(read_delta_update_header(&tmpfile).is_err());
+
+        // Within the maximum, but beyond the end of the payload.
+        payload[12..20].copy_from_slice(&MAX_MANIFEST_SIZE.to_be_bytes());
+        tmpfile.write_all_at(&payload, 0).unwrap();
+        let header = read_delta_update_header(&tmpfile).unwrap();
+        assert!(get_manifest_bytes(&tmpfile, &header).is_err());
+    }
+
+    #[test]
+    fn test_read_extents() {
+        let mut tmpfile = tempfile::tempfile().unwrap();
+        tmpfile.write_all(&(1..=16).collect::<Vec<u8>>()).unwrap();
+
+        let data = read_extents(&tmpfile, &[extent(1, 1), extent(SPARSE_HOLE, 1), extent(0, 1)], 4, None).unwrap();
+        assert_eq!(data, [5, 6, 7, 8, 0, 0, 0, 0, 1, 2, 3, 4]);
+
+        let data = read_extents(&tmpfile, &[extent(1, 1), extent(0, 1)], 4, Some(6)).unwrap();
+        assert_eq!(data, [5, 6, 7, 8, 1, 2]);
+    }
+
+    #[test]
+    fn test_oversized_source_extents() {
+        let mut srcfile = tempfile::tempfile().unwrap();
+        srcfile.write_all(&[1u8; 8192]).unwrap();
+
+        // Extents beyond the source fail, instead of being allocated.
+        for extents in [[extent(0, 1 << 40)], [extent(0, u64::MAX / 2)], [extent(SPARSE_HOLE, 1 << 40)], [extent(u64::MAX / 4096, 1)]] {
+            assert!(read_extents(&srcfile, &extents, 4096, None).is_err());
+
+            let target = tempfile_target();
+            let dst_extents = [extent(0, 1 << 40)];
+            let mut writer = ExtentWriter::new(&target, &dst_extents, 4096, 0).unwrap();
+            assert!(copy_extents(&srcfile, &extents, 4096, &mut writer).is_err());
         }
-    };
 
-    Ok(sigvec.clone().into_boxed_slice())
+        assert!(ExtentWriter::new(&tempfile_target(), &[extent(0, u64::MAX / 2)], 4096, 0).is_err());
+    }
+
+    #[test]
+    fn test_copy_extents() {
+        let mut srcfile = tempfile::tempfile().unwrap();
+        srcfile.write_all(&(1..=16).collect::<Vec<u8>>()).unwrap();
+
+        let target = tempfile_target();
+        let dst_extents = [extent(0, 3)];
+        let mut writer = ExtentWriter::new(&target, &dst_extents, 4, 0).unwrap();
+        copy_extents(&srcfile, &[extent(1, 1), extent(SPARSE_HOLE, 1), extent(0, 1)], 4, &mut writer).unwrap();
+        writer.finish().unwrap();
+
+        let mut written = Vec::new();
+        (&target.file).read_to_end(&mut written).unwrap();
+        assert_eq!(written, [5, 6, 7, 8, 0, 0, 0, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_get_partitions() {
+        let mut manifest = proto::DeltaArchiveManifest::new();
+        manifest.partition_operations.push(proto::InstallOperation::new());
+
+        let mut kernel = proto::InstallProcedure::new();
+        kernel.type_ = Some(proto::install_procedure::Type::KERNEL.into());
+        kernel.operations.push(proto::InstallOperation::new());
+        kernel.operations.push(proto::InstallOperation::new());
+        manifest.procedures.push(kernel);
+
+        let partitions = get_partitions(&manifest);
+        assert_eq!(
+            partitions.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            [PARTITION_USR, "KERNEL"]
+        );
+        assert_eq!(partitions[0].operations.len(), 1);
+        assert_eq!(partitions[1].operations.len(), 2);
+        assert_eq!(partitions[1].block_size, 4096);
+    }
+
+    #[test]
+    fn test_verify_source() {
+        let mut srcfile = tempfile::tempfile().unwrap();
+        srcfile.write_all(b"old partition data, followed by unused space").unwrap();
+
+        let mut old_info = proto::InstallInfo::new();
+        old_info.size = Some(18);
+        old_info.hash = Some(Sha256::digest(b"old partition data").to_vec());
+
+        let mut partition = PartitionUpdate {
+            name: PARTITION_USR.to_string(),
+            block_size: 4096,
+            operations: &[],
+            old_info: Some(&old_info),
+            new_info: None,
+        };
+        verify_source(&srcfile, &partition).unwrap();
+
+        let mut other_info = old_info.clone();
+        other_info.hash = Some(Sha256::digest(b"other partition").to_vec());
+        partition.old_info = Some(&other_info);
+
+        let err = verify_source(&srcfile, &partition).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::SourceHashMismatch { .. })));
+    }
+
+    #[test]
+    fn test_apply_operations_threads() {
+        let image = crate::testutil::pseudo_random_image(64 * 1024);
+        let private_key = verify_sig::get_private_key_file("../src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let payload = writer::PayloadWriter::new().chunk_size(4096).build(&image, private_key).unwrap();
+
+        let mut payload_file = tempfile::tempfile().unwrap();
+        payload_file.write_all(&payload).unwrap();
+        let header = read_delta_update_header(&payload_file).unwrap();
+        let manifest = get_manifest_bytes(&payload_file, &header).unwrap();
+        let partitions = get_partitions(&manifest);
+
+        let outdir = tempfile::tempdir().unwrap();
+        let outpath = outdir.path().join("image");
+        let options = WriteOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        get_partition_data_blobs(&payload_file, &header, &partitions[0], None, &outpath, options.clone()).unwrap();
+        assert_eq!(fs::read(&outpath).unwrap(), image);
+
+        // No operation is applied once cancelled.
+        options.cancel.cancel();
+        let err = get_partition_data_blobs(&payload_file, &header, &partitions[0], None, &outpath, options).unwrap_err();
+        assert_eq!(err.to_string(), "operation was cancelled");
+    }
+
+    #[test]
+    fn test_copy_to_extents_limited() {
+        let target = tempfile_target();
+        let packed = compress_bzip2(&[1u8; 8192]);
+        let extents = [extent(0, 2)];
+
+        let mut writer = ExtentWriter::new(&target, &extents, 4096, 0).unwrap();
+        copy_to_extents(BzDecoder::new(&packed[..]), &mut writer).unwrap();
+        assert_eq!(target.file.metadata().unwrap().len(), 8192);
+
+        let mut writer = ExtentWriter::new(&target, &extents[..], 2048, 42).unwrap();
+        let err = copy_to_extents(BzDecoder::new(&packed[..]), &mut writer).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::OversizedChunk {
+                data_offset: 42,
+                limit: 4096,
+            })
+        );
+    }
 }
@@ -0,0 +1,411 @@
+use std::io::Write;
+
+use anyhow::{Context, Result, bail};
+use bzip2::write::BzEncoder;
+use xz2::write::XzEncoder;
+use protobuf::{EnumOrUnknown, Message};
+use rsa::RsaPrivateKey;
+use rsa::sha2::{Digest, Sha256};
+
+use crate::proto;
+use crate::verify_sig;
+
+use super::{DELTA_UPDATE_FILE_MAGIC, DELTA_UPDATE_HEADER_SIZE, SPARSE_HOLE};
+
+const DEFAULT_BLOCK_SIZE: u32 = 4096;
+const DEFAULT_CHUNK_SIZE: u64 = 2 * 1024 * 1024;
+const XZ_PRESET: u32 = 9;
+
+// Version of the only signature slot, as in the payloads of Flatcar dev builds.
+const SIGNATURE_VERSION: u32 = 2;
+
+// Signs payloads with a key in memory, or with a key kept in hardware.
+pub trait PayloadSigner {
+    // Size of the signature, which has to be known before signing.
+    fn signature_size(&self) -> Result<usize>;
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl PayloadSigner for RsaPrivateKey {
+    fn signature_size(&self) -> Result<usize> {
+        Ok(rsa::traits::PublicKeyParts::size(self))
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        verify_sig::sign_rsa_pkcs(data, self.clone())
+    }
+}
+
+#[cfg(feature = "hardware-keys")]
+impl PayloadSigner for verify_sig::hardware::HardwareSigner {
+    fn signature_size(&self) -> Result<usize> {
+        self.key().signature_size()
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.sign_prehash(verify_sig::DigestAlgo::Sha256, &Sha256::digest(data))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    // REPLACE_XZ operations are only understood by the update_engine of
+    // ChromiumOS, not yet by that of Flatcar.
+    Xz,
+}
+
+// Builds a full update payload from an image, split into REPLACE,
+// REPLACE_BZ or REPLACE_XZ operations of chunk_size bytes each.
+// The whole payload is built in memory, so it is meant for test fixtures
+// and development images rather than production sized images.
+#[derive(Debug, Clone)]
+pub struct PayloadWriter {
+    block_size: u32,
+    chunk_size: u64,
+    compression: Compression,
+}
+
+impl Default for PayloadWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PayloadWriter {
+    pub fn new() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            compression: Compression::Bzip2,
+        }
+    }
+
+    pub fn block_size(mut self, param_block_size: u32) -> Self {
+        self.block_size = param_block_size;
+        self
+    }
+
+    // Rounded up to whole blocks.
+    pub fn chunk_size(mut self, param_chunk_size: u64) -> Self {
+        self.chunk_size = param_chunk_size;
+        self
+    }
+
+    // Chunks that do not get smaller by compression are still stored
+    // uncompressed.
+    pub fn compression(mut self, param_compression: Compression) -> Self {
+        self.compression = param_compression;
+        self
+    }
+
+    fn operation(&self, chunk: &[u8], start_block: u64, data_offset: usize) -> Result<(proto::InstallOperation, Vec<u8>)> {
+        use proto::install_operation::Type;
+
+        let packed = match self.compression {
+            Compression::None => None,
+            Compression::Bzip2 => {
+                let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::best());
+                encoder.write_all(chunk).context("failed to compress chunk")?;
+                Some((Type::REPLACE_BZ as i32, encoder.finish().context("failed to compress chunk")?))
+            }
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(Vec::new(), XZ_PRESET);
+                encoder.write_all(chunk).context("failed to compress chunk")?;
+                Some((Type::REPLACE_XZ as i32, encoder.finish().context("failed to compress chunk")?))
+            }
+        };
+
+        let (op_type, data) = match packed {
+            Some((op_type, packed)) if packed.len() < chunk.len() => (op_type, packed),
+            _ => (Type::REPLACE as i32, chunk.to_vec()),
+        };
+
+        let mut extent = proto::Extent::new();
+        extent.start_block = Some(start_block);
+        extent.num_blocks = Some((chunk.len() as u64).div_ceil(self.block_size as u64));
+
+        let mut op = proto::InstallOperation::new();
+        op.type_ = Some(EnumOrUnknown::from_i32(op_type));
+        op.data_offset = Some(u32::try_from(data_offset).context("data offset does not fit into the payload format")?);
+        op.data_length = Some(u32::try_from(data.len()).context("data length does not fit into the payload format")?);
+        op.dst_extents.push(extent);
+        op.data_sha256_hash = Some(Sha256::digest(&data).to_vec());
+
+        Ok((op, data))
+    }
+
+    // Return the payload for image, signed with private_key.
+    pub fn build(&self, image: &[u8], private_key: RsaPrivateKey) -> Result<Vec<u8>> {
+        self.build_with_signer(image, &private_key)
+    }
+
+    // Return the payload for image, signed by signer.
+    pub fn build_with_signer(&self, image: &[u8], signer: &dyn PayloadSigner) -> Result<Vec<u8>> {
+        let (manifest, blobs) = self.manifest_and_blobs(image)?;
+        assemble(manifest, &blobs, Some(signer))
+    }
+
+    // Return the payload for image without any signatures, e.g. to sign it
+    // later with sign_payload, or to test the handling of unsigned payloads.
+    pub fn build_unsigned(&self, image: &[u8]) -> Result<Vec<u8>> {
+        let (manifest, blobs) = self.manifest_and_blobs(image)?;
+        assemble(manifest, &blobs, None)
+    }
+
+    fn manifest_and_blobs(&self, image: &[u8]) -> Result<(proto::DeltaArchiveManifest, Vec<u8>)> {
+        let block_size = self.block_size as u64;
+        if block_size == 0 || self.chunk_size == 0 {
+            bail!("block size and chunk size must not be 0");
+        }
+        let chunk_blocks = self.chunk_size.div_ceil(block_size);
+
+        let mut manifest = proto::DeltaArchiveManifest::new();
+        manifest.block_size = Some(self.block_size);
+
+        let mut blobs = Vec::new();
+        for (i, chunk) in image.chunks((chunk_blocks * block_size) as usize).enumerate() {
+            let (op, data) = self.operation(chunk, i as u64 * chunk_blocks, blobs.len())?;
+            manifest.partition_operations.push(op);
+            blobs.extend(data);
+        }
+
+        let mut new_info = proto::InstallInfo::new();
+        new_info.size = Some(image.len() as u64);
+        new_info.hash = Some(Sha256::digest(image).to_vec());
+        manifest.new_partition_info = Some(new_info).into();
+
+        Ok((manifest, blobs))
+    }
+}
+
+// Replace the signatures of a payload of major version 1 with a single slot
+// signed by signer, or add them to an unsigned payload. The data blobs and
+// the operations are kept as they are.
+pub fn sign_payload(payload: &[u8], signer: &dyn PayloadSigner) -> Result<Vec<u8>> {
+    let header_size = DELTA_UPDATE_HEADER_SIZE as usize;
+    if payload.len() < header_size || &payload[..4] != DELTA_UPDATE_FILE_MAGIC {
+        bail!("bad file magic");
+    }
+    let version = u64::from_be_bytes(payload[4..12].try_into()?);
+    // Major version 2 also needs a signature of the metadata, which the
+    // PayloadWriter does not produce.
+    if version != 1 {
+        bail!(
+            "only payloads of major version 1 can be signed, not {}: major version 2 needs a metadata signature",
+            version
+        );
+    }
+
+    let manifest_size = u64::from_be_bytes(payload[12..20].try_into()?) as usize;
+    let Some(manifest_bytes) = payload.get(header_size..header_size + manifest_size) else {
+        bail!("manifest size {} beyond the end of the payload", manifest_size);
+    };
+    let mut manifest = proto::DeltaArchiveManifest::parse_from_bytes(manifest_bytes).context("failed to parse manifest")?;
+
+    let data = &payload[header_size + manifest_size..];
+    let blobs = match manifest.signatures_offset {
+        Some(offset) => {
+            // The dummy operation of the old signatures is added again for
+            // the new ones.
+            manifest.noop_operations.retain(|op| op.data_offset.map(u64::from) != Some(offset));
+            data.get(..offset as usize).context("signatures offset beyond the end of the payload")?
+        }
+        None => data,
+    };
+    manifest.signatures_offset = None;
+    manifest.signatures_size = None;
+
+    assemble(manifest, blobs, Some(signer))
+}
+
+fn serialize(manifest: &proto::DeltaArchiveManifest, blobs: &[u8]) -> Result<Vec<u8>> {
+    let manifest_bytes = manifest.write_to_bytes().context("failed to serialize manifest")?;
+
+    let mut payload = Vec::with_capacity(DELTA_UPDATE_HEADER_SIZE as usize + manifest_bytes.len() + blobs.len());
+    payload.extend(DELTA_UPDATE_FILE_MAGIC);
+    payload.extend(1u64.to_be_bytes());
+    payload.extend((manifest_bytes.len() as u64).to_be_bytes());
+    payload.extend(manifest_bytes);
+    payload.extend(blobs);
+
+    Ok(payload)
+}
+
+// Return the payload with manifest and blobs, followed by its signatures
+// unless there is no signer.
+fn assemble(mut manifest: proto::DeltaArchiveManifest, blobs: &[u8], signer: Option<&dyn PayloadSigner>) -> Result<Vec<u8>> {
+    let Some(signer) = signer else {
+        return serialize(&manifest, blobs);
+    };
+
+    let block_size = manifest.block_size() as u64;
+    if block_size == 0 {
+        bail!("block size must not be 0");
+    }
+
+    // The signature blob has the size of the key modulus, which is known
+    // before signing. It has to be part of the manifest, which is signed.
+    let mut signature = proto::signatures::Signature::new();
+    signature.version = Some(SIGNATURE_VERSION);
+    signature.data = Some(vec![0u8; signer.signature_size()?]);
+    let mut signatures = proto::Signatures::new();
+    signatures.signatures.push(signature);
+    let signatures_size = signatures.compute_size();
+
+    manifest.signatures_offset = Some(blobs.len() as u64);
+    manifest.signatures_size = Some(signatures_size);
+
+    // Older update_engine versions need this dummy operation to skip
+    // the signatures, see update_metadata.proto.
+    let mut noop_extent = proto::Extent::new();
+    noop_extent.start_block = Some(SPARSE_HOLE);
+    noop_extent.num_blocks = Some(signatures_size.div_ceil(block_size));
+    let mut noop = proto::InstallOperation::new();
+    noop.type_ = Some(proto::install_operation::Type::REPLACE.into());
+    noop.data_offset = Some(u32::try_from(blobs.len()).context("signatures offset does not fit into the payload format")?);
+    noop.data_length = Some(signatures_size as u32);
+    noop.dst_extents.push(noop_extent);
+    manifest.noop_operations.push(noop);
+
+    let mut payload = serialize(&manifest, blobs)?;
+
+    // Sign everything before the signatures.
+    let sigdata = signer.sign(&payload).context("failed to sign payload")?;
+    signatures.signatures[0].data = Some(sigdata);
+    let signatures_bytes = signatures.write_to_bytes().context("failed to serialize signatures")?;
+    if signatures_bytes.len() as u64 != signatures_size {
+        bail!(
+            "unexpected size of signatures {}, expected {}",
+            signatures_bytes.len(),
+            signatures_size
+        );
+    }
+    payload.extend(signatures_bytes);
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use crate::delta_update;
+    use crate::testutil::pseudo_random_image;
+    use crate::verify_sig::KeyType::KeyTypePkcs8;
+
+    const PRIVKEY_PKCS8_PATH: &str = "../src/testdata/private_key_test_pkcs8.pem";
+    const PUBKEY_PKCS8_PATH: &str = "../src/testdata/public_key_test_pkcs8.pem";
+    const PUBKEY_ECDSA_PATH: &str = "../src/testdata/public_key_test_ecdsa_p256.pem";
+
+    #[test]
+    fn test_build_and_extract() {
+        // Compressible and incompressible chunks, and a partial last block.
+        let mut image = vec![0u8; 8192];
+        image.extend(pseudo_random_image(10000));
+
+        let private_key = verify_sig::get_private_key_pkcs_pem(PRIVKEY_PKCS8_PATH, KeyTypePkcs8).unwrap();
+        let payload = PayloadWriter::new().chunk_size(8192).build(&image, private_key).unwrap();
+
+        let mut payload_file = tempfile::tempfile().unwrap();
+        payload_file.write_all(&payload).unwrap();
+
+        let header = delta_update::read_delta_update_header(&payload_file).unwrap();
+        let mut manifest = delta_update::get_manifest_bytes(&payload_file, &header).unwrap();
+        assert_eq!(manifest.partition_operations.len(), 3);
+
+        let info = delta_update::PayloadInfo::new(&header, &manifest);
+        assert_eq!(info.file_format_version, 1);
+        assert_eq!(info.block_size, DEFAULT_BLOCK_SIZE);
+        assert_eq!(info.operations(), 3);
+        assert_eq!(info.partitions[0].new_info.as_ref().map(|i| i.size), Some(image.len() as u64));
+        assert_eq!(
+            manifest.partition_operations[0].type_,
+            Some(proto::install_operation::Type::REPLACE_BZ.into())
+        );
+
+        // Signature covers everything before the signatures.
+        let sigbytes = delta_update::get_signatures_bytes(&payload_file, &header, &mut manifest).unwrap();
+        let header_data_length = delta_update::get_header_data_length(&header, &manifest).unwrap();
+        let digest = Sha256::digest(&payload[..header_data_length]);
+        delta_update::parse_signature_data(&sigbytes, &digest, &[PUBKEY_PKCS8_PATH.into()]).unwrap();
+
+        // Any of several trusted keys may verify the signature.
+        delta_update::parse_signature_data(&sigbytes, &digest, &[PUBKEY_ECDSA_PATH.into(), PUBKEY_PKCS8_PATH.into()]).unwrap();
+        assert!(delta_update::parse_signature_data(&sigbytes, &digest, &[PUBKEY_ECDSA_PATH.into()]).is_err());
+
+        // The only slot has version 2.
+        let pubkeys = [PUBKEY_PKCS8_PATH.into()];
+        let mut policy = delta_update::SignaturePolicy {
+            required_versions: vec![2],
+            require_all: true,
+            ..Default::default()
+        };
+        delta_update::parse_signature_data_with_policy(&sigbytes, &digest, &pubkeys, &policy).unwrap();
+        policy.required_versions = vec![1, 2];
+        assert!(delta_update::parse_signature_data_with_policy(&sigbytes, &digest, &pubkeys, &policy).is_err());
+        policy.require_all = false;
+        delta_update::parse_signature_data_with_policy(&sigbytes, &digest, &pubkeys, &policy).unwrap();
+        policy.required_versions = vec![1];
+        assert!(delta_update::parse_signature_data_with_policy(&sigbytes, &digest, &pubkeys, &policy).is_err());
+
+        let outdir = tempfile::tempdir().unwrap();
+        let outpath = outdir.path().join("image");
+        delta_update::get_data_blobs(&payload_file, &header, &manifest, &outpath).unwrap();
+
+        let mut extracted = Vec::new();
+        std::fs::File::open(&outpath).unwrap().read_to_end(&mut extracted).unwrap();
+        assert_eq!(extracted, image);
+    }
+
+    #[test]
+    fn test_build_and_extract_xz() {
+        let mut image = vec![0u8; 8192];
+        image.extend(pseudo_random_image(10000));
+
+        let private_key = verify_sig::get_private_key_pkcs_pem(PRIVKEY_PKCS8_PATH, KeyTypePkcs8).unwrap();
+        let payload = PayloadWriter::new().chunk_size(8192).compression(Compression::Xz).build(&image, private_key).unwrap();
+
+        let mut payload_file = tempfile::tempfile().unwrap();
+        payload_file.write_all(&payload).unwrap();
+
+        let header = delta_update::read_delta_update_header(&payload_file).unwrap();
+        let manifest = delta_update::get_manifest_bytes(&payload_file, &header).unwrap();
+        assert_eq!(
+            manifest.partition_operations[0].type_,
+            Some(proto::install_operation::Type::REPLACE_XZ.into())
+        );
+
+        let outdir = tempfile::tempdir().unwrap();
+        let outpath = outdir.path().join("image");
+        delta_update::get_data_blobs(&payload_file, &header, &manifest, &outpath).unwrap();
+
+        let mut extracted = Vec::new();
+        std::fs::File::open(&outpath).unwrap().read_to_end(&mut extracted).unwrap();
+        assert_eq!(extracted, image);
+    }
+
+    #[test]
+    fn test_sign_payload() {
+        let image = pseudo_random_image(20000);
+        let private_key = verify_sig::get_private_key_pkcs_pem(PRIVKEY_PKCS8_PATH, KeyTypePkcs8).unwrap();
+        let writer = PayloadWriter::new().chunk_size(8192);
+        let payload = writer.build(&image, private_key.clone()).unwrap();
+
+        // Signing again replaces the signatures, and the payload stays the same.
+        let resigned = sign_payload(&payload, &private_key).unwrap();
+        assert_eq!(resigned, payload);
+
+        // A payload without signatures gets them appended.
+        let unsigned = writer.build_unsigned(&image).unwrap();
+        assert!(unsigned.len() < payload.len());
+        assert_eq!(sign_payload(&unsigned, &private_key).unwrap(), payload);
+
+        assert!(sign_payload(b"CrAU", &private_key).is_err());
+        let mut version2 = payload.clone();
+        version2[4..12].copy_from_slice(&2u64.to_be_bytes());
+        assert!(sign_payload(&version2, &private_key).is_err());
+    }
+}
@@ -1 +1,3 @@
+// The generated code allows box_pointers, a lint that newer Rust removed.
+#[allow(renamed_and_removed_lints)]
 pub mod update_metadata;
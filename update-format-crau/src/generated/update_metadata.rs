@@ -463,6 +463,8 @@ pub mod install_operation {
         MOVE = 2,
         // @@protoc_insertion_point(enum_value:chromeos_update_engine.InstallOperation.Type.BSDIFF)
         BSDIFF = 3,
+        // @@protoc_insertion_point(enum_value:chromeos_update_engine.InstallOperation.Type.REPLACE_XZ)
+        REPLACE_XZ = 8,
     }
 
     impl ::protobuf::Enum for Type {
@@ -478,6 +480,7 @@ pub mod install_operation {
                 1 => ::std::option::Option::Some(Type::REPLACE_BZ),
                 2 => ::std::option::Option::Some(Type::MOVE),
                 3 => ::std::option::Option::Some(Type::BSDIFF),
+                8 => ::std::option::Option::Some(Type::REPLACE_XZ),
                 _ => ::std::option::Option::None
             }
         }
@@ -487,6 +490,7 @@ pub mod install_operation {
             Type::REPLACE_BZ,
             Type::MOVE,
             Type::BSDIFF,
+            Type::REPLACE_XZ,
         ];
     }
 
@@ -497,7 +501,13 @@ pub mod install_operation {
         }
 
         fn descriptor(&self) -> ::protobuf::reflect::EnumValueDescriptor {
-            let index = *self as usize;
+            let index = match self {
+                Type::REPLACE => 0,
+                Type::REPLACE_BZ => 1,
+                Type::MOVE => 2,
+                Type::BSDIFF => 3,
+                Type::REPLACE_XZ => 4,
+            };
             Self::enum_descriptor().value_by_index(index)
         }
     }
@@ -1856,7 +1866,7 @@ impl ::protobuf::reflect::ProtobufValue for DeltaArchiveManifest {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x15update_metadata.proto\x12\x16chromeos_update_engine\"\xbc\x03\n\
+    \n\x15update_metadata.proto\x12\x16chromeos_update_engine\"\xcc\x03\n\
     \x10InstallOperation\x12A\n\x04type\x18\x01\x20\x02(\x0e2-.chromeos_upda\
     te_engine.InstallOperation.TypeR\x04type\x12\x1f\n\x0bdata_offset\x18\
     \x02\x20\x01(\rR\ndataOffset\x12\x1f\n\x0bdata_length\x18\x03\x20\x01(\r\
@@ -1865,188 +1875,188 @@ static file_descriptor_proto_data: &'static [u8] = b"\
     \x04R\tsrcLength\x12?\n\x0bdst_extents\x18\x06\x20\x03(\x0b2\x1e.chromeo\
     s_update_engine.ExtentR\ndstExtents\x12\x1d\n\ndst_length\x18\x07\x20\
     \x01(\x04R\tdstLength\x12(\n\x10data_sha256_hash\x18\x08\x20\x01(\x0cR\
-    \x0edataSha256Hash\"9\n\x04Type\x12\x0b\n\x07REPLACE\x10\0\x12\x0e\n\nRE\
-    PLACE_BZ\x10\x01\x12\x08\n\x04MOVE\x10\x02\x12\n\n\x06BSDIFF\x10\x03\"H\
-    \n\x06Extent\x12\x1f\n\x0bstart_block\x18\x01\x20\x01(\x04R\nstartBlock\
-    \x12\x1d\n\nnum_blocks\x18\x02\x20\x01(\x04R\tnumBlocks\"\x95\x01\n\nSig\
-    natures\x12L\n\nsignatures\x18\x01\x20\x03(\x0b2,.chromeos_update_engine\
-    .Signatures.SignatureR\nsignatures\x1a9\n\tSignature\x12\x18\n\x07versio\
-    n\x18\x01\x20\x01(\rR\x07version\x12\x12\n\x04data\x18\x02\x20\x01(\x0cR\
-    \x04data\"5\n\x0bInstallInfo\x12\x12\n\x04size\x18\x01\x20\x01(\x04R\x04\
-    size\x12\x12\n\x04hash\x18\x02\x20\x01(\x0cR\x04hash\"\xc3\x02\n\x10Inst\
-    allProcedure\x12A\n\x04type\x18\x01\x20\x01(\x0e2-.chromeos_update_engin\
-    e.InstallProcedure.TypeR\x04type\x12H\n\noperations\x18\x02\x20\x03(\x0b\
-    2(.chromeos_update_engine.InstallOperationR\noperations\x12>\n\x08old_in\
-    fo\x18\x03\x20\x01(\x0b2#.chromeos_update_engine.InstallInfoR\x07oldInfo\
-    \x12>\n\x08new_info\x18\x04\x20\x01(\x0b2#.chromeos_update_engine.Instal\
-    lInfoR\x07newInfo\"\"\n\x04Type\x12\n\n\x06KERNEL\x10\0\x12\x0e\n\nPCR_P\
-    OLICY\x10\x01\"\xb1\x04\n\x14DeltaArchiveManifest\x12[\n\x14partition_op\
-    erations\x18\x01\x20\x03(\x0b2(.chromeos_update_engine.InstallOperationR\
-    \x13partitionOperations\x12Q\n\x0fnoop_operations\x18\x02\x20\x03(\x0b2(\
-    .chromeos_update_engine.InstallOperationR\x0enoopOperations\x12#\n\nbloc\
-    k_size\x18\x03\x20\x01(\r:\x044096R\tblockSize\x12+\n\x11signatures_offs\
-    et\x18\x04\x20\x01(\x04R\x10signaturesOffset\x12'\n\x0fsignatures_size\
-    \x18\x05\x20\x01(\x04R\x0esignaturesSize\x12Q\n\x12old_partition_info\
-    \x18\x08\x20\x01(\x0b2#.chromeos_update_engine.InstallInfoR\x10oldPartit\
-    ionInfo\x12Q\n\x12new_partition_info\x18\t\x20\x01(\x0b2#.chromeos_updat\
-    e_engine.InstallInfoR\x10newPartitionInfo\x12H\n\nprocedures\x18\n\x20\
-    \x03(\x0b2(.chromeos_update_engine.InstallProcedureR\nproceduresJ\xc1H\n\
-    \x07\x12\x05\x04\0\xc1\x01\x01\n\xb1\x01\n\x01\x02\x12\x03\x04\0\x1f2\
-    \xa6\x01\x20Copyright\x20(c)\x202010\x20The\x20Chromium\x20OS\x20Authors\
-    .\x20All\x20rights\x20reserved.\n\x20Use\x20of\x20this\x20source\x20code\
-    \x20is\x20governed\x20by\x20a\x20BSD-style\x20license\x20that\x20can\x20\
-    be\n\x20found\x20in\x20the\x20LICENSE\x20file.\n\n\xbb\x0e\n\x02\x04\0\
-    \x12\x040\0Q\x01\x1a\x82\x06\x20The\x20client\x20will\x20perform\x20each\
-    \x20InstallOperation\x20in\x20order,\x20beginning\x20even\n\x20before\
-    \x20the\x20entire\x20delta\x20file\x20is\x20downloaded\x20(but\x20after\
-    \x20at\x20least\x20the\n\x20protobuf\x20is\x20downloaded).\x20The\x20typ\
-    es\x20of\x20operations\x20are\x20explained:\n\x20-\x20REPLACE:\x20Replac\
-    e\x20the\x20dst_extents\x20on\x20the\x20drive\x20with\x20the\x20attached\
-    \x20data,\n\x20\x20\x20zero\x20padding\x20out\x20to\x20block\x20size.\n\
-    \x20-\x20REPLACE_BZ:\x20bzip2-uncompress\x20the\x20attached\x20data\x20a\
-    nd\x20write\x20it\x20into\n\x20\x20\x20dst_extents\x20on\x20the\x20drive\
-    ,\x20zero\x20padding\x20to\x20block\x20size.\n\x20-\x20MOVE:\x20Copy\x20\
-    the\x20data\x20in\x20src_extents\x20to\x20dst_extents.\x20Extents\x20may\
-    \x20overlap,\n\x20\x20\x20so\x20it\x20may\x20be\x20desirable\x20to\x20re\
-    ad\x20all\x20src_extents\x20data\x20into\x20memory\x20before\n\x20\x20\
-    \x20writing\x20it\x20out.\n\x20-\x20BSDIFF:\x20Read\x20src_length\x20byt\
-    es\x20from\x20src_extents\x20into\x20memory,\x20perform\n\x20\x20\x20bsp\
-    atch\x20with\x20attached\x20data,\x20write\x20new\x20data\x20to\x20dst_e\
-    xtents,\x20zero\x20padding\n\x20\x20\x20to\x20block\x20size.\n2\xfc\x05\
-    \x20Update\x20file\x20format:\x20A\x20delta\x20update\x20file\x20contain\
-    s\x20all\x20the\x20deltas\x20needed\n\x20to\x20update\x20a\x20system\x20\
-    from\x20one\x20specific\x20version\x20to\x20another\x20specific\n\x20ver\
-    sion.\x20The\x20update\x20format\x20is\x20represented\x20by\x20this\x20s\
-    truct\x20pseudocode:\n\x20struct\x20delta_update_file\x20{\n\x20\x20\x20\
-    char\x20magic[4]\x20=\x20\"CrAU\";\n\x20\x20\x20uint64\x20file_format_ve\
-    rsion\x20=\x201;\n\x20\x20\x20uint64\x20manifest_size;\x20\x20//\x20Size\
-    \x20of\x20protobuf\x20DeltaArchiveManifest\n\x20\x20\x20//\x20The\x20Bzi\
-    p2\x20compressed\x20DeltaArchiveManifest\n\x20\x20\x20char\x20manifest[]\
-    ;\n\n\x20\x20\x20//\x20Data\x20blobs\x20for\x20files,\x20no\x20specific\
-    \x20format.\x20The\x20specific\x20offset\n\x20\x20\x20//\x20and\x20lengt\
-    h\x20of\x20each\x20data\x20blob\x20is\x20recorded\x20in\x20the\x20DeltaA\
-    rchiveManifest.\n\x20\x20\x20struct\x20{\n\x20\x20\x20\x20\x20char\x20da\
-    ta[];\n\x20\x20\x20}\x20blobs[];\n\n\x20\x20\x20//\x20The\x20signature\
-    \x20covers\x20all\x20preceding\x20data.\x20Size\x20and\x20location\x20ar\
-    e\n\x20\x20\x20//\x20specified\x20inside\x20the\x20manifest.\n\x20\x20\
-    \x20char\x20signatures_message[];\n\n\x20};\n2\xcf\x01\x20The\x20DeltaAr\
-    chiveManifest\x20protobuf\x20is\x20an\x20ordered\x20list\x20of\x20Instal\
-    lOperation\n\x20objects.\x20These\x20objects\x20are\x20stored\x20in\x20a\
-    \x20linear\x20array\x20in\x20the\n\x20DeltaArchiveManifest.\x20Each\x20o\
-    peration\x20is\x20applied\x20in\x20order\x20by\x20the\x20client.\n2Y\x20\
-    The\x20DeltaArchiveManifest\x20also\x20contains\x20the\x20initial\x20and\
-    \x20final\n\x20checksums\x20for\x20the\x20device.\n\n\n\n\x03\x04\0\x01\
-    \x12\x030\x08\x18\n\x0c\n\x04\x04\0\x04\0\x12\x041\x026\x03\n\x0c\n\x05\
-    \x04\0\x04\0\x01\x12\x031\x07\x0b\n=\n\x06\x04\0\x04\0\x02\0\x12\x032\
-    \x04\x10\".\x20Replace\x20destination\x20extents\x20w/\x20attached\x20da\
-    ta\n\n\x0e\n\x07\x04\0\x04\0\x02\0\x01\x12\x032\x04\x0b\n\x0e\n\x07\x04\
-    \0\x04\0\x02\0\x02\x12\x032\x0e\x0f\nE\n\x06\x04\0\x04\0\x02\x01\x12\x03\
-    3\x04\x13\"6\x20Replace\x20destination\x20extents\x20w/\x20attached\x20b\
-    zipped\x20data\n\n\x0e\n\x07\x04\0\x04\0\x02\x01\x01\x12\x033\x04\x0e\n\
-    \x0e\n\x07\x04\0\x04\0\x02\x01\x02\x12\x033\x11\x12\n;\n\x06\x04\0\x04\0\
-    \x02\x02\x12\x034\x04\r\",\x20Move\x20source\x20extents\x20to\x20destina\
-    tion\x20extents\n\n\x0e\n\x07\x04\0\x04\0\x02\x02\x01\x12\x034\x04\x08\n\
-    \x0e\n\x07\x04\0\x04\0\x02\x02\x02\x12\x034\x0b\x0c\n1\n\x06\x04\0\x04\0\
-    \x02\x03\x12\x035\x04\x0f\"\"\x20The\x20data\x20is\x20a\x20bsdiff\x20bin\
-    ary\x20diff\n\n\x0e\n\x07\x04\0\x04\0\x02\x03\x01\x12\x035\x04\n\n\x0e\n\
-    \x07\x04\0\x04\0\x02\x03\x02\x12\x035\r\x0e\n\x0b\n\x04\x04\0\x02\0\x12\
-    \x037\x02\x19\n\x0c\n\x05\x04\0\x02\0\x04\x12\x037\x02\n\n\x0c\n\x05\x04\
-    \0\x02\0\x06\x12\x037\x0b\x0f\n\x0c\n\x05\x04\0\x02\0\x01\x12\x037\x10\
-    \x14\n\x0c\n\x05\x04\0\x02\0\x03\x12\x037\x17\x18\ne\n\x04\x04\0\x02\x01\
-    \x12\x03:\x02\"\x1aX\x20The\x20offset\x20into\x20the\x20delta\x20file\
-    \x20(after\x20the\x20protobuf)\n\x20where\x20the\x20data\x20(if\x20any)\
-    \x20is\x20stored\n\n\x0c\n\x05\x04\0\x02\x01\x04\x12\x03:\x02\n\n\x0c\n\
-    \x05\x04\0\x02\x01\x05\x12\x03:\x0b\x11\n\x0c\n\x05\x04\0\x02\x01\x01\
-    \x12\x03:\x12\x1d\n\x0c\n\x05\x04\0\x02\x01\x03\x12\x03:\x20!\n7\n\x04\
-    \x04\0\x02\x02\x12\x03<\x02\"\x1a*\x20The\x20length\x20of\x20the\x20data\
-    \x20in\x20the\x20delta\x20file\n\n\x0c\n\x05\x04\0\x02\x02\x04\x12\x03<\
-    \x02\n\n\x0c\n\x05\x04\0\x02\x02\x05\x12\x03<\x0b\x11\n\x0c\n\x05\x04\0\
-    \x02\x02\x01\x12\x03<\x12\x1d\n\x0c\n\x05\x04\0\x02\x02\x03\x12\x03<\x20\
-    !\nR\n\x04\x04\0\x02\x03\x12\x03?\x02\"\x1aE\x20Ordered\x20list\x20of\
-    \x20extents\x20that\x20are\x20read\x20from\x20(if\x20any)\x20and\x20writ\
-    ten\x20to.\n\n\x0c\n\x05\x04\0\x02\x03\x04\x12\x03?\x02\n\n\x0c\n\x05\
-    \x04\0\x02\x03\x06\x12\x03?\x0b\x11\n\x0c\n\x05\x04\0\x02\x03\x01\x12\
-    \x03?\x12\x1d\n\x0c\n\x05\x04\0\x02\x03\x03\x12\x03?\x20!\n\xc5\x01\n\
-    \x04\x04\0\x02\x04\x12\x03C\x02!\x1a\xb7\x01\x20Byte\x20length\x20of\x20\
-    src,\x20not\x20necessarily\x20block\x20aligned.\x20It's\x20only\x20used\
-    \x20for\n\x20BSDIFF,\x20because\x20we\x20need\x20to\x20pass\x20that\x20e\
-    xternal\x20program\x20the\x20number\n\x20of\x20bytes\x20to\x20read\x20fr\
-    om\x20the\x20blocks\x20we\x20pass\x20it.\n\n\x0c\n\x05\x04\0\x02\x04\x04\
-    \x12\x03C\x02\n\n\x0c\n\x05\x04\0\x02\x04\x05\x12\x03C\x0b\x11\n\x0c\n\
-    \x05\x04\0\x02\x04\x01\x12\x03C\x12\x1c\n\x0c\n\x05\x04\0\x02\x04\x03\
-    \x12\x03C\x1f\x20\n\x0b\n\x04\x04\0\x02\x05\x12\x03E\x02\"\n\x0c\n\x05\
-    \x04\0\x02\x05\x04\x12\x03E\x02\n\n\x0c\n\x05\x04\0\x02\x05\x06\x12\x03E\
-    \x0b\x11\n\x0c\n\x05\x04\0\x02\x05\x01\x12\x03E\x12\x1d\n\x0c\n\x05\x04\
-    \0\x02\x05\x03\x12\x03E\x20!\n\xb9\x01\n\x04\x04\0\x02\x06\x12\x03I\x02!\
-    \x1a\xab\x01\x20byte\x20length\x20of\x20dst,\x20not\x20necessarily\x20bl\
+    \x0edataSha256Hash\"I\n\x04Type\x12\x0b\n\x07REPLACE\x10\0\x12\x0e\n\nRE\
+    PLACE_BZ\x10\x01\x12\x08\n\x04MOVE\x10\x02\x12\n\n\x06BSDIFF\x10\x03\x12\
+    \x0e\n\nREPLACE_XZ\x10\x08\"H\n\x06Extent\x12\x1f\n\x0bstart_block\x18\
+    \x01\x20\x01(\x04R\nstartBlock\x12\x1d\n\nnum_blocks\x18\x02\x20\x01(\
+    \x04R\tnumBlocks\"\x95\x01\n\nSignatures\x12L\n\nsignatures\x18\x01\x20\
+    \x03(\x0b2,.chromeos_update_engine.Signatures.SignatureR\nsignatures\x1a\
+    9\n\tSignature\x12\x18\n\x07version\x18\x01\x20\x01(\rR\x07version\x12\
+    \x12\n\x04data\x18\x02\x20\x01(\x0cR\x04data\"5\n\x0bInstallInfo\x12\x12\
+    \n\x04size\x18\x01\x20\x01(\x04R\x04size\x12\x12\n\x04hash\x18\x02\x20\
+    \x01(\x0cR\x04hash\"\xc3\x02\n\x10InstallProcedure\x12A\n\x04type\x18\
+    \x01\x20\x01(\x0e2-.chromeos_update_engine.InstallProcedure.TypeR\x04typ\
+    e\x12H\n\noperations\x18\x02\x20\x03(\x0b2(.chromeos_update_engine.Insta\
+    llOperationR\noperations\x12>\n\x08old_info\x18\x03\x20\x01(\x0b2#.chrom\
+    eos_update_engine.InstallInfoR\x07oldInfo\x12>\n\x08new_info\x18\x04\x20\
+    \x01(\x0b2#.chromeos_update_engine.InstallInfoR\x07newInfo\"\"\n\x04Type\
+    \x12\n\n\x06KERNEL\x10\0\x12\x0e\n\nPCR_POLICY\x10\x01\"\xb1\x04\n\x14De\
+    ltaArchiveManifest\x12[\n\x14partition_operations\x18\x01\x20\x03(\x0b2(\
+    .chromeos_update_engine.InstallOperationR\x13partitionOperations\x12Q\n\
+    \x0fnoop_operations\x18\x02\x20\x03(\x0b2(.chromeos_update_engine.Instal\
+    lOperationR\x0enoopOperations\x12#\n\nblock_size\x18\x03\x20\x01(\r:\x04\
+    4096R\tblockSize\x12+\n\x11signatures_offset\x18\x04\x20\x01(\x04R\x10si\
+    gnaturesOffset\x12'\n\x0fsignatures_size\x18\x05\x20\x01(\x04R\x0esignat\
+    uresSize\x12Q\n\x12old_partition_info\x18\x08\x20\x01(\x0b2#.chromeos_up\
+    date_engine.InstallInfoR\x10oldPartitionInfo\x12Q\n\x12new_partition_inf\
+    o\x18\t\x20\x01(\x0b2#.chromeos_update_engine.InstallInfoR\x10newPartiti\
+    onInfo\x12H\n\nprocedures\x18\n\x20\x03(\x0b2(.chromeos_update_engine.In\
+    stallProcedureR\nproceduresJ\xc1H\n\x07\x12\x05\x04\0\xc1\x01\x01\n\xb1\
+    \x01\n\x01\x02\x12\x03\x04\0\x1f2\xa6\x01\x20Copyright\x20(c)\x202010\
+    \x20The\x20Chromium\x20OS\x20Authors.\x20All\x20rights\x20reserved.\n\
+    \x20Use\x20of\x20this\x20source\x20code\x20is\x20governed\x20by\x20a\x20\
+    BSD-style\x20license\x20that\x20can\x20be\n\x20found\x20in\x20the\x20LIC\
+    ENSE\x20file.\n\n\xbb\x0e\n\x02\x04\0\x12\x040\0Q\x01\x1a\x82\x06\x20The\
+    \x20client\x20will\x20perform\x20each\x20InstallOperation\x20in\x20order\
+    ,\x20beginning\x20even\n\x20before\x20the\x20entire\x20delta\x20file\x20\
+    is\x20downloaded\x20(but\x20after\x20at\x20least\x20the\n\x20protobuf\
+    \x20is\x20downloaded).\x20The\x20types\x20of\x20operations\x20are\x20exp\
+    lained:\n\x20-\x20REPLACE:\x20Replace\x20the\x20dst_extents\x20on\x20the\
+    \x20drive\x20with\x20the\x20attached\x20data,\n\x20\x20\x20zero\x20paddi\
+    ng\x20out\x20to\x20block\x20size.\n\x20-\x20REPLACE_BZ:\x20bzip2-uncompr\
+    ess\x20the\x20attached\x20data\x20and\x20write\x20it\x20into\n\x20\x20\
+    \x20dst_extents\x20on\x20the\x20drive,\x20zero\x20padding\x20to\x20block\
+    \x20size.\n\x20-\x20MOVE:\x20Copy\x20the\x20data\x20in\x20src_extents\
+    \x20to\x20dst_extents.\x20Extents\x20may\x20overlap,\n\x20\x20\x20so\x20\
+    it\x20may\x20be\x20desirable\x20to\x20read\x20all\x20src_extents\x20data\
+    \x20into\x20memory\x20before\n\x20\x20\x20writing\x20it\x20out.\n\x20-\
+    \x20BSDIFF:\x20Read\x20src_length\x20bytes\x20from\x20src_extents\x20int\
+    o\x20memory,\x20perform\n\x20\x20\x20bspatch\x20with\x20attached\x20data\
+    ,\x20write\x20new\x20data\x20to\x20dst_extents,\x20zero\x20padding\n\x20\
+    \x20\x20to\x20block\x20size.\n2\xfc\x05\x20Update\x20file\x20format:\x20\
+    A\x20delta\x20update\x20file\x20contains\x20all\x20the\x20deltas\x20need\
+    ed\n\x20to\x20update\x20a\x20system\x20from\x20one\x20specific\x20versio\
+    n\x20to\x20another\x20specific\n\x20version.\x20The\x20update\x20format\
+    \x20is\x20represented\x20by\x20this\x20struct\x20pseudocode:\n\x20struct\
+    \x20delta_update_file\x20{\n\x20\x20\x20char\x20magic[4]\x20=\x20\"CrAU\
+    \";\n\x20\x20\x20uint64\x20file_format_version\x20=\x201;\n\x20\x20\x20u\
+    int64\x20manifest_size;\x20\x20//\x20Size\x20of\x20protobuf\x20DeltaArch\
+    iveManifest\n\x20\x20\x20//\x20The\x20Bzip2\x20compressed\x20DeltaArchiv\
+    eManifest\n\x20\x20\x20char\x20manifest[];\n\n\x20\x20\x20//\x20Data\x20\
+    blobs\x20for\x20files,\x20no\x20specific\x20format.\x20The\x20specific\
+    \x20offset\n\x20\x20\x20//\x20and\x20length\x20of\x20each\x20data\x20blo\
+    b\x20is\x20recorded\x20in\x20the\x20DeltaArchiveManifest.\n\x20\x20\x20s\
+    truct\x20{\n\x20\x20\x20\x20\x20char\x20data[];\n\x20\x20\x20}\x20blobs[\
+    ];\n\n\x20\x20\x20//\x20The\x20signature\x20covers\x20all\x20preceding\
+    \x20data.\x20Size\x20and\x20location\x20are\n\x20\x20\x20//\x20specified\
+    \x20inside\x20the\x20manifest.\n\x20\x20\x20char\x20signatures_message[]\
+    ;\n\n\x20};\n2\xcf\x01\x20The\x20DeltaArchiveManifest\x20protobuf\x20is\
+    \x20an\x20ordered\x20list\x20of\x20InstallOperation\n\x20objects.\x20The\
+    se\x20objects\x20are\x20stored\x20in\x20a\x20linear\x20array\x20in\x20th\
+    e\n\x20DeltaArchiveManifest.\x20Each\x20operation\x20is\x20applied\x20in\
+    \x20order\x20by\x20the\x20client.\n2Y\x20The\x20DeltaArchiveManifest\x20\
+    also\x20contains\x20the\x20initial\x20and\x20final\n\x20checksums\x20for\
+    \x20the\x20device.\n\n\n\n\x03\x04\0\x01\x12\x030\x08\x18\n\x0c\n\x04\
+    \x04\0\x04\0\x12\x041\x026\x03\n\x0c\n\x05\x04\0\x04\0\x01\x12\x031\x07\
+    \x0b\n=\n\x06\x04\0\x04\0\x02\0\x12\x032\x04\x10\".\x20Replace\x20destin\
+    ation\x20extents\x20w/\x20attached\x20data\n\n\x0e\n\x07\x04\0\x04\0\x02\
+    \0\x01\x12\x032\x04\x0b\n\x0e\n\x07\x04\0\x04\0\x02\0\x02\x12\x032\x0e\
+    \x0f\nE\n\x06\x04\0\x04\0\x02\x01\x12\x033\x04\x13\"6\x20Replace\x20dest\
+    ination\x20extents\x20w/\x20attached\x20bzipped\x20data\n\n\x0e\n\x07\
+    \x04\0\x04\0\x02\x01\x01\x12\x033\x04\x0e\n\x0e\n\x07\x04\0\x04\0\x02\
+    \x01\x02\x12\x033\x11\x12\n;\n\x06\x04\0\x04\0\x02\x02\x12\x034\x04\r\",\
+    \x20Move\x20source\x20extents\x20to\x20destination\x20extents\n\n\x0e\n\
+    \x07\x04\0\x04\0\x02\x02\x01\x12\x034\x04\x08\n\x0e\n\x07\x04\0\x04\0\
+    \x02\x02\x02\x12\x034\x0b\x0c\n1\n\x06\x04\0\x04\0\x02\x03\x12\x035\x04\
+    \x0f\"\"\x20The\x20data\x20is\x20a\x20bsdiff\x20binary\x20diff\n\n\x0e\n\
+    \x07\x04\0\x04\0\x02\x03\x01\x12\x035\x04\n\n\x0e\n\x07\x04\0\x04\0\x02\
+    \x03\x02\x12\x035\r\x0e\n\x0b\n\x04\x04\0\x02\0\x12\x037\x02\x19\n\x0c\n\
+    \x05\x04\0\x02\0\x04\x12\x037\x02\n\n\x0c\n\x05\x04\0\x02\0\x06\x12\x037\
+    \x0b\x0f\n\x0c\n\x05\x04\0\x02\0\x01\x12\x037\x10\x14\n\x0c\n\x05\x04\0\
+    \x02\0\x03\x12\x037\x17\x18\ne\n\x04\x04\0\x02\x01\x12\x03:\x02\"\x1aX\
+    \x20The\x20offset\x20into\x20the\x20delta\x20file\x20(after\x20the\x20pr\
+    otobuf)\n\x20where\x20the\x20data\x20(if\x20any)\x20is\x20stored\n\n\x0c\
+    \n\x05\x04\0\x02\x01\x04\x12\x03:\x02\n\n\x0c\n\x05\x04\0\x02\x01\x05\
+    \x12\x03:\x0b\x11\n\x0c\n\x05\x04\0\x02\x01\x01\x12\x03:\x12\x1d\n\x0c\n\
+    \x05\x04\0\x02\x01\x03\x12\x03:\x20!\n7\n\x04\x04\0\x02\x02\x12\x03<\x02\
+    \"\x1a*\x20The\x20length\x20of\x20the\x20data\x20in\x20the\x20delta\x20f\
+    ile\n\n\x0c\n\x05\x04\0\x02\x02\x04\x12\x03<\x02\n\n\x0c\n\x05\x04\0\x02\
+    \x02\x05\x12\x03<\x0b\x11\n\x0c\n\x05\x04\0\x02\x02\x01\x12\x03<\x12\x1d\
+    \n\x0c\n\x05\x04\0\x02\x02\x03\x12\x03<\x20!\nR\n\x04\x04\0\x02\x03\x12\
+    \x03?\x02\"\x1aE\x20Ordered\x20list\x20of\x20extents\x20that\x20are\x20r\
+    ead\x20from\x20(if\x20any)\x20and\x20written\x20to.\n\n\x0c\n\x05\x04\0\
+    \x02\x03\x04\x12\x03?\x02\n\n\x0c\n\x05\x04\0\x02\x03\x06\x12\x03?\x0b\
+    \x11\n\x0c\n\x05\x04\0\x02\x03\x01\x12\x03?\x12\x1d\n\x0c\n\x05\x04\0\
+    \x02\x03\x03\x12\x03?\x20!\n\xc5\x01\n\x04\x04\0\x02\x04\x12\x03C\x02!\
+    \x1a\xb7\x01\x20Byte\x20length\x20of\x20src,\x20not\x20necessarily\x20bl\
     ock\x20aligned.\x20It's\x20only\x20used\x20for\n\x20BSDIFF,\x20because\
-    \x20we\x20need\x20to\x20fill\x20in\x20the\x20rest\x20of\x20the\x20last\
-    \x20block\n\x20that\x20bsdiff\x20writes\x20with\x20'\\0'\x20bytes.\n\n\
-    \x0c\n\x05\x04\0\x02\x06\x04\x12\x03I\x02\n\n\x0c\n\x05\x04\0\x02\x06\
-    \x05\x12\x03I\x0b\x11\n\x0c\n\x05\x04\0\x02\x06\x01\x12\x03I\x12\x1c\n\
-    \x0c\n\x05\x04\0\x02\x06\x03\x12\x03I\x1f\x20\n\xa0\x02\n\x04\x04\0\x02\
-    \x07\x12\x03P\x02&\x1a\x92\x02\x20Required\x20SHA\x20256\x20hash\x20of\
-    \x20the\x20blob\x20associated\x20with\x20this\x20operation.\n\x20This\
-    \x20is\x20used\x20as\x20a\x20primary\x20validation\x20for\x20http-based\
-    \x20downloads\x20and\n\x20as\x20a\x20defense-in-depth\x20validation\x20f\
-    or\x20https-based\x20downloads.\x20If\n\x20the\x20operation\x20doesn't\
-    \x20refer\x20to\x20any\x20blob,\x20this\x20field\x20will\x20have\n\x20ze\
-    ro\x20bytes.\n\n\x0c\n\x05\x04\0\x02\x07\x04\x12\x03P\x02\n\n\x0c\n\x05\
-    \x04\0\x02\x07\x05\x12\x03P\x0b\x10\n\x0c\n\x05\x04\0\x02\x07\x01\x12\
-    \x03P\x11!\n\x0c\n\x05\x04\0\x02\x07\x03\x12\x03P$%\n\x8f\x06\n\x02\x04\
-    \x01\x12\x04_\0b\x01\x1a\x82\x06\x20Data\x20is\x20packed\x20into\x20bloc\
-    ks\x20on\x20disk,\x20always\x20starting\x20from\x20the\x20beginning\n\
-    \x20of\x20the\x20block.\x20If\x20a\x20file's\x20data\x20is\x20too\x20lar\
-    ge\x20for\x20one\x20block,\x20it\x20overflows\n\x20into\x20another\x20bl\
-    ock,\x20which\x20may\x20or\x20may\x20not\x20be\x20the\x20following\x20bl\
-    ock\x20on\x20the\n\x20physical\x20partition.\x20An\x20ordered\x20list\
-    \x20of\x20extents\x20is\x20another\n\x20representation\x20of\x20an\x20or\
-    dered\x20list\x20of\x20blocks.\x20For\x20example,\x20a\x20file\x20stored\
-    \n\x20in\x20blocks\x209,\x2010,\x2011,\x202,\x2018,\x2012\x20(in\x20that\
-    \x20order)\x20would\x20be\x20stored\x20in\n\x20extents\x20{\x20{9,\x203}\
-    ,\x20{2,\x201},\x20{18,\x201},\x20{12,\x201}\x20}\x20(in\x20that\x20orde\
-    r).\n\x20In\x20general,\x20files\x20are\x20stored\x20sequentially\x20on\
-    \x20disk,\x20so\x20it's\x20more\x20efficient\n\x20to\x20use\x20extents\
-    \x20to\x20encode\x20the\x20block\x20lists\x20(this\x20is\x20effectively\
-    \n\x20run-length\x20encoding).\n\x20A\x20sentinel\x20value\x20(UINT64_MA\
-    X)\x20as\x20the\x20start\x20block\x20denotes\x20a\x20sparse-hole\n\x20in\
-    \x20a\x20file\x20whose\x20block-length\x20is\x20specified\x20by\x20num_b\
-    locks.\n\n\n\n\x03\x04\x01\x01\x12\x03_\x08\x0e\n\x0b\n\x04\x04\x01\x02\
-    \0\x12\x03`\x02\"\n\x0c\n\x05\x04\x01\x02\0\x04\x12\x03`\x02\n\n\x0c\n\
-    \x05\x04\x01\x02\0\x05\x12\x03`\x0b\x11\n\x0c\n\x05\x04\x01\x02\0\x01\
-    \x12\x03`\x12\x1d\n\x0c\n\x05\x04\x01\x02\0\x03\x12\x03`\x20!\n\x0b\n\
-    \x04\x04\x01\x02\x01\x12\x03a\x02!\n\x0c\n\x05\x04\x01\x02\x01\x04\x12\
-    \x03a\x02\n\n\x0c\n\x05\x04\x01\x02\x01\x05\x12\x03a\x0b\x11\n\x0c\n\x05\
-    \x04\x01\x02\x01\x01\x12\x03a\x12\x1c\n\x0c\n\x05\x04\x01\x02\x01\x03\
-    \x12\x03a\x1f\x20\n\xe8\x05\n\x02\x04\x02\x12\x04o\0u\x01\x1a\xdb\x05\
-    \x20Signatures:\x20Updates\x20may\x20be\x20signed\x20by\x20the\x20OS\x20\
-    vendor.\x20The\x20client\x20verifies\n\x20an\x20update's\x20signature\
-    \x20by\x20hashing\x20the\x20entire\x20download.\x20The\x20section\x20of\
-    \x20the\n\x20download\x20that\x20contains\x20the\x20signature\x20is\x20a\
-    t\x20the\x20end\x20of\x20the\x20file,\x20so\x20when\n\x20signing\x20a\
-    \x20file,\x20only\x20the\x20part\x20up\x20to\x20the\x20signature\x20part\
-    \x20is\x20signed.\n\x20Then,\x20the\x20client\x20looks\x20inside\x20the\
-    \x20download's\x20Signatures\x20message\x20for\x20a\n\x20Signature\x20me\
-    ssage\x20that\x20it\x20knows\x20how\x20to\x20handle.\x20Generally,\x20a\
-    \x20client\x20will\n\x20only\x20know\x20how\x20to\x20handle\x20one\x20ty\
-    pe\x20of\x20signature,\x20but\x20an\x20update\x20may\x20contain\n\x20man\
-    y\x20signatures\x20to\x20support\x20many\x20different\x20types\x20of\x20\
-    client.\x20Then\x20client\n\x20selects\x20a\x20Signature\x20message\x20a\
-    nd\x20uses\x20that,\x20along\x20with\x20a\x20known\x20public\x20key,\n\
-    \x20to\x20verify\x20the\x20download.\x20The\x20public\x20key\x20is\x20ex\
-    pected\x20to\x20be\x20part\x20of\x20the\n\x20client.\n\n\n\n\x03\x04\x02\
-    \x01\x12\x03o\x08\x12\n\x0c\n\x04\x04\x02\x03\0\x12\x04p\x02s\x03\n\x0c\
-    \n\x05\x04\x02\x03\0\x01\x12\x03p\n\x13\n\r\n\x06\x04\x02\x03\0\x02\0\
-    \x12\x03q\x04\x20\n\x0e\n\x07\x04\x02\x03\0\x02\0\x04\x12\x03q\x04\x0c\n\
-    \x0e\n\x07\x04\x02\x03\0\x02\0\x05\x12\x03q\r\x13\n\x0e\n\x07\x04\x02\
-    \x03\0\x02\0\x01\x12\x03q\x14\x1b\n\x0e\n\x07\x04\x02\x03\0\x02\0\x03\
-    \x12\x03q\x1e\x1f\n\r\n\x06\x04\x02\x03\0\x02\x01\x12\x03r\x04\x1c\n\x0e\
-    \n\x07\x04\x02\x03\0\x02\x01\x04\x12\x03r\x04\x0c\n\x0e\n\x07\x04\x02\
-    \x03\0\x02\x01\x05\x12\x03r\r\x12\n\x0e\n\x07\x04\x02\x03\0\x02\x01\x01\
-    \x12\x03r\x13\x17\n\x0e\n\x07\x04\x02\x03\0\x02\x01\x03\x12\x03r\x1a\x1b\
-    \n\x0b\n\x04\x04\x02\x02\0\x12\x03t\x02$\n\x0c\n\x05\x04\x02\x02\0\x04\
-    \x12\x03t\x02\n\n\x0c\n\x05\x04\x02\x02\0\x06\x12\x03t\x0b\x14\n\x0c\n\
-    \x05\x04\x02\x02\0\x01\x12\x03t\x15\x1f\n\x0c\n\x05\x04\x02\x02\0\x03\
+    \x20we\x20need\x20to\x20pass\x20that\x20external\x20program\x20the\x20nu\
+    mber\n\x20of\x20bytes\x20to\x20read\x20from\x20the\x20blocks\x20we\x20pa\
+    ss\x20it.\n\n\x0c\n\x05\x04\0\x02\x04\x04\x12\x03C\x02\n\n\x0c\n\x05\x04\
+    \0\x02\x04\x05\x12\x03C\x0b\x11\n\x0c\n\x05\x04\0\x02\x04\x01\x12\x03C\
+    \x12\x1c\n\x0c\n\x05\x04\0\x02\x04\x03\x12\x03C\x1f\x20\n\x0b\n\x04\x04\
+    \0\x02\x05\x12\x03E\x02\"\n\x0c\n\x05\x04\0\x02\x05\x04\x12\x03E\x02\n\n\
+    \x0c\n\x05\x04\0\x02\x05\x06\x12\x03E\x0b\x11\n\x0c\n\x05\x04\0\x02\x05\
+    \x01\x12\x03E\x12\x1d\n\x0c\n\x05\x04\0\x02\x05\x03\x12\x03E\x20!\n\xb9\
+    \x01\n\x04\x04\0\x02\x06\x12\x03I\x02!\x1a\xab\x01\x20byte\x20length\x20\
+    of\x20dst,\x20not\x20necessarily\x20block\x20aligned.\x20It's\x20only\
+    \x20used\x20for\n\x20BSDIFF,\x20because\x20we\x20need\x20to\x20fill\x20i\
+    n\x20the\x20rest\x20of\x20the\x20last\x20block\n\x20that\x20bsdiff\x20wr\
+    ites\x20with\x20'\\0'\x20bytes.\n\n\x0c\n\x05\x04\0\x02\x06\x04\x12\x03I\
+    \x02\n\n\x0c\n\x05\x04\0\x02\x06\x05\x12\x03I\x0b\x11\n\x0c\n\x05\x04\0\
+    \x02\x06\x01\x12\x03I\x12\x1c\n\x0c\n\x05\x04\0\x02\x06\x03\x12\x03I\x1f\
+    \x20\n\xa0\x02\n\x04\x04\0\x02\x07\x12\x03P\x02&\x1a\x92\x02\x20Required\
+    \x20SHA\x20256\x20hash\x20of\x20the\x20blob\x20associated\x20with\x20thi\
+    s\x20operation.\n\x20This\x20is\x20used\x20as\x20a\x20primary\x20validat\
+    ion\x20for\x20http-based\x20downloads\x20and\n\x20as\x20a\x20defense-in-\
+    depth\x20validation\x20for\x20https-based\x20downloads.\x20If\n\x20the\
+    \x20operation\x20doesn't\x20refer\x20to\x20any\x20blob,\x20this\x20field\
+    \x20will\x20have\n\x20zero\x20bytes.\n\n\x0c\n\x05\x04\0\x02\x07\x04\x12\
+    \x03P\x02\n\n\x0c\n\x05\x04\0\x02\x07\x05\x12\x03P\x0b\x10\n\x0c\n\x05\
+    \x04\0\x02\x07\x01\x12\x03P\x11!\n\x0c\n\x05\x04\0\x02\x07\x03\x12\x03P$\
+    %\n\x8f\x06\n\x02\x04\x01\x12\x04_\0b\x01\x1a\x82\x06\x20Data\x20is\x20p\
+    acked\x20into\x20blocks\x20on\x20disk,\x20always\x20starting\x20from\x20\
+    the\x20beginning\n\x20of\x20the\x20block.\x20If\x20a\x20file's\x20data\
+    \x20is\x20too\x20large\x20for\x20one\x20block,\x20it\x20overflows\n\x20i\
+    nto\x20another\x20block,\x20which\x20may\x20or\x20may\x20not\x20be\x20th\
+    e\x20following\x20block\x20on\x20the\n\x20physical\x20partition.\x20An\
+    \x20ordered\x20list\x20of\x20extents\x20is\x20another\n\x20representatio\
+    n\x20of\x20an\x20ordered\x20list\x20of\x20blocks.\x20For\x20example,\x20\
+    a\x20file\x20stored\n\x20in\x20blocks\x209,\x2010,\x2011,\x202,\x2018,\
+    \x2012\x20(in\x20that\x20order)\x20would\x20be\x20stored\x20in\n\x20exte\
+    nts\x20{\x20{9,\x203},\x20{2,\x201},\x20{18,\x201},\x20{12,\x201}\x20}\
+    \x20(in\x20that\x20order).\n\x20In\x20general,\x20files\x20are\x20stored\
+    \x20sequentially\x20on\x20disk,\x20so\x20it's\x20more\x20efficient\n\x20\
+    to\x20use\x20extents\x20to\x20encode\x20the\x20block\x20lists\x20(this\
+    \x20is\x20effectively\n\x20run-length\x20encoding).\n\x20A\x20sentinel\
+    \x20value\x20(UINT64_MAX)\x20as\x20the\x20start\x20block\x20denotes\x20a\
+    \x20sparse-hole\n\x20in\x20a\x20file\x20whose\x20block-length\x20is\x20s\
+    pecified\x20by\x20num_blocks.\n\n\n\n\x03\x04\x01\x01\x12\x03_\x08\x0e\n\
+    \x0b\n\x04\x04\x01\x02\0\x12\x03`\x02\"\n\x0c\n\x05\x04\x01\x02\0\x04\
+    \x12\x03`\x02\n\n\x0c\n\x05\x04\x01\x02\0\x05\x12\x03`\x0b\x11\n\x0c\n\
+    \x05\x04\x01\x02\0\x01\x12\x03`\x12\x1d\n\x0c\n\x05\x04\x01\x02\0\x03\
+    \x12\x03`\x20!\n\x0b\n\x04\x04\x01\x02\x01\x12\x03a\x02!\n\x0c\n\x05\x04\
+    \x01\x02\x01\x04\x12\x03a\x02\n\n\x0c\n\x05\x04\x01\x02\x01\x05\x12\x03a\
+    \x0b\x11\n\x0c\n\x05\x04\x01\x02\x01\x01\x12\x03a\x12\x1c\n\x0c\n\x05\
+    \x04\x01\x02\x01\x03\x12\x03a\x1f\x20\n\xe8\x05\n\x02\x04\x02\x12\x04o\0\
+    u\x01\x1a\xdb\x05\x20Signatures:\x20Updates\x20may\x20be\x20signed\x20by\
+    \x20the\x20OS\x20vendor.\x20The\x20client\x20verifies\n\x20an\x20update'\
+    s\x20signature\x20by\x20hashing\x20the\x20entire\x20download.\x20The\x20\
+    section\x20of\x20the\n\x20download\x20that\x20contains\x20the\x20signatu\
+    re\x20is\x20at\x20the\x20end\x20of\x20the\x20file,\x20so\x20when\n\x20si\
+    gning\x20a\x20file,\x20only\x20the\x20part\x20up\x20to\x20the\x20signatu\
+    re\x20part\x20is\x20signed.\n\x20Then,\x20the\x20client\x20looks\x20insi\
+    de\x20the\x20download's\x20Signatures\x20message\x20for\x20a\n\x20Signat\
+    ure\x20message\x20that\x20it\x20knows\x20how\x20to\x20handle.\x20General\
+    ly,\x20a\x20client\x20will\n\x20only\x20know\x20how\x20to\x20handle\x20o\
+    ne\x20type\x20of\x20signature,\x20but\x20an\x20update\x20may\x20contain\
+    \n\x20many\x20signatures\x20to\x20support\x20many\x20different\x20types\
+    \x20of\x20client.\x20Then\x20client\n\x20selects\x20a\x20Signature\x20me\
+    ssage\x20and\x20uses\x20that,\x20along\x20with\x20a\x20known\x20public\
+    \x20key,\n\x20to\x20verify\x20the\x20download.\x20The\x20public\x20key\
+    \x20is\x20expected\x20to\x20be\x20part\x20of\x20the\n\x20client.\n\n\n\n\
+    \x03\x04\x02\x01\x12\x03o\x08\x12\n\x0c\n\x04\x04\x02\x03\0\x12\x04p\x02\
+    s\x03\n\x0c\n\x05\x04\x02\x03\0\x01\x12\x03p\n\x13\n\r\n\x06\x04\x02\x03\
+    \0\x02\0\x12\x03q\x04\x20\n\x0e\n\x07\x04\x02\x03\0\x02\0\x04\x12\x03q\
+    \x04\x0c\n\x0e\n\x07\x04\x02\x03\0\x02\0\x05\x12\x03q\r\x13\n\x0e\n\x07\
+    \x04\x02\x03\0\x02\0\x01\x12\x03q\x14\x1b\n\x0e\n\x07\x04\x02\x03\0\x02\
+    \0\x03\x12\x03q\x1e\x1f\n\r\n\x06\x04\x02\x03\0\x02\x01\x12\x03r\x04\x1c\
+    \n\x0e\n\x07\x04\x02\x03\0\x02\x01\x04\x12\x03r\x04\x0c\n\x0e\n\x07\x04\
+    \x02\x03\0\x02\x01\x05\x12\x03r\r\x12\n\x0e\n\x07\x04\x02\x03\0\x02\x01\
+    \x01\x12\x03r\x13\x17\n\x0e\n\x07\x04\x02\x03\0\x02\x01\x03\x12\x03r\x1a\
+    \x1b\n\x0b\n\x04\x04\x02\x02\0\x12\x03t\x02$\n\x0c\n\x05\x04\x02\x02\0\
+    \x04\x12\x03t\x02\n\n\x0c\n\x05\x04\x02\x02\0\x06\x12\x03t\x0b\x14\n\x0c\
+    \n\x05\x04\x02\x02\0\x01\x12\x03t\x15\x1f\n\x0c\n\x05\x04\x02\x02\0\x03\
     \x12\x03t\"#\n\x86\x01\n\x02\x04\x03\x12\x04z\0}\x012z\x20Info\x20is\x20\
     used\x20to\x20validate\x20the\x20source\x20prior\x20to\x20the\x20update\
     \x20or\n\x20the\x20destination\x20after\x20the\x20list\x20of\x20InstallO\
@@ -1,7 +1,11 @@
+mod bspatch;
 pub mod delta_update;
 mod generated;
 pub mod verify_sig;
 
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+
 pub mod proto {
     pub use super::generated::update_metadata::*;
 }
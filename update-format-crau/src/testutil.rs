@@ -0,0 +1,107 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use tempfile::NamedTempFile;
+
+use crate::delta_update::writer::{Compression, PayloadSigner, PayloadWriter};
+
+// Image of len bytes that barely compresses, so that operations of it stay
+// as large as the image, unlike those of images of zeros.
+pub fn pseudo_random_image(len: usize) -> Vec<u8> {
+    (0..len as u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect()
+}
+
+// Builds minimal valid payloads of an image as test fixtures, in memory or
+// in temporary files. Payloads are unsigned unless a signer is given.
+pub struct PayloadBuilder {
+    image: Vec<u8>,
+    writer: PayloadWriter,
+    signer: Option<Box<dyn PayloadSigner>>,
+}
+
+impl PayloadBuilder {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            image,
+            writer: PayloadWriter::new(),
+            signer: None,
+        }
+    }
+
+    pub fn block_size(mut self, param_block_size: u32) -> Self {
+        self.writer = self.writer.block_size(param_block_size);
+        self
+    }
+
+    // Size of the data of each operation, so that e.g. an image of three
+    // chunks gives three operations.
+    pub fn chunk_size(mut self, param_chunk_size: u64) -> Self {
+        self.writer = self.writer.chunk_size(param_chunk_size);
+        self
+    }
+
+    pub fn compression(mut self, param_compression: Compression) -> Self {
+        self.writer = self.writer.compression(param_compression);
+        self
+    }
+
+    // Sign the payload, e.g. with a key of src/testdata loaded by
+    // verify_sig::get_private_key_file.
+    pub fn signer(mut self, param_signer: Box<dyn PayloadSigner>) -> Self {
+        self.signer = Some(param_signer);
+        self
+    }
+
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>> {
+        match &self.signer {
+            Some(signer) => self.writer.build_with_signer(&self.image, signer.as_ref()),
+            None => self.writer.build_unsigned(&self.image),
+        }
+    }
+
+    // Like build, but write the payload into a temporary file, which is
+    // removed when it is dropped.
+    pub fn build_file(&self) -> Result<NamedTempFile> {
+        let mut file = NamedTempFile::new().context("failed to create temporary payload file")?;
+        file.write_all(&self.build()?).context("failed to write payload")?;
+        file.flush().context("failed to write payload")?;
+
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+
+    use crate::delta_update;
+    use crate::verify_sig;
+
+    #[test]
+    fn test_payload_builder() {
+        let image: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let builder = PayloadBuilder::new(image.clone()).block_size(1024).chunk_size(2048).compression(Compression::None);
+
+        let payload_file = builder.build_file().unwrap();
+        let upfile = File::open(payload_file.path()).unwrap();
+        let header = delta_update::read_delta_update_header(&upfile).unwrap();
+        let manifest = delta_update::get_manifest_bytes(&upfile, &header).unwrap();
+        assert_eq!(manifest.block_size(), 1024);
+        assert_eq!(manifest.partition_operations.len(), 3);
+        assert_eq!(manifest.signatures_offset, None);
+
+        let outdir = tempfile::tempdir().unwrap();
+        let outpath = outdir.path().join("image");
+        delta_update::get_data_blobs(&upfile, &header, &manifest, &outpath).unwrap();
+        assert_eq!(fs::read(&outpath).unwrap(), builder.image());
+
+        let private_key = verify_sig::get_private_key_file("../src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let signed = builder.signer(Box::new(private_key)).build().unwrap();
+        assert!(signed.len() > fs::metadata(payload_file.path()).unwrap().len() as usize);
+    }
+}
@@ -5,7 +5,8 @@ use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
 use rsa::pkcs1v15;
 use rsa::signature::{SignatureEncoding, Signer, Verifier};
 use rsa::signature::hazmat::PrehashVerifier;
-use rsa::sha2::Sha256;
+use rsa::sha2::{Sha256, Sha512};
+use p256::ecdsa;
 use std::{fs, str};
 
 #[derive(Debug)]
@@ -13,17 +14,72 @@ pub enum KeyType {
     KeyTypeNone,
     KeyTypePkcs1,
     KeyTypePkcs8,
+    // ECDSA key on the NIST P-256 curve, in SPKI (PKCS8) form.
+    KeyTypeEcdsaP256,
+    // Binary DER encoding of KeyTypePkcs1 and KeyTypePkcs8, where public keys
+    // of KeyTypeDerPkcs8 are in SPKI form.
+    KeyTypeDerPkcs1,
+    KeyTypeDerPkcs8,
+}
+
+const PEM_PREFIX: &[u8] = b"-----BEGIN";
+
+#[cfg(feature = "hardware-keys")]
+pub mod hardware;
+
+// Public key of any of the supported algorithms.
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Rsa(RsaPublicKey),
+    EcdsaP256(ecdsa::VerifyingKey),
 }
 
 // Assume that we rely on RSA either PKCS1v1.5 or PKCS8,
-// sha256 for the hash.
+// sha256 for the hash, unless sha512 is given explicitly.
+
+// Digest algorithm that signatures were created over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DigestAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl std::str::FromStr for DigestAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(DigestAlgo::Sha256),
+            "sha512" => Ok(DigestAlgo::Sha512),
+            _ => Err(format!("unknown digest algorithm \"{}\"", s)),
+        }
+    }
+}
+
+impl DigestAlgo {
+    // Detect the algorithm from the length of a digest.
+    pub fn from_digest_len(len: usize) -> Result<Self> {
+        match len {
+            32 => Ok(DigestAlgo::Sha256),
+            64 => Ok(DigestAlgo::Sha512),
+            _ => bail!("unsupported digest length {}", len),
+        }
+    }
+}
 
 // Takes a data buffer and a private key, to sign the data
 // with the private key and verify the data with the public key.
 pub fn sign_rsa_pkcs(databuf: &[u8], private_key: RsaPrivateKey) -> Result<Vec<u8>> {
-    let signing_key = pkcs1v15::SigningKey::<Sha256>::new(private_key);
+    sign_rsa_pkcs_with(DigestAlgo::Sha256, databuf, private_key)
+}
 
-    let signature = signing_key.sign(databuf);
+// Like sign_rsa_pkcs, but with the given digest algorithm.
+pub fn sign_rsa_pkcs_with(algo: DigestAlgo, databuf: &[u8], private_key: RsaPrivateKey) -> Result<Vec<u8>> {
+    let signature = match algo {
+        DigestAlgo::Sha256 => pkcs1v15::SigningKey::<Sha256>::new(private_key).sign(databuf),
+        DigestAlgo::Sha512 => pkcs1v15::SigningKey::<Sha512>::new(private_key).sign(databuf),
+    };
     assert_ne!(signature.to_bytes().as_ref(), databuf);
 
     Ok(signature.to_vec())
@@ -53,28 +109,48 @@ pub fn verify_rsa_pkcs_buf(databuf: &[u8], signature: &[u8], public_key: RsaPubl
 // In contrast to verify_rsa_pkcs_buf, the function takes a digest of an input
 // buffer, so it does not have a limitation of max size of input data.
 // It relies on RSA PrehashVerifier.
+// The digest algorithm is detected from the length of the digest, either
+// sha256 or sha512.
 // TODO: consider migrating to RSA DigestVerifier.
 pub fn verify_rsa_pkcs_prehash(digestbuf: &[u8], signature: &[u8], public_key: RsaPublicKey) -> Result<()> {
-    let verifying_key = pkcs1v15::VerifyingKey::<Sha256>::new(public_key);
+    verify_rsa_pkcs_prehash_with(DigestAlgo::from_digest_len(digestbuf.len())?, digestbuf, signature, public_key)
+}
 
-    verifying_key
-        .verify_prehash(
-            digestbuf,
-            &pkcs1v15::Signature::try_from(signature).context(anyhow!("unable to convert signature into pkcs1v15::Signature"))?,
-        )
-        .context(format!("failed to verify_prehash signature ({:?})", signature))
+// Like verify_rsa_pkcs_prehash, but with an explicit digest algorithm.
+pub fn verify_rsa_pkcs_prehash_with(algo: DigestAlgo, digestbuf: &[u8], signature: &[u8], public_key: RsaPublicKey) -> Result<()> {
+    let signature = pkcs1v15::Signature::try_from(signature).context(anyhow!("unable to convert signature into pkcs1v15::Signature"))?;
+
+    match algo {
+        DigestAlgo::Sha256 => pkcs1v15::VerifyingKey::<Sha256>::new(public_key).verify_prehash(digestbuf, &signature),
+        DigestAlgo::Sha512 => pkcs1v15::VerifyingKey::<Sha512>::new(public_key).verify_prehash(digestbuf, &signature),
+    }
+    .context(format!("failed to verify_prehash signature ({:?}) with {:?}", signature, algo))
+}
+
+// Takes a digest, an ECDSA signature and a P-256 public key, to verify the digest
+// with the public key.
+// The signature is expected in ASN.1 DER form, as created by openssl, but the
+// fixed size form of r and s is accepted too.
+pub fn verify_ecdsa_p256_prehash(digestbuf: &[u8], signature: &[u8], public_key: &ecdsa::VerifyingKey) -> Result<()> {
+    let signature = ecdsa::Signature::from_der(signature).or_else(|_| ecdsa::Signature::from_slice(signature)).context(anyhow!("unable to convert signature into ecdsa::Signature"))?;
+
+    public_key.verify_prehash(digestbuf, &signature).context(format!("failed to verify_prehash ECDSA signature ({:?})", signature))
+}
+
+// Verify the digest with a public key of any supported algorithm.
+pub fn verify_prehash(digestbuf: &[u8], signature: &[u8], public_key: &PublicKey) -> Result<()> {
+    match public_key {
+        PublicKey::Rsa(key) => verify_rsa_pkcs_prehash(digestbuf, signature, key.clone()),
+        PublicKey::EcdsaP256(key) => verify_ecdsa_p256_prehash(digestbuf, signature, key),
+    }
 }
 
 pub fn get_private_key_pkcs_pem(private_key_path: &str, key_type: KeyType) -> Result<RsaPrivateKey> {
     let private_key_buf = fs::read_to_string(private_key_path).context(format!("failed to read private key from path {:?}", private_key_path))?;
     let out_key = match key_type {
-        KeyType::KeyTypePkcs1 => RsaPrivateKey::from_pkcs1_pem(private_key_buf.as_str()).or_else(|error| {
-            bail!("failed to parse PKCS1 PEM message: {:?}", error);
-        }),
-        KeyType::KeyTypePkcs8 => RsaPrivateKey::from_pkcs8_pem(private_key_buf.as_str()).or_else(|error| {
-            bail!("failed to parse PKCS8 PEM message: {:?}", error);
-        }),
-        KeyType::KeyTypeNone => {
+        KeyType::KeyTypePkcs1 => RsaPrivateKey::from_pkcs1_pem(private_key_buf.as_str()).context("failed to parse PKCS1 PEM message"),
+        KeyType::KeyTypePkcs8 => RsaPrivateKey::from_pkcs8_pem(private_key_buf.as_str()).context("failed to parse PKCS8 PEM message"),
+        _ => {
             bail!("invalid key type: {:?}", key_type);
         }
     };
@@ -85,13 +161,9 @@ pub fn get_private_key_pkcs_pem(private_key_path: &str, key_type: KeyType) -> Re
 pub fn get_public_key_pkcs_pem(public_key_path: &str, key_type: KeyType) -> Result<RsaPublicKey> {
     let public_key_buf = fs::read_to_string(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
     let out_key = match key_type {
-        KeyType::KeyTypePkcs1 => RsaPublicKey::from_pkcs1_pem(public_key_buf.as_str()).or_else(|error| {
-            bail!("failed to parse PKCS1 PEM message: {:?}", error);
-        }),
-        KeyType::KeyTypePkcs8 => RsaPublicKey::from_public_key_pem(public_key_buf.as_str()).or_else(|error| {
-            bail!("failed to parse PKCS8 PEM message: {:?}", error);
-        }),
-        KeyType::KeyTypeNone => {
+        KeyType::KeyTypePkcs1 => RsaPublicKey::from_pkcs1_pem(public_key_buf.as_str()).context("failed to parse PKCS1 PEM message"),
+        KeyType::KeyTypePkcs8 => RsaPublicKey::from_public_key_pem(public_key_buf.as_str()).context("failed to parse PKCS8 PEM message"),
+        _ => {
             bail!("invalid key type: {:?}", key_type);
         }
     };
@@ -99,6 +171,167 @@ pub fn get_public_key_pkcs_pem(public_key_path: &str, key_type: KeyType) -> Resu
     out_key
 }
 
+// Like get_private_key_pkcs_pem, but also for keys in DER form.
+pub fn get_private_key_pkcs(private_key_path: &str, key_type: KeyType) -> Result<RsaPrivateKey> {
+    match key_type {
+        KeyType::KeyTypeDerPkcs1 | KeyType::KeyTypeDerPkcs8 => {
+            let private_key_buf = fs::read(private_key_path).context(format!("failed to read private key from path {:?}", private_key_path))?;
+            get_private_key_from_der_bytes(&private_key_buf, key_type)
+        }
+        _ => get_private_key_pkcs_pem(private_key_path, key_type),
+    }
+}
+
+fn get_private_key_from_der_bytes(der: &[u8], key_type: KeyType) -> Result<RsaPrivateKey> {
+    match key_type {
+        KeyType::KeyTypeDerPkcs1 => RsaPrivateKey::from_pkcs1_der(der).context("failed to parse PKCS1 DER message"),
+        KeyType::KeyTypeDerPkcs8 => RsaPrivateKey::from_pkcs8_der(der).context("failed to parse PKCS8 DER message"),
+        _ => {
+            bail!("invalid key type: {:?}", key_type);
+        }
+    }
+}
+
+// Like get_public_key_pkcs_pem, but also for keys in DER form.
+pub fn get_public_key_pkcs(public_key_path: &str, key_type: KeyType) -> Result<RsaPublicKey> {
+    match key_type {
+        KeyType::KeyTypeDerPkcs1 => {
+            let public_key_buf = fs::read(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
+            RsaPublicKey::from_pkcs1_der(&public_key_buf).context("failed to parse PKCS1 DER message")
+        }
+        KeyType::KeyTypeDerPkcs8 => {
+            let public_key_buf = fs::read(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
+            RsaPublicKey::from_public_key_der(&public_key_buf).context("failed to parse SPKI DER message")
+        }
+        _ => get_public_key_pkcs_pem(public_key_path, key_type),
+    }
+}
+
+// Read an RSA private key in PEM or DER form, PKCS1 or PKCS8, detected
+// from the file content.
+pub fn get_private_key_file(private_key_path: &str) -> Result<RsaPrivateKey> {
+    let private_key_buf = fs::read(private_key_path).context(format!("failed to read private key from path {:?}", private_key_path))?;
+
+    if private_key_buf.starts_with(PEM_PREFIX) {
+        let pem = str::from_utf8(&private_key_buf).context(format!("private key {:?} is not valid UTF-8", private_key_path))?;
+        return RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem)).context(format!(
+            "failed to parse private key {:?} as PKCS8 or PKCS1 PEM",
+            private_key_path
+        ));
+    }
+
+    get_private_key_from_der_bytes(&private_key_buf, KeyType::KeyTypeDerPkcs8).or_else(|_| get_private_key_from_der_bytes(&private_key_buf, KeyType::KeyTypeDerPkcs1)).context(format!(
+        "failed to parse private key {:?} as PKCS8 or PKCS1 DER",
+        private_key_path
+    ))
+}
+
+// Read a public key of any supported algorithm in PEM or DER form,
+// detected from the file content.
+pub fn get_public_key_file(public_key_path: &str) -> Result<PublicKey> {
+    let public_key_buf = fs::read(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
+
+    if public_key_buf.starts_with(PEM_PREFIX) {
+        get_public_key_from_pem_bytes(&public_key_buf)
+    } else {
+        get_public_key_from_der_bytes(&public_key_buf)
+    }
+    .context(format!("failed to parse public key {:?}", public_key_path))
+}
+
+// Like get_public_key_pkcs_pem, but also for key types other than RSA.
+pub fn get_public_key_pem(public_key_path: &str, key_type: KeyType) -> Result<PublicKey> {
+    match key_type {
+        KeyType::KeyTypeEcdsaP256 => {
+            let public_key_buf = fs::read_to_string(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
+            let key = ecdsa::VerifyingKey::from_public_key_pem(public_key_buf.as_str()).context("failed to parse ECDSA P-256 PEM message")?;
+            Ok(PublicKey::EcdsaP256(key))
+        }
+        _ => get_public_key_pkcs_pem(public_key_path, key_type).map(PublicKey::Rsa),
+    }
+}
+
+// Read a public key in SPKI PEM form, and detect whether it is an RSA or
+// an ECDSA P-256 key.
+pub fn get_public_key_spki_pem(public_key_path: &str) -> Result<PublicKey> {
+    let public_key_buf = fs::read(public_key_path).context(format!("failed to read public key from path {:?}", public_key_path))?;
+
+    get_public_key_from_pem_bytes(&public_key_buf).context(format!("failed to parse public key {:?}", public_key_path))
+}
+
+// Parse a public key from PEM data in memory, e.g. a key compiled into the
+// binary. Accepts RSA keys in SPKI or PKCS1 form, and ECDSA P-256 keys in
+// SPKI form.
+pub fn get_public_key_from_pem_bytes(pem: &[u8]) -> Result<PublicKey> {
+    let pem = str::from_utf8(pem).context("public key PEM is not valid UTF-8")?;
+
+    if let Ok(key) = RsaPublicKey::from_public_key_pem(pem) {
+        return Ok(PublicKey::Rsa(key));
+    }
+    if let Ok(key) = RsaPublicKey::from_pkcs1_pem(pem) {
+        return Ok(PublicKey::Rsa(key));
+    }
+    match ecdsa::VerifyingKey::from_public_key_pem(pem) {
+        Ok(key) => Ok(PublicKey::EcdsaP256(key)),
+        Err(error) => Err(error).context("failed to parse public key as RSA or ECDSA P-256 PEM"),
+    }
+}
+
+// Like get_public_key_from_pem_bytes, but for binary DER data.
+pub fn get_public_key_from_der_bytes(der: &[u8]) -> Result<PublicKey> {
+    if let Ok(key) = RsaPublicKey::from_public_key_der(der) {
+        return Ok(PublicKey::Rsa(key));
+    }
+    if let Ok(key) = RsaPublicKey::from_pkcs1_der(der) {
+        return Ok(PublicKey::Rsa(key));
+    }
+    match ecdsa::VerifyingKey::from_public_key_der(der) {
+        Ok(key) => Ok(PublicKey::EcdsaP256(key)),
+        Err(error) => Err(error).context("failed to parse public key as RSA or ECDSA P-256 DER"),
+    }
+}
+
+// Where to get a trusted public key from.
+#[derive(Clone)]
+pub enum PubkeySource {
+    // Path to a PEM or DER file.
+    Path(String),
+    // PEM data in memory.
+    Pem(Vec<u8>),
+    // Key in a PKCS#11 token or a TPM.
+    #[cfg(feature = "hardware-keys")]
+    Hardware(hardware::HardwareKey),
+}
+
+impl PubkeySource {
+    pub fn load(&self) -> Result<PublicKey> {
+        match self {
+            PubkeySource::Path(path) => get_public_key_file(path),
+            PubkeySource::Pem(pem) => get_public_key_from_pem_bytes(pem),
+            #[cfg(feature = "hardware-keys")]
+            PubkeySource::Hardware(key) => key.public_key(),
+        }
+    }
+}
+
+impl From<&str> for PubkeySource {
+    fn from(path: &str) -> Self {
+        PubkeySource::Path(path.to_string())
+    }
+}
+
+// Keep log messages short, the PEM data is not of interest.
+impl std::fmt::Debug for PubkeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PubkeySource::Path(path) => write!(f, "{:?}", path),
+            PubkeySource::Pem(pem) => write!(f, "<{} bytes of PEM>", pem.len()),
+            #[cfg(feature = "hardware-keys")]
+            PubkeySource::Hardware(key) => write!(f, "{:?}", key),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,6 +342,15 @@ mod tests {
     const PUBKEY_PKCS1_PATH: &str = "../src/testdata/public_key_test_pkcs1.pem";
     const PRIVKEY_PKCS8_PATH: &str = "../src/testdata/private_key_test_pkcs8.pem";
     const PUBKEY_PKCS8_PATH: &str = "../src/testdata/public_key_test_pkcs8.pem";
+    const PRIVKEY_ECDSA_PATH: &str = "../src/testdata/private_key_test_ecdsa_p256.pem";
+    const PUBKEY_ECDSA_PATH: &str = "../src/testdata/public_key_test_ecdsa_p256.pem";
+
+    #[test]
+    fn test_parse_error_source() {
+        // The error of the underlying parser is kept as source.
+        let err = get_public_key_from_der_bytes(b"not a key").unwrap_err();
+        assert!(err.chain().count() > 1);
+    }
 
     #[test]
     fn test_verify_sig() {
@@ -121,7 +363,7 @@ mod tests {
             panic!("failed to sign data: {:?}", error);
         });
 
-        _ = verify_rsa_pkcs_buf(
+        verify_rsa_pkcs_buf(
             TESTDATA.as_bytes(),
             signature.as_slice(),
             get_public_key_pkcs_pem(PUBKEY_PKCS1_PATH, KeyTypePkcs1).unwrap(),
@@ -139,7 +381,7 @@ mod tests {
             panic!("failed to sign data: {:?}", error);
         });
 
-        _ = verify_rsa_pkcs_buf(
+        verify_rsa_pkcs_buf(
             TESTDATA.as_bytes(),
             signature.as_slice(),
             get_public_key_pkcs_pem(PUBKEY_PKCS8_PATH, KeyTypePkcs8).unwrap(),
@@ -148,4 +390,105 @@ mod tests {
             panic!("failed to verify data: {:?}", error);
         });
     }
+
+    #[test]
+    fn test_verify_prehash_sha512() {
+        use rsa::sha2::Digest;
+
+        let signature = sign_rsa_pkcs_with(
+            DigestAlgo::Sha512,
+            TESTDATA.as_bytes(),
+            get_private_key_pkcs_pem(PRIVKEY_PKCS8_PATH, KeyTypePkcs8).unwrap(),
+        )
+        .unwrap();
+        let public_key = get_public_key_pkcs_pem(PUBKEY_PKCS8_PATH, KeyTypePkcs8).unwrap();
+
+        let digest512 = Sha512::digest(TESTDATA.as_bytes());
+        verify_rsa_pkcs_prehash(&digest512, &signature, public_key.clone()).unwrap();
+
+        let digest256 = Sha256::digest(TESTDATA.as_bytes());
+        assert!(verify_rsa_pkcs_prehash(&digest256, &signature, public_key.clone()).is_err());
+        assert!(verify_rsa_pkcs_prehash(&digest512[..20], &signature, public_key).is_err());
+    }
+
+    #[test]
+    fn test_verify_ecdsa_p256() {
+        use p256::ecdsa::signature::hazmat::PrehashSigner;
+        use p256::pkcs8::DecodePrivateKey;
+        use rsa::sha2::Digest;
+
+        let signing_key = ecdsa::SigningKey::from_pkcs8_pem(&fs::read_to_string(PRIVKEY_ECDSA_PATH).unwrap()).unwrap();
+        let digest = Sha256::digest(TESTDATA.as_bytes());
+        let signature: ecdsa::Signature = signing_key.sign_prehash(&digest).unwrap();
+
+        let public_key = get_public_key_spki_pem(PUBKEY_ECDSA_PATH).unwrap();
+        assert!(matches!(public_key, PublicKey::EcdsaP256(_)));
+        assert!(matches!(get_public_key_spki_pem(PUBKEY_PKCS8_PATH).unwrap(), PublicKey::Rsa(_)));
+
+        // DER and fixed size signatures
+        verify_prehash(&digest, signature.to_der().as_bytes(), &public_key).unwrap();
+        verify_prehash(&digest, &signature.to_bytes(), &public_key).unwrap();
+
+        let other = Sha256::digest(b"other data");
+        assert!(verify_prehash(&other, signature.to_der().as_bytes(), &public_key).is_err());
+        assert!(get_public_key_pkcs_pem(PUBKEY_ECDSA_PATH, KeyType::KeyTypeEcdsaP256).is_err());
+        assert!(matches!(
+            get_public_key_pem(PUBKEY_ECDSA_PATH, KeyType::KeyTypeEcdsaP256).unwrap(),
+            PublicKey::EcdsaP256(_)
+        ));
+    }
+
+    #[test]
+    fn test_public_key_from_bytes() {
+        use rsa::pkcs8::EncodePublicKey;
+
+        for path in [PUBKEY_PKCS1_PATH, PUBKEY_PKCS8_PATH] {
+            let pem = fs::read(path).unwrap();
+            assert!(matches!(get_public_key_from_pem_bytes(&pem).unwrap(), PublicKey::Rsa(_)));
+            assert!(matches!(PubkeySource::Pem(pem).load().unwrap(), PublicKey::Rsa(_)));
+        }
+
+        let PublicKey::EcdsaP256(ec_key) = PubkeySource::from(PUBKEY_ECDSA_PATH).load().unwrap() else {
+            panic!("expected an ECDSA key");
+        };
+        let der = ec_key.to_public_key_der().unwrap();
+        assert!(matches!(
+            get_public_key_from_der_bytes(der.as_bytes()).unwrap(),
+            PublicKey::EcdsaP256(_)
+        ));
+
+        let rsa_key = get_public_key_pkcs_pem(PUBKEY_PKCS8_PATH, KeyTypePkcs8).unwrap();
+        let der = rsa_key.to_public_key_der().unwrap();
+        assert!(matches!(
+            get_public_key_from_der_bytes(der.as_bytes()).unwrap(),
+            PublicKey::Rsa(_)
+        ));
+
+        // DER files, detected or selected
+        let dir = tempfile::tempdir().unwrap();
+        let der_path = dir.path().join("public_key.der");
+        fs::write(&der_path, der.as_bytes()).unwrap();
+        let der_path = der_path.to_str().unwrap();
+        assert!(matches!(get_public_key_file(der_path).unwrap(), PublicKey::Rsa(_)));
+        assert_eq!(get_public_key_pkcs(der_path, KeyType::KeyTypeDerPkcs8).unwrap(), rsa_key);
+        assert!(get_public_key_pkcs(der_path, KeyType::KeyTypeDerPkcs1).is_err());
+
+        let private_key = get_private_key_pkcs(PRIVKEY_PKCS1_PATH, KeyTypePkcs1).unwrap();
+        let der_path = dir.path().join("private_key.der");
+        fs::write(
+            &der_path,
+            rsa::pkcs1::EncodeRsaPrivateKey::to_pkcs1_der(&private_key).unwrap().as_bytes(),
+        )
+        .unwrap();
+        let der_path = der_path.to_str().unwrap();
+        assert_eq!(get_private_key_file(der_path).unwrap(), private_key);
+        assert_eq!(get_private_key_pkcs(der_path, KeyType::KeyTypeDerPkcs1).unwrap(), private_key);
+        assert_eq!(
+            get_private_key_file(PRIVKEY_PKCS8_PATH).unwrap(),
+            get_private_key_pkcs_pem(PRIVKEY_PKCS8_PATH, KeyTypePkcs8).unwrap()
+        );
+
+        assert!(get_public_key_from_pem_bytes(b"not a key").is_err());
+        assert!(get_public_key_from_der_bytes(b"not a key").is_err());
+    }
 }
@@ -0,0 +1,404 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail};
+use rsa::traits::PublicKeyParts;
+
+use super::{DigestAlgo, PublicKey, get_public_key_from_der_bytes, get_public_key_from_pem_bytes};
+
+// DER encoded DigestInfo prefixes of PKCS#1 v1.5 signatures, see RFC 8017.
+// Raw RSA-PKCS mechanisms of tokens expect them in front of the digest.
+const DIGEST_INFO_SHA256: &[u8] = &[0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20];
+const DIGEST_INFO_SHA512: &[u8] = &[0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40];
+
+const TPM_PREFIX: &str = "tpm:";
+const PKCS11_PREFIX: &str = "pkcs11:";
+
+// Key that stays inside a PKCS#11 token or a TPM, so that it never appears
+// on the filesystem. Operations are done through the standard tools of the
+// host:
+//  - p11tool of GnuTLS (3.x) exports public keys of PKCS#11 tokens,
+//    with --export-pubkey, and --provider for the module-path attribute.
+//  - pkcs11-tool of OpenSC signs with them, with --sign --mechanism
+//    RSA-PKCS. It does not take PKCS#11 URIs, so the token, object, id and
+//    slot-id attributes and the module-path query attribute are translated
+//    to its options. Other attributes are ignored. The PIN is written to its
+//    stdin, so that it neither shows up in its arguments nor its environment.
+//  - tpm2_readpublic and tpm2_sign of tpm2-tools (5.x) for TPM keys.
+// The tools write their results into files of a temporary directory, which
+// unlike /dev/stdout works on any platform, and keeps messages out of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HardwareKey {
+    // PKCS#11 URI as of RFC 7512, e.g. "pkcs11:token=ue;object=payload-key".
+    Pkcs11(String),
+    // Persistent TPM handle, e.g. 0x81000001.
+    Tpm(u32),
+}
+
+// Parse either a PKCS#11 URI, or "tpm:" followed by a handle in hex.
+impl FromStr for HardwareKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with(PKCS11_PREFIX) {
+            return Ok(HardwareKey::Pkcs11(s.to_string()));
+        }
+
+        let Some(handle) = s.strip_prefix(TPM_PREFIX) else {
+            bail!("unknown hardware key reference {:?}, expected a pkcs11: URI or tpm:<handle>", s);
+        };
+        let handle = handle.trim_start_matches("0x");
+        u32::from_str_radix(handle, 16).map(HardwareKey::Tpm).context(format!("invalid TPM handle {:?}", s))
+    }
+}
+
+// Where to read the PIN of a token, or the authorization value of a TPM key,
+// from. Never from the arguments, which other users can see.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinSource {
+    // Name of an environment variable.
+    Env(String),
+    // File that contains only the PIN, with an optional trailing newline.
+    File(PathBuf),
+}
+
+// Parse "env:NAME" or "file:PATH", like OpenSC and tpm2-tools do.
+impl FromStr for PinSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(name) = s.strip_prefix("env:") {
+            return Ok(PinSource::Env(name.to_string()));
+        }
+        if let Some(path) = s.strip_prefix("file:") {
+            return Ok(PinSource::File(PathBuf::from(path)));
+        }
+        bail!("unknown PIN source {:?}, expected env:<name> or file:<path>", s)
+    }
+}
+
+impl PinSource {
+    fn read(&self) -> Result<String> {
+        let pin = match self {
+            PinSource::Env(name) => std::env::var(name).context(format!("failed to read PIN from environment variable {}", name))?,
+            PinSource::File(path) => fs::read_to_string(path).context(format!("failed to read PIN from {}", path.display()))?,
+        };
+        Ok(pin.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+// Decode %XX escapes of a PKCS#11 URI attribute value.
+fn percent_decode(value: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+        let byte = std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()).filter(|_| hex.len() == 2);
+        out.push(byte.context(format!("invalid percent encoding in {:?}", value))?);
+    }
+    Ok(out)
+}
+
+// Value of the module-path query attribute of a PKCS#11 URI.
+fn pkcs11_module_path(uri: &str) -> Result<Option<String>> {
+    let args = pkcs11_tool_args(uri)?;
+    Ok(args.iter().position(|a| a == "--module").map(|i| args[i + 1].clone()))
+}
+
+// Options of pkcs11-tool that select the key of a PKCS#11 URI.
+fn pkcs11_tool_args(uri: &str) -> Result<Vec<String>> {
+    let rest = uri.strip_prefix(PKCS11_PREFIX).context(format!("not a PKCS#11 URI: {:?}", uri))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let mut args = Vec::new();
+    for attr in path.split(';').chain(query.split('&')).filter(|a| !a.is_empty()) {
+        let (name, value) = attr.split_once('=').context(format!("invalid attribute {:?} in PKCS#11 URI", attr))?;
+        let value = percent_decode(value)?;
+        let text = || String::from_utf8(value.clone()).context(format!("attribute {} of PKCS#11 URI is not UTF-8", name));
+        match name {
+            "token" => args.extend(["--token-label".to_string(), text()?]),
+            "object" => args.extend(["--label".to_string(), text()?]),
+            "id" => args.extend(["--id".to_string(), value.iter().map(|b| format!("{:02x}", b)).collect()]),
+            "slot-id" => args.extend(["--slot".to_string(), text()?]),
+            "module-path" => args.extend(["--module".to_string(), text()?]),
+            _ => (),
+        }
+    }
+    Ok(args)
+}
+
+fn run(command: &mut Command, input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().context(format!("failed to run {}", program))?;
+
+    if let Some(input) = input {
+        child.stdin.take().context("no stdin")?.write_all(input).context(format!("failed to write to {}", program))?;
+    }
+    drop(child.stdin.take());
+
+    let output = child.wait_with_output().context(format!("failed to wait for {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+// Like run, but return what command wrote into the file output instead of
+// its stdout.
+fn run_to_file(command: &mut Command, input: Option<&[u8]>, output: &Path) -> Result<Vec<u8>> {
+    run(command, input)?;
+    fs::read(output).context(format!(
+        "failed to read output of {} from {}",
+        command.get_program().to_string_lossy(),
+        output.display()
+    ))
+}
+
+fn digest_info(algo: DigestAlgo, digest: &[u8]) -> Vec<u8> {
+    let prefix = match algo {
+        DigestAlgo::Sha256 => DIGEST_INFO_SHA256,
+        DigestAlgo::Sha512 => DIGEST_INFO_SHA512,
+    };
+
+    [prefix, digest].concat()
+}
+
+impl HardwareKey {
+    // Export the public part of the key.
+    pub fn public_key(&self) -> Result<PublicKey> {
+        match self {
+            HardwareKey::Pkcs11(uri) => {
+                let mut command = Command::new("p11tool");
+                if let Some(module) = pkcs11_module_path(uri)? {
+                    command.arg("--provider").arg(module);
+                }
+                let pem = run(command.arg("--export-pubkey").arg(uri), None)?;
+                get_public_key_from_pem_bytes(&pem)
+            }
+            HardwareKey::Tpm(handle) => {
+                let dir = tempfile::tempdir().context("failed to create temporary directory")?;
+                let output = dir.path().join("public.der");
+                let der = run_to_file(
+                    Command::new("tpm2_readpublic").arg("-Q").arg("-c").arg(format!("{:#x}", handle)).args(["-f", "der", "-o"]).arg(&output),
+                    None,
+                    &output,
+                )?;
+                get_public_key_from_der_bytes(&der)
+            }
+        }
+    }
+
+    // Size of signatures made with the key, needed to reserve space for them
+    // in payloads. Only RSA keys have a fixed signature size.
+    pub fn signature_size(&self) -> Result<usize> {
+        match self.public_key()? {
+            PublicKey::Rsa(key) => Ok(key.size()),
+            PublicKey::EcdsaP256(_) => bail!("signing payloads is only supported with RSA keys"),
+        }
+    }
+}
+
+// Signs with a HardwareKey, logging in with the PIN of the token or the
+// authorization value of the TPM key if one is given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardwareSigner {
+    key: HardwareKey,
+    pin: Option<PinSource>,
+}
+
+impl HardwareSigner {
+    pub fn new(key: HardwareKey) -> Self {
+        Self {
+            key,
+            pin: None,
+        }
+    }
+
+    pub fn pin(mut self, param_pin: PinSource) -> Self {
+        self.pin = Some(param_pin);
+        self
+    }
+
+    pub fn key(&self) -> &HardwareKey {
+        &self.key
+    }
+
+    // Sign a digest with PKCS#1 v1.5 padding inside the token or TPM.
+    pub fn sign_prehash(&self, algo: DigestAlgo, digest: &[u8]) -> Result<Vec<u8>> {
+        if DigestAlgo::from_digest_len(digest.len())? != algo {
+            bail!("digest of {} bytes does not match {:?}", digest.len(), algo);
+        }
+
+        let dir = tempfile::tempdir().context("failed to create temporary directory")?;
+        let output = dir.path().join("signature");
+
+        match &self.key {
+            HardwareKey::Pkcs11(uri) => {
+                // stdin carries the PIN, so the data to sign is passed in a file.
+                let input = dir.path().join("digest-info");
+                fs::write(&input, digest_info(algo, digest)).context(format!("failed to write {}", input.display()))?;
+
+                let mut command = Command::new("pkcs11-tool");
+                command.args(pkcs11_tool_args(uri)?).args(["--sign", "--mechanism", "RSA-PKCS", "--input-file"]).arg(&input).arg("--output-file").arg(&output);
+                let pin = match &self.pin {
+                    Some(pin) => {
+                        // Without --pin, pkcs11-tool reads the PIN from stdin.
+                        command.arg("--login");
+                        Some(format!("{}\n", pin.read()?))
+                    }
+                    None => None,
+                };
+                run_to_file(&mut command, pin.as_deref().map(str::as_bytes), &output)
+            }
+            HardwareKey::Tpm(handle) => {
+                let hash = match algo {
+                    DigestAlgo::Sha256 => "sha256",
+                    DigestAlgo::Sha512 => "sha512",
+                };
+                let mut command = Command::new("tpm2_sign");
+                command.arg("-c").arg(format!("{:#x}", handle)).args(["-g", hash, "-s", "rsassa", "-f", "plain", "-d", "-o"]).arg(&output);
+                // tpm2-tools reads authorization values from files itself, but
+                // not from the environment, and stdin already carries the digest.
+                match &self.pin {
+                    Some(PinSource::File(path)) => {
+                        command.arg("-p").arg(format!("file:{}", path.display()));
+                    }
+                    Some(PinSource::Env(_)) => bail!("the authorization value of TPM keys can only be read from a file:<path>"),
+                    None => (),
+                }
+                run_to_file(&mut command, Some(digest), &output)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hardware_key_from_str() {
+        assert_eq!(
+            "pkcs11:token=ue;object=key".parse::<HardwareKey>().unwrap(),
+            HardwareKey::Pkcs11("pkcs11:token=ue;object=key".to_string())
+        );
+        assert_eq!("tpm:0x81000001".parse::<HardwareKey>().unwrap(), HardwareKey::Tpm(0x81000001));
+        assert_eq!("tpm:81000002".parse::<HardwareKey>().unwrap(), HardwareKey::Tpm(0x81000002));
+        assert!("tpm:handle".parse::<HardwareKey>().is_err());
+        assert!("/path/to/key.pem".parse::<HardwareKey>().is_err());
+    }
+
+    #[test]
+    fn test_pkcs11_tool_args() {
+        assert_eq!(
+            pkcs11_tool_args("pkcs11:token=ue%20keys;object=payload-key;id=%01%a0;type=private?module-path=/usr/lib/softhsm/libsofthsm2.so").unwrap(),
+            ["--token-label", "ue keys", "--label", "payload-key", "--id", "01a0", "--module", "/usr/lib/softhsm/libsofthsm2.so"]
+        );
+        assert!(pkcs11_tool_args("pkcs11:object=bad%2").is_err());
+        assert!(pkcs11_tool_args("pkcs11:object").is_err());
+
+        assert_eq!(
+            pkcs11_module_path("pkcs11:token=ue?module-path=/usr/lib/softhsm/libsofthsm2.so").unwrap().as_deref(),
+            Some("/usr/lib/softhsm/libsofthsm2.so")
+        );
+        assert_eq!(pkcs11_module_path("pkcs11:token=ue").unwrap(), None);
+    }
+
+    #[test]
+    fn test_pin_source() {
+        assert_eq!(
+            "env:TOKEN_PIN".parse::<PinSource>().unwrap(),
+            PinSource::Env("TOKEN_PIN".to_string())
+        );
+        assert!("1234".parse::<PinSource>().is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pin");
+        fs::write(&path, "1234\n").unwrap();
+        let pin = format!("file:{}", path.display()).parse::<PinSource>().unwrap();
+        assert_eq!(pin.read().unwrap(), "1234");
+    }
+
+    #[test]
+    fn test_digest_info() {
+        use rsa::Pkcs1v15Sign;
+        use rsa::sha2::{Digest, Sha256};
+
+        // Raw RSA over DigestInfo must give the same signature as the RSA crate.
+        let private_key = super::super::get_private_key_file("../src/testdata/private_key_test_pkcs8.pem").unwrap();
+        let digest = Sha256::digest(b"test data");
+
+        let expected = private_key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest).unwrap();
+        let raw = private_key.sign(Pkcs1v15Sign::new_unprefixed(), &digest_info(DigestAlgo::Sha256, &digest)).unwrap();
+        assert_eq!(raw, expected);
+    }
+
+    // Needs softhsm2-util of SoftHSM 2, pkcs11-tool and p11tool. The module
+    // is taken from SOFTHSM2_MODULE, or the path of Debian and Fedora.
+    // Run with: cargo test --features hardware-keys -- --ignored softhsm
+    #[test]
+    #[ignore]
+    fn test_sign_softhsm() {
+        use rsa::sha2::{Digest, Sha256};
+
+        let module = std::env::var("SOFTHSM2_MODULE").unwrap_or_else(|_| "/usr/lib/softhsm/libsofthsm2.so".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let tokens = dir.path().join("tokens");
+        fs::create_dir(&tokens).unwrap();
+        let conf = dir.path().join("softhsm2.conf");
+        fs::write(&conf, format!("directories.tokendir = {}\n", tokens.display())).unwrap();
+        // Only this test sets it, and the tools inherit it.
+        std::env::set_var("SOFTHSM2_CONF", &conf);
+
+        run(
+            Command::new("softhsm2-util").args(["--init-token", "--free", "--label", "ue", "--pin", "1234", "--so-pin", "5678"]),
+            None,
+        )
+        .unwrap();
+        run(
+            Command::new("pkcs11-tool").arg("--module").arg(&module).args([
+                "--token-label",
+                "ue",
+                "--login",
+                "--pin",
+                "1234",
+                "--keypairgen",
+                "--key-type",
+                "rsa:2048",
+                "--label",
+                "payload-key",
+                "--id",
+                "01",
+            ]),
+            None,
+        )
+        .unwrap();
+
+        let pin = dir.path().join("pin");
+        fs::write(&pin, "1234\n").unwrap();
+        let key: HardwareKey = format!("pkcs11:token=ue;object=payload-key?module-path={}", module).parse().unwrap();
+        let signer = HardwareSigner::new(key.clone()).pin(PinSource::File(pin));
+
+        let digest = Sha256::digest(b"test data");
+        let signature = signer.sign_prehash(DigestAlgo::Sha256, &digest).unwrap();
+        assert_eq!(signature.len(), key.signature_size().unwrap());
+        super::super::verify_prehash(&digest, &signature, &key.public_key().unwrap()).unwrap();
+
+        // Without the PIN, the token refuses to sign.
+        assert!(HardwareSigner::new(key).sign_prehash(DigestAlgo::Sha256, &digest).is_err());
+    }
+}